@@ -0,0 +1,137 @@
+use std::fmt;
+
+use bevy::prelude::*;
+
+// What is known about a shape besides its geometry. Attributes stay on the CPU;
+// tools look them up by the shape's index in its layer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapeAttributes {
+    pub net: Option<String>,
+    // cells the shape was placed through, e.g. TOP/INV[3]
+    pub instance: Option<String>,
+    // GDS properties, attribute number and value
+    pub properties: Vec<(i16, String)>,
+}
+
+impl fmt::Display for ShapeAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(net) = &self.net {
+            parts.push(format!("net {}", net));
+        }
+        if let Some(instance) = &self.instance {
+            parts.push(format!("instance {}", instance));
+        }
+        for (attribute, value) in self.properties.iter() {
+            parts.push(format!("property {}={}", attribute, value));
+        }
+        match parts.is_empty() {
+            true => write!(f, "no attributes"),
+            false => write!(f, "{}", parts.join(", ")),
+        }
+    }
+}
+
+// Rects without attributes.
+const NONE: u32 = u32::MAX;
+
+// The attributes of a layer's rects, by rect index. Rects that come from one shape
+// share an entry. On a batch entity, this follows the batch's validated rects.
+#[derive(Clone, Component, Debug, Default, PartialEq)]
+pub struct QuadAttributes {
+    table: Vec<ShapeAttributes>,
+    of_rect: Vec<u32>,
+}
+
+impl QuadAttributes {
+    pub fn get(&self, index: usize) -> Option<&ShapeAttributes> {
+        let entry = *self.of_rect.get(index)?;
+        self.table.get(entry as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.of_rect.len()
+    }
+
+    // Adds `count` rects; the previous entry is reused when it is equal.
+    pub fn push(&mut self, attributes: Option<ShapeAttributes>, count: usize) {
+        let entry = match attributes {
+            None => NONE,
+            Some(attributes) if self.table.last() == Some(&attributes) => {
+                self.table.len() as u32 - 1
+            }
+            Some(attributes) => {
+                self.table.push(attributes);
+                self.table.len() as u32 - 1
+            }
+        };
+        self.of_rect.extend(std::iter::repeat_n(entry, count));
+    }
+
+    // Sets the nets of `count` rects, keeping their other attributes; a rect
+    // whose `net` is None keeps its net.
+    pub fn set_nets(&mut self, count: usize, mut net: impl FnMut(usize) -> Option<String>) {
+        let mut updated = QuadAttributes::default();
+        for index in 0..count {
+            let attributes = match (self.get(index), net(index)) {
+                (attributes, None) => attributes.cloned(),
+                (attributes, Some(net)) => Some(ShapeAttributes {
+                    net: Some(net),
+                    ..attributes.cloned().unwrap_or_default()
+                }),
+            };
+            updated.push(attributes, 1);
+        }
+        *self = updated;
+    }
+
+    // Keeps the rects for which `keep(index)` is true, as `Vec::retain` does with
+    // the rects themselves.
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let mut index = 0;
+        self.of_rect.retain(|_| {
+            index += 1;
+            keep(index - 1)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn on_net(net: &str) -> Option<ShapeAttributes> {
+        Some(ShapeAttributes {
+            net: Some(net.into()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn rects_share_attributes() {
+        let mut attributes = QuadAttributes::default();
+        attributes.push(on_net("VDD"), 2);
+        attributes.push(None, 1);
+        attributes.push(on_net("VDD"), 1);
+        attributes.push(on_net("VSS"), 1);
+        assert_eq!(attributes.len(), 5);
+        assert_eq!(attributes.table.len(), 2);
+        assert_eq!(attributes.get(1), on_net("VDD").as_ref());
+        assert_eq!(attributes.get(2), None);
+        assert_eq!(attributes.get(9), None);
+
+        attributes.retain(|i| i != 0 && i != 2);
+        assert_eq!(attributes.len(), 3);
+        assert_eq!(attributes.get(2), on_net("VSS").as_ref());
+        assert_eq!(
+            attributes.get(0).unwrap().to_string(),
+            "net VDD".to_string()
+        );
+
+        attributes.set_nets(4, |i| (i == 1).then(|| "GND".to_string()));
+        assert_eq!(attributes.len(), 4);
+        assert_eq!(attributes.get(1).unwrap().net.as_deref(), Some("GND"));
+        assert_eq!(attributes.get(2), on_net("VSS").as_ref());
+        assert_eq!(attributes.get(3), None);
+    }
+}
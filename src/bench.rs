@@ -0,0 +1,247 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use bevy::render::renderer::RenderDevice;
+use bevy::render::{RenderApp, RenderStage};
+use serde::Serialize;
+
+use crate::layout::Layout;
+use crate::vpull::{quads_per_chunk, QuadDrawMode, QuadMemory};
+
+// Flies the camera along a fixed path for a fixed number of frames, then writes
+// frame times and the CPU time of the render stages that this crate feeds to a
+// JSON file and quits. The path depends only on the frame number and the layout's
+// extent, so runs on the same scene can be compared across commits.
+pub struct BenchPlugin {
+    pub settings: BenchSettings,
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchSettings {
+    // what is being drawn, copied into the report
+    pub scene: String,
+    pub frames: usize,
+    // frames at the start that are left out of the numbers, e.g. the first upload
+    pub warmup: usize,
+    pub output: PathBuf,
+}
+
+impl Plugin for BenchPlugin {
+    fn build(&self, app: &mut App) {
+        let clock = StageClock::default();
+        app.insert_resource(self.settings.clone())
+            .insert_resource(clock.clone())
+            .init_resource::<FrameTimes>()
+            .add_system_to_stage(CoreStage::First, record_frame)
+            .add_system_to_stage(CoreStage::PreUpdate, fly_camera);
+
+        // Exclusive systems at the start and end of a stage bracket all of its
+        // parallel systems. Extract runs on the main world and the other stages on
+        // the render world, so both hold the same clock.
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.insert_resource(clock);
+        for (stage, timed) in [
+            (RenderStage::Extract, TimedStage::Extract),
+            (RenderStage::Prepare, TimedStage::Prepare),
+            (RenderStage::Queue, TimedStage::Queue),
+        ] {
+            render_app
+                .add_system_to_stage(
+                    stage.clone(),
+                    (|clock: Res<StageClock>| clock.start())
+                        .exclusive_system()
+                        .at_start(),
+                )
+                .add_system_to_stage(
+                    stage,
+                    (move |clock: Res<StageClock>| clock.stop(timed))
+                        .exclusive_system()
+                        .at_end(),
+                );
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum TimedStage {
+    Extract,
+    Prepare,
+    Queue,
+}
+
+#[derive(Default)]
+struct StageTimes {
+    started: Option<Instant>,
+    extract: Vec<f64>,
+    prepare: Vec<f64>,
+    queue: Vec<f64>,
+}
+
+#[derive(Clone, Default)]
+struct StageClock(Arc<Mutex<StageTimes>>);
+
+impl StageClock {
+    fn start(&self) {
+        self.0.lock().unwrap().started = Some(Instant::now());
+    }
+
+    fn stop(&self, stage: TimedStage) {
+        let mut times = self.0.lock().unwrap();
+        let ms = match times.started.take() {
+            Some(started) => started.elapsed().as_secs_f64() * 1000.0,
+            None => return,
+        };
+        match stage {
+            TimedStage::Extract => times.extract.push(ms),
+            TimedStage::Prepare => times.prepare.push(ms),
+            TimedStage::Queue => times.queue.push(ms),
+        }
+    }
+}
+
+#[derive(Default)]
+struct FrameTimes {
+    frames: usize,
+    ms: Vec<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    mean: f64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl Summary {
+    fn new(samples: &[f64]) -> Option<Summary> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // nearest rank
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+        Some(Summary {
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: *sorted.last().unwrap(),
+        })
+    }
+}
+
+// Everything is in milliseconds.
+#[derive(Debug, Serialize)]
+struct Report {
+    scene: String,
+    rects: usize,
+    frames: usize,
+    warmup: usize,
+    window: [f32; 2],
+    commit: Option<String>,
+    draw_mode: &'static str,
+    gpu_memory: QuadMemory,
+    frame_time_ms: Option<Summary>,
+    extract_ms: Option<Summary>,
+    prepare_ms: Option<Summary>,
+    queue_ms: Option<Summary>,
+}
+
+fn git_commit() -> Option<String> {
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// The frame that just ended took `time.delta()`.
+#[allow(clippy::too_many_arguments)]
+fn record_frame(
+    time: Res<Time>,
+    settings: Res<BenchSettings>,
+    clock: Res<StageClock>,
+    layout: Res<Layout>,
+    window: Res<WindowDescriptor>,
+    draw_mode: Res<QuadDrawMode>,
+    render_device: Res<RenderDevice>,
+    mut frame_times: ResMut<FrameTimes>,
+    mut exit: EventWriter<AppExit>,
+) {
+    frame_times.frames += 1;
+    if frame_times.frames > settings.warmup + 1 {
+        frame_times.ms.push(time.delta_seconds_f64() * 1000.0);
+    }
+    if frame_times.frames <= settings.warmup + settings.frames {
+        return;
+    }
+
+    let times = clock.0.lock().unwrap();
+    let skip = |samples: &[f64]| Summary::new(samples.get(settings.warmup..).unwrap_or_default());
+    let report = Report {
+        scene: settings.scene.clone(),
+        rects: layout.rect_count(),
+        frames: settings.frames,
+        warmup: settings.warmup,
+        window: [window.width, window.height],
+        commit: git_commit(),
+        draw_mode: draw_mode.name(),
+        gpu_memory: QuadMemory::new(
+            layout.rect_count(),
+            quads_per_chunk(render_device.limits().max_storage_buffer_binding_size),
+            *draw_mode,
+        ),
+        frame_time_ms: Summary::new(&frame_times.ms),
+        extract_ms: skip(&times.extract),
+        prepare_ms: skip(&times.prepare),
+        queue_ms: skip(&times.queue),
+    };
+    let json = serde_json::to_string_pretty(&report).unwrap();
+    match std::fs::write(&settings.output, &json) {
+        Ok(()) => info!("wrote benchmark results to {}", settings.output.display()),
+        Err(e) => error!("can't write {}: {}", settings.output.display(), e),
+    }
+    exit.send(AppExit);
+}
+
+// Zooms out to the whole layout, circles around it while zooming in 50x, and
+// zooms back out, once over the benchmark's frames.
+fn fly_camera(
+    settings: Res<BenchSettings>,
+    frame_times: Res<FrameTimes>,
+    layout: Res<Layout>,
+    window: Res<WindowDescriptor>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+) {
+    let bounds = match layout.bounds() {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    let t = frame_times.frames.saturating_sub(settings.warmup) as f32 / settings.frames as f32;
+    let t = t.clamp(0.0, 1.0);
+    let center = (bounds.min + bounds.max) * 0.5;
+    let fit = (bounds.size() / Vec2::new(window.width, window.height)).max_element() * 1.1;
+
+    // 0 at both ends of the path, 1 in the middle
+    let depth = (t * std::f32::consts::PI).sin();
+    let angle = t * std::f32::consts::TAU * 2.0;
+    let offset = Vec2::new(angle.cos(), angle.sin()) * bounds.size() * 0.3 * depth;
+    for (mut transform, mut projection) in cameras.iter_mut() {
+        transform.translation.x = center.x + offset.x;
+        transform.translation.y = center.y + offset.y;
+        projection.scale = fit.max(f32::EPSILON) / 50f32.powf(depth);
+    }
+}
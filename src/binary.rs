@@ -0,0 +1,329 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem::size_of;
+use std::path::Path;
+
+use bevy::core::{Pod, Zeroable};
+use bytemuck::{cast_slice, cast_slice_mut, pod_read_unaligned, try_cast_slice};
+use memmap2::Mmap;
+
+use crate::gpu_data::GpuQuad;
+use crate::layout::{LayerKey, Layout};
+use crate::{DRect, LayerRects};
+
+// A binary file of flattened layers (.drb), for layouts too big to parse quickly.
+// The rects of a layer are stored exactly as the GPU reads them, so loading maps
+// the file and casts each layer's rects straight out of the map into its batch,
+// without parsing them. Only the rects are kept: no texts and no shape attributes.
+//
+// All numbers are little-endian:
+//
+//   header                  32 bytes, see `Header`
+//   layer table             32 bytes per layer, see `LayerEntry`
+//   name                    `name_len` bytes of UTF-8
+//   rects of each layer     `GpuQuad`s, each array starting at a multiple of 16
+//
+// The table and name are covered by a CRC-32 in the header and each layer's rects
+// by one in its entry; a file that fails either is refused.
+
+const MAGIC: [u8; 8] = *b"DOUGRECT";
+pub const VERSION: u32 = 1;
+// where each layer's rects start
+const ALIGN: usize = 16;
+
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    layer_count: u32,
+    // 0 when the layout has no database unit
+    db_unit: f32,
+    name_len: u32,
+    // of the layer table and the name
+    table_checksum: u32,
+    reserved: u32,
+}
+
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+struct LayerEntry {
+    layer: i16,
+    datatype: i16,
+    // of the rects' bytes
+    checksum: u32,
+    // from the start of the file, in bytes
+    offset: u64,
+    count: u64,
+    reserved: u64,
+}
+
+// The rects are written straight from a layer's `DRect`s.
+const _: () = assert!(size_of::<DRect>() == size_of::<GpuQuad>());
+
+fn align(offset: usize) -> usize {
+    offset.div_ceil(ALIGN) * ALIGN
+}
+
+// Written next to the path and renamed over it, so a viewer that has the old file
+// mapped keeps reading it whole.
+pub fn write_binary(layout: &Layout, path: &Path) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    out.write_all(&binary_bytes(layout))?;
+    out.flush()?;
+    drop(out);
+    std::fs::rename(&partial, path)
+}
+
+fn binary_bytes(layout: &Layout) -> Vec<u8> {
+    let name = layout.name.as_bytes();
+    let mut offset =
+        align(size_of::<Header>() + layout.layers.len() * size_of::<LayerEntry>() + name.len());
+    let mut table: Vec<LayerEntry> = Vec::with_capacity(layout.layers.len());
+    for (layer, key) in layout.layers.iter().zip(layout.layer_keys.iter()) {
+        let bytes: &[u8] = cast_slice(&layer.rects);
+        table.push(LayerEntry {
+            layer: key.layer,
+            datatype: key.datatype,
+            checksum: crc32fast::hash(bytes),
+            offset: offset as u64,
+            count: layer.rects.len() as u64,
+            reserved: 0,
+        });
+        offset = align(offset + bytes.len());
+    }
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(cast_slice(&table));
+    hasher.update(name);
+    let header = Header {
+        magic: MAGIC,
+        version: VERSION,
+        layer_count: table.len() as u32,
+        db_unit: layout.db_unit.unwrap_or(0.0),
+        name_len: name.len() as u32,
+        table_checksum: hasher.finalize(),
+        reserved: 0,
+    };
+
+    let mut bytes = Vec::with_capacity(offset);
+    bytes.extend_from_slice(cast_slice(&[header]));
+    bytes.extend_from_slice(cast_slice(&table));
+    bytes.extend_from_slice(name);
+    for layer in layout.layers.iter() {
+        bytes.resize(align(bytes.len()), 0);
+        bytes.extend_from_slice(cast_slice(&layer.rects));
+    }
+    bytes
+}
+
+// Slices `len` bytes at `offset`, or says what is missing.
+fn section<'a>(bytes: &'a [u8], offset: usize, len: usize, what: &str) -> Result<&'a [u8], String> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| format!("the file ends inside the {}", what))
+}
+
+pub fn parse_binary(bytes: &[u8]) -> Result<Layout, String> {
+    if cfg!(target_endian = "big") {
+        return Err("binary layouts can only be read on little-endian machines".into());
+    }
+    let header: Header = pod_read_unaligned(section(bytes, 0, size_of::<Header>(), "header")?);
+    if header.magic != MAGIC {
+        return Err("not a binary layout".into());
+    }
+    if header.version != VERSION {
+        return Err(format!(
+            "binary layout version {}, this viewer reads version {}",
+            header.version, VERSION
+        ));
+    }
+    if header.layer_count > 256 {
+        return Err(format!(
+            "{} layers, at most 256 can be shown",
+            header.layer_count
+        ));
+    }
+
+    let table_len = header.layer_count as usize * size_of::<LayerEntry>();
+    let table_bytes = section(
+        bytes,
+        size_of::<Header>(),
+        table_len + header.name_len as usize,
+        "layer table",
+    )?;
+    if crc32fast::hash(table_bytes) != header.table_checksum {
+        return Err("the layer table's checksum doesn't match".into());
+    }
+    let (table_bytes, name) = table_bytes.split_at(table_len);
+    let name = std::str::from_utf8(name).map_err(|_| "the name is not UTF-8".to_string())?;
+
+    let mut layout = Layout {
+        name: name.into(),
+        db_unit: (header.db_unit > 0.0).then_some(header.db_unit),
+        ..Default::default()
+    };
+    for (index, entry) in table_bytes
+        .chunks_exact(size_of::<LayerEntry>())
+        .map(pod_read_unaligned::<LayerEntry>)
+        .enumerate()
+    {
+        let key = LayerKey {
+            layer: entry.layer,
+            datatype: entry.datatype,
+        };
+        let len = usize::try_from(entry.count)
+            .ok()
+            .and_then(|count| count.checked_mul(size_of::<DRect>()))
+            .ok_or_else(|| format!("layer {} is too big", key))?;
+        let what = format!("rects of layer {}", key);
+        let quads = section(bytes, entry.offset as usize, len, &what)?;
+        if crc32fast::hash(quads) != entry.checksum {
+            return Err(format!("the checksum of layer {} doesn't match", key));
+        }
+        // a map is page aligned and so are the rects in it; other bytes may not be
+        let rects = match try_cast_slice::<u8, DRect>(quads) {
+            Ok(rects) => rects.to_vec(),
+            Err(_) => {
+                let mut rects = vec![DRect::zeroed(); entry.count as usize];
+                cast_slice_mut(&mut rects).copy_from_slice(quads);
+                rects
+            }
+        };
+        layout.layers.push(LayerRects {
+            rects,
+            index: index as u8,
+            attributes: None,
+        });
+        layout.layer_keys.push(key);
+    }
+    Ok(layout)
+}
+
+pub fn read_binary(path: &Path) -> io::Result<Layout> {
+    let file = File::open(path)?;
+    // SAFETY: the map is read-only and dropped once the rects are copied out. A
+    // file cut short while it is mapped would fault, so writers should replace
+    // binary layouts rather than rewrite them in place.
+    let map = unsafe { Mmap::map(&file)? };
+    // loading errors are reported with the path already
+    parse_binary(&map).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point;
+
+    fn layout() -> Layout {
+        let rect = |x: f32, color: u32| DRect {
+            p0: Point { x, y: 0.0 },
+            p1: Point { x: x + 1.0, y: 2.0 },
+            stroke_width: 0.01,
+            color,
+        };
+        Layout {
+            name: "TOP".into(),
+            layers: vec![
+                LayerRects {
+                    rects: vec![rect(0.0, 0), rect(3.0, 0)],
+                    index: 0,
+                    attributes: None,
+                },
+                LayerRects {
+                    rects: Vec::new(),
+                    index: 1,
+                    attributes: None,
+                },
+                LayerRects {
+                    rects: vec![rect(-1.5, 2)],
+                    index: 2,
+                    attributes: None,
+                },
+            ],
+            layer_keys: vec![
+                LayerKey {
+                    layer: 2,
+                    datatype: 0,
+                },
+                LayerKey {
+                    layer: 1,
+                    datatype: 5,
+                },
+                LayerKey {
+                    layer: -3,
+                    datatype: 0,
+                },
+            ],
+            db_unit: Some(0.001),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn binary_layouts_round_trip() {
+        let layout = layout();
+        let bytes = binary_bytes(&layout);
+        let read = parse_binary(&bytes).unwrap();
+        assert_eq!(read.name, "TOP");
+        assert_eq!(read.db_unit, Some(0.001));
+        assert_eq!(read.layer_keys, layout.layer_keys);
+        for (read, layer) in read.layers.iter().zip(layout.layers.iter()) {
+            assert_eq!(read.rects, layer.rects);
+            assert_eq!(read.index, layer.index);
+        }
+
+        // the rects are stored as the GPU reads them
+        let entry: LayerEntry = pod_read_unaligned(&bytes[32..64]);
+        assert_eq!(entry.offset as usize % ALIGN, 0);
+        let quads: Vec<GpuQuad> = layout.layers[0].rects.iter().map(GpuQuad::from).collect();
+        let start = entry.offset as usize;
+        assert_eq!(
+            &bytes[start..start + 2 * size_of::<GpuQuad>()],
+            cast_slice::<GpuQuad, u8>(&quads)
+        );
+
+        // bytes that are not aligned for the rects are read as well
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(&bytes);
+        let read = parse_binary(&shifted[1..]).unwrap();
+        assert_eq!(read.layers[0].rects, layout.layers[0].rects);
+
+        // and mapped from a file, which is written whole or not at all
+        let path = std::env::temp_dir().join(format!("doug-{}.drb", std::process::id()));
+        write_binary(&layout, &path).unwrap();
+        assert!(!path.with_extension("drb.partial").exists());
+        let read = read_binary(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap().layers[2].rects, layout.layers[2].rects);
+    }
+
+    #[test]
+    fn damaged_binary_layouts_are_refused() {
+        let bytes = binary_bytes(&layout());
+
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        let e = parse_binary(&flipped).unwrap_err();
+        assert_eq!(e, "the checksum of layer -3/0 doesn't match");
+
+        let mut renamed = bytes.clone();
+        renamed[32 + 3 * 32] = b'X';
+        assert!(parse_binary(&renamed).unwrap_err().contains("layer table"));
+
+        let mut newer = bytes.clone();
+        newer[8] = 2;
+        assert!(parse_binary(&newer).unwrap_err().contains("version 2"));
+
+        assert!(parse_binary(&bytes[..bytes.len() - 4])
+            .unwrap_err()
+            .contains("ends inside"));
+        assert_eq!(
+            parse_binary(b"GDSII").unwrap_err(),
+            "the file ends inside the header"
+        );
+    }
+}
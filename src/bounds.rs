@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::{BatchedQuads, DRect};
+
+// An axis-aligned box in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Bounds {
+    pub fn from_rect(rect: &DRect) -> Self {
+        let (p0, p1) = (
+            Vec2::new(rect.p0.x, rect.p0.y),
+            Vec2::new(rect.p1.x, rect.p1.y),
+        );
+        Self {
+            min: p0.min(p1),
+            max: p0.max(p1),
+        }
+    }
+
+    pub fn from_rects<'a>(rects: impl IntoIterator<Item = &'a DRect>) -> Option<Self> {
+        rects
+            .into_iter()
+            .map(Bounds::from_rect)
+            .reduce(Bounds::union)
+    }
+
+    pub fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        self.max - self.min
+    }
+}
+
+// Bounding box of every batch of rects, kept up to date as batches come and go.
+#[derive(Default)]
+pub struct LayoutExtent {
+    pub bounds: Option<Bounds>,
+}
+
+// What the extent has taken in of a batch.
+#[derive(Default)]
+pub struct BatchBounds {
+    generation: u64,
+    len: usize,
+    bounds: Option<Bounds>,
+}
+
+// Batches that were only appended to add the bounds of their new rects.
+pub fn update_layout_extent(
+    changed: Query<(Entity, &BatchedQuads), Changed<BatchedQuads>>,
+    removed: RemovedComponents<BatchedQuads>,
+    mut batch_bounds: Local<HashMap<Entity, BatchBounds>>,
+    mut extent: ResMut<LayoutExtent>,
+) {
+    let mut updated = false;
+    for entity in removed.iter() {
+        updated |= batch_bounds.remove(&entity).is_some();
+    }
+    for (entity, batch) in changed.iter() {
+        if !batch.validated {
+            updated |= batch_bounds.remove(&entity).is_some();
+            continue;
+        }
+        let taken = batch_bounds.entry(entity).or_default();
+        if taken.generation != batch.generation || taken.len > batch.data.len() {
+            *taken = BatchBounds {
+                generation: batch.generation,
+                ..Default::default()
+            };
+            updated = true;
+        }
+        if let Some(added) = Bounds::from_rects(&batch.data[taken.len..]) {
+            taken.bounds = Some(taken.bounds.map_or(added, |bounds| bounds.union(added)));
+            updated = true;
+        }
+        taken.len = batch.data.len();
+    }
+    if !updated {
+        return;
+    }
+    let bounds = batch_bounds
+        .values()
+        .filter_map(|taken| taken.bounds)
+        .reduce(Bounds::union);
+    if extent.bounds != bounds {
+        extent.bounds = bounds;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::rect;
+
+    #[test]
+    fn the_extent_follows_appends_and_replaced_batches() {
+        let mut world = World::new();
+        world.insert_resource(LayoutExtent::default());
+        let batch = world
+            .spawn()
+            .insert(BatchedQuads {
+                data: vec![rect(0.0, 0.0, 1.0, 1.0)],
+                validated: true,
+                generation: 1,
+                ..Default::default()
+            })
+            .id();
+        world.spawn().insert(BatchedQuads {
+            data: vec![rect(-5.0, 0.0, -4.0, 1.0)],
+            ..Default::default()
+        });
+        let mut stage = SystemStage::single_threaded().with_system(update_layout_extent);
+        let extent = |world: &mut World, stage: &mut SystemStage| {
+            stage.run(world);
+            world
+                .resource::<LayoutExtent>()
+                .bounds
+                .map(|bounds| (bounds.min, bounds.max))
+        };
+        // batches waiting to be validated don't count
+        assert_eq!(
+            extent(&mut world, &mut stage),
+            Some((Vec2::ZERO, Vec2::ONE))
+        );
+
+        let mut quads = world.get_mut::<BatchedQuads>(batch).unwrap();
+        quads.data.push(rect(2.0, 0.0, 3.0, 4.0));
+        assert_eq!(
+            extent(&mut world, &mut stage),
+            Some((Vec2::ZERO, Vec2::new(3.0, 4.0)))
+        );
+
+        let mut quads = world.get_mut::<BatchedQuads>(batch).unwrap();
+        quads.data = vec![rect(2.0, 2.0, 3.0, 3.0)];
+        quads.generation = 2;
+        assert_eq!(
+            extent(&mut world, &mut stage),
+            Some((Vec2::splat(2.0), Vec2::splat(3.0)))
+        );
+
+        world.despawn(batch);
+        assert_eq!(extent(&mut world, &mut stage), None);
+    }
+}
@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::Bounds;
+use crate::layout::LayerKey;
+use crate::split_views::{LayerVisibility, Viewport};
+
+// Converts a position in window pixels (origin at the bottom left, as reported by
+// `Window::cursor_position`) into world coordinates.
+pub fn screen_to_world(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    screen_pos: Vec2,
+) -> Vec2 {
+    let window_size = Vec2::new(window.width(), window.height());
+    let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix.inverse();
+    ndc_to_world.project_point3(ndc.extend(-1.0)).truncate()
+}
+
+// For a camera drawing into a viewport, the cursor is taken relative to it.
+pub fn cursor_world_position(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    viewport: Option<&Viewport>,
+) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    let cursor = viewport.map_or(cursor, |viewport| viewport.window_position(window, cursor));
+    Some(screen_to_world(window, camera, camera_transform, cursor))
+}
+
+// Size of one window pixel in world units, used to turn pixel tolerances into
+// world distances.
+pub fn world_units_per_pixel(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> f32 {
+    let origin = screen_to_world(window, camera, camera_transform, Vec2::ZERO);
+    let one_pixel = screen_to_world(window, camera, camera_transform, Vec2::X);
+    origin.distance(one_pixel)
+}
+
+// Size of a camera's view in logical pixels.
+pub fn view_size(window: &Window, viewport: Option<&Viewport>) -> Vec2 {
+    let (min, max) = viewport.copied().unwrap_or_default().logical_rect(window);
+    (max - min).max(Vec2::ONE)
+}
+
+// The world-space box a camera shows in a view of `view_size` logical pixels; at
+// scale 1 a pixel is one world unit.
+pub fn visible_bounds(
+    transform: &Transform,
+    projection: &OrthographicProjection,
+    view_size: Vec2,
+) -> Bounds {
+    let center = transform.translation.truncate();
+    let half = view_size * projection.scale * 0.5;
+    Bounds {
+        min: center - half,
+        max: center + half,
+    }
+}
+
+// Centers the camera on `bounds` and zooms so that they take up 1 / `margin` of the
+// view along their longer side, relative to the view.
+pub fn fit_camera(
+    transform: &mut Transform,
+    projection: &mut OrthographicProjection,
+    bounds: &Bounds,
+    view_size: Vec2,
+    margin: f32,
+) {
+    let center = (bounds.min + bounds.max) * 0.5;
+    transform.translation.x = center.x;
+    transform.translation.y = center.y;
+    let fit = bounds.size() / view_size;
+    projection.scale = (fit.max_element() * margin).max(f32::EPSILON);
+}
+
+// Set while the cursor is over a screen-space widget such as the minimap, so that
+// layout tools ignore clicks meant for the widget.
+#[derive(Default)]
+pub struct CursorOverUi(pub bool);
+
+// Systems that decide whether the cursor is over a widget run under this label, so
+// that camera input can run after them.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, SystemLabel)]
+pub struct UiInput;
+
+// A named view: the world box it shows and the layers it hides, recalled with a
+// number key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub key: u8,
+    // x0, y0, x1, y1
+    pub bbox: [f32; 4],
+    // "layer/datatype" keys
+    pub hidden_layers: Vec<String>,
+}
+
+impl Bookmark {
+    pub fn bounds(&self) -> Bounds {
+        let [x0, y0, x1, y1] = self.bbox;
+        Bounds {
+            min: Vec2::new(x0.min(x1), y0.min(y1)),
+            max: Vec2::new(x0.max(x1), y0.max(y1)),
+        }
+    }
+
+    // Shows the layers of `layer_keys` the bookmark does not hide, and hides the
+    // others.
+    pub fn restore_layers(&self, layer_keys: &[LayerKey], layers: &mut LayerVisibility) {
+        for (index, layer) in layer_keys.iter().enumerate() {
+            let hidden = self.hidden_layers.contains(&layer.to_string());
+            layers.set_visible(index as u8, !hidden);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bookmarks {
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn get(&self, key: u8) -> Option<&Bookmark> {
+        self.bookmarks.iter().find(|b| b.key == key)
+    }
+
+    // Bookmarks `bounds` and the layers of `layer_keys` that `layers` hides on `key`,
+    // keeping the name of a bookmark already there. Returns whether one was.
+    pub fn save(
+        &mut self,
+        key: u8,
+        bounds: &Bounds,
+        layer_keys: &[LayerKey],
+        layers: Option<&LayerVisibility>,
+    ) -> bool {
+        let hidden_layers = layer_keys
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| layers.is_some_and(|layers| !layers.is_visible(*index as u8)))
+            .map(|(_, key)| key.to_string())
+            .collect();
+        let bbox = [bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y];
+        match self.bookmarks.iter_mut().find(|b| b.key == key) {
+            Some(bookmark) => {
+                bookmark.bbox = bbox;
+                bookmark.hidden_layers = hidden_layers;
+                true
+            }
+            None => {
+                self.bookmarks.push(Bookmark {
+                    name: format!("bookmark {}", key),
+                    key,
+                    bbox,
+                    hidden_layers,
+                });
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::navigation::FIT_MARGIN;
+    use crate::test_support::bounds;
+
+    fn assert_near(a: Vec2, b: Vec2) {
+        assert!(a.abs_diff_eq(b, 1e-3), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn the_visible_box_follows_the_camera() {
+        let transform = Transform::from_xyz(100.0, 50.0, 0.0);
+        let projection = OrthographicProjection {
+            scale: 2.0,
+            ..Default::default()
+        };
+        let visible = visible_bounds(&transform, &projection, Vec2::new(800.0, 400.0));
+        assert_eq!(visible, bounds(-700.0, -350.0, 900.0, 450.0));
+    }
+
+    #[test]
+    fn fitting_centers_the_bounds_with_a_margin() {
+        let mut transform = Transform::from_xyz(-5.0, 7.0, 3.0);
+        let mut projection = OrthographicProjection::default();
+        let layout = bounds(0.0, 0.0, 100.0, 20.0);
+        let view = Vec2::new(800.0, 400.0);
+        fit_camera(&mut transform, &mut projection, &layout, view, FIT_MARGIN);
+        assert_eq!(transform.translation, Vec3::new(50.0, 10.0, 3.0));
+
+        // the wide bounds fill the wide view, less the margin, and are centered
+        // in the other direction
+        let visible = visible_bounds(&transform, &projection, view);
+        assert_near(visible.size(), Vec2::new(100.0, 50.0) * FIT_MARGIN);
+        assert_near(visible.min + visible.max, layout.min + layout.max);
+
+        // tall bounds in the same view are fitted along their height
+        let layout = bounds(0.0, 0.0, 20.0, 100.0);
+        fit_camera(&mut transform, &mut projection, &layout, view, FIT_MARGIN);
+        let visible = visible_bounds(&transform, &projection, view);
+        assert_near(visible.size(), Vec2::new(200.0, 100.0) * FIT_MARGIN);
+        assert_near(visible.min + visible.max, layout.min + layout.max);
+    }
+
+    #[test]
+    fn jumping_to_a_bookmark_restores_its_view_and_layers() {
+        let layer_keys: Vec<LayerKey> = ["1/0", "2/0", "3/1"]
+            .iter()
+            .map(|key| key.parse().unwrap())
+            .collect();
+        let mut layers = LayerVisibility::default();
+        layers.set_visible(1, false);
+        let mut transform = Transform::from_xyz(100.0, 50.0, 0.0);
+        let mut projection = OrthographicProjection {
+            scale: 2.0,
+            ..Default::default()
+        };
+        let view = Vec2::new(800.0, 400.0);
+        let saved = visible_bounds(&transform, &projection, view);
+
+        let mut bookmarks = Bookmarks::default();
+        assert!(!bookmarks.save(3, &saved, &layer_keys, Some(&layers)));
+        let bookmark = bookmarks.get(3).unwrap();
+        assert_eq!(bookmark.name, "bookmark 3");
+        assert_eq!(bookmark.hidden_layers, vec!["2/0".to_string()]);
+
+        // move away and change the layers, then jump back
+        transform.translation = Vec3::new(-1000.0, 0.0, 0.0);
+        projection.scale = 0.25;
+        layers = LayerVisibility::default();
+        layers.set_visible(2, false);
+        fit_camera(
+            &mut transform,
+            &mut projection,
+            &bookmark.bounds(),
+            view,
+            1.0,
+        );
+        bookmark.restore_layers(&layer_keys, &mut layers);
+        assert_eq!(visible_bounds(&transform, &projection, view), saved);
+        assert!(layers.is_visible(0) && !layers.is_visible(1) && layers.is_visible(2));
+
+        // saving on the same key again updates the bookmark
+        assert!(bookmarks.save(3, &saved, &layer_keys, None));
+        assert_eq!(bookmarks.bookmarks.len(), 1);
+        assert!(bookmarks.get(3).unwrap().hidden_layers.is_empty());
+    }
+}
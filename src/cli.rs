@@ -0,0 +1,411 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use clap::{Args, CommandFactory, ErrorKind, FromArgMatches, Parser, Subcommand, ValueSource};
+
+use crate::bounds::Bounds;
+use crate::diff::DiffMode;
+use crate::geometry::DerivedLayer;
+use crate::layout::{LayerKey, LoadOptions};
+use crate::scenes::Scene;
+use crate::search::{SearchQuery, SEARCH_OUTPUT};
+use crate::vpull::QuadDrawMode;
+
+#[derive(Debug, Parser)]
+#[clap(name = "doug_renderers", about = "View and export rect layouts")]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    // for the viewer started without a command
+    #[clap(flatten)]
+    pub viewer: ViewerArgs,
+}
+
+impl Cli {
+    // Parses the command line. Viewer options before a command are refused rather
+    // than ignored: the command takes its own.
+    pub fn parse_args() -> Cli {
+        let matches = Cli::command().get_matches();
+        let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        let given = [
+            "draw-mode",
+            "session",
+            "no-session",
+            "socket",
+            "stdin",
+            "grid-pitch",
+            "manufacturing-grid",
+        ]
+        .into_iter()
+        .find(|id| matches.value_source(id) == Some(ValueSource::CommandLine));
+        if let (Some(command), Some(flag)) = (&cli.command, given) {
+            let name = matches.subcommand_name().unwrap_or_default();
+            let message = match command.viewer() {
+                Some(_) => format!(
+                    "--{} goes after the command, as in {} ... --{}",
+                    flag, name, flag
+                ),
+                None => format!(
+                    "{} doesn't open the viewer, --{} is for the commands that do",
+                    name, flag
+                ),
+            };
+            Cli::command()
+                .error(ErrorKind::ArgumentConflict, message)
+                .exit();
+        }
+        cli
+    }
+
+    // The options of the viewer, from the command that opens it or, without one,
+    // from the program's own.
+    pub fn viewer(&self) -> &ViewerArgs {
+        self.command
+            .as_ref()
+            .and_then(Command::viewer)
+            .unwrap_or(&self.viewer)
+    }
+
+    pub fn viewer_mut(&mut self) -> &mut ViewerArgs {
+        match &mut self.command {
+            Some(Command::View(args)) => &mut args.viewer,
+            Some(Command::Scene(args)) => &mut args.viewer,
+            Some(Command::Bench(args)) => &mut args.scene.viewer,
+            Some(Command::Diff(args)) => &mut args.viewer,
+            Some(Command::Drc(args)) => &mut args.viewer,
+            _ => &mut self.viewer,
+        }
+    }
+}
+
+// Options of the commands that open a window.
+#[derive(Clone, Debug, Args)]
+pub struct ViewerArgs {
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "indexed",
+        help = "Draw quads through an index buffer, or without one to save memory"
+    )]
+    pub draw_mode: QuadDrawMode,
+    #[clap(
+        long,
+        default_value = ".doug_session.json",
+        help = "Where the viewer keeps the camera, layers, rulers and bookmarks between launches"
+    )]
+    pub session: PathBuf,
+    #[clap(long, help = "Neither restore nor save the session")]
+    pub no_session: bool,
+    #[clap(
+        long,
+        help = "Unix socket on which the viewer takes geometry from other tools"
+    )]
+    pub socket: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Add the shapes written to standard input to the view as they arrive, one JSON object per line"
+    )]
+    pub stdin: bool,
+    #[clap(
+        long,
+        parse(try_from_str = parse_length),
+        help = "Spacing of the grid lines in world units, instead of one that follows the zoom"
+    )]
+    pub grid_pitch: Option<f32>,
+    #[clap(
+        long,
+        parse(try_from_str = parse_length),
+        help = "Manufacturing grid in world units: grid lines fall on it and snapped points stay on it"
+    )]
+    pub manufacturing_grid: Option<f32>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[clap(about = "Open a layout in the viewer")]
+    View(ViewArgs),
+    #[clap(about = "Render a layout to a PNG image without opening a window")]
+    ExportPng(ExportPngArgs),
+    #[clap(about = "Write a layout as an SVG drawing")]
+    ExportSvg(ExportArgs),
+    #[clap(about = "Print the size and per-layer counts of a layout")]
+    Stats(InputArgs),
+    #[clap(about = "Convert a layout to another format, picked from the output's extension")]
+    Convert(ExportArgs),
+    #[clap(about = "Open a generated scene in the viewer")]
+    Scene(SceneArgs),
+    #[clap(
+        about = "Fly through a scene or layout for a number of frames and write timings as JSON"
+    )]
+    Bench(BenchArgs),
+    #[clap(about = "Compare two layouts in the viewer, or print what changed")]
+    Diff(DiffArgs),
+    #[clap(about = "Check a layout against design rules, in the viewer or as a list")]
+    Drc(DrcArgs),
+    #[clap(about = "List the shapes on a net, in a cell or with a property")]
+    Search(SearchArgs),
+    #[clap(about = "Send the layers of a layout to a viewer started with --socket")]
+    Push(PushArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct InputArgs {
+    #[clap(
+        help = "Layout file to read (.gds, .json or .ndjson shapes, or .drb written by convert)"
+    )]
+    pub file: PathBuf,
+    #[clap(long, help = "Cell to show; defaults to the only top cell")]
+    pub cell: Option<String>,
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Layers to show, in draw order, e.g. 1/0,2/0; all layers by default"
+    )]
+    pub layers: Vec<LayerKey>,
+    #[clap(
+        long,
+        parse(try_from_str = parse_bbox),
+        allow_hyphen_values = true,
+        help = "Only load shapes inside x0,y0,x1,y1 (world units)"
+    )]
+    pub bbox: Option<Bounds>,
+    #[clap(
+        long,
+        default_value = "0.01",
+        help = "Outline width of every shape, in world units"
+    )]
+    pub stroke: f32,
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        help = "Add a layer computed from loaded ones, e.g. 10/0=1/0 and 2/0, 11/0=1/0 not 2/0, 12/0=1/0 grow 0.1 (also or, xor, shrink)"
+    )]
+    pub derive: Vec<DerivedLayer>,
+}
+
+impl InputArgs {
+    pub fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            cell: self.cell.clone(),
+            layers: self.layers.clone(),
+            bbox: self.bbox,
+            stroke_width: self.stroke,
+            derived: self.derive.clone(),
+        }
+    }
+}
+
+impl Command {
+    // The viewer options of the commands that can open the viewer.
+    pub fn viewer(&self) -> Option<&ViewerArgs> {
+        match self {
+            Command::View(args) => Some(&args.viewer),
+            Command::Scene(args) => Some(&args.viewer),
+            Command::Bench(args) => Some(&args.scene.viewer),
+            Command::Diff(args) => Some(&args.viewer),
+            Command::Drc(args) => Some(&args.viewer),
+            _ => None,
+        }
+    }
+
+    // The layout files the command opens.
+    pub fn files(&self) -> Vec<PathBuf> {
+        match self {
+            Command::View(args) => vec![args.input.file.clone()],
+            Command::Diff(args) => vec![args.old.file.clone(), args.new.clone()],
+            Command::Drc(args) => vec![args.input.file.clone()],
+            Command::Search(args) => vec![args.input.file.clone()],
+            Command::Push(args) => vec![args.input.file.clone()],
+            Command::Bench(args) => args.layout.iter().cloned().collect(),
+            Command::ExportPng(args) => vec![args.export.input.file.clone()],
+            Command::ExportSvg(args) | Command::Convert(args) => vec![args.input.file.clone()],
+            Command::Stats(args) => vec![args.file.clone()],
+            Command::Scene(_) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ViewArgs {
+    #[clap(flatten)]
+    pub input: InputArgs,
+    #[clap(
+        long,
+        help = "Layer stack file: lines like conductor 1/0, via 2/0 1/0 3/0; clicking a shape then highlights its net"
+    )]
+    pub layer_stack: Option<PathBuf>,
+    #[clap(long, help = "Don't reload the layout when its file changes")]
+    pub no_watch: bool,
+    #[clap(
+        long,
+        help = "Highlight the shapes matching net=<regex>, cell=<regex>, property=<regex> or property:<attribute>=<regex>"
+    )]
+    pub search: Option<SearchQuery>,
+    #[clap(
+        long,
+        default_value = SEARCH_OUTPUT,
+        help = "Output file the search matches are written to when X is pressed in the viewer"
+    )]
+    pub search_output: PathBuf,
+    #[clap(flatten)]
+    pub viewer: ViewerArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[clap(flatten)]
+    pub input: InputArgs,
+    #[clap(short, long, help = "File to write")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportPngArgs {
+    #[clap(flatten)]
+    pub export: ExportArgs,
+    #[clap(long, default_value = "1920", help = "Image width in pixels")]
+    pub width: u32,
+    #[clap(long, default_value = "1080", help = "Image height in pixels")]
+    pub height: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct SceneArgs {
+    #[clap(arg_enum, default_value = "small-random")]
+    pub scene: Scene,
+    #[clap(
+        long,
+        default_value = "100000",
+        help = "Number of rects, for scenes that take one"
+    )]
+    pub count: usize,
+    #[clap(long, default_value = "0", help = "Seed of the random scenes")]
+    pub seed: u64,
+    #[clap(flatten)]
+    pub viewer: ViewerArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    #[clap(flatten)]
+    pub scene: SceneArgs,
+    #[clap(long, help = "Benchmark a layout file instead of a generated scene")]
+    pub layout: Option<PathBuf>,
+    #[clap(long, default_value = "600")]
+    pub frames: usize,
+    #[clap(long, default_value = "60", help = "Frames to run before measuring")]
+    pub warmup: usize,
+    #[clap(short, long, default_value = "bench.json")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    #[clap(flatten)]
+    pub old: InputArgs,
+    #[clap(help = "Layout to compare with the first one, loaded with the same options")]
+    pub new: PathBuf,
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "overlay",
+        help = "How to show the differences; D switches between them in the viewer"
+    )]
+    pub mode: DiffMode,
+    #[clap(
+        long,
+        help = "Print added, removed and changed shapes per layer instead of opening a window"
+    )]
+    pub report: bool,
+    #[clap(flatten)]
+    pub viewer: ViewerArgs,
+}
+
+impl DiffArgs {
+    pub fn new_input(&self) -> InputArgs {
+        InputArgs {
+            file: self.new.clone(),
+            cell: self.old.cell.clone(),
+            layers: self.old.layers.clone(),
+            bbox: self.old.bbox,
+            stroke: self.old.stroke,
+            derive: self.old.derive.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DrcArgs {
+    #[clap(flatten)]
+    pub input: InputArgs,
+    #[clap(
+        long,
+        help = "Rules file: lines like width 1/0 0.1, spacing 1/0 0.1, area 1/0 0.05, enclosure 2/0 1/0 0.02"
+    )]
+    pub rules: PathBuf,
+    #[clap(long, help = "Print the violations instead of opening a window")]
+    pub report: bool,
+    #[clap(flatten)]
+    pub viewer: ViewerArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SearchArgs {
+    #[clap(flatten)]
+    pub input: InputArgs,
+    #[clap(
+        help = "net=<regex>, cell=<regex>, property=<regex> or property:<attribute>=<regex>; a bare regex is a cell name"
+    )]
+    pub query: SearchQuery,
+    #[clap(long, help = "Layer stack file, to name nets after their text labels")]
+    pub layer_stack: Option<PathBuf>,
+    #[clap(
+        short,
+        long,
+        help = "Write the matches to a file instead of printing them"
+    )]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct PushArgs {
+    #[clap(flatten)]
+    pub input: InputArgs,
+    #[clap(
+        long,
+        help = "Add the rects to the viewer's layers instead of replacing them"
+    )]
+    pub append: bool,
+    #[clap(long, help = "Fit the viewer's camera to the layout")]
+    pub fit: bool,
+    #[clap(long, help = "Socket of the viewer, as given to its --socket")]
+    pub socket: PathBuf,
+}
+
+// A positive distance.
+fn parse_length(s: &str) -> Result<f32, String> {
+    match s.trim().parse::<f32>() {
+        Ok(length) if length.is_finite() && length > 0.0 => Ok(length),
+        Ok(_) => Err(format!("{} is not a positive length", s)),
+        Err(e) => Err(format!("bad length {:?}: {}", s, e)),
+    }
+}
+
+// "x0,y0,x1,y1", in any corner order
+fn parse_bbox(s: &str) -> Result<Bounds, String> {
+    let values = s
+        .split(',')
+        .map(|value| value.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|e| format!("bad bbox {:?}: {}", s, e))?;
+    match values.as_slice() {
+        [x0, y0, x1, y1] => {
+            let (a, b) = (Vec2::new(*x0, *y0), Vec2::new(*x1, *y1));
+            Ok(Bounds {
+                min: a.min(b),
+                max: a.max(b),
+            })
+        }
+        _ => Err(format!("bad bbox {:?}, expected x0,y0,x1,y1", s)),
+    }
+}
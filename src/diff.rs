@@ -0,0 +1,524 @@
+use std::fmt::Write as _;
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use bevy::utils::HashMap;
+use clap::ArgEnum;
+
+use crate::bounds::Bounds;
+use crate::geometry::{area, boolean, BooleanOp};
+use crate::layout::{LayerKey, Layout, LoadError};
+use crate::navigation::CameraController;
+use crate::split_views::{split_camera, LayerVisibility, SplitCamera, Viewport};
+use crate::{DRect, LayerRects};
+
+// Comparing two versions of a layout. Both are loaded into one layout, one after
+// the other, followed by a layer of their XOR for every layer they have; the diff
+// modes pick which of these each view shows.
+
+// Palette colors of the old shapes, the new shapes and the XOR.
+const OLD_COLOR: u32 = 0;
+const NEW_COLOR: u32 = 3;
+const XOR_COLOR: u32 = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ArgEnum)]
+pub enum DiffMode {
+    // both layouts in one view, in two colors
+    #[default]
+    Overlay,
+    // only the area covered by one of the layouts and not the other
+    Xor,
+    // the old layout on the left and the new one on the right, panning together
+    SideBySide,
+}
+
+impl DiffMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiffMode::Overlay => "overlay",
+            DiffMode::Xor => "xor",
+            DiffMode::SideBySide => "side by side",
+        }
+    }
+
+    fn next(&self) -> DiffMode {
+        match self {
+            DiffMode::Overlay => DiffMode::Xor,
+            DiffMode::Xor => DiffMode::SideBySide,
+            DiffMode::SideBySide => DiffMode::Overlay,
+        }
+    }
+}
+
+// Which layers of the combined layout hold what.
+#[derive(Clone, Debug)]
+pub struct LayoutDiff {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    pub xor: Range<usize>,
+}
+
+impl LayoutDiff {
+    fn visibility(&self, shown: &[&Range<usize>]) -> LayerVisibility {
+        let mut layers = LayerVisibility::default();
+        for layer in 0..=255u8 {
+            let visible = shown.iter().any(|range| range.contains(&(layer as usize)));
+            layers.set_visible(layer, visible);
+        }
+        layers
+    }
+}
+
+// Loads both layouts into one, see the top of this file.
+pub fn diff_layout(old: &Layout, new: &Layout) -> Result<(Layout, LayoutDiff), LoadError> {
+    let keys = layer_keys(old, new);
+    let layer_count = old.layers.len() + new.layers.len() + keys.len();
+    if layer_count > 256 {
+        return Err(LoadError::TooManyLayers(layer_count));
+    }
+
+    let mut layout = Layout {
+        name: format!("{} -> {}", old.name, new.name),
+        skipped_polygons: old.skipped_polygons + new.skipped_polygons,
+        db_unit: old.db_unit.or(new.db_unit),
+        ..Default::default()
+    };
+    let mut push_layer = |key: LayerKey, source: &LayerRects, color: u32| {
+        let mut layer = source.clone();
+        for rect in layer.rects.iter_mut() {
+            rect.color = color;
+        }
+        layer.index = layout.layers.len() as u8;
+        layout.layers.push(layer);
+        layout.layer_keys.push(key);
+    };
+    for (layer, key) in old.layers.iter().zip(old.layer_keys.iter()) {
+        push_layer(*key, layer, OLD_COLOR);
+    }
+    for (layer, key) in new.layers.iter().zip(new.layer_keys.iter()) {
+        push_layer(*key, layer, NEW_COLOR);
+    }
+    for key in keys.iter() {
+        let (old_rects, new_rects) = (layer_rects(old, *key), layer_rects(new, *key));
+        let stroke_width = old_rects
+            .iter()
+            .chain(new_rects.iter())
+            .map(|rect| rect.stroke_width)
+            .next()
+            .unwrap_or_default();
+        let mut xor = boolean(&old_rects, &new_rects, BooleanOp::Xor);
+        for rect in xor.iter_mut() {
+            rect.stroke_width = stroke_width;
+        }
+        let xor = LayerRects {
+            rects: xor,
+            ..Default::default()
+        };
+        push_layer(*key, &xor, XOR_COLOR);
+    }
+
+    // the new layout's texts, on its layers
+    let offset = old.layers.len() as u8;
+    layout.texts = new
+        .texts
+        .iter()
+        .cloned()
+        .map(|mut text| {
+            text.layer += offset;
+            text
+        })
+        .collect();
+
+    let old_end = old.layers.len();
+    let new_end = old_end + new.layers.len();
+    let diff = LayoutDiff {
+        old: 0..old_end,
+        new: old_end..new_end,
+        xor: new_end..layout.layers.len(),
+    };
+    Ok((layout, diff))
+}
+
+// The layers of both layouts, the old one's first.
+fn layer_keys(old: &Layout, new: &Layout) -> Vec<LayerKey> {
+    let mut keys = old.layer_keys.clone();
+    for key in new.layer_keys.iter() {
+        if !keys.contains(key) {
+            keys.push(*key);
+        }
+    }
+    keys
+}
+
+fn layer_rects(layout: &Layout, key: LayerKey) -> Vec<DRect> {
+    layout
+        .layers
+        .iter()
+        .zip(layout.layer_keys.iter())
+        .filter(|(_, k)| **k == key)
+        .flat_map(|(layer, _)| layer.rects.iter().copied())
+        .collect()
+}
+
+// How one layer changed. Shapes are compared by their corners; a removed shape
+// that overlaps an added one is reported as changed into it.
+#[derive(Clone, Debug, Default)]
+pub struct LayerDiff {
+    pub key: LayerKey,
+    pub added: Vec<DRect>,
+    pub removed: Vec<DRect>,
+    pub changed: Vec<(DRect, DRect)>,
+    pub unchanged: usize,
+    pub xor_area: f32,
+}
+
+impl LayerDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn corners(rect: &DRect) -> [u32; 4] {
+    let r = Bounds::from_rect(rect);
+    [
+        r.min.x.to_bits(),
+        r.min.y.to_bits(),
+        r.max.x.to_bits(),
+        r.max.y.to_bits(),
+    ]
+}
+
+fn overlaps(a: &DRect, b: &DRect) -> bool {
+    let (a, b) = (Bounds::from_rect(a), Bounds::from_rect(b));
+    let (min, max) = (a.min.max(b.min), a.max.min(b.max));
+    min.x < max.x && min.y < max.y
+}
+
+// Pairs every removed shape with the first added one it overlaps that isn't
+// paired yet. The added shapes are sorted on min.x, so each removed shape only
+// looks at those that can reach it.
+fn pair_changed(removed: Vec<DRect>, added: Vec<DRect>, diff: &mut LayerDiff) {
+    let bounds: Vec<Bounds> = added.iter().map(Bounds::from_rect).collect();
+    let mut order: Vec<usize> = (0..added.len()).collect();
+    order.sort_by(|a, b| bounds[*a].min.x.total_cmp(&bounds[*b].min.x));
+    let widest = bounds.iter().map(|b| b.size().x).fold(0.0, f32::max);
+
+    let mut paired = vec![false; added.len()];
+    for rect in removed {
+        let r = Bounds::from_rect(&rect);
+        let first = order.partition_point(|&i| bounds[i].min.x <= r.min.x - widest);
+        let into = order[first..]
+            .iter()
+            .take_while(|&&i| bounds[i].min.x < r.max.x)
+            .filter(|&&i| !paired[i] && overlaps(&rect, &added[i]))
+            .min();
+        match into {
+            Some(&i) => {
+                paired[i] = true;
+                diff.changed.push((rect, added[i]));
+            }
+            None => diff.removed.push(rect),
+        }
+    }
+    diff.added = added
+        .into_iter()
+        .zip(paired)
+        .filter(|(_, paired)| !paired)
+        .map(|(rect, _)| rect)
+        .collect();
+}
+
+pub fn diff_layers(old: &Layout, new: &Layout) -> Vec<LayerDiff> {
+    layer_keys(old, new)
+        .into_iter()
+        .map(|key| {
+            let (old_rects, new_rects) = (layer_rects(old, key), layer_rects(new, key));
+            let mut diff = LayerDiff {
+                key,
+                xor_area: area(&boolean(&old_rects, &new_rects, BooleanOp::Xor)),
+                ..Default::default()
+            };
+
+            // shapes in both, counted so that duplicates match one to one
+            let mut remaining: HashMap<[u32; 4], usize> = HashMap::default();
+            for rect in old_rects.iter() {
+                *remaining.entry(corners(rect)).or_default() += 1;
+            }
+            let mut added = Vec::new();
+            for rect in new_rects.iter() {
+                match remaining.get_mut(&corners(rect)) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        diff.unchanged += 1;
+                    }
+                    _ => added.push(*rect),
+                }
+            }
+            let mut removed = Vec::new();
+            for rect in old_rects.iter() {
+                if let Some(count) = remaining
+                    .get_mut(&corners(rect))
+                    .filter(|count| **count > 0)
+                {
+                    *count -= 1;
+                    removed.push(*rect);
+                }
+            }
+
+            pair_changed(removed, added, &mut diff);
+            diff
+        })
+        .collect()
+}
+
+fn write_rect(out: &mut String, rect: &DRect) {
+    let r = Bounds::from_rect(rect);
+    write!(
+        out,
+        "({}, {}) - ({}, {})",
+        r.min.x, r.min.y, r.max.x, r.max.y
+    )
+    .unwrap();
+}
+
+// Added, removed and changed shapes of every layer that differs.
+pub fn diff_report(old: &Layout, new: &Layout) -> String {
+    let diffs = diff_layers(old, new);
+    let mut out = String::new();
+    writeln!(out, "old: {}, {} rects", old.name, old.rect_count()).unwrap();
+    writeln!(out, "new: {}, {} rects", new.name, new.rect_count()).unwrap();
+    let changed_layers = diffs.iter().filter(|diff| !diff.is_empty()).count();
+    writeln!(out, "{} of {} layers differ", changed_layers, diffs.len()).unwrap();
+    for diff in diffs.iter().filter(|diff| !diff.is_empty()) {
+        writeln!(
+            out,
+            "layer {}: {} added, {} removed, {} changed, {} unchanged, xor area {}",
+            diff.key,
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len(),
+            diff.unchanged,
+            diff.xor_area
+        )
+        .unwrap();
+        for rect in diff.added.iter() {
+            out.push_str("  added   ");
+            write_rect(&mut out, rect);
+            out.push('\n');
+        }
+        for rect in diff.removed.iter() {
+            out.push_str("  removed ");
+            write_rect(&mut out, rect);
+            out.push('\n');
+        }
+        for (old_rect, new_rect) in diff.changed.iter() {
+            out.push_str("  changed ");
+            write_rect(&mut out, old_rect);
+            out.push_str(" -> ");
+            write_rect(&mut out, new_rect);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// D cycles through the diff modes.
+pub struct DiffPlugin {
+    pub diff: LayoutDiff,
+    pub mode: DiffMode,
+}
+
+impl Plugin for DiffPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.diff.clone())
+            .insert_resource(self.mode)
+            .add_system(cycle_diff_mode)
+            .add_system(apply_diff_mode.after(cycle_diff_mode));
+    }
+}
+
+fn cycle_diff_mode(keys: Res<Input<KeyCode>>, mut mode: ResMut<DiffMode>) {
+    if keys.just_pressed(KeyCode::D) {
+        *mode = mode.next();
+        info!("diff: {}", mode.name());
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn apply_diff_mode(
+    mut commands: Commands,
+    mode: Res<DiffMode>,
+    diff: Res<LayoutDiff>,
+    mut cameras: Query<
+        (
+            Entity,
+            &Transform,
+            &OrthographicProjection,
+            &mut Viewport,
+            &mut LayerVisibility,
+            Option<&CameraController>,
+            Option<&SplitCamera>,
+        ),
+        With<Camera2d>,
+    >,
+) {
+    if !mode.is_changed() {
+        return;
+    }
+    let split = cameras
+        .iter()
+        .any(|(.., split_camera)| split_camera.is_some());
+    if *mode == DiffMode::SideBySide {
+        if split {
+            for (.., mut layers, _, split_camera) in cameras.iter_mut() {
+                *layers = match split_camera {
+                    Some(_) => diff.visibility(&[&diff.new]),
+                    None => diff.visibility(&[&diff.old]),
+                };
+            }
+        } else if let Some((_, transform, projection, mut viewport, mut layers, controller, _)) =
+            cameras.iter_mut().next()
+        {
+            *layers = diff.visibility(&[&diff.old]);
+            split_camera(
+                &mut commands,
+                transform,
+                projection,
+                &mut viewport,
+                diff.visibility(&[&diff.new]),
+                controller,
+            );
+        }
+        return;
+    }
+
+    let shown = match *mode {
+        DiffMode::Xor => diff.visibility(&[&diff.xor]),
+        _ => diff.visibility(&[&diff.old, &diff.new]),
+    };
+    for (entity, .., mut viewport, mut layers, _, split_camera) in cameras.iter_mut() {
+        if split_camera.is_some() {
+            commands.entity(entity).despawn();
+        } else {
+            *viewport = Viewport::default();
+            *layers = shown.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{layout, rect};
+
+    #[test]
+    fn layers_are_compared_shape_by_shape() {
+        let same = rect(0.0, 0.0, 1.0, 1.0);
+        let twice = rect(10.0, 0.0, 11.0, 1.0);
+        let old = layout(vec![
+            (
+                1,
+                vec![
+                    same,
+                    rect(5.0, 0.0, 6.0, 1.0),
+                    twice,
+                    twice,
+                    rect(30.0, 0.0, 31.0, 1.0),
+                ],
+            ),
+            (2, vec![same]),
+        ]);
+        let new = layout(vec![
+            (
+                1,
+                // the same shapes in another order and with flipped corners match
+                vec![
+                    rect(20.0, 0.0, 21.0, 1.0),
+                    rect(11.0, 1.0, 10.0, 0.0),
+                    rect(5.0, 0.0, 6.0, 2.0),
+                    same,
+                ],
+            ),
+            (2, vec![same]),
+            (3, vec![rect(0.0, 0.0, 2.0, 2.0)]),
+        ]);
+
+        let diffs = diff_layers(&old, &new);
+        let keys: Vec<i16> = diffs.iter().map(|diff| diff.key.layer).collect();
+        assert_eq!(keys, vec![1, 2, 3]);
+
+        let diff = &diffs[0];
+        assert_eq!(diff.unchanged, 2);
+        assert_eq!(
+            diff.changed,
+            vec![(rect(5.0, 0.0, 6.0, 1.0), rect(5.0, 0.0, 6.0, 2.0))]
+        );
+        // one of the two copies is gone
+        assert_eq!(diff.removed, vec![twice, rect(30.0, 0.0, 31.0, 1.0)]);
+        assert_eq!(diff.added, vec![rect(20.0, 0.0, 21.0, 1.0)]);
+        // the area, where the copy covers nothing new
+        assert_eq!(diff.xor_area, 3.0);
+
+        assert!(diffs[1].is_empty());
+        assert_eq!((diffs[1].unchanged, diffs[1].xor_area), (1, 0.0));
+        assert_eq!(diffs[2].added.len(), 1);
+        assert_eq!(diffs[2].xor_area, 4.0);
+    }
+
+    #[test]
+    fn removed_shapes_change_into_the_first_added_shape_they_overlap() {
+        let old = layout(vec![(
+            1,
+            vec![
+                rect(50.0, 0.0, 51.0, 2.0),
+                rect(50.5, 0.0, 51.5, 2.0),
+                rect(50.2, 0.0, 51.2, 2.0),
+            ],
+        )]);
+        let new = layout(vec![(
+            1,
+            vec![
+                // starts far to the left of the shapes it overlaps
+                rect(0.0, 0.0, 100.0, 1.0),
+                rect(50.8, 0.5, 52.0, 3.0),
+                rect(200.0, 0.0, 201.0, 1.0),
+            ],
+        )]);
+        let diff = &diff_layers(&old, &new)[0];
+        assert_eq!(
+            diff.changed,
+            vec![
+                (rect(50.0, 0.0, 51.0, 2.0), rect(0.0, 0.0, 100.0, 1.0)),
+                (rect(50.5, 0.0, 51.5, 2.0), rect(50.8, 0.5, 52.0, 3.0)),
+            ]
+        );
+        // both shapes it overlaps are taken already
+        assert_eq!(diff.removed, vec![rect(50.2, 0.0, 51.2, 2.0)]);
+        assert_eq!(diff.added, vec![rect(200.0, 0.0, 201.0, 1.0)]);
+    }
+
+    #[test]
+    fn reports_list_the_layers_that_differ() {
+        let old = Layout {
+            name: "old".into(),
+            ..layout(vec![(1, vec![rect(0.0, 0.0, 1.0, 1.0)]), (2, vec![])])
+        };
+        let new = Layout {
+            name: "new".into(),
+            ..layout(vec![
+                (1, vec![rect(0.0, 0.0, 2.0, 1.0), rect(4.0, 0.0, 5.0, 1.0)]),
+                (2, vec![]),
+            ])
+        };
+        assert_eq!(
+            diff_report(&old, &new),
+            "old: old, 1 rects\n\
+             new: new, 2 rects\n\
+             1 of 2 layers differ\n\
+             layer 1/0: 1 added, 0 removed, 1 changed, 0 unchanged, xor area 2\n  \
+             added   (4, 0) - (5, 1)\n  \
+             changed (0, 0) - (1, 1) -> (0, 0) - (2, 1)\n"
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::fmt::{self, Write as _};
+use std::io;
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::bounds::Bounds;
+use crate::camera::{fit_camera, view_size};
+use crate::geometry::{area, boolean, connected_groups, merge, size, BooleanOp};
+use crate::layout::{LayerKey, Layout};
+use crate::overlay::Overlay;
+use crate::split_views::{FocusedView, Viewport};
+use crate::DRect;
+
+// Design-rule checks on the rects of a layout. Checks work on the merged area of
+// a layer, and distances are measured along x and y, so two corners are as far
+// apart as the larger of their x and y distances.
+
+const MARKER_COLOR: Color = Color::rgb(1.0, 0.15, 0.15);
+const CURRENT_COLOR: Color = Color::YELLOW;
+const MARKER_WIDTH_PX: f32 = 1.5;
+// Shapes exactly at a rule's limit pass; distances are compared with this much
+// slack, relative to the limit, to absorb rounding.
+const SLACK: f32 = 1e-3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrcRule {
+    // every part of the layer is at least this wide
+    Width(LayerKey, f32),
+    // shapes and notches of the layer are at least this far apart
+    Spacing(LayerKey, f32),
+    // every shape of the layer covers at least this much area
+    Area(LayerKey, f32),
+    // the first layer covers the second with at least this much margin
+    Enclosure(LayerKey, LayerKey, f32),
+}
+
+impl fmt::Display for DrcRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DrcRule::Width(layer, width) => write!(f, "width {} {}", layer, width),
+            DrcRule::Spacing(layer, spacing) => write!(f, "spacing {} {}", layer, spacing),
+            DrcRule::Area(layer, area) => write!(f, "area {} {}", layer, area),
+            DrcRule::Enclosure(outer, inner, margin) => {
+                write!(f, "enclosure {} {} {}", outer, inner, margin)
+            }
+        }
+    }
+}
+
+// One rule per line, `#` starts a comment:
+//   width 1/0 0.1
+//   spacing 1/0 0.12
+//   area 1/0 0.05
+//   enclosure 2/0 1/0 0.02    (2/0 encloses 1/0 by 0.02)
+pub fn parse_rules(text: &str) -> Result<Vec<DrcRule>, String> {
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let rule = parse_rule(&words).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+fn parse_rule(words: &[&str]) -> Result<DrcRule, String> {
+    let value = |word: &str| {
+        word.parse::<f32>()
+            .ok()
+            .filter(|value| *value >= 0.0)
+            .ok_or_else(|| format!("bad value {:?}", word))
+    };
+    match words {
+        ["width", layer, width] => Ok(DrcRule::Width(layer.parse()?, value(width)?)),
+        ["spacing", layer, spacing] => Ok(DrcRule::Spacing(layer.parse()?, value(spacing)?)),
+        ["area", layer, area] => Ok(DrcRule::Area(layer.parse()?, value(area)?)),
+        ["enclosure", outer, inner, margin] => Ok(DrcRule::Enclosure(
+            outer.parse()?,
+            inner.parse()?,
+            value(margin)?,
+        )),
+        _ => Err(format!(
+            "expected width, spacing or area <layer> <value>, or enclosure <outer> <inner> <value>, got {:?}",
+            words.join(" ")
+        )),
+    }
+}
+
+pub fn read_rules(path: &Path) -> io::Result<Vec<DrcRule>> {
+    parse_rules(&std::fs::read_to_string(path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+// A place where a rule is broken: the offending area, as rects.
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub rule: DrcRule,
+    pub markers: Vec<DRect>,
+    pub bounds: Bounds,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at ({}, {}) - ({}, {})",
+            self.rule, self.bounds.min.x, self.bounds.min.y, self.bounds.max.x, self.bounds.max.y
+        )
+    }
+}
+
+// Merged rects of a layer; layers that are not loaded are empty.
+fn layer_area(layout: &Layout, key: LayerKey) -> Vec<DRect> {
+    layout
+        .layer(key)
+        .map(|layer| merge(&layer.rects))
+        .unwrap_or_default()
+}
+
+// One violation per connected piece of `markers`.
+fn violations(rule: &DrcRule, markers: Vec<DRect>) -> Vec<Violation> {
+    connected_groups(&markers)
+        .into_iter()
+        .map(|group| {
+            let markers: Vec<DRect> = group.into_iter().map(|i| markers[i]).collect();
+            Violation {
+                rule: rule.clone(),
+                bounds: Bounds::from_rects(markers.iter()).unwrap(),
+                markers,
+            }
+        })
+        .collect()
+}
+
+pub fn check_rule(layout: &Layout, rule: &DrcRule) -> Vec<Violation> {
+    match rule {
+        // what is left after opening (shrinking, then growing back) is wide enough
+        DrcRule::Width(layer, width) => {
+            let region = layer_area(layout, *layer);
+            let amount = width * 0.5 * (1.0 - SLACK);
+            let wide = size(&size(&region, -amount), amount);
+            violations(rule, boolean(&region, &wide, BooleanOp::Not))
+        }
+        // closing (growing, then shrinking back) fills the gaps that are too narrow
+        DrcRule::Spacing(layer, spacing) => {
+            let region = layer_area(layout, *layer);
+            let amount = spacing * 0.5 * (1.0 - SLACK);
+            let closed = size(&size(&region, amount), -amount);
+            violations(rule, boolean(&closed, &region, BooleanOp::Not))
+        }
+        DrcRule::Area(layer, min_area) => {
+            let region = layer_area(layout, *layer);
+            connected_groups(&region)
+                .into_iter()
+                .map(|group| group.into_iter().map(|i| region[i]).collect::<Vec<_>>())
+                .filter(|shape| area(shape) < min_area * (1.0 - SLACK))
+                .map(|shape| Violation {
+                    rule: rule.clone(),
+                    bounds: Bounds::from_rects(shape.iter()).unwrap(),
+                    markers: shape,
+                })
+                .collect()
+        }
+        // the inner layer must fit in the outer one shrunk by the margin
+        DrcRule::Enclosure(outer, inner, margin) => {
+            let outer = layer_area(layout, *outer);
+            let inner = layer_area(layout, *inner);
+            let inside = size(&outer, -margin * (1.0 - SLACK));
+            violations(rule, boolean(&inner, &inside, BooleanOp::Not))
+        }
+    }
+}
+
+pub fn check(layout: &Layout, rules: &[DrcRule]) -> Vec<Violation> {
+    rules
+        .iter()
+        .flat_map(|rule| check_rule(layout, rule))
+        .collect()
+}
+
+// Violation counts per rule, then every violation.
+pub fn drc_report(rules: &[DrcRule], violations: &[Violation]) -> String {
+    let mut out = String::new();
+    for rule in rules.iter() {
+        let count = violations.iter().filter(|v| v.rule == *rule).count();
+        writeln!(out, "{:>8}  {}", count, rule).unwrap();
+    }
+    writeln!(out, "{} violations", violations.len()).unwrap();
+    for (i, violation) in violations.iter().enumerate() {
+        writeln!(out, "{:>6}: {}", i + 1, violation).unwrap();
+    }
+    out
+}
+
+pub struct DrcViolations {
+    pub violations: Vec<Violation>,
+    // the one last stepped to
+    pub current: Option<usize>,
+}
+
+// Draws a marker over every violation. ] and [ step to the next and previous
+// violation, centering the focused view on it.
+pub struct DrcPlugin {
+    pub violations: Vec<Violation>,
+}
+
+impl Plugin for DrcPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DrcViolations {
+            violations: self.violations.clone(),
+            current: None,
+        })
+        .init_resource::<FocusedView>()
+        .add_system(step_violations)
+        .add_system(draw_violations.after(step_violations));
+    }
+}
+
+fn step_violations(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    focused: Res<FocusedView>,
+    mut drc: ResMut<DrcViolations>,
+    mut cameras: Query<(
+        &mut Transform,
+        &mut OrthographicProjection,
+        Option<&Viewport>,
+    )>,
+) {
+    let count = drc.violations.len();
+    let step = match (
+        keys.just_pressed(KeyCode::RBracket),
+        keys.just_pressed(KeyCode::LBracket),
+    ) {
+        (true, false) => 1,
+        (false, true) => count.saturating_sub(1),
+        _ => return,
+    };
+    if count == 0 {
+        info!("no design rule violations");
+        return;
+    }
+    let current = match drc.current {
+        Some(current) => (current + step) % count,
+        None if step == 1 => 0,
+        None => count - 1,
+    };
+    drc.current = Some(current);
+    let violation = &drc.violations[current];
+    info!("violation {}/{}: {}", current + 1, count, violation);
+
+    // the violation takes up about a quarter of the view
+    let camera = focused.0.and_then(|entity| cameras.get_mut(entity).ok());
+    if let (Some((mut transform, mut projection, viewport)), Some(window)) =
+        (camera, windows.get_primary())
+    {
+        fit_camera(
+            &mut transform,
+            &mut projection,
+            &violation.bounds,
+            view_size(window, viewport),
+            4.0,
+        );
+    }
+}
+
+fn draw_violations(drc: Res<DrcViolations>, mut overlay: ResMut<Overlay>) {
+    for (i, violation) in drc.violations.iter().enumerate() {
+        let current = drc.current == Some(i);
+        let color = if current { CURRENT_COLOR } else { MARKER_COLOR };
+        for marker in violation.markers.iter() {
+            let r = Bounds::from_rect(marker);
+            overlay.rect(r.min, r.max, MARKER_WIDTH_PX, color);
+        }
+        if current {
+            let size = violation.bounds.size().max_element() * 0.25;
+            overlay.rect(
+                violation.bounds.min - Vec2::splat(size),
+                violation.bounds.max + Vec2::splat(size),
+                MARKER_WIDTH_PX * 2.0,
+                color,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{layer, layout, rect};
+
+    #[test]
+    fn rules_parse() {
+        let rules = parse_rules(
+            "# metal 1\nwidth 1/0 0.1\n\nspacing 1 0.12  # same layer\narea 1/0 2\nenclosure 2/0 1/0 0.05\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                DrcRule::Width(layer(1), 0.1),
+                DrcRule::Spacing(layer(1), 0.12),
+                DrcRule::Area(layer(1), 2.0),
+                DrcRule::Enclosure(layer(2), layer(1), 0.05),
+            ]
+        );
+        assert_eq!(rules[3].to_string(), "enclosure 2/0 1/0 0.05");
+        let error = parse_rules("width 1/0 0.1\nwidth 1/0\n").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{}", error);
+        assert!(parse_rules("width 1/0 -1").is_err());
+        assert!(parse_rules("height 1/0 1").is_err());
+    }
+
+    #[test]
+    fn width() {
+        // a 1 wide bar with a 0.5 wide stub, and a bar exactly at the limit
+        let layout = layout(vec![(
+            1,
+            vec![
+                rect(0.0, 0.0, 10.0, 1.0),
+                rect(4.0, 1.0, 4.5, 3.0),
+                rect(0.0, 5.0, 10.0, 6.0),
+            ],
+        )]);
+        let found = check_rule(&layout, &DrcRule::Width(layer(1), 1.0));
+        assert_eq!(found.len(), 1);
+        let bounds = found[0].bounds;
+        assert!(
+            (bounds.min - Vec2::new(4.0, 1.0)).length() < 1e-3,
+            "{:?}",
+            bounds
+        );
+        assert!(
+            (bounds.max - Vec2::new(4.5, 3.0)).length() < 1e-3,
+            "{:?}",
+            bounds
+        );
+        assert!(check_rule(&layout, &DrcRule::Width(layer(1), 0.5)).is_empty());
+    }
+
+    #[test]
+    fn spacing() {
+        let layout = layout(vec![(
+            1,
+            vec![
+                rect(0.0, 0.0, 1.0, 1.0),
+                // 0.5 to the right of the first
+                rect(1.5, 0.0, 2.5, 1.0),
+                // 1 above the first, exactly at the limit
+                rect(0.0, 2.0, 1.0, 3.0),
+            ],
+        )]);
+        let found = check_rule(&layout, &DrcRule::Spacing(layer(1), 1.0));
+        assert_eq!(found.len(), 1);
+        assert!((found[0].bounds.min - Vec2::new(1.0, 0.0)).length() < 1e-3);
+        assert!((found[0].bounds.max - Vec2::new(1.5, 1.0)).length() < 1e-3);
+        assert!(check_rule(&layout, &DrcRule::Spacing(layer(1), 0.5)).is_empty());
+    }
+
+    #[test]
+    fn area_of_merged_shapes() {
+        let layout = layout(vec![(
+            1,
+            vec![
+                // 2 in two abutting halves
+                rect(0.0, 0.0, 1.0, 1.0),
+                rect(1.0, 0.0, 2.0, 1.0),
+                rect(5.0, 5.0, 6.0, 6.0),
+            ],
+        )]);
+        let found = check_rule(&layout, &DrcRule::Area(layer(1), 1.5));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].bounds.min, Vec2::new(5.0, 5.0));
+        assert!(check_rule(&layout, &DrcRule::Area(layer(1), 1.0)).is_empty());
+    }
+
+    #[test]
+    fn enclosure() {
+        let layout = layout(vec![
+            (2, vec![rect(0.0, 0.0, 10.0, 10.0)]),
+            (
+                1,
+                vec![
+                    rect(1.0, 1.0, 2.0, 2.0),
+                    // 0.25 from the right edge
+                    rect(8.0, 4.0, 9.75, 5.0),
+                    // outside
+                    rect(20.0, 0.0, 21.0, 1.0),
+                ],
+            ),
+        ]);
+        let found = check_rule(&layout, &DrcRule::Enclosure(layer(2), layer(1), 0.5));
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            check_rule(&layout, &DrcRule::Enclosure(layer(2), layer(1), 0.25)).len(),
+            1
+        );
+        // a layer that isn't loaded has no shapes
+        assert!(check_rule(&layout, &DrcRule::Width(layer(7), 1.0)).is_empty());
+    }
+}
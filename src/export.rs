@@ -0,0 +1,331 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::render::render_resource::WgpuLimits;
+
+use crate::binary::write_binary;
+use crate::bounds::Bounds;
+use crate::layout::Layout;
+use crate::split_views::LayerVisibility;
+use crate::validation::sanitize_rects;
+use crate::vpull::{quads_per_chunk, Palette, QuadDrawMode, QuadMemory, PALETTE};
+
+// Exports that run without a window: images of a layout, its numbers, and other
+// file formats.
+
+fn palette_hex(color: u32) -> &'static str {
+    PALETTE[color as usize % PALETTE.len()]
+}
+
+// Maps world space onto an image, fitting `bounds` and keeping its aspect ratio.
+struct ImageFrame {
+    origin: Vec2,
+    // pixels per world unit
+    scale: f32,
+    size: Vec2,
+}
+
+impl ImageFrame {
+    fn new(bounds: Bounds, width: u32, height: u32) -> Self {
+        let size = Vec2::new(width as f32, height as f32);
+        let extent = bounds.size().max(Vec2::splat(f32::MIN_POSITIVE));
+        let scale = (size / extent).min_element();
+        // center the layout in the image
+        let origin = (bounds.min + bounds.max) * 0.5 - size / scale * 0.5;
+        Self {
+            origin,
+            scale,
+            size,
+        }
+    }
+
+    // Pixel coordinates, from the top left.
+    fn to_pixels(&self, p: Vec2) -> Vec2 {
+        let local = (p - self.origin) * self.scale;
+        Vec2::new(local.x, self.size.y - local.y)
+    }
+}
+
+// Images are at most this many pixels, e.g. 8192 x 8192; each takes 12 bytes while
+// it is drawn.
+pub const MAX_IMAGE_PIXELS: usize = 1 << 26;
+
+// The number of pixels of an image, or why it can't be made.
+pub fn image_pixels(width: u32, height: u32) -> io::Result<usize> {
+    match (width as usize).checked_mul(height as usize) {
+        Some(0) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "images need a width and a height",
+        )),
+        Some(pixels) if pixels <= MAX_IMAGE_PIXELS => Ok(pixels),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} x {} pixels is too big, images have at most {} pixels",
+                width, height, MAX_IMAGE_PIXELS
+            ),
+        )),
+    }
+}
+
+// The colors rects index and the layers that are left out of an image; by default
+// the viewer's starting palette and every layer.
+#[derive(Clone, Debug, Default)]
+pub struct PngStyle {
+    pub palette: Palette,
+    pub visibility: LayerVisibility,
+}
+
+// Draws the layout like the viewer does: translucent fills with opaque outlines
+// `stroke_width` wide (at least a pixel), over a black background.
+pub fn export_png(
+    layout: &Layout,
+    bounds: Option<Bounds>,
+    width: u32,
+    height: u32,
+    style: &PngStyle,
+    path: &Path,
+) -> io::Result<()> {
+    let mut pixels = vec![Vec3::ZERO; image_pixels(width, height)?];
+    let colors = &style.palette.colors;
+    if let (Some(bounds), false) = (bounds.or_else(|| layout.bounds()), colors.is_empty()) {
+        let frame = ImageFrame::new(bounds, width, height);
+        let rects = layout
+            .layers
+            .iter()
+            .filter(|layer| style.visibility.is_visible(layer.index))
+            .flat_map(|layer| layer.rects.iter());
+        for rect in rects {
+            let color = colors[rect.color as usize % colors.len()];
+            let color = Vec3::new(color.r(), color.g(), color.b());
+            let r = Bounds::from_rect(rect);
+            let (a, b) = (frame.to_pixels(r.min), frame.to_pixels(r.max));
+            let (min, max) = (a.min(b), a.max(b));
+            let stroke = (rect.stroke_width * frame.scale).max(1.0);
+
+            // every rect covers at least one pixel, so that tiny rects stay visible
+            let x0 = min.x.floor().max(0.0) as u32;
+            let y0 = min.y.floor().max(0.0) as u32;
+            let x1 = (max.x.ceil().max(min.x.floor() + 1.0) as u32).min(width);
+            let y1 = (max.y.ceil().max(min.y.floor() + 1.0) as u32).min(height);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    let to_edge = (center - min).min(max - center).min_element();
+                    let alpha = if to_edge < stroke { 1.0 } else { 0.2 };
+                    let pixel = &mut pixels[y as usize * width as usize + x as usize];
+                    *pixel = pixel.lerp(color, alpha);
+                }
+            }
+        }
+    }
+
+    let data: Vec<u8> = pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_array())
+        .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect();
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(io::Error::other)
+}
+
+// One group per layer, in draw order, with world units as SVG user units.
+pub fn export_svg(layout: &Layout, bounds: Option<Bounds>, path: &Path) -> io::Result<()> {
+    let bounds = bounds.or_else(|| layout.bounds()).unwrap_or(Bounds {
+        min: Vec2::ZERO,
+        max: Vec2::ONE,
+    });
+    let size = bounds.size();
+    let mut svg = String::new();
+    // SVG's y axis points down, so the layout is flipped about y = 0
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        bounds.min.x, -bounds.max.y, size.x, size.y
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="black"/>"#,
+        bounds.min.x, -bounds.max.y, size.x, size.y
+    )
+    .unwrap();
+    for (layer, key) in layout.layers.iter().zip(layout.layer_keys.iter()) {
+        let color = palette_hex(layer.index as u32 % PALETTE.len() as u32);
+        writeln!(
+            svg,
+            "<g id=\"layer {}\" fill=\"#{}\" fill-opacity=\"0.2\" stroke=\"#{}\">",
+            key, color, color
+        )
+        .unwrap();
+        for rect in layer.rects.iter() {
+            let r = Bounds::from_rect(rect);
+            writeln!(
+                svg,
+                r#"  <rect x="{}" y="{}" width="{}" height="{}" stroke-width="{}"/>"#,
+                r.min.x,
+                -r.max.y,
+                r.size().x,
+                r.size().y,
+                rect.stroke_width
+            )
+            .unwrap();
+        }
+        writeln!(svg, "</g>").unwrap();
+    }
+    for text in layout.texts.iter() {
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-size="{}" font-family="monospace" fill="white">{}</text>"#,
+            text.position.x,
+            -text.position.y,
+            text.height * 1.4,
+            escape_xml(&text.text)
+        )
+        .unwrap();
+    }
+    writeln!(svg, "</svg>").unwrap();
+    std::fs::write(path, svg)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// One rect per line: layer,datatype,x0,y0,x1,y1
+pub fn export_csv(layout: &Layout, path: &Path) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "layer,datatype,x0,y0,x1,y1")?;
+    for (layer, key) in layout.layers.iter().zip(layout.layer_keys.iter()) {
+        for rect in layer.rects.iter() {
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                key.layer, key.datatype, rect.p0.x, rect.p0.y, rect.p1.x, rect.p1.y
+            )?;
+        }
+    }
+    out.flush()
+}
+
+// Writes the layout in the format its extension names.
+pub fn convert(layout: &Layout, path: &Path) -> io::Result<()> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "csv" => export_csv(layout, path),
+        "svg" => export_svg(layout, None, path),
+        "drb" => write_binary(layout, path),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("don't know how to write {}", path.display()),
+        )),
+    }
+}
+
+// A human-readable summary: size, layers, and what validation would do.
+pub fn stats(layout: &Layout) -> String {
+    let mut out = String::new();
+    writeln!(out, "cell: {}", layout.name).unwrap();
+    match layout.bounds() {
+        Some(bounds) => writeln!(
+            out,
+            "extent: ({}, {}) - ({}, {}), {} x {}",
+            bounds.min.x,
+            bounds.min.y,
+            bounds.max.x,
+            bounds.max.y,
+            bounds.size().x,
+            bounds.size().y
+        )
+        .unwrap(),
+        None => writeln!(out, "extent: empty").unwrap(),
+    }
+    writeln!(out, "rects: {}", layout.rect_count()).unwrap();
+    writeln!(out, "texts: {}", layout.texts.len()).unwrap();
+    if layout.skipped_polygons > 0 {
+        writeln!(
+            out,
+            "skipped polygons with slanted edges: {}",
+            layout.skipped_polygons
+        )
+        .unwrap();
+    }
+    // what the upload would take with the default limits
+    let per_chunk = quads_per_chunk(WgpuLimits::default().max_storage_buffer_binding_size);
+    for mode in [
+        QuadDrawMode::Indexed,
+        QuadDrawMode::NonIndexed,
+        QuadDrawMode::GpuCulled,
+    ] {
+        let memory = QuadMemory::new(layout.rect_count(), per_chunk, mode);
+        writeln!(
+            out,
+            "gpu memory, {} draw: {} bytes ({} in quads, {} in indices, {} per view for culling)",
+            mode.name(),
+            memory.total(),
+            memory.quad_bytes,
+            memory.index_bytes,
+            memory.cull_bytes_per_view
+        )
+        .unwrap();
+    }
+    writeln!(out, "layers:").unwrap();
+    for (layer, key) in layout.layers.iter().zip(layout.layer_keys.iter()) {
+        let (_, diagnostics) = sanitize_rects(&layer.rects);
+        let area: f32 = layer
+            .rects
+            .iter()
+            .map(|rect| {
+                let size = Bounds::from_rect(rect).size();
+                size.x * size.y
+            })
+            .sum();
+        writeln!(
+            out,
+            "  {:>8}  {:>10} rects  area {:<12}  rejected {}  repaired {}",
+            key.to_string(),
+            layer.rects.len(),
+            area,
+            diagnostics.rejected(),
+            diagnostics.repaired()
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_sizes_are_checked() {
+        assert_eq!(image_pixels(1920, 1080).unwrap(), 1920 * 1080);
+        assert_eq!(image_pixels(8192, 8192).unwrap(), MAX_IMAGE_PIXELS);
+        // 65536 * 65536 overflows a u32
+        assert!(image_pixels(65536, 65536).is_err());
+        assert!(image_pixels(u32::MAX, u32::MAX).is_err());
+        assert!(image_pixels(0, 1080).is_err());
+
+        let path = std::env::temp_dir().join(format!("doug-{}.png", std::process::id()));
+        let layout = Layout::default();
+        let style = PngStyle::default();
+        assert!(export_png(&layout, None, 65536, 65536, &style, &path).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,766 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::rc::Rc;
+
+// A minimal GDSII stream reader: enough of the format to flatten a cell into
+// boxes, polygons, paths and texts.
+
+const HEADER: u8 = 0x00;
+const BGNLIB: u8 = 0x01;
+const LIBNAME: u8 = 0x02;
+const UNITS: u8 = 0x03;
+const ENDLIB: u8 = 0x04;
+const BGNSTR: u8 = 0x05;
+const STRNAME: u8 = 0x06;
+const ENDSTR: u8 = 0x07;
+const BOUNDARY: u8 = 0x08;
+const PATH: u8 = 0x09;
+const SREF: u8 = 0x0a;
+const AREF: u8 = 0x0b;
+const TEXT: u8 = 0x0c;
+const LAYER: u8 = 0x0d;
+const DATATYPE: u8 = 0x0e;
+const WIDTH: u8 = 0x0f;
+const XY: u8 = 0x10;
+const ENDEL: u8 = 0x11;
+const SNAME: u8 = 0x12;
+const COLROW: u8 = 0x13;
+const NODE: u8 = 0x15;
+const TEXTTYPE: u8 = 0x16;
+const PRESENTATION: u8 = 0x17;
+const STRING: u8 = 0x19;
+const STRANS: u8 = 0x1a;
+const MAG: u8 = 0x1b;
+const ANGLE: u8 = 0x1c;
+const PATHTYPE: u8 = 0x21;
+const PROPATTR: u8 = 0x2b;
+const PROPVALUE: u8 = 0x2c;
+const BOX: u8 = 0x2d;
+const BOXTYPE: u8 = 0x2e;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Strans {
+    // mirror about the x axis, before rotating
+    pub reflect: bool,
+    pub mag: f64,
+    // counter-clockwise, in degrees
+    pub angle: f64,
+}
+
+// Where a text's origin sits on the text, from the low bits of its PRESENTATION.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Justification {
+    // 0: left, 1: center, 2: right
+    pub horizontal: u8,
+    // 0: top, 1: middle, 2: bottom
+    pub vertical: u8,
+}
+
+impl Justification {
+    fn from_bits(bits: u16) -> Self {
+        Justification {
+            horizontal: (bits & 0b11) as u8,
+            vertical: (bits >> 2 & 0b11) as u8,
+        }
+    }
+}
+
+// PROPATTR / PROPVALUE pairs of an element.
+pub type Properties = Vec<(i16, String)>;
+
+#[derive(Clone, Debug)]
+pub enum GdsElement {
+    // closed polygon, the last point repeats the first
+    Boundary {
+        layer: i16,
+        datatype: i16,
+        points: Vec<(i32, i32)>,
+        properties: Properties,
+    },
+    Path {
+        layer: i16,
+        datatype: i16,
+        // 0: flush ends, 1: round ends, 2: ends extended by half the width
+        pathtype: i16,
+        width: i32,
+        points: Vec<(i32, i32)>,
+        properties: Properties,
+    },
+    Box {
+        layer: i16,
+        boxtype: i16,
+        points: Vec<(i32, i32)>,
+        properties: Properties,
+    },
+    Text {
+        layer: i16,
+        texttype: i16,
+        text: String,
+        origin: (i32, i32),
+        strans: Strans,
+        // None without a PRESENTATION record
+        justification: Option<Justification>,
+    },
+    Sref {
+        cell: String,
+        origin: (i32, i32),
+        strans: Strans,
+    },
+    Aref {
+        cell: String,
+        columns: i16,
+        rows: i16,
+        // origin, origin displaced by all columns, origin displaced by all rows
+        points: [(i32, i32); 3],
+        strans: Strans,
+    },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GdsCell {
+    pub name: String,
+    pub elements: Vec<GdsElement>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GdsLibrary {
+    pub name: String,
+    // size of a database unit in user units (usually microns)
+    pub user_units_per_db_unit: f64,
+    pub meters_per_db_unit: f64,
+    pub cells: Vec<GdsCell>,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct Record {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+impl Record {
+    fn i16s(&self) -> Vec<i16> {
+        self.data
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    fn i16(&self) -> io::Result<i16> {
+        self.i16s()
+            .first()
+            .copied()
+            .ok_or_else(|| invalid("empty integer record"))
+    }
+
+    fn i32s(&self) -> Vec<i32> {
+        self.data
+            .chunks_exact(4)
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    fn points(&self) -> Vec<(i32, i32)> {
+        self.i32s().chunks_exact(2).map(|p| (p[0], p[1])).collect()
+    }
+
+    // GDSII 8-byte reals: sign bit, excess-64 base-16 exponent, 56-bit mantissa.
+    fn f64s(&self) -> Vec<f64> {
+        self.data
+            .chunks_exact(8)
+            .map(|b| {
+                let sign = if b[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+                let exponent = (b[0] & 0x7f) as i32 - 64;
+                let mantissa = b[1..]
+                    .iter()
+                    .fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+                sign * mantissa as f64 / 2f64.powi(56) * 16f64.powi(exponent)
+            })
+            .collect()
+    }
+
+    fn f64(&self) -> io::Result<f64> {
+        self.f64s()
+            .first()
+            .copied()
+            .ok_or_else(|| invalid("empty real record"))
+    }
+
+    fn string(&self) -> String {
+        let end = self
+            .data
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.data.len());
+        String::from_utf8_lossy(&self.data[..end]).into_owned()
+    }
+}
+
+struct Records<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Records<'a> {
+    fn next(&mut self) -> io::Result<Record> {
+        let header = self
+            .bytes
+            .get(self.offset..self.offset + 4)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        let length = u16::from_be_bytes([header[0], header[1]]) as usize;
+        let kind = header[2];
+        if length < 4 {
+            return Err(invalid(format!(
+                "bad record length {} at byte {}",
+                length, self.offset
+            )));
+        }
+        let data = self
+            .bytes
+            .get(self.offset + 4..self.offset + length)
+            .ok_or_else(|| invalid("unexpected end of file"))?
+            .to_vec();
+        self.offset += length;
+        Ok(Record { kind, data })
+    }
+}
+
+impl GdsLibrary {
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::parse(&bytes)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut records = Records { bytes, offset: 0 };
+        let mut library = GdsLibrary::default();
+        if records.next()?.kind != HEADER {
+            return Err(invalid("not a GDSII stream"));
+        }
+        loop {
+            let record = records.next()?;
+            match record.kind {
+                BGNLIB => {}
+                LIBNAME => library.name = record.string(),
+                UNITS => {
+                    let units = record.f64s();
+                    if units.len() != 2 {
+                        return Err(invalid("bad UNITS record"));
+                    }
+                    library.user_units_per_db_unit = units[0];
+                    library.meters_per_db_unit = units[1];
+                }
+                BGNSTR => library.cells.push(read_cell(&mut records)?),
+                ENDLIB => return Ok(library),
+                // reference libraries, fonts, generations, ...
+                _ => {}
+            }
+        }
+    }
+
+    pub fn cell(&self, name: &str) -> Option<&GdsCell> {
+        self.cells.iter().find(|cell| cell.name == name)
+    }
+
+    // Cells that no other cell references.
+    pub fn top_cells(&self) -> Vec<&GdsCell> {
+        let referenced: HashSet<&str> = self
+            .cells
+            .iter()
+            .flat_map(|cell| cell.elements.iter())
+            .filter_map(|element| match element {
+                GdsElement::Sref { cell, .. } | GdsElement::Aref { cell, .. } => {
+                    Some(cell.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        self.cells
+            .iter()
+            .filter(|cell| !referenced.contains(cell.name.as_str()))
+            .collect()
+    }
+}
+
+fn read_cell(records: &mut Records) -> io::Result<GdsCell> {
+    let mut cell = GdsCell::default();
+    loop {
+        let record = records.next()?;
+        match record.kind {
+            STRNAME => cell.name = record.string(),
+            ENDSTR => return Ok(cell),
+            BOUNDARY | PATH | BOX | TEXT | SREF | AREF => {
+                if let Some(element) = read_element(records, record.kind)? {
+                    cell.elements.push(element);
+                }
+            }
+            NODE => {
+                // nodes carry no geometry to draw
+                while records.next()?.kind != ENDEL {}
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_element(records: &mut Records, kind: u8) -> io::Result<Option<GdsElement>> {
+    let mut layer = 0;
+    let mut datatype = 0;
+    let mut pathtype = 0;
+    let mut width = 0;
+    let mut points = Vec::new();
+    let mut name = String::new();
+    let mut text = String::new();
+    let mut colrow = (1, 1);
+    let mut justification = None;
+    let mut properties = Vec::new();
+    let mut strans = Strans {
+        reflect: false,
+        mag: 1.0,
+        angle: 0.0,
+    };
+    loop {
+        let record = records.next()?;
+        match record.kind {
+            LAYER => layer = record.i16()?,
+            DATATYPE | TEXTTYPE | BOXTYPE => datatype = record.i16()?,
+            PATHTYPE => pathtype = record.i16()?,
+            // negative widths are absolute, i.e. not scaled by references
+            WIDTH => width = record.i32s().first().map_or(0, |w| w.abs()),
+            XY => points = record.points(),
+            SNAME => name = record.string(),
+            STRING => text = record.string(),
+            COLROW => {
+                let values = record.i16s();
+                if values.len() != 2 {
+                    return Err(invalid("bad COLROW record"));
+                }
+                colrow = (values[0], values[1]);
+            }
+            PRESENTATION => justification = Some(Justification::from_bits(record.i16()? as u16)),
+            STRANS => strans.reflect = record.data.first().is_some_and(|b| b & 0x80 != 0),
+            MAG => strans.mag = record.f64()?,
+            ANGLE => strans.angle = record.f64()?,
+            PROPATTR => properties.push((record.i16()?, String::new())),
+            PROPVALUE => {
+                if let Some((_, value)) = properties.last_mut() {
+                    *value = record.string();
+                }
+            }
+            ENDEL => break,
+            // font, element flags, ...
+            _ => {}
+        }
+    }
+
+    let first = points.first().copied().unwrap_or_default();
+    Ok(match kind {
+        BOUNDARY => Some(GdsElement::Boundary {
+            layer,
+            datatype,
+            points,
+            properties,
+        }),
+        PATH => Some(GdsElement::Path {
+            layer,
+            datatype,
+            pathtype,
+            width,
+            points,
+            properties,
+        }),
+        BOX => Some(GdsElement::Box {
+            layer,
+            boxtype: datatype,
+            points,
+            properties,
+        }),
+        TEXT => Some(GdsElement::Text {
+            layer,
+            texttype: datatype,
+            text,
+            origin: first,
+            strans,
+            justification,
+        }),
+        SREF => Some(GdsElement::Sref {
+            cell: name,
+            origin: first,
+            strans,
+        }),
+        AREF if points.len() == 3 => Some(GdsElement::Aref {
+            cell: name,
+            columns: colrow.0,
+            rows: colrow.1,
+            points: [points[0], points[1], points[2]],
+            strans,
+        }),
+        _ => None,
+    })
+}
+
+// An affine transform of the plane, in database units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform2 {
+    // columns of the linear part
+    pub x_axis: (f64, f64),
+    pub y_axis: (f64, f64),
+    pub offset: (f64, f64),
+}
+
+impl Transform2 {
+    pub const IDENTITY: Transform2 = Transform2 {
+        x_axis: (1.0, 0.0),
+        y_axis: (0.0, 1.0),
+        offset: (0.0, 0.0),
+    };
+
+    // Placement of a referenced cell: reflect, scale, rotate, then move to origin.
+    pub fn placement(origin: (f64, f64), strans: &Strans) -> Self {
+        // quarter turns are exact, so that Manhattan shapes stay Manhattan
+        let (sin, cos) = match strans.angle.rem_euclid(360.0) {
+            0.0 => (0.0, 1.0),
+            90.0 => (1.0, 0.0),
+            180.0 => (0.0, -1.0),
+            270.0 => (-1.0, 0.0),
+            a => a.to_radians().sin_cos(),
+        };
+        let mirror = if strans.reflect { -1.0 } else { 1.0 };
+        Transform2 {
+            x_axis: (strans.mag * cos, strans.mag * sin),
+            y_axis: (-strans.mag * sin * mirror, strans.mag * cos * mirror),
+            offset: origin,
+        }
+    }
+
+    pub fn apply(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        (
+            self.x_axis.0 * x + self.y_axis.0 * y + self.offset.0,
+            self.x_axis.1 * x + self.y_axis.1 * y + self.offset.1,
+        )
+    }
+
+    // `self` applied after `inner`.
+    pub fn then(&self, inner: &Transform2) -> Transform2 {
+        let linear = |(x, y): (f64, f64)| {
+            (
+                self.x_axis.0 * x + self.y_axis.0 * y,
+                self.x_axis.1 * x + self.y_axis.1 * y,
+            )
+        };
+        Transform2 {
+            x_axis: linear(inner.x_axis),
+            y_axis: linear(inner.y_axis),
+            offset: self.apply(inner.offset),
+        }
+    }
+
+    pub fn scale(&self) -> f64 {
+        (self.x_axis.0 * self.x_axis.0 + self.x_axis.1 * self.x_axis.1).sqrt()
+    }
+}
+
+// A shape of the flattened hierarchy, in database units.
+#[derive(Clone, Debug)]
+pub enum FlatShape {
+    Polygon {
+        layer: i16,
+        datatype: i16,
+        points: Vec<(f64, f64)>,
+        // the references the shape was reached through, see `flatten_cell`
+        instance: Rc<str>,
+        properties: Properties,
+    },
+    Text {
+        layer: i16,
+        texttype: i16,
+        text: String,
+        origin: (f64, f64),
+        mag: f64,
+        // of the baseline, counter-clockwise in radians
+        rotation: f64,
+        justification: Option<Justification>,
+    },
+}
+
+impl GdsLibrary {
+    // Every shape below `cell`, with paths and boxes turned into polygons.
+    pub fn flatten(&self, cell: &GdsCell) -> io::Result<Vec<FlatShape>> {
+        let cells: HashMap<&str, &GdsCell> = self
+            .cells
+            .iter()
+            .map(|cell| (cell.name.as_str(), cell))
+            .collect();
+        let mut shapes = Vec::new();
+        let mut stack = Vec::new();
+        let instance = Rc::from(cell.name.as_str());
+        flatten_cell(
+            &cells,
+            cell,
+            &Transform2::IDENTITY,
+            &instance,
+            &mut stack,
+            &mut shapes,
+        )?;
+        Ok(shapes)
+    }
+}
+
+fn to_f64((x, y): (i32, i32)) -> (f64, f64) {
+    (x as f64, y as f64)
+}
+
+// Instance paths start at the flattened cell and add "/CELL[n]" per reference,
+// where n is the reference's position among the elements of the referencing cell,
+// and "/CELL[n:column,row]" for an array element.
+fn flatten_cell<'a>(
+    cells: &HashMap<&str, &'a GdsCell>,
+    cell: &'a GdsCell,
+    transform: &Transform2,
+    instance: &Rc<str>,
+    stack: &mut Vec<&'a str>,
+    shapes: &mut Vec<FlatShape>,
+) -> io::Result<()> {
+    if stack.contains(&cell.name.as_str()) {
+        return Err(invalid(format!("cell {} references itself", cell.name)));
+    }
+    stack.push(&cell.name);
+    let transform_points = |points: &[(i32, i32)]| -> Vec<(f64, f64)> {
+        points.iter().map(|p| transform.apply(to_f64(*p))).collect()
+    };
+    let lookup = |name: &str| {
+        cells
+            .get(name)
+            .copied()
+            .ok_or_else(|| invalid(format!("reference to missing cell {}", name)))
+    };
+
+    for (n, element) in cell.elements.iter().enumerate() {
+        match element {
+            GdsElement::Boundary {
+                layer,
+                datatype,
+                points,
+                properties,
+            } => shapes.push(FlatShape::Polygon {
+                layer: *layer,
+                datatype: *datatype,
+                points: transform_points(points),
+                instance: instance.clone(),
+                properties: properties.clone(),
+            }),
+            GdsElement::Box {
+                layer,
+                boxtype,
+                points,
+                properties,
+            } => shapes.push(FlatShape::Polygon {
+                layer: *layer,
+                datatype: *boxtype,
+                points: transform_points(points),
+                instance: instance.clone(),
+                properties: properties.clone(),
+            }),
+            GdsElement::Path {
+                layer,
+                datatype,
+                pathtype,
+                width,
+                points,
+                properties,
+            } => {
+                for outline in path_outlines(points, *width, *pathtype) {
+                    shapes.push(FlatShape::Polygon {
+                        layer: *layer,
+                        datatype: *datatype,
+                        points: outline.iter().map(|p| transform.apply(*p)).collect(),
+                        instance: instance.clone(),
+                        properties: properties.clone(),
+                    });
+                }
+            }
+            GdsElement::Text {
+                layer,
+                texttype,
+                text,
+                origin,
+                strans,
+                justification,
+            } => {
+                // placed like a reference, so that the instances above turn it too
+                let placement = transform.then(&Transform2::placement(to_f64(*origin), strans));
+                shapes.push(FlatShape::Text {
+                    layer: *layer,
+                    texttype: *texttype,
+                    text: text.clone(),
+                    origin: placement.offset,
+                    mag: placement.scale(),
+                    rotation: placement.x_axis.1.atan2(placement.x_axis.0),
+                    justification: *justification,
+                })
+            }
+            GdsElement::Sref {
+                cell: name,
+                origin,
+                strans,
+            } => {
+                let placement = transform.then(&Transform2::placement(to_f64(*origin), strans));
+                let instance = Rc::from(format!("{}/{}[{}]", instance, name, n));
+                flatten_cell(cells, lookup(name)?, &placement, &instance, stack, shapes)?;
+            }
+            GdsElement::Aref {
+                cell: name,
+                columns,
+                rows,
+                points,
+                strans,
+            } => {
+                let referenced = lookup(name)?;
+                let (columns, rows) = ((*columns).max(1), (*rows).max(1));
+                let origin = to_f64(points[0]);
+                let column_step = (
+                    (points[1].0 as f64 - origin.0) / columns as f64,
+                    (points[1].1 as f64 - origin.1) / columns as f64,
+                );
+                let row_step = (
+                    (points[2].0 as f64 - origin.0) / rows as f64,
+                    (points[2].1 as f64 - origin.1) / rows as f64,
+                );
+                for row in 0..rows {
+                    for column in 0..columns {
+                        let (c, r) = (column as f64, row as f64);
+                        let instance_origin = (
+                            origin.0 + c * column_step.0 + r * row_step.0,
+                            origin.1 + c * column_step.1 + r * row_step.1,
+                        );
+                        let placement =
+                            transform.then(&Transform2::placement(instance_origin, strans));
+                        let instance =
+                            Rc::from(format!("{}/{}[{}:{},{}]", instance, name, n, column, row));
+                        flatten_cell(cells, referenced, &placement, &instance, stack, shapes)?;
+                    }
+                }
+            }
+        }
+    }
+    stack.pop();
+    Ok(())
+}
+
+// One rectangle-like outline per path segment; segments are widened around their
+// center line, and the path ends are extended for path type 2 (and, approximately,
+// for round ends).
+fn path_outlines(points: &[(i32, i32)], width: i32, pathtype: i16) -> Vec<[(f64, f64); 4]> {
+    let half = width as f64 / 2.0;
+    if half <= 0.0 {
+        return Vec::new();
+    }
+    let last = points.len().saturating_sub(2);
+    points
+        .windows(2)
+        .enumerate()
+        .filter_map(|(i, segment)| {
+            let (a, b) = (to_f64(segment[0]), to_f64(segment[1]));
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+            let length = (dx * dx + dy * dy).sqrt();
+            if length == 0.0 {
+                return None;
+            }
+            let (ux, uy) = (dx / length, dy / length);
+            // interior joints are always extended, so that corners are filled
+            let extend = |end: bool| {
+                if !end || pathtype == 1 || pathtype == 2 {
+                    half
+                } else {
+                    0.0
+                }
+            };
+            let (start_extension, end_extension) = (extend(i == 0), extend(i == last));
+            let a = (a.0 - ux * start_extension, a.1 - uy * start_extension);
+            let b = (b.0 + ux * end_extension, b.1 + uy * end_extension);
+            let (nx, ny) = (-uy * half, ux * half);
+            Some([
+                (a.0 + nx, a.1 + ny),
+                (b.0 + nx, b.1 + ny),
+                (b.0 - nx, b.1 - ny),
+                (a.0 - nx, a.1 - ny),
+            ])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(layer: i16, properties: Properties) -> GdsElement {
+        GdsElement::Boundary {
+            layer,
+            datatype: 0,
+            points: vec![(0, 0), (10, 0), (10, 10), (0, 10), (0, 0)],
+            properties,
+        }
+    }
+
+    #[test]
+    fn shapes_know_their_instance_and_properties() {
+        let library = GdsLibrary {
+            cells: vec![
+                GdsCell {
+                    name: "INV".into(),
+                    elements: vec![square(1, vec![(1, "VDD".into())])],
+                },
+                GdsCell {
+                    name: "TOP".into(),
+                    elements: vec![
+                        square(2, Vec::new()),
+                        GdsElement::Sref {
+                            cell: "INV".into(),
+                            origin: (100, 0),
+                            strans: Strans {
+                                mag: 1.0,
+                                ..Default::default()
+                            },
+                        },
+                    ],
+                },
+            ],
+            ..Default::default()
+        };
+        let shapes = library.flatten(library.cell("TOP").unwrap()).unwrap();
+        let found: Vec<(i16, &str, &Properties)> = shapes
+            .iter()
+            .filter_map(|shape| match shape {
+                FlatShape::Polygon {
+                    layer,
+                    instance,
+                    properties,
+                    ..
+                } => Some((*layer, instance.as_ref(), properties)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, "TOP", &Vec::new()),
+                (1, "TOP/INV[1]", &vec![(1, "VDD".to_string())]),
+            ]
+        );
+    }
+
+    #[test]
+    fn presentation_bits_give_the_justification() {
+        let justification = |bits| {
+            let j = Justification::from_bits(bits);
+            (j.horizontal, j.vertical)
+        };
+        assert_eq!(justification(0), (0, 0));
+        assert_eq!(justification(0b1001), (1, 2));
+        // the font number above them is ignored
+        assert_eq!(justification(0b11_0110), (2, 1));
+    }
+}
@@ -0,0 +1,469 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::bounds::Bounds;
+use crate::layout::LayerKey;
+use crate::{DRect, Point};
+
+// Boolean operations and sizing on sets of rects, as drawn on one layer. Rects of
+// a set may overlap; results never do, and only carry geometry, so callers set
+// their color and stroke width.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BooleanOp {
+    And,
+    Or,
+    Xor,
+    // in the first set and not in the second
+    Not,
+}
+
+impl BooleanOp {
+    fn inside(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            BooleanOp::And => in_a && in_b,
+            BooleanOp::Or => in_a || in_b,
+            BooleanOp::Xor => in_a != in_b,
+            BooleanOp::Not => in_a && !in_b,
+        }
+    }
+}
+
+// The plane is cut into horizontal bands at every rect's top and bottom, each band
+// is swept left to right counting the rects of each set over it, and equal spans
+// of neighbouring bands are merged.
+pub fn boolean(a: &[DRect], b: &[DRect], op: BooleanOp) -> Vec<DRect> {
+    let mut boxes: Vec<(Bounds, bool)> = a
+        .iter()
+        .map(|rect| (Bounds::from_rect(rect), false))
+        .chain(b.iter().map(|rect| (Bounds::from_rect(rect), true)))
+        .filter(|(r, _)| r.min.x < r.max.x && r.min.y < r.max.y)
+        .collect();
+    boxes.sort_by(|(r0, _), (r1, _)| r0.min.y.total_cmp(&r1.min.y));
+    let mut ys: Vec<f32> = boxes.iter().flat_map(|(r, _)| [r.min.y, r.max.y]).collect();
+    ys.sort_by(|a, b| a.total_cmp(b));
+    ys.dedup();
+
+    let mut rects: Vec<DRect> = Vec::new();
+    // rects that end at the top of the previous band
+    let mut open: Vec<usize> = Vec::new();
+    let mut active: Vec<(Bounds, bool)> = Vec::new();
+    let mut next_box = 0;
+    for band in ys.windows(2) {
+        let (y0, y1) = (band[0], band[1]);
+        active.retain(|(r, _)| r.max.y > y0);
+        while next_box < boxes.len() && boxes[next_box].0.min.y <= y0 {
+            active.push(boxes[next_box]);
+            next_box += 1;
+        }
+
+        // +1 where a rect starts and -1 where it ends, for each set
+        let mut edges: Vec<(f32, i32, bool)> = active
+            .iter()
+            .flat_map(|(r, in_b)| [(r.min.x, 1, *in_b), (r.max.x, -1, *in_b)])
+            .collect();
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut spans = Vec::new();
+        let (mut count_a, mut count_b) = (0, 0);
+        let mut span_start = None;
+        for (i, (x, delta, in_b)) in edges.iter().enumerate() {
+            if *in_b {
+                count_b += delta;
+            } else {
+                count_a += delta;
+            }
+            // edges at the same x all apply before the span is decided
+            if edges.get(i + 1).is_some_and(|next| next.0 == *x) {
+                continue;
+            }
+            let inside = op.inside(count_a > 0, count_b > 0);
+            match (inside, span_start) {
+                (true, None) => span_start = Some(*x),
+                (false, Some(x0)) => {
+                    spans.push((x0, *x));
+                    span_start = None;
+                }
+                _ => {}
+            }
+        }
+
+        let mut next_open = Vec::new();
+        for (x0, x1) in spans {
+            let continued = open.iter().copied().find(|i| {
+                let rect: &DRect = &rects[*i];
+                rect.p0.x == x0 && rect.p1.x == x1 && rect.p1.y == y0
+            });
+            match continued {
+                Some(i) => {
+                    rects[i].p1.y = y1;
+                    next_open.push(i);
+                }
+                None => {
+                    rects.push(DRect {
+                        p0: Point { x: x0, y: y0 },
+                        p1: Point { x: x1, y: y1 },
+                        ..Default::default()
+                    });
+                    next_open.push(rects.len() - 1);
+                }
+            }
+        }
+        open = next_open;
+    }
+    rects
+}
+
+// The same area without overlaps.
+pub fn merge(rects: &[DRect]) -> Vec<DRect> {
+    boolean(rects, &[], BooleanOp::Or)
+}
+
+fn expand(rect: &DRect, amount: f32) -> Option<DRect> {
+    let r = Bounds::from_rect(rect);
+    let (min, max) = (r.min - Vec2::splat(amount), r.max + Vec2::splat(amount));
+    (min.x < max.x && min.y < max.y).then_some(DRect {
+        p0: Point { x: min.x, y: min.y },
+        p1: Point { x: max.x, y: max.y },
+        ..*rect
+    })
+}
+
+// Moves every edge of the set's outline out by `amount`, or in for negative
+// amounts. Growing the union is the union of the grown rects; shrinking is
+// growing the space around the set, which is then cut away from it.
+pub fn size(rects: &[DRect], amount: f32) -> Vec<DRect> {
+    if amount >= 0.0 {
+        let grown: Vec<DRect> = rects
+            .iter()
+            .filter_map(|rect| expand(rect, amount))
+            .collect();
+        return merge(&grown);
+    }
+    let bounds = match Bounds::from_rects(rects.iter()) {
+        Some(bounds) => bounds,
+        None => return Vec::new(),
+    };
+    let margin = Vec2::splat(-amount * 2.0 + 1.0);
+    let (min, max) = (bounds.min - margin, bounds.max + margin);
+    let frame = DRect {
+        p0: Point { x: min.x, y: min.y },
+        p1: Point { x: max.x, y: max.y },
+        ..Default::default()
+    };
+    let outside = boolean(&[frame], rects, BooleanOp::Not);
+    boolean(rects, &size(&outside, -amount), BooleanOp::Not)
+}
+
+// Overlapping, or sharing part of an edge; meeting at a corner is not enough.
+pub fn touches(a: &Bounds, b: &Bounds) -> bool {
+    let (min, max) = (a.min.max(b.min), a.max.min(b.max));
+    min.x <= max.x && min.y <= max.y && (min.x < max.x || min.y < max.y)
+}
+
+// Sharing some area.
+pub fn overlaps(a: &Bounds, b: &Bounds) -> bool {
+    let (min, max) = (a.min.max(b.min), a.max.min(b.max));
+    min.x < max.x && min.y < max.y
+}
+
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+// Groups boxes into connected components, where `connects` decides for boxes
+// that meet or overlap whether they are connected. Groups are in the order of
+// their first box, and boxes in a group are in order.
+pub fn group_by(bounds: &[Bounds], connects: impl Fn(usize, usize) -> bool) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..bounds.len()).collect();
+    order.sort_by(|a, b| bounds[*a].min.x.total_cmp(&bounds[*b].min.x));
+
+    // union-find, with the smallest index of a group as its root
+    let mut parent: Vec<usize> = (0..bounds.len()).collect();
+    for (n, &i) in order.iter().enumerate() {
+        for &j in order[n + 1..].iter() {
+            if bounds[j].min.x > bounds[i].max.x {
+                break;
+            }
+            let meet = bounds[j].min.y <= bounds[i].max.y && bounds[i].min.y <= bounds[j].max.y;
+            if meet && connects(i, j) {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    // group of each root
+    let mut group_of = vec![usize::MAX; bounds.len()];
+    for i in 0..bounds.len() {
+        let r = root(&mut parent, i);
+        if group_of[r] == usize::MAX {
+            group_of[r] = groups.len();
+            groups.push(Vec::new());
+        }
+        groups[group_of[r]].push(i);
+    }
+    groups
+}
+
+// Indices of the rects that form one shape, for every shape.
+pub fn connected_groups(rects: &[DRect]) -> Vec<Vec<usize>> {
+    let bounds: Vec<Bounds> = rects.iter().map(Bounds::from_rect).collect();
+    group_by(&bounds, |i, j| touches(&bounds[i], &bounds[j]))
+}
+
+pub fn area(rects: &[DRect]) -> f32 {
+    rects
+        .iter()
+        .map(|rect| {
+            let size = Bounds::from_rect(rect).size();
+            size.x * size.y
+        })
+        .sum()
+}
+
+// A layer computed from others: "out=a and b", "out=a or b", "out=a xor b",
+// "out=a not b", "out=a grow 0.1" or "out=a shrink 0.1", with layers written
+// "layer/datatype". Derived layers can use the ones derived before them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedLayer {
+    pub key: LayerKey,
+    pub operation: LayerOperation,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayerOperation {
+    Boolean(LayerKey, BooleanOp, LayerKey),
+    Size(LayerKey, f32),
+}
+
+impl FromStr for DerivedLayer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || {
+            format!(
+                "bad derived layer {:?}, expected e.g. 10/0=1/0 and 2/0 or 10/0=1/0 grow 0.1",
+                s
+            )
+        };
+        let (key, expression) = s.split_once('=').ok_or_else(bad)?;
+        let words: Vec<&str> = expression.split_whitespace().collect();
+        let (a, operator, b) = match words.as_slice() {
+            [a, operator, b] => (a, operator.to_ascii_lowercase(), b),
+            _ => return Err(bad()),
+        };
+        let a: LayerKey = a.parse()?;
+        let boolean = |op| Ok::<_, String>(LayerOperation::Boolean(a, op, b.parse()?));
+        let amount = || b.parse::<f32>().map_err(|_| bad());
+        let operation = match operator.as_str() {
+            "and" => boolean(BooleanOp::And)?,
+            "or" => boolean(BooleanOp::Or)?,
+            "xor" => boolean(BooleanOp::Xor)?,
+            "not" => boolean(BooleanOp::Not)?,
+            "grow" => LayerOperation::Size(a, amount()?),
+            "shrink" => LayerOperation::Size(a, -amount()?),
+            _ => return Err(bad()),
+        };
+        Ok(DerivedLayer {
+            key: key.trim().parse()?,
+            operation,
+        })
+    }
+}
+
+impl fmt::Display for DerivedLayer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.operation {
+            LayerOperation::Boolean(a, op, b) => {
+                let op = match op {
+                    BooleanOp::And => "and",
+                    BooleanOp::Or => "or",
+                    BooleanOp::Xor => "xor",
+                    BooleanOp::Not => "not",
+                };
+                write!(f, "{}={} {} {}", self.key, a, op, b)
+            }
+            LayerOperation::Size(a, amount) if *amount < 0.0 => {
+                write!(f, "{}={} shrink {}", self.key, a, -amount)
+            }
+            LayerOperation::Size(a, amount) => write!(f, "{}={} grow {}", self.key, a, amount),
+        }
+    }
+}
+
+impl DerivedLayer {
+    // The layers it is computed from.
+    pub fn inputs(&self) -> Vec<LayerKey> {
+        match &self.operation {
+            LayerOperation::Boolean(a, _, b) => vec![*a, *b],
+            LayerOperation::Size(a, _) => vec![*a],
+        }
+    }
+
+    // `layer` gives the rects of a layer.
+    pub fn compute<'a>(&self, layer: impl Fn(LayerKey) -> &'a [DRect]) -> Vec<DRect> {
+        match &self.operation {
+            LayerOperation::Boolean(a, op, b) => boolean(layer(*a), layer(*b), *op),
+            LayerOperation::Size(a, amount) => size(layer(*a), *amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{layer, rect};
+
+    fn bounds(rects: &[DRect]) -> Vec<[f32; 4]> {
+        let mut bounds: Vec<[f32; 4]> = rects
+            .iter()
+            .map(|rect| {
+                let r = Bounds::from_rect(rect);
+                [r.min.x, r.min.y, r.max.x, r.max.y]
+            })
+            .collect();
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        bounds
+    }
+
+    fn overlap_free(rects: &[DRect]) -> bool {
+        rects.iter().enumerate().all(|(i, a)| {
+            rects[i + 1..].iter().all(|b| {
+                let (a, b) = (Bounds::from_rect(a), Bounds::from_rect(b));
+                let (min, max) = (a.min.max(b.min), a.max.min(b.max));
+                min.x >= max.x || min.y >= max.y
+            })
+        })
+    }
+
+    // two 2x2 squares overlapping in a 1x1 square
+    fn squares() -> (Vec<DRect>, Vec<DRect>) {
+        (
+            vec![rect(0.0, 0.0, 2.0, 2.0)],
+            vec![rect(1.0, 1.0, 3.0, 3.0)],
+        )
+    }
+
+    #[test]
+    fn and_is_the_overlap() {
+        let (a, b) = squares();
+        assert_eq!(
+            bounds(&boolean(&a, &b, BooleanOp::And)),
+            vec![[1.0, 1.0, 2.0, 2.0]]
+        );
+        assert!(boolean(&a, &[], BooleanOp::And).is_empty());
+    }
+
+    #[test]
+    fn areas_add_up() {
+        let (a, b) = squares();
+        for (op, expected) in [
+            (BooleanOp::And, 1.0),
+            (BooleanOp::Or, 7.0),
+            (BooleanOp::Xor, 6.0),
+            (BooleanOp::Not, 3.0),
+        ] {
+            let result = boolean(&a, &b, op);
+            assert_eq!(area(&result), expected, "{:?}", op);
+            assert!(overlap_free(&result), "{:?}", op);
+        }
+        // a not b is a's side of the xor
+        assert_eq!(
+            bounds(&boolean(&b, &a, BooleanOp::Not)),
+            vec![[1.0, 2.0, 3.0, 3.0], [2.0, 1.0, 3.0, 2.0]]
+        );
+    }
+
+    #[test]
+    fn merge_joins_overlaps_and_touching_rects() {
+        let rects = vec![
+            rect(0.0, 0.0, 2.0, 1.0),
+            rect(1.0, 0.0, 3.0, 1.0),
+            rect(0.0, 1.0, 3.0, 2.0),
+            // a copy
+            rect(0.0, 1.0, 3.0, 2.0),
+        ];
+        assert_eq!(bounds(&merge(&rects)), vec![[0.0, 0.0, 3.0, 2.0]]);
+        // corners in any order, and empty rects are dropped
+        let rects = vec![rect(2.0, 2.0, 0.0, 0.0), rect(5.0, 5.0, 5.0, 6.0)];
+        assert_eq!(bounds(&merge(&rects)), vec![[0.0, 0.0, 2.0, 2.0]]);
+    }
+
+    #[test]
+    fn xor_of_equal_sets_is_empty() {
+        let (a, _) = squares();
+        let split = vec![rect(0.0, 0.0, 1.0, 2.0), rect(1.0, 0.0, 2.0, 2.0)];
+        assert!(boolean(&a, &split, BooleanOp::Xor).is_empty());
+        assert!(boolean(&a, &a, BooleanOp::Not).is_empty());
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let (a, b) = squares();
+        assert_eq!(bounds(&size(&a, 0.5)), vec![[-0.5, -0.5, 2.5, 2.5]]);
+        assert_eq!(bounds(&size(&a, -0.5)), vec![[0.5, 0.5, 1.5, 1.5]]);
+        assert!(size(&a, -1.0).is_empty());
+        assert_eq!(bounds(&size(&a, 0.0)), bounds(&a));
+
+        // grown, the gap between two rects closes
+        let apart = vec![rect(0.0, 0.0, 1.0, 1.0), rect(2.0, 0.0, 3.0, 1.0)];
+        assert_eq!(bounds(&size(&apart, 0.5)), vec![[-0.5, -0.5, 3.5, 1.5]]);
+
+        // an L shape loses its thin arm when shrunk
+        let l_shape = vec![rect(0.0, 0.0, 4.0, 1.0), rect(0.0, 1.0, 2.0, 3.0)];
+        assert_eq!(bounds(&size(&l_shape, -0.6)), vec![[0.6, 0.6, 1.4, 2.4]]);
+
+        // shrinking then growing keeps what is wide enough
+        let union = boolean(&a, &b, BooleanOp::Or);
+        let opened = size(&size(&union, -0.25), 0.25);
+        assert_eq!(area(&opened), area(&union));
+    }
+
+    #[test]
+    fn groups_join_overlapping_and_abutting_rects() {
+        let rects = vec![
+            rect(0.0, 0.0, 1.0, 1.0),
+            // abuts the first
+            rect(1.0, 0.0, 2.0, 1.0),
+            // only meets the second at a corner
+            rect(2.0, 1.0, 3.0, 2.0),
+            // overlaps the third
+            rect(2.5, 1.5, 4.0, 4.0),
+            rect(10.0, 10.0, 11.0, 11.0),
+        ];
+        assert_eq!(
+            connected_groups(&rects),
+            vec![vec![0, 1], vec![2, 3], vec![4]]
+        );
+        assert!(connected_groups(&[]).is_empty());
+    }
+
+    #[test]
+    fn derived_layers_parse() {
+        let derived: DerivedLayer = "10/0=1/0 and 2".parse().unwrap();
+        assert_eq!(
+            derived.operation,
+            LayerOperation::Boolean(layer(1), BooleanOp::And, layer(2))
+        );
+        assert_eq!(derived.key, layer(10));
+        let derived: DerivedLayer = "11/0 = 1/0 SHRINK 0.5".parse().unwrap();
+        assert_eq!(derived.operation, LayerOperation::Size(layer(1), -0.5));
+        assert_eq!(derived.to_string(), "11/0=1/0 shrink 0.5");
+        assert_eq!(derived.inputs(), vec![layer(1)]);
+        for bad in [
+            "1/0 and 2/0",
+            "10/0=1/0 nand 2/0",
+            "10/0=1/0 grow",
+            "10/0=1/0 grow x",
+        ] {
+            assert!(bad.parse::<DerivedLayer>().is_err(), "{}", bad);
+        }
+    }
+}
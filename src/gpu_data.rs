@@ -1,6 +1,11 @@
 use bevy::core::{Pod, Zeroable};
 use bevy::prelude::*;
-use bevy::render::render_resource::{BindGroup, Buffer, BufferUsages, BufferVec};
+use bevy::render::render_resource::{
+    std140::AsStd140, BindGroup, Buffer, BufferUsages, BufferVec, DynamicUniformVec, Sampler,
+    TextureView,
+};
+
+use bevy::utils::HashMap;
 
 use crate::DRect;
 
@@ -25,25 +30,30 @@ impl From<&DRect> for GpuQuad {
     }
 }
 
-#[derive(Component)]
+// The GPU side of every `BatchedQuads` entity, keyed by the main world entity.
+// The index buffer is shared: every chunk starts at quad 0 of its own buffer, so
+// the indices of the largest chunk serve them all.
+#[derive(Default)]
 pub struct GpuQuads {
+    pub batches: HashMap<Entity, GpuQuadBatch>,
     pub index_buffer: Option<Buffer>,
-    pub index_count: u32,
-    pub instances: BufferVec<GpuQuad>,
+    pub index_buffer_quads: usize,
 }
 
-impl Default for GpuQuads {
-    fn default() -> Self {
-        Self {
-            index_buffer: None,
-            index_count: 0,
-            instances: BufferVec::<GpuQuad>::new(BufferUsages::STORAGE),
-        }
-    }
+// A batch's quads, split into chunks that each fit in one storage buffer binding.
+#[derive(Default)]
+pub struct GpuQuadBatch {
+    pub chunks: Vec<GpuQuadsChunk>,
 }
 
-#[derive(Component)]
-pub struct GpuDataBindGroup {
+// One storage buffer of quads, with its own bind group and draw. The buffer can
+// hold more quads than are drawn, so that updates can reuse it.
+pub struct GpuQuadsChunk {
+    pub buffer: Buffer,
+    // a bit per quad, set for highlighted quads
+    pub flags: Buffer,
+    pub capacity: usize,
+    pub quad_count: u32,
     pub bind_group: BindGroup,
 }
 
@@ -59,3 +69,76 @@ impl Default for GpuPalette {
         }
     }
 }
+
+// One glyph of a label, as a world-space parallelogram sampling the SDF atlas
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+#[repr(C)]
+pub struct GpuGlyph {
+    pub origin: Vec2,
+    pub x_axis: Vec2,
+    pub y_axis: Vec2,
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+    pub color: u32,
+    pub hide_below: f32,
+}
+
+pub struct GpuLabels {
+    pub glyphs: BufferVec<GpuGlyph>,
+    pub atlas: Option<(TextureView, Sampler)>,
+    pub bind_group: Option<BindGroup>,
+}
+
+impl Default for GpuLabels {
+    fn default() -> Self {
+        Self {
+            glyphs: BufferVec::<GpuGlyph>::new(BufferUsages::STORAGE),
+            atlas: None,
+            bind_group: None,
+        }
+    }
+}
+
+// A line segment between two points, with a width in screen pixels
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+#[repr(C)]
+pub struct GpuSegment {
+    pub a: Vec2,
+    pub b: Vec2,
+    pub width: f32,
+    pub color: u32,
+    pub flags: u32,
+    pub _padding: u32,
+}
+
+impl GpuSegment {
+    // The end points are in physical pixels from the bottom left of the window,
+    // instead of in world space.
+    pub const SCREEN_SPACE: u32 = 1;
+    // The segment stops at its end points instead of extending by half its width.
+    pub const BUTT_CAPS: u32 = 2;
+}
+
+// Per view: where the view's viewport starts, in physical pixels from the bottom
+// left of the window, so that screen-space segments land in the same place in
+// every view.
+#[derive(Clone, AsStd140)]
+pub struct GpuOverlayView {
+    pub viewport_origin: Vec2,
+}
+
+pub struct GpuOverlay {
+    pub segments: BufferVec<GpuSegment>,
+    pub views: DynamicUniformVec<GpuOverlayView>,
+    pub bind_group: Option<BindGroup>,
+}
+
+impl Default for GpuOverlay {
+    fn default() -> Self {
+        Self {
+            segments: BufferVec::<GpuSegment>::new(BufferUsages::STORAGE),
+            views: DynamicUniformVec::default(),
+            bind_group: None,
+        }
+    }
+}
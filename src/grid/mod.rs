@@ -0,0 +1,299 @@
+mod pipeline;
+mod render_graph;
+
+use bevy::core_pipeline::draw_2d_graph;
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use bevy::render::render_graph::RenderGraph;
+use bevy::render::render_resource::{
+    std140::AsStd140, BindGroup, BindGroupDescriptor, BindGroupEntry, DynamicUniformVec,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::ViewUniforms;
+
+use bevy::app::{App, Plugin};
+use bevy::render::{RenderApp, RenderStage};
+
+use crate::camera::world_units_per_pixel;
+use crate::vpull::VPULL_PASS;
+
+use self::pipeline::{GridPipeline, GRID_SHADER_HANDLE};
+use self::render_graph::{GridPassNode, GRID_PASS};
+
+pub struct GridSettings {
+    pub visible: bool,
+    // Adaptive grid lines are never closer together than this on screen.
+    pub min_spacing_px: f32,
+    // Overrides the adaptive 1-2-5 pitch.
+    pub fixed_pitch: Option<f32>,
+    // Every pitch is a multiple of the manufacturing grid, and snapping never
+    // leaves it.
+    pub manufacturing_grid: Option<f32>,
+    // Every n-th line is drawn in the major color.
+    pub major_every: u32,
+    pub color: Color,
+    pub major_color: Color,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            min_spacing_px: 16.0,
+            fixed_pitch: None,
+            manufacturing_grid: None,
+            major_every: 5,
+            color: Color::rgba(1.0, 1.0, 1.0, 0.06),
+            major_color: Color::rgba(1.0, 1.0, 1.0, 0.14),
+        }
+    }
+}
+
+// The smallest value of the 1-2-5 sequence (..., 0.5, 1, 2, 5, 10, 20, ...) that
+// is at least `min`.
+pub fn nice_pitch(min: f32) -> f32 {
+    // in f64, so that powers of ten come out as the nearest f32 and not just under
+    let base = 10f64.powi(f64::from(min).log10().floor() as i32);
+    [1.0, 2.0, 5.0]
+        .into_iter()
+        .map(|step| (base * step) as f32)
+        .find(|pitch| *pitch >= min)
+        .unwrap_or((base * 10.0) as f32)
+}
+
+impl GridSettings {
+    // Grid pitch in world units for a view where one pixel spans `world_per_pixel`.
+    pub fn pitch(&self, world_per_pixel: f32) -> f32 {
+        let pitch = self
+            .fixed_pitch
+            .unwrap_or_else(|| nice_pitch(self.min_spacing_px * world_per_pixel));
+        match self.manufacturing_grid {
+            Some(grid) if grid > 0.0 => {
+                let steps = pitch / grid;
+                // a pitch that is a multiple already is kept as it is
+                if steps > 1.0 - 1e-4 && (steps - steps.round()).abs() < 1e-4 {
+                    pitch
+                } else {
+                    steps.ceil().max(1.0) * grid
+                }
+            }
+            _ => pitch,
+        }
+    }
+}
+
+// The grid pitch currently used by a camera.
+#[derive(Clone, Copy, Component, Debug)]
+pub struct ViewGrid {
+    pub pitch: f32,
+}
+
+#[derive(Clone, AsStd140)]
+pub struct GridUniform {
+    color: Vec4,
+    major_color: Vec4,
+    pitch: f32,
+    major_every: f32,
+}
+
+#[derive(Component)]
+struct ExtractedGrid {
+    uniform: GridUniform,
+}
+
+#[derive(Default)]
+struct GridUniforms {
+    uniforms: DynamicUniformVec<GridUniform>,
+}
+
+#[derive(Component)]
+pub struct ViewGridOffset {
+    pub offset: u32,
+}
+
+#[derive(Default)]
+pub struct GridBindGroup {
+    pub bind_group: Option<BindGroup>,
+}
+
+// G toggles the grid.
+pub struct GridPlugin;
+
+impl Plugin for GridPlugin {
+    fn build(&self, app: &mut App) {
+        app.world.resource_mut::<Assets<Shader>>().set_untracked(
+            GRID_SHADER_HANDLE,
+            Shader::from_wgsl(include_str!("../shaders/grid.wgsl")),
+        );
+
+        app.init_resource::<GridSettings>()
+            .add_system(toggle_grid)
+            .add_system(update_view_grids);
+
+        let render_app = app.sub_app_mut(RenderApp);
+
+        render_app
+            .init_resource::<GridPipeline>()
+            .init_resource::<GridUniforms>()
+            .init_resource::<GridBindGroup>()
+            .add_system_to_stage(RenderStage::Extract, extract_grid)
+            .add_system_to_stage(RenderStage::Prepare, prepare_grid)
+            .add_system_to_stage(RenderStage::Queue, queue_grid_bind_group);
+
+        // the grid is the background, so it goes before the quads
+        let grid_pass_node = GridPassNode::new(&mut render_app.world);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        let draw_2d_graph = graph.get_sub_graph_mut(draw_2d_graph::NAME).unwrap();
+        draw_2d_graph.add_node(GRID_PASS, grid_pass_node);
+        draw_2d_graph.add_node_edge(GRID_PASS, VPULL_PASS).unwrap();
+        draw_2d_graph
+            .add_slot_edge(
+                draw_2d_graph.input_node().unwrap().id,
+                draw_2d_graph::input::VIEW_ENTITY,
+                GRID_PASS,
+                GridPassNode::IN_VIEW,
+            )
+            .unwrap();
+    }
+}
+
+fn toggle_grid(keys: Res<Input<KeyCode>>, mut settings: ResMut<GridSettings>) {
+    if keys.just_pressed(KeyCode::G) {
+        settings.visible = !settings.visible;
+    }
+}
+
+fn update_view_grids(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    settings: Res<GridSettings>,
+    cameras: Query<(Entity, &Camera, &GlobalTransform), With<Camera2d>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    for (entity, camera, camera_transform) in cameras.iter() {
+        let world_per_pixel = world_units_per_pixel(window, camera, camera_transform);
+        commands.entity(entity).insert(ViewGrid {
+            pitch: settings.pitch(world_per_pixel),
+        });
+    }
+}
+
+// EXTRACT:
+fn extract_grid(
+    mut commands: Commands,
+    settings: Res<GridSettings>,
+    view_grids: Query<(Entity, &ViewGrid)>,
+) {
+    if !settings.visible {
+        return;
+    }
+    for (entity, view_grid) in view_grids.iter() {
+        commands.get_or_spawn(entity).insert(ExtractedGrid {
+            uniform: GridUniform {
+                color: settings.color.as_rgba_f32().into(),
+                major_color: settings.major_color.as_rgba_f32().into(),
+                pitch: view_grid.pitch,
+                major_every: settings.major_every.max(1) as f32,
+            },
+        });
+    }
+}
+
+// PREPARE:
+fn prepare_grid(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut grid_uniforms: ResMut<GridUniforms>,
+    views: Query<(Entity, &ExtractedGrid)>,
+) {
+    grid_uniforms.uniforms.clear();
+    for (entity, extracted_grid) in views.iter() {
+        let offset = grid_uniforms.uniforms.push(extracted_grid.uniform.clone());
+        commands.entity(entity).insert(ViewGridOffset { offset });
+    }
+    grid_uniforms
+        .uniforms
+        .write_buffer(&render_device, &render_queue);
+}
+
+// QUEUE:
+fn queue_grid_bind_group(
+    render_device: Res<RenderDevice>,
+    grid_pipeline: Res<GridPipeline>,
+    view_uniforms: Res<ViewUniforms>,
+    grid_uniforms: Res<GridUniforms>,
+    mut grid_bind_group: ResMut<GridBindGroup>,
+) {
+    grid_bind_group.bind_group = match (
+        view_uniforms.uniforms.binding(),
+        grid_uniforms.uniforms.binding(),
+    ) {
+        (Some(view_binding), Some(grid_binding)) => {
+            Some(render_device.create_bind_group(&BindGroupDescriptor {
+                label: Some("grid_bind_group"),
+                layout: &grid_pipeline.layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: view_binding,
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: grid_binding,
+                    },
+                ],
+            }))
+        }
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pitches_follow_the_1_2_5_sequence() {
+        assert_eq!(nice_pitch(0.7), 1.0);
+        assert_eq!(nice_pitch(1.01), 2.0);
+        assert_eq!(nice_pitch(3.0), 5.0);
+        assert_eq!(nice_pitch(6.0), 10.0);
+        assert_eq!(nice_pitch(0.013), 0.02);
+        assert_eq!(nice_pitch(4.2e-4), 5e-4);
+        assert_eq!(nice_pitch(350.0), 500.0);
+    }
+
+    #[test]
+    fn nice_values_are_their_own_pitch() {
+        for exponent in -6..=6 {
+            for step in [1.0, 2.0, 5.0] {
+                let value = (step * 10f64.powi(exponent)) as f32;
+                assert_eq!(nice_pitch(value), value, "{}", value);
+            }
+        }
+        assert_eq!(nice_pitch(1000.0), 1000.0);
+        assert_eq!(nice_pitch(0.001), 0.001);
+    }
+
+    #[test]
+    fn pitches_are_multiples_of_the_manufacturing_grid() {
+        let settings = |fixed_pitch, manufacturing_grid| GridSettings {
+            min_spacing_px: 10.0,
+            fixed_pitch,
+            manufacturing_grid,
+            ..Default::default()
+        };
+        // 10 px at 0.01 per pixel is 0.1, on the grid already
+        assert_eq!(settings(None, None).pitch(0.01), 0.1);
+        assert_eq!(settings(None, Some(0.005)).pitch(0.01), 0.1);
+        assert_eq!(settings(Some(0.3), Some(0.1)).pitch(1.0), 0.3);
+        assert_eq!(settings(Some(0.3), Some(0.25)).pitch(1.0), 0.5);
+        // never finer than the manufacturing grid
+        assert_eq!(settings(None, Some(0.005)).pitch(1e-5), 0.005);
+        assert_eq!(settings(Some(0.3), Some(0.0)).pitch(1.0), 0.3);
+    }
+}
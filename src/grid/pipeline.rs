@@ -0,0 +1,108 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::PrimitiveTopology,
+        render_resource::{
+            std140::AsStd140, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingType, BlendState, BufferBindingType, BufferSize, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState,
+            PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor, ShaderStages,
+            TextureFormat, VertexState,
+        },
+        renderer::RenderDevice,
+        texture::BevyDefault,
+        view::ViewUniform,
+    },
+};
+
+use super::GridUniform;
+
+pub struct GridPipeline {
+    pub pipeline_id: CachedRenderPipelineId,
+    pub layout: BindGroupLayout,
+}
+
+pub const GRID_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 5084307291718842330);
+
+impl FromWorld for GridPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    entries: &[
+                        // View
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: BufferSize::new(
+                                    ViewUniform::std140_size_static() as u64,
+                                ),
+                            },
+                            count: None,
+                        },
+                        // Grid
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::FRAGMENT,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: BufferSize::new(
+                                    GridUniform::std140_size_static() as u64,
+                                ),
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("grid_layout"),
+                });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("grid_pipeline".into()),
+            layout: Some(vec![layout.clone()]),
+            vertex: VertexState {
+                shader: GRID_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader: GRID_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: Msaa::default().samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        });
+
+        Self {
+            pipeline_id,
+            layout,
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use bevy::render::render_graph::{NodeRunError, RenderGraphContext, SlotInfo, SlotType};
+use bevy::render::render_phase::TrackedRenderPass;
+use bevy::render::render_resource::{LoadOp, Operations, PipelineCache, RenderPassDescriptor};
+use bevy::render::view::{ExtractedView, ViewTarget, ViewUniformOffset};
+use bevy::render::{render_graph, renderer::RenderContext};
+
+use crate::split_views::ExtractedViewport;
+
+use super::pipeline::GridPipeline;
+use super::{GridBindGroup, ViewGridOffset};
+
+pub const GRID_PASS: &str = "GRID_PASS";
+
+// Draws the background grid as a single full-screen triangle, before any geometry.
+pub struct GridPassNode {
+    query: QueryState<
+        (
+            &'static ViewTarget,
+            &'static ViewUniformOffset,
+            &'static ViewGridOffset,
+            Option<&'static ExtractedViewport>,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl GridPassNode {
+    pub const IN_VIEW: &'static str = "view";
+
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl render_graph::Node for GridPassNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(GridPassNode::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        // views without a grid (or without a window) have nothing to draw
+        let (target, view_uniform_offset, view_grid_offset, viewport) =
+            match self.query.get_manual(world, view_entity) {
+                Ok(query) => query,
+                Err(_) => return Ok(()),
+            };
+        let bind_group = match &world.resource::<GridBindGroup>().bind_group {
+            Some(bind_group) => bind_group,
+            None => return Ok(()),
+        };
+        let pipeline = match world
+            .resource::<PipelineCache>()
+            .get_render_pipeline(world.resource::<GridPipeline>().pipeline_id)
+        {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+
+        #[cfg(feature = "trace")]
+        let _main_grid_pass_span = info_span!("main_grid_pass").entered();
+        let pass_descriptor = RenderPassDescriptor {
+            label: Some("main_grid_pass"),
+            // NOTE: The grid is blended over the clear color.
+            color_attachments: &[target.get_color_attachment(Operations {
+                load: LoadOp::Load,
+                store: true,
+            })],
+            depth_stencil_attachment: None,
+        };
+
+        let render_pass = render_context
+            .command_encoder
+            .begin_render_pass(&pass_descriptor);
+        let mut tracked_pass = TrackedRenderPass::new(render_pass);
+        tracked_pass.set_render_pipeline(pipeline);
+        if let Some(viewport) = viewport {
+            viewport.set(&mut tracked_pass);
+        }
+        tracked_pass.set_bind_group(
+            0,
+            bind_group,
+            &[view_uniform_offset.offset, view_grid_offset.offset],
+        );
+        tracked_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
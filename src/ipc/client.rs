@@ -0,0 +1,81 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use super::{Request, Response, WireRect};
+use crate::layout::LayerKey;
+use crate::DRect;
+
+// Talks to a viewer's socket, one request at a time. Requests the viewer refuses
+// are errors with the viewer's message. There are helpers for what push sends;
+// other requests go through `request`.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    pub fn connect(path: &Path) -> io::Result<Client> {
+        let writer = UnixStream::connect(path)?;
+        Ok(Client {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    pub fn request(&mut self, request: &Request) -> io::Result<Response> {
+        let mut json = serde_json::to_string(request)?;
+        json.push('\n');
+        self.writer.write_all(json.as_bytes())?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the viewer closed the connection",
+            ));
+        }
+        let response: Response = serde_json::from_str(&line)?;
+        match response.ok {
+            true => Ok(response),
+            false => Err(io::Error::other(response.error.unwrap_or_default())),
+        }
+    }
+
+    // Rects keep their palette index; `stroke` defaults to the viewer's.
+    pub fn replace_layer(
+        &mut self,
+        layer: LayerKey,
+        rects: &[DRect],
+        stroke: Option<f32>,
+    ) -> io::Result<()> {
+        self.request(&Request::ReplaceLayer {
+            layer: layer.to_string(),
+            rects: rects.iter().map(WireRect::from).collect(),
+            stroke,
+        })
+        .map(drop)
+    }
+
+    pub fn append_rects(
+        &mut self,
+        layer: LayerKey,
+        rects: &[DRect],
+        stroke: Option<f32>,
+    ) -> io::Result<()> {
+        self.request(&Request::AppendRects {
+            layer: layer.to_string(),
+            rects: rects.iter().map(WireRect::from).collect(),
+            stroke,
+        })
+        .map(drop)
+    }
+
+    pub fn fit_camera(&mut self, bbox: [f32; 4]) -> io::Result<()> {
+        self.request(&Request::SetCamera {
+            center: None,
+            scale: None,
+            bbox: Some(bbox),
+        })
+        .map(drop)
+    }
+}
@@ -0,0 +1,501 @@
+use serde::{Deserialize, Serialize};
+
+use crate::layout::{LayerKey, Layout};
+use crate::vpull::PALETTE;
+use crate::{DRect, Point};
+
+pub mod client;
+pub mod server;
+
+// A local protocol for pushing geometry into a running viewer, over a Unix socket
+// (`--socket <path>`). A request is a JSON object on one line, and the viewer
+// answers every request with one line, in order: {"ok": true, ...} or
+// {"ok": false, "error": "..."}. A request that fails changes nothing.
+//
+//   {"op": "layers"}
+//       -> {"ok": true, "layers": [{"layer": "1/0", "rects": 120}, ...]}, in draw order
+//   {"op": "create_layer", "layer": "10/0"}
+//       adds an empty layer on top; fails if the layer exists
+//   {"op": "replace_layer", "layer": "10/0", "rects": [[x0, y0, x1, y1], ...]}
+//       sets the rects of a layer, adding it on top if it doesn't exist
+//   {"op": "append_rects", "layer": "10/0", "rects": [[x0, y0, x1, y1, color], ...]}
+//       adds rects to a layer, adding it on top if it doesn't exist
+//   {"op": "delete_layer", "layer": "10/0"}
+//   {"op": "set_camera", "center": [x, y], "scale": 0.5}
+//   {"op": "set_camera", "bbox": [x0, y0, x1, y1]}
+//       moves the main view, or fits a box into it; scale is world units per pixel
+//   {"op": "snapshot", "path": "view.png", "width": 1920, "height": 1080}
+//       draws the part of the layout the main view shows to a PNG, with the view's
+//       visible layers and the current palette; the size defaults to 1920x1080,
+//       and images have at most 64 megapixels. The layout is drawn again on the
+//       CPU, not read back from the window, so labels, highlights and tool
+//       overlays (rulers, DRC markers, search matches) are not in it
+//
+// Coordinates are world units. The optional fifth value of a rect is a palette
+// index; rects without one take their layer's color. replace_layer and
+// append_rects take an optional "stroke", the outline width of their rects.
+//
+// From Python, for example:
+//   s = socket.socket(socket.AF_UNIX); s.connect("/tmp/doug.sock")
+//   s.sendall(b'{"op": "append_rects", "layer": "1/0", "rects": [[0, 0, 1, 1]]}\n')
+//   s.makefile().readline()
+
+// The outline width of rects that don't say, as for loaded files.
+pub const DEFAULT_STROKE: f32 = 0.01;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    Layers,
+    CreateLayer {
+        layer: String,
+    },
+    ReplaceLayer {
+        layer: String,
+        rects: Vec<WireRect>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stroke: Option<f32>,
+    },
+    AppendRects {
+        layer: String,
+        rects: Vec<WireRect>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stroke: Option<f32>,
+    },
+    DeleteLayer {
+        layer: String,
+    },
+    SetCamera {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        center: Option<[f32; 2]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scale: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bbox: Option<[f32; 4]>,
+    },
+    Snapshot {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        width: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        height: Option<u32>,
+    },
+}
+
+// [x0, y0, x1, y1] or [x0, y0, x1, y1, color]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WireRect {
+    Plain([f32; 4]),
+    Colored(f32, f32, f32, f32, u32),
+}
+
+impl WireRect {
+    fn to_rect(self, color: u32, stroke_width: f32) -> DRect {
+        let ([x0, y0, x1, y1], color) = match self {
+            WireRect::Plain(corners) => (corners, color),
+            WireRect::Colored(x0, y0, x1, y1, color) => ([x0, y0, x1, y1], color),
+        };
+        DRect {
+            p0: Point { x: x0, y: y0 },
+            p1: Point { x: x1, y: y1 },
+            stroke_width,
+            color,
+        }
+    }
+}
+
+impl From<&DRect> for WireRect {
+    fn from(rect: &DRect) -> Self {
+        WireRect::Colored(rect.p0.x, rect.p0.y, rect.p1.x, rect.p1.y, rect.color)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerInfo {
+    pub layer: String,
+    pub rects: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layers: Option<Vec<LayerInfo>>,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Response {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Response {
+            ok: false,
+            error: Some(error.into()),
+            ..Default::default()
+        }
+    }
+}
+
+// What a request did to the layout's layers, for the viewer to update its batches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayerChange {
+    None,
+    // the layer at this index has new rects, or is new
+    Changed(u8),
+    // the layer at this index is gone and the layers above moved down
+    Removed(u8),
+}
+
+fn parse_layer(layer: &str) -> Result<LayerKey, String> {
+    layer
+        .parse()
+        .map_err(|e| format!("bad layer {:?}: {}", layer, e))
+}
+
+fn layer_color(index: usize) -> u32 {
+    (index % PALETTE.len()) as u32
+}
+
+// Applies the requests that change layers; the others are left to the viewer.
+pub fn apply(layout: &mut Layout, request: &Request) -> Result<(Response, LayerChange), String> {
+    let change = match request {
+        Request::Layers => {
+            let layers = layout
+                .layers
+                .iter()
+                .map(|layer| LayerInfo {
+                    layer: layout.layer_keys[layer.index as usize].to_string(),
+                    rects: layer.rects.len(),
+                })
+                .collect();
+            let response = Response {
+                layers: Some(layers),
+                ..Response::ok()
+            };
+            return Ok((response, LayerChange::None));
+        }
+        Request::CreateLayer { layer } => {
+            let key = parse_layer(layer)?;
+            if layout.layer_keys.contains(&key) {
+                return Err(format!("layer {} exists", key));
+            }
+            LayerChange::Changed(layout.layer_or_add(key)? as u8)
+        }
+        Request::ReplaceLayer {
+            layer,
+            rects,
+            stroke,
+        }
+        | Request::AppendRects {
+            layer,
+            rects,
+            stroke,
+        } => {
+            let key = parse_layer(layer)?;
+            let index = layout.layer_or_add(key)?;
+            let (color, stroke) = (layer_color(index), stroke.unwrap_or(DEFAULT_STROKE));
+            let rects = rects.iter().map(|rect| rect.to_rect(color, stroke));
+            let layer = &mut layout.layers[index];
+            if matches!(request, Request::ReplaceLayer { .. }) {
+                layer.rects = rects.collect();
+                layer.attributes = None;
+            } else {
+                let count = layer.rects.len();
+                layer.rects.extend(rects);
+                if let Some(attributes) = layer.attributes.as_mut() {
+                    attributes.push(None, layer.rects.len() - count);
+                }
+            }
+            LayerChange::Changed(index as u8)
+        }
+        Request::DeleteLayer { layer } => {
+            let key = parse_layer(layer)?;
+            let index = layout
+                .layer_keys
+                .iter()
+                .position(|k| *k == key)
+                .ok_or_else(|| format!("no layer {}", key))?;
+            layout.layers.remove(index);
+            layout.layer_keys.remove(index);
+            for layer in layout.layers[index..].iter_mut() {
+                layer.index -= 1;
+            }
+            layout.texts.retain(|text| text.layer as usize != index);
+            for text in layout.texts.iter_mut() {
+                if text.layer as usize > index {
+                    text.layer -= 1;
+                }
+            }
+            LayerChange::Removed(index as u8)
+        }
+        Request::SetCamera { .. } | Request::Snapshot { .. } => LayerChange::None,
+    };
+    Ok((Response::ok(), change))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+
+    use super::client::Client;
+    use super::server::IpcServer;
+    use super::*;
+    use crate::labels::LabelAnchor;
+    use crate::layout::LayoutText;
+
+    fn key(layer: i16) -> LayerKey {
+        LayerKey { layer, datatype: 0 }
+    }
+
+    fn rects(layout: &Layout, layer: i16) -> Vec<DRect> {
+        let index = layout.layer_keys.iter().position(|k| *k == key(layer));
+        index.map_or_else(Vec::new, |index| layout.layers[index].rects.clone())
+    }
+
+    #[test]
+    fn requests_edit_layers() {
+        let mut layout = Layout::from_rects("test", vec![DRect::default()]);
+        layout.texts.push(LayoutText {
+            text: "A".into(),
+            position: Point::default(),
+            layer: 1,
+            height: 1.0,
+            anchor: LabelAnchor::BottomLeft,
+            rotation: 0.0,
+        });
+        let append = |layer: &str, rects: Vec<WireRect>| Request::AppendRects {
+            layer: layer.into(),
+            rects,
+            stroke: None,
+        };
+
+        let (_, change) =
+            apply(&mut layout, &append("1/0", vec![WireRect::Plain([0.0; 4])])).unwrap();
+        assert_eq!(change, LayerChange::Changed(1));
+        let colored = WireRect::Colored(0.0, 0.0, 2.0, 1.0, 3);
+        apply(&mut layout, &append("1/0", vec![colored])).unwrap();
+        let layer = rects(&layout, 1);
+        assert_eq!((layer.len(), layer[0].color, layer[1].color), (2, 1, 3));
+        assert_eq!(layer[1].stroke_width, DEFAULT_STROKE);
+
+        let create = Request::CreateLayer {
+            layer: "1/0".into(),
+        };
+        assert!(apply(&mut layout, &create).is_err());
+        let replace = Request::ReplaceLayer {
+            layer: "2/0".into(),
+            rects: vec![colored],
+            stroke: Some(0.5),
+        };
+        assert_eq!(
+            apply(&mut layout, &replace).unwrap().1,
+            LayerChange::Changed(2)
+        );
+
+        let delete = |layer: &str| Request::DeleteLayer {
+            layer: layer.into(),
+        };
+        assert_eq!(
+            apply(&mut layout, &delete("0/0")).unwrap().1,
+            LayerChange::Removed(0)
+        );
+        assert!(apply(&mut layout, &delete("0/0")).is_err());
+        assert_eq!(layout.layer_keys, vec![key(1), key(2)]);
+        let indices: Vec<u8> = layout.layers.iter().map(|layer| layer.index).collect();
+        assert_eq!(indices, vec![0, 1]);
+        assert_eq!(layout.texts[0].layer, 0);
+
+        let (response, _) = apply(&mut layout, &Request::Layers).unwrap();
+        let layers = response.layers.unwrap();
+        assert_eq!(
+            layers,
+            vec![
+                LayerInfo {
+                    layer: "1/0".into(),
+                    rects: 2
+                },
+                LayerInfo {
+                    layer: "2/0".into(),
+                    rects: 1
+                },
+            ]
+        );
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("doug-{}-{}.sock", name, std::process::id()))
+    }
+
+    // Plays the viewer: applies requests to a layout until `count` were answered,
+    // and returns the layout with the requests as they arrived.
+    fn serve(
+        server: IpcServer,
+        mut layout: Layout,
+        count: usize,
+    ) -> std::thread::JoinHandle<(Layout, Vec<Request>)> {
+        std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..count {
+                let pending = server.recv().unwrap();
+                let response = match apply(&mut layout, &pending.request) {
+                    Ok((response, _)) => response,
+                    Err(e) => Response::error(e),
+                };
+                requests.push(pending.request.clone());
+                pending.reply(response);
+            }
+            (layout, requests)
+        })
+    }
+
+    #[test]
+    fn clients_stream_rects_over_a_socket() {
+        let path = socket_path("stream");
+        let server = IpcServer::bind(&path).unwrap();
+        let viewer = serve(server, Layout::default(), 5);
+
+        let mut client = Client::connect(&path).unwrap();
+        let rect = DRect {
+            p1: Point { x: 1.0, y: 1.0 },
+            ..Default::default()
+        };
+        client.append_rects(key(1), &[rect, rect], None).unwrap();
+        client.append_rects(key(1), &[rect], None).unwrap();
+        client.replace_layer(key(2), &[rect], Some(0.0)).unwrap();
+        let error = client
+            .request(&Request::DeleteLayer {
+                layer: "7/0".into(),
+            })
+            .unwrap_err();
+        assert!(error.to_string().contains("no layer 7/0"), "{}", error);
+        let layers = client.request(&Request::Layers).unwrap().layers.unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].rects, 3);
+
+        let (layout, _) = viewer.join().unwrap();
+        assert_eq!(rects(&layout, 2)[0].stroke_width, 0.0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn every_request_goes_through_a_client() {
+        let path = socket_path("requests");
+        let server = IpcServer::bind(&path).unwrap();
+        let requests = [
+            Request::CreateLayer {
+                layer: "3/1".into(),
+            },
+            Request::ReplaceLayer {
+                layer: "1/0".into(),
+                rects: vec![WireRect::Plain([0.0, 0.0, 1.0, 1.0])],
+                stroke: Some(0.25),
+            },
+            Request::AppendRects {
+                layer: "1/0".into(),
+                rects: vec![
+                    WireRect::Plain([2.0, 0.0, 3.0, 1.0]),
+                    WireRect::Colored(4.0, 0.0, 5.0, 1.0, 4),
+                ],
+                stroke: None,
+            },
+            Request::SetCamera {
+                center: None,
+                scale: None,
+                bbox: Some([0.0, 0.0, 5.0, 1.0]),
+            },
+            Request::SetCamera {
+                center: Some([2.5, 0.5]),
+                scale: Some(0.01),
+                bbox: None,
+            },
+            Request::Snapshot {
+                path: "view.png".into(),
+                width: Some(640),
+                height: None,
+            },
+            Request::DeleteLayer {
+                layer: "3/1".into(),
+            },
+            Request::Layers,
+        ];
+        let viewer = serve(server, Layout::default(), requests.len() + 3);
+
+        let mut client = Client::connect(&path).unwrap();
+        for request in requests.iter() {
+            let response = client.request(request).unwrap();
+            assert_eq!(response.layers.is_some(), *request == Request::Layers);
+        }
+        // the helpers send the same requests
+        let rect = |x: f32| DRect {
+            p0: Point { x, y: 0.0 },
+            p1: Point { x: x + 1.0, y: 1.0 },
+            stroke_width: 0.25,
+            color: 4,
+        };
+        client
+            .replace_layer(key(1), &[rect(0.0)], Some(0.25))
+            .unwrap();
+        client.append_rects(key(1), &[rect(2.0)], None).unwrap();
+        client.fit_camera([0.0, 0.0, 5.0, 1.0]).unwrap();
+
+        let (layout, received) = viewer.join().unwrap();
+        assert_eq!(received[..requests.len()], requests[..]);
+        let colored = |x: f32| WireRect::Colored(x, 0.0, x + 1.0, 1.0, 4);
+        assert_eq!(
+            received[requests.len()..],
+            [
+                Request::ReplaceLayer {
+                    layer: "1/0".into(),
+                    rects: vec![colored(0.0)],
+                    stroke: Some(0.25),
+                },
+                Request::AppendRects {
+                    layer: "1/0".into(),
+                    rects: vec![colored(2.0)],
+                    stroke: None,
+                },
+                requests[3].clone(),
+            ]
+        );
+        assert_eq!(layout.layer_keys, vec![key(1)]);
+        assert_eq!(rects(&layout, 1).len(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn bad_lines_are_answered_with_errors() {
+        let path = socket_path("bad");
+        let server = IpcServer::bind(&path).unwrap();
+        // a second viewer can't take over a socket in use
+        assert!(IpcServer::bind(&path).is_err());
+        let viewer = serve(server, Layout::default(), 1);
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer
+            .write_all(b"{\"op\": \"fly\"}\n\n{\"op\": \"layers\"}\n")
+            .unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let response: Response = serde_json::from_str(&line).unwrap();
+        assert!(!response.ok);
+        assert!(response.error.unwrap().starts_with("line 1:"));
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        let response: Response = serde_json::from_str(&line).unwrap();
+        assert_eq!(response.layers, Some(Vec::new()));
+
+        viewer.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,362 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+
+use super::{apply, LayerChange, Request, Response};
+use crate::attributes::QuadAttributes;
+use crate::bounds::Bounds;
+use crate::camera::{fit_camera, view_size, visible_bounds};
+use crate::export::{export_png, image_pixels, PngStyle};
+use crate::layout::Layout;
+use crate::split_views::{LayerVisibility, SplitCamera, Viewport};
+use crate::vpull::Palette;
+use crate::{spawn_layout_label, BatchedQuads, LayoutLabel};
+
+// Every connection gets a thread that reads its requests and waits for the viewer
+// to answer each one; the viewer answers the requests of all connections once a
+// frame.
+
+// A request waiting for the viewer.
+pub struct PendingRequest {
+    pub request: Request,
+    reply: Sender<Response>,
+}
+
+impl PendingRequest {
+    pub fn reply(self, response: Response) {
+        // the client may be gone already
+        let _ = self.reply.send(response);
+    }
+}
+
+pub struct IpcServer {
+    path: PathBuf,
+    requests: Mutex<Receiver<PendingRequest>>,
+}
+
+impl IpcServer {
+    // Listens on `path`. A socket file left behind by a viewer that is gone is
+    // replaced; one that a viewer still listens on is an error.
+    pub fn bind(path: &Path) -> io::Result<IpcServer> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another viewer", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        std::thread::spawn(move || {
+                            if let Err(e) = serve_connection(stream, sender) {
+                                warn!("ipc connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("ipc connection failed: {}", e),
+                }
+            }
+        });
+        Ok(IpcServer {
+            path: path.to_owned(),
+            requests: Mutex::new(receiver),
+        })
+    }
+
+    // Waits for the next request.
+    #[cfg(test)]
+    pub fn recv(&self) -> Option<PendingRequest> {
+        self.requests.lock().unwrap().recv().ok()
+    }
+
+    pub fn try_recv(&self) -> Option<PendingRequest> {
+        self.requests.lock().unwrap().try_recv().ok()
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn serve_connection(stream: UnixStream, requests: Sender<PendingRequest>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for (number, line) in BufReader::new(stream).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if requests.send(PendingRequest { request, reply }).is_err() {
+                    // the viewer is closing
+                    return Ok(());
+                }
+                response
+                    .recv()
+                    .unwrap_or_else(|_| Response::error("the viewer is closing"))
+            }
+            Err(e) => Response::error(format!("line {}: {}", number + 1, e)),
+        };
+        let mut json = serde_json::to_string(&response)?;
+        json.push('\n');
+        writer.write_all(json.as_bytes())?;
+    }
+    Ok(())
+}
+
+// Serves the protocol on a Unix socket; see the `ipc` module.
+pub struct IpcPlugin {
+    pub path: PathBuf,
+}
+
+impl Plugin for IpcPlugin {
+    fn build(&self, app: &mut App) {
+        match IpcServer::bind(&self.path) {
+            Ok(server) => {
+                info!("listening on {}", self.path.display());
+                app.insert_resource(server).add_system(serve_requests);
+            }
+            Err(e) => error!("could not listen on {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+// Snapshots are this big unless the request says otherwise.
+const SNAPSHOT_SIZE: (u32, u32) = (1920, 1080);
+
+#[allow(clippy::too_many_arguments)]
+fn serve_requests(
+    mut commands: Commands,
+    server: Res<IpcServer>,
+    windows: Res<Windows>,
+    mut layout: ResMut<Layout>,
+    mut batches: Batches,
+    labels: Query<Entity, With<LayoutLabel>>,
+    palette: Res<Palette>,
+    mut cameras: Cameras,
+) {
+    // layers are handed to their batches once all requests of the frame are done
+    let mut changed: Vec<u8> = Vec::new();
+    let mut despawned: Vec<Entity> = Vec::new();
+    let texts = layout.texts.clone();
+    while let Some(pending) = server.try_recv() {
+        let response = match &pending.request {
+            Request::SetCamera {
+                center,
+                scale,
+                bbox,
+            } => set_camera(&windows, &mut cameras, *center, *scale, *bbox),
+            Request::Snapshot {
+                path,
+                width,
+                height,
+            } => {
+                let width = width.unwrap_or(SNAPSHOT_SIZE.0);
+                let height = height.unwrap_or(SNAPSHOT_SIZE.1);
+                match image_pixels(width, height) {
+                    Ok(_) => snapshot(&windows, &cameras, &layout, &palette, width, height, path),
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            request => match apply(&mut layout, request) {
+                Ok((response, change)) => {
+                    match change {
+                        LayerChange::None => {}
+                        LayerChange::Changed(index) => {
+                            if !changed.contains(&index) {
+                                changed.push(index);
+                            }
+                        }
+                        LayerChange::Removed(index) => {
+                            remove_layer(
+                                &mut commands,
+                                &mut batches,
+                                &mut despawned,
+                                &mut cameras,
+                                index,
+                            );
+                            changed.retain(|i| *i != index);
+                            for i in changed.iter_mut().filter(|i| **i > index) {
+                                *i -= 1;
+                            }
+                        }
+                    }
+                    response
+                }
+                Err(e) => Response::error(e),
+            },
+        };
+        pending.reply(response);
+    }
+
+    for index in changed {
+        let layer = &layout.layers[index as usize];
+        let batch = batches
+            .iter_mut()
+            .find(|(entity, batch, _)| batch.layer == index && !despawned.contains(entity));
+        match batch {
+            Some((entity, mut batch, attributes)) => {
+                *batch = BatchedQuads {
+                    data: layer.rects.clone(),
+                    layer: index,
+                    ..Default::default()
+                };
+                // validation runs after the commands are applied
+                match (attributes, &layer.attributes) {
+                    (Some(mut attributes), Some(new)) => *attributes = new.clone(),
+                    (None, Some(new)) => {
+                        commands.entity(entity).insert(new.clone());
+                    }
+                    (Some(_), None) => {
+                        commands.entity(entity).remove::<QuadAttributes>();
+                    }
+                    (None, None) => {}
+                }
+            }
+            None => {
+                let mut batch = commands.spawn_bundle((BatchedQuads {
+                    data: layer.rects.clone(),
+                    layer: index,
+                    ..Default::default()
+                },));
+                if let Some(attributes) = &layer.attributes {
+                    batch.insert(attributes.clone());
+                }
+            }
+        }
+    }
+    if layout.texts != texts {
+        for entity in labels.iter() {
+            commands.entity(entity).despawn();
+        }
+        for text in layout.texts.iter() {
+            spawn_layout_label(&mut commands, text);
+        }
+    }
+}
+
+type Batches<'w, 's, 'a, 'b> =
+    Query<'w, 's, (Entity, &'a mut BatchedQuads, Option<&'b mut QuadAttributes>)>;
+
+type Cameras<'w, 's, 'a, 'b, 'c, 'd, 'e> = Query<
+    'w,
+    's,
+    (
+        &'a mut Transform,
+        &'b mut OrthographicProjection,
+        Option<&'c Viewport>,
+        &'d mut LayerVisibility,
+        Option<&'e SplitCamera>,
+    ),
+    With<Camera2d>,
+>;
+
+// Draws the layout as the main view shows it, with its layers and the current
+// palette; see the `ipc` module for what is left out.
+fn snapshot(
+    windows: &Windows,
+    cameras: &Cameras,
+    layout: &Layout,
+    palette: &Palette,
+    width: u32,
+    height: u32,
+    path: &str,
+) -> Response {
+    let view = cameras.iter().find(|(.., split)| split.is_none());
+    let (transform, projection, viewport, visibility, _) = match view {
+        Some(view) => view,
+        None => return Response::error("no view"),
+    };
+    let bounds = windows
+        .get_primary()
+        .map(|window| visible_bounds(transform, projection, view_size(window, viewport)));
+    let style = PngStyle {
+        palette: palette.clone(),
+        visibility: visibility.clone(),
+    };
+    match export_png(layout, bounds, width, height, &style, Path::new(path)) {
+        Ok(()) => Response::ok(),
+        Err(e) => Response::error(format!("{}: {}", path, e)),
+    }
+}
+
+fn set_camera(
+    windows: &Windows,
+    cameras: &mut Cameras,
+    center: Option<[f32; 2]>,
+    scale: Option<f32>,
+    bbox: Option<[f32; 4]>,
+) -> Response {
+    let camera = cameras.iter_mut().find(|(.., split)| split.is_none());
+    let (mut transform, mut projection, viewport, ..) = match camera {
+        Some(camera) => camera,
+        None => return Response::error("no view"),
+    };
+    if let Some([x0, y0, x1, y1]) = bbox {
+        let (a, b) = (Vec2::new(x0, y0), Vec2::new(x1, y1));
+        let bounds = Bounds {
+            min: a.min(b),
+            max: a.max(b),
+        };
+        if let Some(window) = windows.get_primary() {
+            fit_camera(
+                &mut transform,
+                &mut projection,
+                &bounds,
+                view_size(window, viewport),
+                1.0,
+            );
+        }
+    }
+    if let Some([x, y]) = center {
+        transform.translation.x = x;
+        transform.translation.y = y;
+    }
+    match scale {
+        Some(scale) if scale > 0.0 && scale.is_finite() => projection.scale = scale,
+        Some(scale) => return Response::error(format!("bad scale {}", scale)),
+        None => {}
+    }
+    Response::ok()
+}
+
+// Despawns the batch of a deleted layer and moves the batches and layer visibility
+// of the layers above it down. Batches despawned earlier in the frame are still
+// queried until the commands are applied, and are skipped.
+fn remove_layer(
+    commands: &mut Commands,
+    batches: &mut Batches,
+    despawned: &mut Vec<Entity>,
+    cameras: &mut Cameras,
+    index: u8,
+) {
+    for (entity, mut batch, _) in batches.iter_mut() {
+        if despawned.contains(&entity) {
+            continue;
+        }
+        if batch.layer == index {
+            commands.entity(entity).despawn();
+            despawned.push(entity);
+        } else if batch.layer > index {
+            batch.layer -= 1;
+        }
+    }
+    for (.., mut layers, _) in cameras.iter_mut() {
+        layers.remove_layer(index);
+    }
+}
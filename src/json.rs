@@ -0,0 +1,489 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::attributes::QuadAttributes;
+use crate::camera::{fit_camera, view_size};
+use crate::layout::{manhattan_polygon_to_rects, LayerKey, Layout, LoadError, LoadOptions};
+use crate::navigation::FIT_MARGIN;
+use crate::split_views::{SplitCamera, Viewport};
+use crate::vpull::PALETTE;
+use crate::{BatchedQuads, DRect, Point};
+
+// Shapes as JSON, for scripts: one object per shape, in an array (.json) or one per
+// line (.ndjson, .jsonl, and the viewer's --stdin):
+//
+//   {"layer": "1/0", "rect": [0, 0, 10, 5]}
+//   {"layer": 2, "polygon": [[0, 0], [4, 0], [4, 4], [0, 4]], "color": 3}
+//
+// `layer` is "layer/datatype" or a layer number, and 0/0 if left out. `color` is a
+// palette index, by default the layer's. Layers are drawn in the order they first
+// appear. Polygons need horizontal and vertical edges, as in GDS files; others are
+// skipped. Coordinates too big for an f32 are an error.
+
+#[derive(Deserialize)]
+struct RawShape {
+    #[serde(default)]
+    layer: Option<Value>,
+    #[serde(default)]
+    color: Option<u32>,
+    // read wide, so that numbers past f32 are refused rather than made infinite
+    #[serde(default)]
+    rect: Option<[f64; 4]>,
+    #[serde(default)]
+    polygon: Option<Vec<[f64; 2]>>,
+}
+
+fn coordinate(value: f64) -> Result<f32, String> {
+    let coordinate = value as f32;
+    match coordinate.is_finite() {
+        true => Ok(coordinate),
+        false => Err(format!("coordinate {:e} is out of range", value)),
+    }
+}
+
+fn point([x, y]: [f64; 2]) -> Result<Point, String> {
+    Ok(Point {
+        x: coordinate(x)?,
+        y: coordinate(y)?,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonShape {
+    pub layer: LayerKey,
+    pub color: Option<u32>,
+    pub geometry: Geometry,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    Rect(DRect),
+    Polygon(Vec<Point>),
+}
+
+impl TryFrom<RawShape> for JsonShape {
+    type Error = String;
+
+    fn try_from(raw: RawShape) -> Result<Self, Self::Error> {
+        let layer = match raw.layer {
+            None => LayerKey::default(),
+            Some(Value::String(key)) => key.parse()?,
+            Some(Value::Number(number)) => LayerKey {
+                layer: number
+                    .as_i64()
+                    .and_then(|n| i16::try_from(n).ok())
+                    .ok_or_else(|| format!("bad layer {}", number))?,
+                datatype: 0,
+            },
+            Some(value) => {
+                return Err(format!(
+                    "bad layer {}, expected \"layer/datatype\" or a number",
+                    value
+                ))
+            }
+        };
+        let geometry = match (raw.rect, raw.polygon) {
+            (Some([x0, y0, x1, y1]), None) => {
+                let (a, b) = (point([x0, y0])?, point([x1, y1])?);
+                Geometry::Rect(DRect {
+                    p0: Point {
+                        x: a.x.min(b.x),
+                        y: a.y.min(b.y),
+                    },
+                    p1: Point {
+                        x: a.x.max(b.x),
+                        y: a.y.max(b.y),
+                    },
+                    ..Default::default()
+                })
+            }
+            (None, Some(points)) if points.len() < 3 => {
+                return Err("a polygon needs at least 3 points".into())
+            }
+            (None, Some(points)) => Geometry::Polygon(
+                points
+                    .into_iter()
+                    .map(point)
+                    .collect::<Result<Vec<Point>, String>>()?,
+            ),
+            (None, None) => return Err("a shape needs a rect or a polygon".into()),
+            (Some(_), Some(_)) => return Err("a shape has a rect or a polygon, not both".into()),
+        };
+        Ok(JsonShape {
+            layer,
+            color: raw.color,
+            geometry,
+        })
+    }
+}
+
+// Shapes are checked while their object is read, so that serde_json puts errors at
+// the end of the object rather than after it.
+impl<'de> Deserialize<'de> for JsonShape {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ShapeVisitor;
+
+        impl<'de> Visitor<'de> for ShapeVisitor {
+            type Value = JsonShape;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a shape")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<JsonShape, A::Error> {
+                let raw = RawShape::deserialize(MapAccessDeserializer::new(map))?;
+                JsonShape::try_from(raw).map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_map(ShapeVisitor)
+    }
+}
+
+// "line 3, column 14: ..." for an error on line `line`; serde_json's message ends
+// with the position it counted itself, which is dropped.
+fn position_error(e: &serde_json::Error, line: usize) -> String {
+    let message = e.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(message, _)| message);
+    format!("line {}, column {}: {}", line, e.column(), message)
+}
+
+// A line of NDJSON, numbered from 1; blank lines have no shape.
+pub fn parse_shape_line(line: &str, number: usize) -> Result<Option<JsonShape>, String> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(line)
+        .map(Some)
+        .map_err(|e| position_error(&e, number))
+}
+
+// A JSON array of shapes, or NDJSON.
+pub fn parse_shapes(text: &str) -> Result<Vec<JsonShape>, String> {
+    if text.trim_start().starts_with('[') {
+        return serde_json::from_str(text).map_err(|e| position_error(&e, e.line()));
+    }
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| parse_shape_line(line, i + 1).transpose())
+        .collect()
+}
+
+pub fn read_shapes(path: &Path) -> io::Result<Vec<JsonShape>> {
+    // loading errors are reported with the path already
+    parse_shapes(&std::fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Adds a shape to its layer, which is added on top if it is new. Returns the index
+// of the layer and the number of rects added, or None for a polygon with slanted
+// edges.
+pub fn add_shape(
+    layout: &mut Layout,
+    shape: &JsonShape,
+    stroke_width: f32,
+) -> Result<Option<(u8, usize)>, String> {
+    let index = layout.layer_or_add(shape.layer)?;
+    let mut rects = match &shape.geometry {
+        Geometry::Rect(rect) => vec![*rect],
+        Geometry::Polygon(points) => match manhattan_polygon_to_rects(points) {
+            Some(rects) => rects,
+            None => {
+                layout.skipped_polygons += 1;
+                return Ok(None);
+            }
+        },
+    };
+    let color = shape.color.unwrap_or((index % PALETTE.len()) as u32);
+    for rect in rects.iter_mut() {
+        rect.color = color;
+        rect.stroke_width = stroke_width;
+    }
+    let layer = &mut layout.layers[index];
+    if let Some(attributes) = layer.attributes.as_mut() {
+        attributes.push(None, rects.len());
+    }
+    let count = rects.len();
+    layer.rects.extend(rects);
+    Ok(Some((index as u8, count)))
+}
+
+// The layout of a file of shapes. Requested layers come first, in their order, and
+// shapes on other layers are left out.
+pub fn shapes_layout(
+    name: &str,
+    shapes: &[JsonShape],
+    options: &LoadOptions,
+) -> Result<Layout, LoadError> {
+    let mut keys: Vec<LayerKey> = options.layers.clone();
+    if keys.is_empty() {
+        for shape in shapes {
+            if !keys.contains(&shape.layer) {
+                keys.push(shape.layer);
+            }
+        }
+    }
+    if keys.len() > 256 {
+        return Err(LoadError::TooManyLayers(keys.len()));
+    }
+    let mut layout = Layout {
+        name: name.into(),
+        ..Default::default()
+    };
+    for key in keys.iter() {
+        layout.layer_or_add(*key).unwrap();
+    }
+    for shape in shapes.iter().filter(|shape| keys.contains(&shape.layer)) {
+        add_shape(&mut layout, shape, options.stroke_width).unwrap();
+    }
+    if let Some(bbox) = options.bbox {
+        layout.clip(bbox);
+    }
+    Ok(layout)
+}
+
+// Shapes read from standard input are added to the layout and handed to the batches
+// this often, in seconds, so that a script writing one shape at a time doesn't make
+// every frame update the layout.
+const FLUSH_SECONDS: f64 = 0.2;
+
+// Adds the NDJSON shapes written to standard input to the view as they arrive.
+// Lines that don't parse are logged with their position and skipped. Batches are
+// only appended to, so a long stream uploads each shape once. A layout that starts
+// out empty is fitted to the first shapes.
+pub struct StdinPlugin {
+    pub stroke_width: f32,
+}
+
+struct ShapeStream {
+    shapes: Mutex<Receiver<Result<JsonShape, String>>>,
+    // read, and waiting for the next flush
+    pending: Vec<JsonShape>,
+    last_flush: f64,
+    stroke_width: f32,
+    // the view has been fitted to the layout, or the layout didn't start out empty
+    fitted: bool,
+}
+
+impl Plugin for StdinPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (i, line) in io::stdin().lock().lines().enumerate() {
+                let shape = line
+                    .map_err(|e| format!("line {}: {}", i + 1, e))
+                    .and_then(|line| parse_shape_line(&line, i + 1))
+                    .transpose();
+                if let Some(shape) = shape {
+                    if sender.send(shape).is_err() {
+                        // the viewer is closing
+                        return;
+                    }
+                }
+            }
+            info!("standard input is closed");
+        });
+        let empty = app
+            .world
+            .get_resource::<Layout>()
+            .is_none_or(|layout| layout.bounds().is_none());
+        app.insert_resource(ShapeStream {
+            shapes: Mutex::new(receiver),
+            pending: Vec::new(),
+            last_flush: 0.0,
+            stroke_width: self.stroke_width,
+            fitted: !empty,
+        })
+        .add_system(stream_shapes);
+    }
+}
+
+type Batches<'w, 's, 'a, 'b> =
+    Query<'w, 's, (Entity, &'a mut BatchedQuads, Option<&'b mut QuadAttributes>)>;
+
+type Cameras<'w, 's, 'a, 'b, 'c> = Query<
+    'w,
+    's,
+    (
+        &'a mut Transform,
+        &'b mut OrthographicProjection,
+        Option<&'c Viewport>,
+    ),
+    (With<Camera2d>, Without<SplitCamera>),
+>;
+
+#[allow(clippy::too_many_arguments)]
+fn stream_shapes(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut stream: ResMut<ShapeStream>,
+    windows: Res<Windows>,
+    mut layout: ResMut<Layout>,
+    mut batches: Batches,
+    mut cameras: Cameras,
+) {
+    let stream = &mut *stream;
+    for shape in stream.shapes.get_mut().unwrap().try_iter() {
+        match shape {
+            Ok(shape) => stream.pending.push(shape),
+            Err(e) => error!("standard input: {}", e),
+        }
+    }
+    let now = time.seconds_since_startup();
+    if stream.pending.is_empty() || now - stream.last_flush < FLUSH_SECONDS {
+        return;
+    }
+    stream.last_flush = now;
+
+    // the first new rect of each layer
+    let mut added: Vec<(u8, usize)> = Vec::new();
+    for shape in stream.pending.drain(..) {
+        match add_shape(&mut layout, &shape, stream.stroke_width) {
+            Ok(Some((index, count))) => {
+                if !added.iter().any(|(i, _)| *i == index) {
+                    let len = layout.layers[index as usize].rects.len();
+                    added.push((index, len - count));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("standard input: {}", e),
+        }
+    }
+
+    for (index, start) in added {
+        let layer = &layout.layers[index as usize];
+        match batches
+            .iter_mut()
+            .find(|(_, batch, _)| batch.layer == index)
+        {
+            Some((_, mut batch, attributes)) => {
+                let rects = &layer.rects[start..];
+                batch.append(rects);
+                if let Some(mut attributes) = attributes {
+                    attributes.push(None, rects.len());
+                }
+            }
+            None => {
+                let mut batch = commands.spawn_bundle((BatchedQuads {
+                    data: layer.rects.clone(),
+                    layer: index,
+                    ..Default::default()
+                },));
+                if let Some(attributes) = &layer.attributes {
+                    batch.insert(attributes.clone());
+                }
+            }
+        }
+    }
+
+    if !stream.fitted {
+        stream.fitted = true;
+        let view = cameras.iter_mut().next().zip(windows.get_primary());
+        if let (Some(((mut transform, mut projection, viewport), window)), Some(bounds)) =
+            (view, layout.bounds())
+        {
+            fit_camera(
+                &mut transform,
+                &mut projection,
+                &bounds,
+                view_size(window, viewport),
+                FIT_MARGIN,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_are_read_from_json_and_ndjson() {
+        let ndjson = r#"
+{"layer": "1/0", "rect": [10, 5, 0, 0]}
+{"layer": 2, "polygon": [[0, 0], [4, 0], [4, 2], [2, 2], [2, 4], [0, 4]], "color": 3}
+
+{"polygon": [[0, 0], [4, 4], [0, 4]]}
+{"layer": "1/0", "rect": [20, 0, 21, 1], "note": "extra fields are ignored"}
+"#;
+        let shapes = parse_shapes(ndjson).unwrap();
+        assert_eq!(shapes.len(), 4);
+        let json = format!(
+            "[{}]",
+            ndjson.trim().replace("\n\n", "\n").replace('\n', ",")
+        );
+        assert_eq!(parse_shapes(&json).unwrap(), shapes);
+
+        let options = LoadOptions {
+            stroke_width: 0.5,
+            ..Default::default()
+        };
+        let layout = shapes_layout("script", &shapes, &options).unwrap();
+        let keys: Vec<String> = layout.layer_keys.iter().map(|k| k.to_string()).collect();
+        assert_eq!(keys, vec!["1/0", "2/0", "0/0"]);
+        let rects = &layout.layers[0].rects;
+        assert_eq!((rects[0].p0, rects[0].p1.x), (Point::default(), 10.0));
+        assert_eq!((rects[1].color, rects[1].stroke_width), (0, 0.5));
+        // the L shape is cut into rects in its own color
+        assert_eq!(layout.layers[1].rects.len(), 2);
+        assert!(layout.layers[1].rects.iter().all(|rect| rect.color == 3));
+        // the slanted triangle is skipped
+        assert!(layout.layers[2].rects.is_empty());
+        assert_eq!(layout.skipped_polygons, 1);
+
+        let options = LoadOptions {
+            layers: vec!["2/0".parse().unwrap()],
+            ..Default::default()
+        };
+        let layout = shapes_layout("script", &shapes, &options).unwrap();
+        assert_eq!((layout.layers.len(), layout.rect_count()), (1, 2));
+    }
+
+    #[test]
+    fn parse_errors_have_a_line_and_column() {
+        let text = "{\"rect\": [0, 0, 1, 1]}\n\n{\"rect\": [0, 0, 1]}\n";
+        assert_eq!(
+            parse_shapes(text).unwrap_err(),
+            "line 3, column 18: invalid length 3, expected an array of length 4"
+        );
+        assert_eq!(
+            parse_shapes("{\"rect\": [0, 0, 1, 1], \"layer\": \"a/b\"}").unwrap_err(),
+            "line 1, column 38: bad layer \"a/b\", expected layer/datatype"
+        );
+        assert_eq!(
+            parse_shapes("[\n  {\"layer\": 1},\n  {\"rect\": [0, 0, 1, 1]}\n]").unwrap_err(),
+            "line 2, column 14: a shape needs a rect or a polygon"
+        );
+        assert!(parse_shapes("{\"rect\": [0, 0, 1, 1]")
+            .unwrap_err()
+            .starts_with("line 1, column 21"));
+
+        // numbers that don't fit an f32
+        assert_eq!(
+            parse_shapes("{\"rect\": [0, 0, 1, 1]}\n{\"rect\": [0, 0, 1e39, 1]}").unwrap_err(),
+            "line 2, column 25: coordinate 1e39 is out of range"
+        );
+        assert_eq!(
+            parse_shapes("{\"polygon\": [[0, 0], [-4e38, 0], [0, 1]]}").unwrap_err(),
+            "line 1, column 41: coordinate -4e38 is out of range"
+        );
+        assert_eq!(
+            parse_shapes("[{\"rect\": [0, 0, 3.4e38, 1]}]")
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,196 @@
+// A built-in 5x7 bitmap font, turned into a signed distance field atlas at startup so
+// that labels stay crisp at any zoom without shipping a font file.
+
+pub const GLYPH_COLUMNS: usize = 5;
+pub const GLYPH_ROWS: usize = 7;
+// Horizontal advance of one glyph, in font pixels (one column of spacing).
+pub const GLYPH_ADVANCE: f32 = 6.0;
+
+// Atlas texels per font pixel.
+const TEXELS_PER_PIXEL: usize = 4;
+// Padding around each glyph in font pixels; this is also the SDF spread.
+pub const PAD: f32 = 1.5;
+const PAD_TEXELS: usize = 6;
+
+pub const CELL_WIDTH: usize = GLYPH_COLUMNS * TEXELS_PER_PIXEL + 2 * PAD_TEXELS;
+pub const CELL_HEIGHT: usize = GLYPH_ROWS * TEXELS_PER_PIXEL + 2 * PAD_TEXELS;
+const ATLAS_COLUMNS: usize = 16;
+const ATLAS_ROWS: usize = 6;
+pub const ATLAS_WIDTH: usize = ATLAS_COLUMNS * CELL_WIDTH;
+pub const ATLAS_HEIGHT: usize = ATLAS_ROWS * CELL_HEIGHT;
+
+const FIRST_CHAR: u8 = b' ';
+const LAST_CHAR: u8 = b'~';
+
+#[rustfmt::skip]
+const GLYPHS: [[&str; GLYPH_ROWS]; (LAST_CHAR - FIRST_CHAR + 1) as usize] = [
+    [".....", ".....", ".....", ".....", ".....", ".....", "....."], // ' '
+    ["..#..", "..#..", "..#..", "..#..", "..#..", ".....", "..#.."], // !
+    [".#.#.", ".#.#.", ".#.#.", ".....", ".....", ".....", "....."], // "
+    [".#.#.", ".#.#.", "#####", ".#.#.", "#####", ".#.#.", ".#.#."], // #
+    ["..#..", ".####", "#.#..", ".###.", "..#.#", "####.", "..#.."], // $
+    ["##...", "##..#", "...#.", "..#..", ".#...", "#..##", "...##"], // %
+    [".##..", "#..#.", "#.#..", ".#...", "#.#.#", "#..#.", ".##.#"], // &
+    ["..#..", "..#..", ".#...", ".....", ".....", ".....", "....."], // '
+    ["...#.", "..#..", ".#...", ".#...", ".#...", "..#..", "...#."], // (
+    [".#...", "..#..", "...#.", "...#.", "...#.", "..#..", ".#..."], // )
+    [".....", "..#..", "#.#.#", ".###.", "#.#.#", "..#..", "....."], // *
+    [".....", "..#..", "..#..", "#####", "..#..", "..#..", "....."], // +
+    [".....", ".....", ".....", ".....", ".##..", "..#..", ".#..."], // ,
+    [".....", ".....", ".....", "#####", ".....", ".....", "....."], // -
+    [".....", ".....", ".....", ".....", ".....", ".##..", ".##.."], // .
+    [".....", "....#", "...#.", "..#..", ".#...", "#....", "....."], // /
+    [".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###."], // 0
+    ["..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###."], // 1
+    [".###.", "#...#", "....#", "...#.", "..#..", ".#...", "#####"], // 2
+    ["#####", "...#.", "..#..", "...#.", "....#", "#...#", ".###."], // 3
+    ["...#.", "..##.", ".#.#.", "#..#.", "#####", "...#.", "...#."], // 4
+    ["#####", "#....", "####.", "....#", "....#", "#...#", ".###."], // 5
+    ["..##.", ".#...", "#....", "####.", "#...#", "#...#", ".###."], // 6
+    ["#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#..."], // 7
+    [".###.", "#...#", "#...#", ".###.", "#...#", "#...#", ".###."], // 8
+    [".###.", "#...#", "#...#", ".####", "....#", "...#.", ".##.."], // 9
+    [".....", ".##..", ".##..", ".....", ".##..", ".##..", "....."], // :
+    [".....", ".##..", ".##..", ".....", ".##..", "..#..", ".#..."], // ;
+    ["...#.", "..#..", ".#...", "#....", ".#...", "..#..", "...#."], // <
+    [".....", ".....", "#####", ".....", "#####", ".....", "....."], // =
+    [".#...", "..#..", "...#.", "....#", "...#.", "..#..", ".#..."], // >
+    [".###.", "#...#", "....#", "...#.", "..#..", ".....", "..#.."], // ?
+    [".###.", "#...#", "....#", ".##.#", "#.#.#", "#.#.#", ".###."], // @
+    [".###.", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"], // A
+    ["####.", "#...#", "#...#", "####.", "#...#", "#...#", "####."], // B
+    [".###.", "#...#", "#....", "#....", "#....", "#...#", ".###."], // C
+    ["###..", "#..#.", "#...#", "#...#", "#...#", "#..#.", "###.."], // D
+    ["#####", "#....", "#....", "####.", "#....", "#....", "#####"], // E
+    ["#####", "#....", "#....", "####.", "#....", "#....", "#...."], // F
+    [".###.", "#...#", "#....", "#.###", "#...#", "#...#", ".####"], // G
+    ["#...#", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"], // H
+    [".###.", "..#..", "..#..", "..#..", "..#..", "..#..", ".###."], // I
+    ["..###", "...#.", "...#.", "...#.", "...#.", "#..#.", ".##.."], // J
+    ["#...#", "#..#.", "#.#..", "##...", "#.#..", "#..#.", "#...#"], // K
+    ["#....", "#....", "#....", "#....", "#....", "#....", "#####"], // L
+    ["#...#", "##.##", "#.#.#", "#.#.#", "#...#", "#...#", "#...#"], // M
+    ["#...#", "#...#", "##..#", "#.#.#", "#..##", "#...#", "#...#"], // N
+    [".###.", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."], // O
+    ["####.", "#...#", "#...#", "####.", "#....", "#....", "#...."], // P
+    [".###.", "#...#", "#...#", "#...#", "#.#.#", "#..#.", ".##.#"], // Q
+    ["####.", "#...#", "#...#", "####.", "#.#..", "#..#.", "#...#"], // R
+    [".####", "#....", "#....", ".###.", "....#", "....#", "####."], // S
+    ["#####", "..#..", "..#..", "..#..", "..#..", "..#..", "..#.."], // T
+    ["#...#", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."], // U
+    ["#...#", "#...#", "#...#", "#...#", "#...#", ".#.#.", "..#.."], // V
+    ["#...#", "#...#", "#...#", "#.#.#", "#.#.#", "#.#.#", ".#.#."], // W
+    ["#...#", "#...#", ".#.#.", "..#..", ".#.#.", "#...#", "#...#"], // X
+    ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#..", "..#.."], // Y
+    ["#####", "....#", "...#.", "..#..", ".#...", "#....", "#####"], // Z
+    [".###.", ".#...", ".#...", ".#...", ".#...", ".#...", ".###."], // [
+    [".....", "#....", ".#...", "..#..", "...#.", "....#", "....."], // \
+    [".###.", "...#.", "...#.", "...#.", "...#.", "...#.", ".###."], // ]
+    ["..#..", ".#.#.", "#...#", ".....", ".....", ".....", "....."], // ^
+    [".....", ".....", ".....", ".....", ".....", ".....", "#####"], // _
+    [".#...", "..#..", "...#.", ".....", ".....", ".....", "....."], // `
+    [".....", ".....", ".###.", "....#", ".####", "#...#", ".####"], // a
+    ["#....", "#....", "#.##.", "##..#", "#...#", "#...#", "####."], // b
+    [".....", ".....", ".###.", "#....", "#....", "#...#", ".###."], // c
+    ["....#", "....#", ".##.#", "#..##", "#...#", "#...#", ".####"], // d
+    [".....", ".....", ".###.", "#...#", "#####", "#....", ".###."], // e
+    ["..##.", ".#..#", ".#...", "###..", ".#...", ".#...", ".#..."], // f
+    [".....", ".####", "#...#", "#...#", ".####", "....#", ".###."], // g
+    ["#....", "#....", "#.##.", "##..#", "#...#", "#...#", "#...#"], // h
+    ["..#..", ".....", ".##..", "..#..", "..#..", "..#..", ".###."], // i
+    ["...#.", ".....", "..##.", "...#.", "...#.", "#..#.", ".##.."], // j
+    ["#....", "#....", "#..#.", "#.#..", "##...", "#.#..", "#..#."], // k
+    [".##..", "..#..", "..#..", "..#..", "..#..", "..#..", ".###."], // l
+    [".....", ".....", "##.#.", "#.#.#", "#.#.#", "#...#", "#...#"], // m
+    [".....", ".....", "#.##.", "##..#", "#...#", "#...#", "#...#"], // n
+    [".....", ".....", ".###.", "#...#", "#...#", "#...#", ".###."], // o
+    [".....", ".....", "####.", "#...#", "####.", "#....", "#...."], // p
+    [".....", ".....", ".##.#", "#..##", ".####", "....#", "....#"], // q
+    [".....", ".....", "#.##.", "##..#", "#....", "#....", "#...."], // r
+    [".....", ".....", ".###.", "#....", ".###.", "....#", "####."], // s
+    [".#...", ".#...", "###..", ".#...", ".#...", ".#..#", "..##."], // t
+    [".....", ".....", "#...#", "#...#", "#...#", "#..##", ".##.#"], // u
+    [".....", ".....", "#...#", "#...#", "#...#", ".#.#.", "..#.."], // v
+    [".....", ".....", "#...#", "#...#", "#.#.#", "#.#.#", ".#.#."], // w
+    [".....", ".....", "#...#", ".#.#.", "..#..", ".#.#.", "#...#"], // x
+    [".....", ".....", "#...#", "#...#", ".####", "....#", ".###."], // y
+    [".....", ".....", "#####", "...#.", "..#..", ".#...", "#####"], // z
+    ["...#.", "..#..", "..#..", ".#...", "..#..", "..#..", "...#."], // {
+    ["..#..", "..#..", "..#..", "..#..", "..#..", "..#..", "..#.."], // |
+    [".#...", "..#..", "..#..", "...#.", "..#..", "..#..", ".#..."], // }
+    [".....", ".....", ".#...", "#.#.#", "...#.", ".....", "....."], // ~
+];
+
+// Characters outside of printable ASCII are drawn as '?'.
+fn glyph_index(c: char) -> usize {
+    let c = if (FIRST_CHAR as char..=LAST_CHAR as char).contains(&c) {
+        c as u8
+    } else {
+        b'?'
+    };
+    (c - FIRST_CHAR) as usize
+}
+
+// Texture coordinates of the padded cell holding `c`, as (min, max) with v
+// growing downwards.
+pub fn glyph_uv(c: char) -> ([f32; 2], [f32; 2]) {
+    let index = glyph_index(c);
+    let x = (index % ATLAS_COLUMNS * CELL_WIDTH) as f32;
+    let y = (index / ATLAS_COLUMNS * CELL_HEIGHT) as f32;
+    (
+        [x / ATLAS_WIDTH as f32, y / ATLAS_HEIGHT as f32],
+        [
+            (x + CELL_WIDTH as f32) / ATLAS_WIDTH as f32,
+            (y + CELL_HEIGHT as f32) / ATLAS_HEIGHT as f32,
+        ],
+    )
+}
+
+fn filled(glyph: &[&str; GLYPH_ROWS], column: i32, row: i32) -> bool {
+    if column < 0 || row < 0 || column >= GLYPH_COLUMNS as i32 || row >= GLYPH_ROWS as i32 {
+        return false;
+    }
+    glyph[row as usize].as_bytes()[column as usize] == b'#'
+}
+
+// Signed distance (negative inside) from a point in font pixel coordinates to the
+// union of the filled pixels of a glyph.
+fn signed_distance(glyph: &[&str; GLYPH_ROWS], x: f32, y: f32) -> f32 {
+    let inside = filled(glyph, x.floor() as i32, y.floor() as i32);
+    let mut best = f32::MAX;
+    // Pixels just outside of the glyph grid count as empty.
+    for row in -1..=GLYPH_ROWS as i32 {
+        for column in -1..=GLYPH_COLUMNS as i32 {
+            if filled(glyph, column, row) == inside {
+                continue;
+            }
+            let dx = (column as f32 - x).max(x - (column + 1) as f32).max(0.0);
+            let dy = (row as f32 - y).max(y - (row + 1) as f32).max(0.0);
+            best = best.min((dx * dx + dy * dy).sqrt());
+        }
+    }
+    if inside {
+        -best
+    } else {
+        best
+    }
+}
+
+// Builds the single-channel SDF atlas. Values above 0.5 are inside a glyph.
+pub fn build_sdf_atlas() -> Vec<u8> {
+    let mut atlas = vec![0u8; ATLAS_WIDTH * ATLAS_HEIGHT];
+    for (index, glyph) in GLYPHS.iter().enumerate() {
+        let cell_x = index % ATLAS_COLUMNS * CELL_WIDTH;
+        let cell_y = index / ATLAS_COLUMNS * CELL_HEIGHT;
+        for ty in 0..CELL_HEIGHT {
+            for tx in 0..CELL_WIDTH {
+                let x = (tx as f32 + 0.5) / TEXELS_PER_PIXEL as f32 - PAD;
+                let y = (ty as f32 + 0.5) / TEXELS_PER_PIXEL as f32 - PAD;
+                let d = signed_distance(glyph, x, y);
+                let value = (0.5 - d / (2.0 * PAD)).clamp(0.0, 1.0);
+                atlas[(cell_y + ty) * ATLAS_WIDTH + cell_x + tx] = (value * 255.0).round() as u8;
+            }
+        }
+    }
+    atlas
+}
@@ -0,0 +1,451 @@
+mod font;
+mod pipeline;
+mod render_command;
+
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy::render::render_phase::{AddRenderCommand, DrawFunctions, RenderPhase};
+use bevy::render::render_resource::{
+    AddressMode, BindGroupDescriptor, BindGroupEntry, BindingResource, Extent3d, FilterMode,
+    SamplerDescriptor, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureViewDescriptor,
+};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+
+use bevy::app::{App, Plugin};
+use bevy::render::{RenderApp, RenderStage};
+
+use crate::gpu_data::{GpuGlyph, GpuLabels};
+use crate::phase_item::{QuadsPhaseItem, LABELS_SORT_KEY};
+use crate::split_views::LayerVisibility;
+use crate::Point;
+
+use self::font::{GLYPH_ADVANCE, GLYPH_COLUMNS, GLYPH_ROWS, PAD};
+use self::pipeline::{LabelsPipeline, LABELS_SHADER_HANDLE};
+use self::render_command::DrawLabels;
+
+// Where the label position sits on the text box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelAnchor {
+    BottomLeft,
+    Bottom,
+    BottomRight,
+    Left,
+    Center,
+    Right,
+    TopLeft,
+    Top,
+    TopRight,
+}
+
+impl LabelAnchor {
+    // Fraction of the text box width and height that lies left of and below the anchor.
+    fn offset(self) -> (f32, f32) {
+        match self {
+            LabelAnchor::BottomLeft => (0.0, 0.0),
+            LabelAnchor::Bottom => (0.5, 0.0),
+            LabelAnchor::BottomRight => (1.0, 0.0),
+            LabelAnchor::Left => (0.0, 0.5),
+            LabelAnchor::Center => (0.5, 0.5),
+            LabelAnchor::Right => (1.0, 0.5),
+            LabelAnchor::TopLeft => (0.0, 1.0),
+            LabelAnchor::Top => (0.5, 1.0),
+            LabelAnchor::TopRight => (1.0, 1.0),
+        }
+    }
+}
+
+// A text label in layout space, e.g. a GDS TEXT element, a pin name or a cell name.
+#[derive(Clone, Component, Debug)]
+pub struct Label {
+    pub text: String,
+    pub position: Point,
+    pub anchor: LabelAnchor,
+    // Counter-clockwise, in radians, around the anchor.
+    pub rotation: f32,
+    // Labels on higher layers are drawn on top.
+    pub layer: u8,
+    // Height of a capital letter in world units.
+    pub height: f32,
+    pub color: Color,
+}
+
+pub struct LabelSettings {
+    // Labels whose capital letters would be shorter than this on screen are hidden.
+    pub min_pixel_height: f32,
+}
+
+impl Default for LabelSettings {
+    fn default() -> Self {
+        Self {
+            min_pixel_height: 6.0,
+        }
+    }
+}
+
+// Glyph instances laid out in the main world, waiting to be extracted.
+#[derive(Default)]
+struct LabelGlyphs {
+    data: Vec<GpuGlyph>,
+    // the glyphs of each layer, in `data` order
+    layers: Vec<(u8, Range<u32>)>,
+    dirty: bool,
+}
+
+struct ExtractedGlyphs {
+    data: Vec<GpuGlyph>,
+    prepared: bool,
+}
+
+// The glyphs of the labels on one layer, drawn in the views that show the layer.
+#[derive(Component)]
+pub struct LabelsBatch {
+    layer: u8,
+    glyphs: Range<u32>,
+}
+
+pub struct LabelsPlugin;
+
+impl Plugin for LabelsPlugin {
+    fn build(&self, app: &mut App) {
+        app.world.resource_mut::<Assets<Shader>>().set_untracked(
+            LABELS_SHADER_HANDLE,
+            Shader::from_wgsl(include_str!("../shaders/labels.wgsl")),
+        );
+
+        app.init_resource::<LabelSettings>()
+            .init_resource::<LabelGlyphs>()
+            .add_system_to_stage(CoreStage::PostUpdate, layout_labels);
+
+        let render_app = app.sub_app_mut(RenderApp);
+
+        render_app
+            .add_render_command::<QuadsPhaseItem, DrawLabels>()
+            .init_resource::<LabelsPipeline>()
+            .init_resource::<GpuLabels>()
+            .add_system_to_stage(RenderStage::Extract, extract_labels)
+            .add_system_to_stage(RenderStage::Prepare, prepare_labels)
+            .add_system_to_stage(RenderStage::Queue, queue_labels);
+    }
+}
+
+fn rotate(v: Vec2, rotation: f32) -> Vec2 {
+    let (s, c) = rotation.sin_cos();
+    Vec2::new(c * v.x - s * v.y, s * v.x + c * v.y)
+}
+
+fn layout_label(label: &Label, settings: &LabelSettings, glyphs: &mut Vec<GpuGlyph>) {
+    // the height of a label is the height of the 5x7 glyph grid
+    let scale = label.height / GLYPH_ROWS as f32;
+    let char_count = label.text.chars().count();
+    if char_count == 0 || !scale.is_finite() || scale <= 0.0 {
+        return;
+    }
+    let width = ((char_count as f32) * GLYPH_ADVANCE - 1.0) * scale;
+    let (ax, ay) = label.anchor.offset();
+    let anchor = Vec2::new(label.position.x, label.position.y);
+
+    let cell = Vec2::new(
+        GLYPH_COLUMNS as f32 + 2.0 * PAD,
+        GLYPH_ROWS as f32 + 2.0 * PAD,
+    ) * scale;
+    let x_axis = rotate(Vec2::new(cell.x, 0.0), label.rotation);
+    let y_axis = rotate(Vec2::new(0.0, cell.y), label.rotation);
+    let hide_below = settings.min_pixel_height * cell.y / label.height;
+    let color = label.color.as_rgba_u32();
+
+    for (i, c) in label.text.chars().enumerate() {
+        if c == ' ' {
+            continue;
+        }
+        let local = Vec2::new(
+            -ax * width + i as f32 * GLYPH_ADVANCE * scale - PAD * scale,
+            -ay * label.height - PAD * scale,
+        );
+        let (uv_min, uv_max) = font::glyph_uv(c);
+        glyphs.push(GpuGlyph {
+            origin: anchor + rotate(local, label.rotation),
+            x_axis,
+            y_axis,
+            uv_min: uv_min.into(),
+            uv_max: uv_max.into(),
+            color,
+            hide_below,
+        });
+    }
+}
+
+// Labels change rarely, so the glyph instances are only rebuilt when a label is
+// added, changed or removed.
+fn layout_labels(
+    labels: Query<&Label>,
+    changed: Query<(), Changed<Label>>,
+    removed: RemovedComponents<Label>,
+    settings: Res<LabelSettings>,
+    mut label_glyphs: ResMut<LabelGlyphs>,
+) {
+    if changed.is_empty() && removed.iter().next().is_none() && !settings.is_changed() {
+        return;
+    }
+
+    let mut sorted = labels.iter().collect::<Vec<&Label>>();
+    sorted.sort_by_key(|label| label.layer);
+
+    let label_glyphs = &mut *label_glyphs;
+    label_glyphs.data.clear();
+    label_glyphs.layers.clear();
+    for label in sorted {
+        let start = label_glyphs.data.len() as u32;
+        layout_label(label, &settings, &mut label_glyphs.data);
+        let end = label_glyphs.data.len() as u32;
+        match label_glyphs.layers.last_mut() {
+            Some((layer, glyphs)) if *layer == label.layer => glyphs.end = end,
+            _ if end > start => label_glyphs.layers.push((label.layer, start..end)),
+            _ => {}
+        }
+    }
+    label_glyphs.dirty = true;
+}
+
+// EXTRACT:
+fn extract_labels(mut commands: Commands, mut label_glyphs: ResMut<LabelGlyphs>) {
+    if label_glyphs.dirty {
+        commands.insert_resource(ExtractedGlyphs {
+            data: std::mem::take(&mut label_glyphs.data),
+            prepared: false,
+        });
+        label_glyphs.dirty = false;
+    }
+    for (layer, glyphs) in label_glyphs.layers.iter() {
+        commands.spawn_bundle((LabelsBatch {
+            layer: *layer,
+            glyphs: glyphs.clone(),
+        },));
+    }
+}
+
+// PREPARE:
+// The SDF atlas is built and uploaded once, glyph instances whenever labels change.
+fn prepare_labels(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut gpu_labels: ResMut<GpuLabels>,
+    extracted_glyphs: Option<ResMut<ExtractedGlyphs>>,
+    labels_pipeline: Res<LabelsPipeline>,
+) {
+    let mut extracted_glyphs = match extracted_glyphs {
+        Some(extracted_glyphs) if !extracted_glyphs.prepared => extracted_glyphs,
+        _ => return,
+    };
+    extracted_glyphs.prepared = true;
+
+    if gpu_labels.atlas.is_none() {
+        let texture = render_device.create_texture_with_data(
+            &render_queue,
+            &TextureDescriptor {
+                label: Some("labels_sdf_atlas"),
+                size: Extent3d {
+                    width: font::ATLAS_WIDTH as u32,
+                    height: font::ATLAS_HEIGHT as u32,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            },
+            &font::build_sdf_atlas(),
+        );
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("labels_sdf_sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        gpu_labels.atlas = Some((
+            texture.create_view(&TextureViewDescriptor::default()),
+            sampler,
+        ));
+    }
+
+    gpu_labels.glyphs.clear();
+    for glyph in extracted_glyphs.data.drain(..) {
+        gpu_labels.glyphs.push(glyph);
+    }
+    if gpu_labels.glyphs.is_empty() {
+        gpu_labels.bind_group = None;
+        return;
+    }
+    gpu_labels
+        .glyphs
+        .write_buffer(&render_device, &render_queue);
+
+    let (atlas_view, atlas_sampler) = gpu_labels.atlas.as_ref().unwrap();
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("labels_bind_group"),
+        layout: &labels_pipeline.data_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: gpu_labels.glyphs.buffer().unwrap().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(atlas_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(atlas_sampler),
+            },
+        ],
+    });
+    gpu_labels.bind_group = Some(bind_group);
+}
+
+// QUEUE:
+// Labels share the quads phase, and sort after the geometry they annotate. Like
+// quads, labels on layers hidden in a view are left out of its phase.
+fn queue_labels(
+    draw_functions: Res<DrawFunctions<QuadsPhaseItem>>,
+    gpu_labels: Res<GpuLabels>,
+    mut views: Query<(&mut RenderPhase<QuadsPhaseItem>, Option<&LayerVisibility>)>,
+    batches: Query<(Entity, &LabelsBatch)>,
+) {
+    if gpu_labels.bind_group.is_none() {
+        return;
+    }
+    let draw_labels = draw_functions.read().get_id::<DrawLabels>().unwrap();
+
+    for (mut phase, layers) in views.iter_mut() {
+        for (entity, batch) in batches.iter() {
+            if layers.is_some_and(|layers| !layers.is_visible(batch.layer)) {
+                continue;
+            }
+            phase.add(QuadsPhaseItem {
+                entity,
+                draw_function: draw_labels,
+                sort_key: LABELS_SORT_KEY,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(text: &str, anchor: LabelAnchor, rotation: f32) -> Label {
+        Label {
+            text: text.into(),
+            position: Point { x: 10.0, y: 20.0 },
+            anchor,
+            rotation,
+            layer: 0,
+            // one world unit per glyph pixel
+            height: GLYPH_ROWS as f32,
+            color: Color::WHITE,
+        }
+    }
+
+    fn glyphs(label: &Label) -> Vec<GpuGlyph> {
+        let mut glyphs = Vec::new();
+        layout_label(label, &LabelSettings::default(), &mut glyphs);
+        glyphs
+    }
+
+    #[test]
+    fn glyphs_are_laid_out_along_the_text() {
+        let glyphs = glyphs(&label("AB C", LabelAnchor::BottomLeft, 0.0));
+        // no glyph for the space, but it still takes its place
+        assert_eq!(glyphs.len(), 3);
+        let xs: Vec<f32> = glyphs.iter().map(|glyph| glyph.origin.x).collect();
+        let first = 10.0 - PAD;
+        assert_eq!(
+            xs,
+            vec![first, first + GLYPH_ADVANCE, first + 3.0 * GLYPH_ADVANCE]
+        );
+        assert!(glyphs.iter().all(|glyph| glyph.origin.y == 20.0 - PAD));
+        let cell = Vec2::new(GLYPH_COLUMNS as f32, GLYPH_ROWS as f32) + 2.0 * PAD;
+        assert_eq!(glyphs[0].x_axis, Vec2::new(cell.x, 0.0));
+        assert_eq!(glyphs[0].y_axis, Vec2::new(0.0, cell.y));
+        assert_ne!(glyphs[0].uv_min, glyphs[1].uv_min);
+        assert_eq!(glyphs[0].color, Color::WHITE.as_rgba_u32());
+    }
+
+    #[test]
+    fn anchors_and_rotation_move_the_text_around_its_position() {
+        // "AB" is 11 glyph pixels wide: two glyphs and the gap between them
+        let width = 2.0 * GLYPH_ADVANCE - 1.0;
+        let height = GLYPH_ROWS as f32;
+        for (anchor, offset) in [
+            (LabelAnchor::Center, Vec2::new(-width / 2.0, -height / 2.0)),
+            (LabelAnchor::TopRight, Vec2::new(-width, -height)),
+            (LabelAnchor::Left, Vec2::new(0.0, -height / 2.0)),
+        ] {
+            let glyphs = glyphs(&label("AB", anchor, 0.0));
+            assert_eq!(glyphs[0].origin, Vec2::new(10.0, 20.0) + offset - PAD);
+        }
+
+        // a quarter turn counter-clockwise runs the text upwards
+        let glyphs = glyphs(&label(
+            "AB",
+            LabelAnchor::BottomLeft,
+            std::f32::consts::FRAC_PI_2,
+        ));
+        let step = glyphs[1].origin - glyphs[0].origin;
+        assert!(
+            step.abs_diff_eq(Vec2::new(0.0, GLYPH_ADVANCE), 1e-5),
+            "{}",
+            step
+        );
+        assert!(glyphs[0].x_axis.x.abs() < 1e-5 && glyphs[0].x_axis.y > 0.0);
+    }
+
+    #[test]
+    fn labels_too_small_to_read_are_hidden() {
+        assert!(glyphs(&label("", LabelAnchor::BottomLeft, 0.0)).is_empty());
+        let flat = Label {
+            height: 0.0,
+            ..label("A", LabelAnchor::BottomLeft, 0.0)
+        };
+        assert!(glyphs(&flat).is_empty());
+
+        // as the shader does: a glyph is hidden when its cell is shorter on screen
+        // than `hide_below`, i.e. when its capitals are under the minimum height
+        let min_pixel_height = LabelSettings::default().min_pixel_height;
+        let label = Label {
+            height: 0.5,
+            ..label("A", LabelAnchor::BottomLeft, 0.0)
+        };
+        let glyph = glyphs(&label)[0];
+        let hidden =
+            |world_per_pixel: f32| glyph.y_axis.length() / world_per_pixel < glyph.hide_below;
+        let legible = label.height / min_pixel_height;
+        assert!(!hidden(legible * 0.99));
+        assert!(hidden(legible * 1.01));
+    }
+
+    #[test]
+    fn glyphs_are_grouped_by_layer() {
+        let mut world = World::new();
+        world.insert_resource(LabelSettings::default());
+        world.insert_resource(LabelGlyphs::default());
+        for (text, layer) in [("AB", 2), ("C", 0), ("", 1), ("DEF", 2)] {
+            world.spawn().insert(Label {
+                layer,
+                ..label(text, LabelAnchor::BottomLeft, 0.0)
+            });
+        }
+        let mut stage = SystemStage::single_threaded().with_system(layout_labels);
+        stage.run(&mut world);
+
+        // a layer without glyphs gets no batch
+        let label_glyphs = world.resource::<LabelGlyphs>();
+        assert_eq!(label_glyphs.layers, vec![(0, 0..1), (2, 1..6)]);
+        assert_eq!(label_glyphs.data.len(), 6);
+    }
+}
@@ -0,0 +1,132 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::PrimitiveTopology,
+        render_resource::{
+            std140::AsStd140, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingType, BlendState, BufferBindingType, BufferSize, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState,
+            PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor,
+            SamplerBindingType, ShaderStages, TextureFormat, TextureSampleType,
+            TextureViewDimension, VertexState,
+        },
+        renderer::RenderDevice,
+        texture::BevyDefault,
+        view::ViewUniform,
+    },
+};
+
+pub struct LabelsPipeline {
+    pub pipeline_id: CachedRenderPipelineId,
+    pub data_layout: BindGroupLayout,
+}
+
+pub const LABELS_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 3818273506195463174);
+
+impl FromWorld for LabelsPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let view_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    entries: &[
+                        // View
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: BufferSize::new(
+                                    ViewUniform::std140_size_static() as u64,
+                                ),
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("labels_view_layout"),
+                });
+
+        let data_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("labels_data_layout"),
+                    entries: &[
+                        // Glyphs
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::VERTEX,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(0),
+                            },
+                            count: None,
+                        },
+                        // SDF atlas
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::FRAGMENT,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: true },
+                                view_dimension: TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::FRAGMENT,
+                            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("labels_pipeline".into()),
+            layout: Some(vec![view_layout, data_layout.clone()]),
+            vertex: VertexState {
+                shader: LABELS_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader: LABELS_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                // rotated or mirrored labels must not be culled
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: Msaa::default().samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        });
+
+        Self {
+            pipeline_id,
+            data_layout,
+        }
+    }
+}
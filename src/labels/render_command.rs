@@ -0,0 +1,92 @@
+use bevy::{
+    ecs::system::{
+        lifetimeless::{Read, SQuery, SRes},
+        SystemParamItem,
+    },
+    pbr::SetShadowViewBindGroup,
+    prelude::Entity,
+    render::{
+        render_phase::{
+            EntityRenderCommand, PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass,
+        },
+        render_resource::PipelineCache,
+    },
+};
+
+use crate::gpu_data::GpuLabels;
+
+use super::pipeline::LabelsPipeline;
+use super::LabelsBatch;
+
+pub type DrawLabels = (
+    SetLabelsPipeline,
+    SetShadowViewBindGroup<0>,
+    SetLabelsBindGroup<1>,
+    DrawLabelGlyphs,
+);
+
+pub struct SetLabelsPipeline;
+impl<P: PhaseItem> RenderCommand<P> for SetLabelsPipeline {
+    type Param = (SRes<PipelineCache>, SRes<LabelsPipeline>);
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        _item: &P,
+        params: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let (pipeline_cache, labels_pipeline) = params;
+        if let Some(pipeline) = pipeline_cache
+            .into_inner()
+            .get_render_pipeline(labels_pipeline.pipeline_id)
+        {
+            pass.set_render_pipeline(pipeline);
+            RenderCommandResult::Success
+        } else {
+            RenderCommandResult::Failure
+        }
+    }
+}
+
+pub struct SetLabelsBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetLabelsBindGroup<I> {
+    type Param = SRes<GpuLabels>;
+
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        _item: Entity,
+        gpu_labels: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        match gpu_labels.into_inner().bind_group.as_ref() {
+            Some(bind_group) => {
+                pass.set_bind_group(I, bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
+    }
+}
+
+// Draws the glyphs of the item's layer.
+pub struct DrawLabelGlyphs;
+impl EntityRenderCommand for DrawLabelGlyphs {
+    type Param = SQuery<Read<LabelsBatch>>;
+
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        batches: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        match batches.get_inner(item) {
+            Ok(batch) => {
+                pass.draw(batch.glyphs.start * 6..batch.glyphs.end * 6, 0..1);
+                RenderCommandResult::Success
+            }
+            Err(_) => RenderCommandResult::Failure,
+        }
+    }
+}
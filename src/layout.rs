@@ -0,0 +1,898 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::attributes::{QuadAttributes, ShapeAttributes};
+use crate::binary::read_binary;
+use crate::bounds::Bounds;
+use crate::gds::{FlatShape, GdsLibrary, Justification};
+use crate::geometry::DerivedLayer;
+use crate::json::{read_shapes, shapes_layout};
+use crate::labels::LabelAnchor;
+use crate::{DRect, LayerRects, Point};
+
+// A GDS layer and datatype, written "layer/datatype" as in "1/0".
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LayerKey {
+    pub layer: i16,
+    pub datatype: i16,
+}
+
+impl fmt::Display for LayerKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.layer, self.datatype)
+    }
+}
+
+impl FromStr for LayerKey {
+    type Err = String;
+
+    // "1/0", or "1" for datatype 0
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (layer, datatype) = s.split_once('/').unwrap_or((s, "0"));
+        let parse = |part: &str| {
+            part.trim()
+                .parse::<i16>()
+                .map_err(|_| format!("bad layer {:?}, expected layer/datatype", s))
+        };
+        Ok(LayerKey {
+            layer: parse(layer)?,
+            datatype: parse(datatype)?,
+        })
+    }
+}
+
+// A text from the layout, e.g. a pin or net name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutText {
+    pub text: String,
+    pub position: Point,
+    // index into `Layout::layers`
+    pub layer: u8,
+    // height of a capital letter in world units
+    pub height: f32,
+    // where `position` sits on the text
+    pub anchor: LabelAnchor,
+    // counter-clockwise, in radians, around `position`
+    pub rotation: f32,
+}
+
+// A layout flattened to rects, one `LayerRects` per layer, in draw order.
+// World units are the file's user units (usually microns).
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    pub name: String,
+    pub layers: Vec<LayerRects>,
+    // the file's layer for each entry of `layers`
+    pub layer_keys: Vec<LayerKey>,
+    pub texts: Vec<LayoutText>,
+    // polygons that are not made of horizontal and vertical edges
+    pub skipped_polygons: usize,
+    // size of the file's database unit in world units, for files that have one
+    pub db_unit: Option<f32>,
+}
+
+// Which part of a file to load.
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    // defaults to the only top cell
+    pub cell: Option<String>,
+    // layers to keep, in draw order; all layers if empty
+    pub layers: Vec<LayerKey>,
+    // shapes are clipped to this box
+    pub bbox: Option<Bounds>,
+    // outline width of every rect, in world units
+    pub stroke_width: f32,
+    // layers computed from the loaded ones, added after them
+    pub derived: Vec<DerivedLayer>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    UnknownFormat(PathBuf),
+    NoSuchCell(String),
+    // the file has several top cells and none was picked
+    AmbiguousTopCell(Vec<String>),
+    TooManyLayers(usize),
+    // a derived layer's input is not loaded
+    NoSuchLayer(LayerKey),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::UnknownFormat(path) => {
+                write!(f, "don't know how to read {}", path.display())
+            }
+            LoadError::NoSuchCell(cell) => write!(f, "no cell named {}", cell),
+            LoadError::AmbiguousTopCell(cells) => write!(
+                f,
+                "the file has several top cells ({}), pick one with --cell",
+                cells.join(", ")
+            ),
+            LoadError::TooManyLayers(count) => {
+                write!(f, "{} layers, at most 256 can be shown", count)
+            }
+            LoadError::NoSuchLayer(key) => write!(f, "no layer {} to derive from", key),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+impl Layout {
+    // Reads a layout file; the format is picked from the extension.
+    pub fn load(path: &Path, options: &LoadOptions) -> Result<Layout, LoadError> {
+        let mut layout = match extension(path).as_str() {
+            "gds" | "gds2" | "gdsii" => {
+                let library = GdsLibrary::read(BufReader::new(File::open(path)?))?;
+                Layout::from_gds(&library, options)?
+            }
+            "json" | "ndjson" | "jsonl" => {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                shapes_layout(&name, &read_shapes(path)?, options)?
+            }
+            "drb" => {
+                let mut layout = read_binary(path)?;
+                layout.keep_layers(&options.layers)?;
+                if let Some(bbox) = options.bbox {
+                    layout.clip(bbox);
+                }
+                layout
+            }
+            _ => return Err(LoadError::UnknownFormat(path.to_owned())),
+        };
+        layout.derive_layers(&options.derived, options.stroke_width)?;
+        Ok(layout)
+    }
+
+    pub fn from_gds(library: &GdsLibrary, options: &LoadOptions) -> Result<Layout, LoadError> {
+        let cell = match &options.cell {
+            Some(name) => library
+                .cell(name)
+                .ok_or_else(|| LoadError::NoSuchCell(name.clone()))?,
+            None => match library.top_cells().as_slice() {
+                [cell] => *cell,
+                [] => return Err(LoadError::NoSuchCell("(top cell)".into())),
+                cells => {
+                    return Err(LoadError::AmbiguousTopCell(
+                        cells.iter().map(|cell| cell.name.clone()).collect(),
+                    ))
+                }
+            },
+        };
+        let scale = library.user_units_per_db_unit;
+        let to_world = |(x, y): (f64, f64)| Point {
+            x: (x * scale) as f32,
+            y: (y * scale) as f32,
+        };
+
+        let mut rects: BTreeMap<LayerKey, Vec<DRect>> = BTreeMap::new();
+        let mut attributes: BTreeMap<LayerKey, QuadAttributes> = BTreeMap::new();
+        let mut texts = Vec::new();
+        let mut skipped_polygons = 0;
+        let wanted = |key: &LayerKey| options.layers.is_empty() || options.layers.contains(key);
+        for shape in library.flatten(cell)? {
+            match shape {
+                FlatShape::Polygon {
+                    layer,
+                    datatype,
+                    points,
+                    instance,
+                    properties,
+                } => {
+                    let key = LayerKey { layer, datatype };
+                    if !wanted(&key) {
+                        continue;
+                    }
+                    let points: Vec<Point> = points.into_iter().map(to_world).collect();
+                    match manhattan_polygon_to_rects(&points) {
+                        Some(polygon_rects) => {
+                            let shape = ShapeAttributes {
+                                net: None,
+                                instance: Some(instance.to_string()),
+                                properties,
+                            };
+                            attributes
+                                .entry(key)
+                                .or_default()
+                                .push(Some(shape), polygon_rects.len());
+                            rects.entry(key).or_default().extend(polygon_rects);
+                        }
+                        None => skipped_polygons += 1,
+                    }
+                }
+                FlatShape::Text {
+                    layer,
+                    texttype,
+                    text,
+                    origin,
+                    mag,
+                    rotation,
+                    justification,
+                } => {
+                    let key = LayerKey {
+                        layer,
+                        datatype: texttype,
+                    };
+                    if wanted(&key) {
+                        // MAG is the height in user units, so a TEXT without one is
+                        // a user unit (usually a micron) tall
+                        let height = mag as f32;
+                        let anchor = text_anchor(justification);
+                        let rotation = rotation as f32;
+                        texts.push((key, text, to_world(origin), height, anchor, rotation));
+                    }
+                }
+            }
+        }
+
+        // requested layers keep their order; the others are sorted by number
+        let mut layer_keys: Vec<LayerKey> = if options.layers.is_empty() {
+            rects.keys().copied().collect()
+        } else {
+            options.layers.clone()
+        };
+        for (key, ..) in texts.iter() {
+            if !layer_keys.contains(key) {
+                layer_keys.push(*key);
+            }
+        }
+        if layer_keys.len() > 256 {
+            return Err(LoadError::TooManyLayers(layer_keys.len()));
+        }
+
+        let mut layout = Layout {
+            name: cell.name.clone(),
+            skipped_polygons,
+            db_unit: Some(scale as f32),
+            ..Default::default()
+        };
+        for (index, key) in layer_keys.iter().enumerate() {
+            let mut layer_rects = rects.remove(key).unwrap_or_default();
+            for rect in layer_rects.iter_mut() {
+                rect.color = index as u32 % 5;
+                rect.stroke_width = options.stroke_width;
+            }
+            layout.layers.push(LayerRects {
+                rects: layer_rects,
+                index: index as u8,
+                attributes: attributes.remove(key),
+            });
+        }
+        layout.layer_keys = layer_keys;
+        for (key, text, position, height, anchor, rotation) in texts {
+            let layer = layout.layer_keys.iter().position(|k| *k == key).unwrap();
+            layout.texts.push(LayoutText {
+                text,
+                position,
+                layer: layer as u8,
+                height,
+                anchor,
+                rotation,
+            });
+        }
+        if let Some(bbox) = options.bbox {
+            layout.clip(bbox);
+        }
+        Ok(layout)
+    }
+
+    // Computes derived layers in order and adds them on top. One with the key of an
+    // existing layer replaces it.
+    pub fn derive_layers(
+        &mut self,
+        derived: &[DerivedLayer],
+        stroke_width: f32,
+    ) -> Result<(), LoadError> {
+        for layer in derived {
+            if let Some(key) = layer
+                .inputs()
+                .into_iter()
+                .find(|key| !self.layer_keys.contains(key))
+            {
+                return Err(LoadError::NoSuchLayer(key));
+            }
+            let mut rects = layer.compute(|key| {
+                let index = self.layer_keys.iter().position(|k| *k == key).unwrap();
+                &self.layers[index].rects
+            });
+            let index = match self.layer_keys.iter().position(|k| *k == layer.key) {
+                Some(index) => index,
+                None if self.layers.len() < 256 => {
+                    self.layers.push(LayerRects {
+                        rects: Vec::new(),
+                        index: self.layers.len() as u8,
+                        attributes: None,
+                    });
+                    self.layer_keys.push(layer.key);
+                    self.layers.len() - 1
+                }
+                None => return Err(LoadError::TooManyLayers(self.layers.len() + 1)),
+            };
+            for rect in rects.iter_mut() {
+                rect.color = index as u32 % 5;
+                rect.stroke_width = stroke_width;
+            }
+            self.layers[index].rects = rects;
+            self.layers[index].attributes = None;
+        }
+        Ok(())
+    }
+
+    // Moves layers to the bottom of the draw order in the order of `order`, where a
+    // key that appears n times stands for the first n layers with that key. The
+    // other layers keep their order above them; keys without a layer are ignored.
+    pub fn reorder_layers(&mut self, order: &[LayerKey]) {
+        let mut indices: Vec<usize> = Vec::with_capacity(self.layers.len());
+        for key in order {
+            if let Some(i) =
+                (0..self.layers.len()).find(|i| self.layer_keys[*i] == *key && !indices.contains(i))
+            {
+                indices.push(i);
+            }
+        }
+        let rest: Vec<usize> = (0..self.layers.len())
+            .filter(|i| !indices.contains(i))
+            .collect();
+        indices.extend(rest);
+
+        let mut new_index = vec![0u8; self.layers.len()];
+        for (new, old) in indices.iter().enumerate() {
+            new_index[*old] = new as u8;
+        }
+        let mut layers = std::mem::take(&mut self.layers);
+        self.layers = indices
+            .iter()
+            .map(|i| LayerRects {
+                rects: std::mem::take(&mut layers[*i].rects),
+                index: new_index[*i],
+                attributes: layers[*i].attributes.take(),
+            })
+            .collect();
+        self.layer_keys = indices.iter().map(|i| self.layer_keys[*i]).collect();
+        for text in self.texts.iter_mut() {
+            text.layer = new_index[text.layer as usize];
+        }
+    }
+
+    // Keeps the layers of `keys` in that order, as loading a GDS file with them
+    // does: keys without a layer get an empty one. All layers are kept if `keys` is
+    // empty.
+    pub fn keep_layers(&mut self, keys: &[LayerKey]) -> Result<(), LoadError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut wanted: Vec<LayerKey> = Vec::with_capacity(keys.len());
+        for key in keys {
+            if !wanted.contains(key) {
+                wanted.push(*key);
+            }
+        }
+        if wanted.len() > 256 {
+            return Err(LoadError::TooManyLayers(wanted.len()));
+        }
+        for key in wanted.iter() {
+            if !self.layer_keys.contains(key) {
+                self.layers.push(LayerRects::default());
+                self.layer_keys.push(*key);
+            }
+        }
+        // layers past 256 are dropped below, so their index doesn't matter
+        self.reorder_layers(&wanted);
+        let count = wanted.len();
+        self.layers.truncate(count);
+        self.layer_keys.truncate(count);
+        self.texts.retain(|text| (text.layer as usize) < count);
+        Ok(())
+    }
+
+    // The index of a layer, added on top if it doesn't exist.
+    pub fn layer_or_add(&mut self, key: LayerKey) -> Result<usize, String> {
+        if let Some(index) = self.layer_keys.iter().position(|k| *k == key) {
+            return Ok(index);
+        }
+        let index = self.layers.len();
+        if index > u8::MAX as usize {
+            return Err(format!("no room for layer {}", key));
+        }
+        self.layers.push(LayerRects {
+            index: index as u8,
+            ..Default::default()
+        });
+        self.layer_keys.push(key);
+        Ok(index)
+    }
+
+    // A single-layer layout, for generated scenes.
+    pub fn from_rects(name: &str, rects: Vec<DRect>) -> Layout {
+        Layout {
+            name: name.into(),
+            layers: vec![LayerRects {
+                rects,
+                index: 0,
+                attributes: None,
+            }],
+            layer_keys: vec![LayerKey::default()],
+            ..Default::default()
+        }
+    }
+
+    // Drops everything outside of `bbox` and cuts rects that cross it.
+    pub fn clip(&mut self, bbox: Bounds) {
+        for layer in self.layers.iter_mut() {
+            let mut kept = Vec::with_capacity(layer.rects.len());
+            layer.rects.retain_mut(|rect| {
+                let r = Bounds::from_rect(rect);
+                let (min, max) = (r.min.max(bbox.min), r.max.min(bbox.max));
+                let keep = min.x < max.x && min.y < max.y;
+                kept.push(keep);
+                if keep {
+                    rect.p0 = Point { x: min.x, y: min.y };
+                    rect.p1 = Point { x: max.x, y: max.y };
+                }
+                keep
+            });
+            if let Some(attributes) = layer.attributes.as_mut() {
+                attributes.retain(|i| kept[i]);
+            }
+        }
+        self.texts.retain(|text| {
+            let p = Vec2::new(text.position.x, text.position.y);
+            p.cmpge(bbox.min).all() && p.cmple(bbox.max).all()
+        });
+    }
+
+    pub fn layer(&self, key: LayerKey) -> Option<&LayerRects> {
+        let index = self.layer_keys.iter().position(|k| *k == key)?;
+        self.layers.get(index)
+    }
+
+    pub fn rect_count(&self) -> usize {
+        self.layers.iter().map(|layer| layer.rects.len()).sum()
+    }
+
+    pub fn bounds(&self) -> Option<Bounds> {
+        Bounds::from_rects(self.layers.iter().flat_map(|layer| layer.rects.iter()))
+    }
+}
+
+// Texts without a PRESENTATION record keep their origin at the bottom left.
+fn text_anchor(justification: Option<Justification>) -> LabelAnchor {
+    let justification = match justification {
+        Some(justification) => justification,
+        None => return LabelAnchor::BottomLeft,
+    };
+    match (justification.vertical, justification.horizontal) {
+        (0, 0) => LabelAnchor::TopLeft,
+        (0, 1) => LabelAnchor::Top,
+        (0, _) => LabelAnchor::TopRight,
+        (1, 0) => LabelAnchor::Left,
+        (1, 1) => LabelAnchor::Center,
+        (1, _) => LabelAnchor::Right,
+        (_, 0) => LabelAnchor::BottomLeft,
+        (_, 1) => LabelAnchor::Bottom,
+        (_, _) => LabelAnchor::BottomRight,
+    }
+}
+
+// Cuts a polygon made of horizontal and vertical edges into rects: the polygon is
+// sliced into horizontal bands at every vertex, each band is filled with the
+// even-odd rule, and equal spans of neighbouring bands are merged. Returns None for
+// polygons with slanted edges.
+pub fn manhattan_polygon_to_rects(points: &[Point]) -> Option<Vec<DRect>> {
+    let mut points = points.to_vec();
+    if points.len() > 1 && points.first().map(|p| (p.x, p.y)) == points.last().map(|p| (p.x, p.y)) {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return Some(Vec::new());
+    }
+    let edges: Vec<(Point, Point)> = (0..points.len())
+        .map(|i| (points[i], points[(i + 1) % points.len()]))
+        .collect();
+    if edges.iter().any(|(a, b)| a.x != b.x && a.y != b.y) {
+        return None;
+    }
+
+    let mut ys: Vec<f32> = points.iter().map(|p| p.y).collect();
+    ys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ys.dedup();
+
+    let mut rects: Vec<DRect> = Vec::new();
+    // rects that end at the top of the previous band, by span
+    let mut open: Vec<usize> = Vec::new();
+    for band in ys.windows(2) {
+        let (y0, y1) = (band[0], band[1]);
+        let mid = (y0 + y1) * 0.5;
+        let mut crossings: Vec<f32> = edges
+            .iter()
+            .filter(|(a, b)| a.x == b.x && (a.y.min(b.y) < mid) && (a.y.max(b.y) > mid))
+            .map(|(a, _)| a.x)
+            .collect();
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let mut next_open = Vec::new();
+        for span in crossings.chunks_exact(2) {
+            let (x0, x1) = (span[0], span[1]);
+            if x0 == x1 {
+                continue;
+            }
+            let continued = open.iter().copied().find(|i| {
+                let rect: &DRect = &rects[*i];
+                rect.p0.x == x0 && rect.p1.x == x1 && rect.p1.y == y0
+            });
+            match continued {
+                Some(i) => {
+                    rects[i].p1.y = y1;
+                    next_open.push(i);
+                }
+                None => {
+                    rects.push(DRect {
+                        p0: Point { x: x0, y: y0 },
+                        p1: Point { x: x1, y: y1 },
+                        ..Default::default()
+                    });
+                    next_open.push(rects.len() - 1);
+                }
+            }
+        }
+        open = next_open;
+    }
+    Some(rects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bounds, layer, rect};
+
+    fn polygon(points: &[(f32, f32)]) -> Vec<Point> {
+        points.iter().map(|&(x, y)| Point { x, y }).collect()
+    }
+
+    fn text(text: &str, x: f32, layer: u8) -> LayoutText {
+        LayoutText {
+            text: text.into(),
+            position: Point { x, y: 0.5 },
+            layer,
+            height: 1.0,
+            anchor: LabelAnchor::BottomLeft,
+            rotation: 0.0,
+        }
+    }
+
+    // one 1 x 1 rect per layer, at x = layer
+    fn layered(keys: &[LayerKey]) -> Layout {
+        Layout {
+            layers: (0..keys.len())
+                .map(|i| LayerRects {
+                    rects: vec![rect(i as f32, 0.0, i as f32 + 1.0, 1.0)],
+                    index: i as u8,
+                    attributes: None,
+                })
+                .collect(),
+            layer_keys: keys.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn layer_keys_are_parsed() {
+        assert_eq!(
+            "1/0".parse(),
+            Ok(LayerKey {
+                layer: 1,
+                datatype: 0
+            })
+        );
+        assert_eq!("7".parse(), Ok(layer(7)));
+        assert_eq!(
+            " -2 / 5 ".parse(),
+            Ok(LayerKey {
+                layer: -2,
+                datatype: 5
+            })
+        );
+        for bad in ["", "a/0", "1/", "1/2/3", "40000/0"] {
+            let e = bad.parse::<LayerKey>().unwrap_err();
+            assert_eq!(e, format!("bad layer {:?}, expected layer/datatype", bad));
+        }
+        assert_eq!(
+            LayerKey {
+                layer: 3,
+                datatype: 1
+            }
+            .to_string(),
+            "3/1"
+        );
+    }
+
+    #[test]
+    fn manhattan_polygons_are_cut_into_rects() {
+        let l = polygon(&[
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]);
+        assert_eq!(
+            manhattan_polygon_to_rects(&l),
+            Some(vec![rect(0.0, 0.0, 2.0, 1.0), rect(0.0, 1.0, 1.0, 2.0)])
+        );
+
+        let u = polygon(&[
+            (0.0, 0.0),
+            (3.0, 0.0),
+            (3.0, 2.0),
+            (2.0, 2.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]);
+        assert_eq!(
+            manhattan_polygon_to_rects(&u),
+            Some(vec![
+                rect(0.0, 0.0, 3.0, 1.0),
+                rect(0.0, 1.0, 1.0, 2.0),
+                rect(2.0, 1.0, 3.0, 2.0),
+            ])
+        );
+
+        // closed, with a vertex halfway up a side: the two bands are merged
+        let square = polygon(&[
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 2.0),
+            (0.0, 2.0),
+            (0.0, 1.0),
+            (0.0, 0.0),
+        ]);
+        assert_eq!(
+            manhattan_polygon_to_rects(&square),
+            Some(vec![rect(0.0, 0.0, 2.0, 2.0)])
+        );
+
+        let slanted = polygon(&[(0.0, 0.0), (2.0, 0.0), (1.0, 1.0)]);
+        assert_eq!(manhattan_polygon_to_rects(&slanted), None);
+        let line = polygon(&[(0.0, 0.0), (2.0, 0.0), (0.0, 0.0)]);
+        assert_eq!(manhattan_polygon_to_rects(&line), Some(Vec::new()));
+    }
+
+    #[test]
+    fn clipping_keeps_attributes_with_their_rects() {
+        let net = |name: &str| {
+            Some(ShapeAttributes {
+                net: Some(name.into()),
+                ..Default::default()
+            })
+        };
+        let mut attributes = QuadAttributes::default();
+        for name in ["outside", "crossing", "inside"] {
+            attributes.push(net(name), 1);
+        }
+        let mut layout = Layout {
+            layers: vec![LayerRects {
+                rects: vec![
+                    rect(-5.0, -5.0, -4.0, -4.0),
+                    rect(0.0, 0.0, 2.0, 2.0),
+                    rect(3.0, 3.0, 4.0, 4.0),
+                ],
+                index: 0,
+                attributes: Some(attributes),
+            }],
+            layer_keys: vec![layer(1)],
+            texts: vec![text("in", 1.0, 0), text("out", -1.0, 0)],
+            ..Default::default()
+        };
+        layout.clip(bounds(0.5, 0.5, 10.0, 10.0));
+
+        let layer = &layout.layers[0];
+        assert_eq!(
+            layer.rects,
+            vec![rect(0.5, 0.5, 2.0, 2.0), rect(3.0, 3.0, 4.0, 4.0)]
+        );
+        let attributes = layer.attributes.as_ref().unwrap();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes.get(0).unwrap().net.as_deref(), Some("crossing"));
+        assert_eq!(attributes.get(1).unwrap().net.as_deref(), Some("inside"));
+        let texts: Vec<&str> = layout.texts.iter().map(|text| text.text.as_str()).collect();
+        assert_eq!(texts, vec!["in"]);
+    }
+
+    #[test]
+    fn kept_layers_come_in_the_order_asked_for() {
+        let mut layout = layered(&[layer(1), layer(2), layer(3)]);
+        layout.texts = vec![
+            text("one", 0.5, 0),
+            text("two", 1.5, 1),
+            text("three", 2.5, 2),
+        ];
+        layout
+            .keep_layers(&[layer(3), layer(1), layer(3), layer(5)])
+            .unwrap();
+
+        assert_eq!(layout.layer_keys, vec![layer(3), layer(1), layer(5)]);
+        let indices: Vec<u8> = layout.layers.iter().map(|layer| layer.index).collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+        assert_eq!(layout.layers[0].rects, vec![rect(2.0, 0.0, 3.0, 1.0)]);
+        assert_eq!(layout.layers[1].rects, vec![rect(0.0, 0.0, 1.0, 1.0)]);
+        assert!(layout.layers[2].rects.is_empty());
+        // texts follow their layers, and go with the layers left out
+        let texts: Vec<(&str, u8)> = layout
+            .texts
+            .iter()
+            .map(|text| (text.text.as_str(), text.layer))
+            .collect();
+        assert_eq!(texts, vec![("one", 1), ("three", 0)]);
+
+        let mut all = layered(&[layer(1), layer(2)]);
+        all.keep_layers(&[]).unwrap();
+        assert_eq!(all.layer_keys, vec![layer(1), layer(2)]);
+    }
+
+    #[test]
+    fn reordered_layers_go_to_the_bottom_in_order() {
+        // the same key twice stands for both of its layers, first one first
+        let mut layout = layered(&[layer(1), layer(2), layer(1), layer(3)]);
+        layout.texts = vec![text("second 1", 2.5, 2)];
+        layout.reorder_layers(&[layer(3), layer(1), layer(9), layer(1)]);
+
+        assert_eq!(
+            layout.layer_keys,
+            vec![layer(3), layer(1), layer(1), layer(2)]
+        );
+        let xs: Vec<f32> = layout
+            .layers
+            .iter()
+            .map(|layer| layer.rects[0].p0.x)
+            .collect();
+        assert_eq!(xs, vec![3.0, 0.0, 2.0, 1.0]);
+        let indices: Vec<u8> = layout.layers.iter().map(|layer| layer.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3]);
+        assert_eq!(layout.texts[0].layer, 2);
+    }
+
+    // A GDSII record: length, kind, data type (ignored when reading) and data.
+    fn record(bytes: &mut Vec<u8>, kind: u8, data: &[u8]) {
+        bytes.extend_from_slice(&((4 + data.len()) as u16).to_be_bytes());
+        bytes.extend_from_slice(&[kind, 0]);
+        bytes.extend_from_slice(data);
+    }
+
+    // A positive GDSII 8-byte real.
+    fn real(value: f64) -> [u8; 8] {
+        let exponent = value.log(16.0).floor() as i32 + 1;
+        let mantissa = (value / 16f64.powi(exponent) * 2f64.powi(56)).round() as u64;
+        let mut bytes = mantissa.to_be_bytes();
+        bytes[0] = (exponent + 64) as u8;
+        bytes
+    }
+
+    #[test]
+    fn texts_without_mag_are_a_user_unit_tall() {
+        let mut gds = Vec::new();
+        record(&mut gds, 0x00, &600i16.to_be_bytes()); // HEADER
+        record(&mut gds, 0x01, &[0; 24]); // BGNLIB
+        let units = [real(0.001), real(1e-9)].concat();
+        record(&mut gds, 0x03, &units); // UNITS
+        record(&mut gds, 0x05, &[0; 24]); // BGNSTR
+        record(&mut gds, 0x06, b"TOP\0"); // STRNAME
+        record(&mut gds, 0x0c, &[]); // TEXT
+        record(&mut gds, 0x0d, &3i16.to_be_bytes()); // LAYER
+        record(&mut gds, 0x16, &0i16.to_be_bytes()); // TEXTTYPE
+        record(
+            &mut gds,
+            0x10,
+            &[2000i32.to_be_bytes(), 500i32.to_be_bytes()].concat(),
+        ); // XY
+        record(&mut gds, 0x19, b"VDD\0"); // STRING
+        record(&mut gds, 0x11, &[]); // ENDEL
+        record(&mut gds, 0x07, &[]); // ENDSTR
+        record(&mut gds, 0x04, &[]); // ENDLIB
+
+        let library = GdsLibrary::parse(&gds).unwrap();
+        let layout = Layout::from_gds(&library, &LoadOptions::default()).unwrap();
+        let text = &layout.texts[0];
+        assert_eq!(text.text, "VDD");
+        assert_eq!((text.position.x, text.position.y), (2.0, 0.5));
+        assert_eq!(text.height, 1.0);
+    }
+
+    #[test]
+    fn texts_turn_with_the_references_above_them() {
+        use crate::gds::{GdsCell, GdsElement, Strans};
+        use std::f32::consts::{FRAC_PI_2, PI};
+
+        let turned = |angle, reflect| Strans {
+            reflect,
+            mag: 1.0,
+            angle,
+        };
+        let library = GdsLibrary {
+            user_units_per_db_unit: 0.001,
+            cells: vec![
+                GdsCell {
+                    name: "PIN".into(),
+                    elements: vec![GdsElement::Text {
+                        layer: 1,
+                        texttype: 0,
+                        text: "A".into(),
+                        origin: (1000, 0),
+                        strans: Strans {
+                            mag: 2.0,
+                            ..turned(90.0, false)
+                        },
+                        justification: None,
+                    }],
+                },
+                GdsCell {
+                    name: "TOP".into(),
+                    elements: vec![
+                        GdsElement::Sref {
+                            cell: "PIN".into(),
+                            origin: (100_000, 0),
+                            strans: turned(90.0, false),
+                        },
+                        // mirrored, the text reads downwards
+                        GdsElement::Sref {
+                            cell: "PIN".into(),
+                            origin: (0, 0),
+                            strans: turned(0.0, true),
+                        },
+                    ],
+                },
+            ],
+            ..Default::default()
+        };
+        let layout = Layout::from_gds(&library, &LoadOptions::default()).unwrap();
+        let placed: Vec<(f32, f32, f32, f32)> = layout
+            .texts
+            .iter()
+            .map(|text| (text.position.x, text.position.y, text.rotation, text.height))
+            .collect();
+        assert_eq!(
+            placed,
+            vec![(100.0, 1.0, PI, 2.0), (1.0, 0.0, -FRAC_PI_2, 2.0)]
+        );
+    }
+
+    #[test]
+    fn text_justification_picks_the_anchor() {
+        let anchor = |horizontal, vertical| {
+            text_anchor(Some(Justification {
+                horizontal,
+                vertical,
+            }))
+        };
+        assert_eq!(text_anchor(None), LabelAnchor::BottomLeft);
+        assert_eq!(anchor(0, 0), LabelAnchor::TopLeft);
+        assert_eq!(anchor(1, 1), LabelAnchor::Center);
+        assert_eq!(anchor(2, 1), LabelAnchor::Right);
+        assert_eq!(anchor(1, 2), LabelAnchor::Bottom);
+        assert_eq!(anchor(2, 2), LabelAnchor::BottomRight);
+    }
+}
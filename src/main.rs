@@ -1,21 +1,147 @@
+mod attributes;
+mod bench;
+mod binary;
+mod bounds;
+mod camera;
+mod cli;
+mod diff;
+mod drc;
+mod export;
+mod gds;
+mod geometry;
 mod gpu_data;
+mod grid;
+#[cfg(unix)]
+mod ipc;
+mod json;
+mod labels;
+mod layout;
+mod minimap;
+mod navigation;
+mod nets;
+mod overlay;
 mod phase_item;
+mod reload;
+mod ruler;
+mod scenes;
+mod search;
+mod selection;
+mod session;
+mod snap;
+mod split_views;
 mod state;
+#[cfg(test)]
+mod test_support;
+mod validation;
 mod vpull;
 
+use attributes::QuadAttributes;
+use bench::{BenchPlugin, BenchSettings};
+use bevy::core::{Pod, Zeroable};
 use bevy::prelude::*;
+use bevy::window::PresentMode;
 use bevy::{app::App, diagnostic::LogDiagnosticsPlugin, window::WindowDescriptor};
+use camera::fit_camera;
+use clap::Parser;
+use cli::{BenchArgs, Cli, Command, DiffArgs, DrcArgs, InputArgs, SceneArgs, ViewArgs};
+use diff::DiffPlugin;
+use drc::DrcPlugin;
+use grid::{GridPlugin, GridSettings};
+use labels::{Label, LabelAnchor, LabelsPlugin};
+use layout::{Layout, LayoutText};
+use minimap::MinimapPlugin;
+use navigation::{CameraController, NavigationPlugin, FIT_MARGIN};
+use nets::NetsPlugin;
+use overlay::OverlayPlugin;
+use reload::ReloadPlugin;
+use ruler::RulerPlugin;
+use scenes::{ordered_rects, Scene};
+use search::SearchPlugin;
+use selection::SelectionPlugin;
+use session::{Session, SessionPlugin};
+use snap::SnapPlugin;
+use split_views::{LayerVisibility, SplitViewsPlugin, Viewport};
+use std::path::Path;
+use std::sync::Arc;
 use vpull::VpullPlugin;
 
-use bevy_pancam::{PanCam, PanCamPlugin};
-use rand::Rng;
-
 fn main() {
-    App::new()
+    let (cli, session, args) = session_setup(Cli::parse_args());
+    let files = cli.command.as_ref().map_or_else(Vec::new, Command::files);
+    let viewer = cli.viewer().clone();
+    let mut bench = None;
+    let mut diff = None;
+    let mut drc = None;
+    let mut nets = None;
+    let mut reload = None;
+    let mut search = SearchPlugin::default();
+    let mut stroke_width = 0.01;
+    let mut layout = match cli.command {
+        None if viewer.stdin => Layout {
+            name: "stdin".into(),
+            ..Default::default()
+        },
+        None => demo_layout(),
+        Some(Command::View(args)) => {
+            let (layout, plugin, reload_plugin) = view_setup(&args);
+            nets = plugin;
+            // reloading the file would drop the shapes read from standard input
+            reload = reload_plugin.filter(|_| !viewer.stdin);
+            stroke_width = args.input.stroke;
+            search = SearchPlugin {
+                query: args.search,
+                output: args.search_output,
+            };
+            layout
+        }
+        Some(Command::Diff(args)) if !args.report => {
+            let (layout, plugin) = diff_setup(&args);
+            diff = Some(plugin);
+            layout
+        }
+        Some(Command::Drc(args)) if !args.report => {
+            let (layout, plugin) = drc_setup(&args);
+            drc = Some(plugin);
+            layout
+        }
+        Some(Command::Scene(args)) => scene_layout(&args),
+        Some(Command::Bench(args)) => {
+            let (layout, settings) = bench_setup(args);
+            bench = Some(settings);
+            layout
+        }
+        Some(command) => {
+            if let Err(e) = run_command(command) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+
+    // the layer order has to be restored before the layers are spawned
+    if let Some(session) = session.as_ref().filter(|session| session.matches(&files)) {
+        layout.reorder_layers(&session.layer_order());
+    }
+
+    let mut app = App::new();
+    app.insert_resource(layout)
+        .insert_resource(viewer.draw_mode)
+        .insert_resource(GridSettings {
+            fixed_pitch: viewer.grid_pitch,
+            manufacturing_grid: viewer.manufacturing_grid,
+            ..Default::default()
+        })
         .insert_resource(WindowDescriptor {
             title: "doug_renderer".into(),
             width: 1920.0,
             height: 1080.0,
+            // frame times are meaningless when they wait for vsync
+            present_mode: if bench.is_some() {
+                PresentMode::Immediate
+            } else {
+                PresentMode::Fifo
+            },
             ..Default::default()
         })
         .insert_resource(ClearColor(Color::BLACK))
@@ -23,159 +149,391 @@ fn main() {
         // .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_plugin(VpullPlugin)
-        .add_plugin(PanCamPlugin::default())
-        .add_startup_system(setup)
-        // .add_system(camera_controller)
-        .run();
+        .add_plugin(LabelsPlugin)
+        .add_plugin(GridPlugin)
+        .add_plugin(OverlayPlugin)
+        .add_plugin(SplitViewsPlugin)
+        .add_plugin(MinimapPlugin)
+        .add_plugin(SnapPlugin)
+        .add_plugin(SelectionPlugin)
+        .add_plugin(RulerPlugin)
+        .add_plugin(search)
+        .add_plugin(NavigationPlugin::default())
+        .add_startup_system(setup);
+    // shapes read from standard input can't be opened again, so they get no session
+    if let Some(settings) = bench {
+        app.add_plugin(BenchPlugin { settings });
+    } else if !viewer.no_session && !viewer.stdin {
+        app.add_plugin(SessionPlugin {
+            path: viewer.session,
+            command: args,
+            files,
+            restore: session,
+        });
+    }
+    if let Some(plugin) = diff {
+        app.add_plugin(plugin);
+    }
+    if let Some(plugin) = drc {
+        app.add_plugin(plugin);
+    }
+    if let Some(plugin) = nets {
+        app.add_plugin(plugin);
+    }
+    if let Some(plugin) = reload {
+        app.add_plugin(plugin);
+    }
+    if viewer.stdin {
+        app.add_plugin(json::StdinPlugin { stroke_width });
+    }
+    #[cfg(unix)]
+    if let Some(path) = viewer.socket {
+        app.add_plugin(ipc::server::IpcPlugin { path });
+    }
+    app.run();
 }
 
-// Ultimately, Doug converts ints into f32s
-#[derive(Clone, Copy, Default, Debug)]
-pub struct Point {
-    pub x: f32,
-    pub y: f32,
+fn load_or_exit(args: &InputArgs) -> Layout {
+    Layout::load(&args.file, &args.load_options()).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", args.file.display(), e);
+        std::process::exit(1);
+    })
 }
 
-// Rect in the format Doug uses
-#[derive(Clone, Copy, Default, Component, Debug)]
-pub struct DRect {
-    pub p0: Point,
-    pub p1: Point,
-    pub stroke_width: f32,
-    pub color: u32,
+// Reads the session; started without a command, the viewer replays the one that
+// opened the last session's files. Also returns the arguments of the command run.
+fn session_setup(cli: Cli) -> (Cli, Option<Session>, Vec<String>) {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_default();
+    let args: Vec<String> = args.collect();
+    let viewer = cli.viewer();
+    if viewer.no_session {
+        return (cli, None, args);
+    }
+    let session = Session::read(&viewer.session).unwrap_or_else(|e| {
+        eprintln!("warning: ignoring {}: {}", viewer.session.display(), e);
+        None
+    });
+    let saved = match &session {
+        Some(session) if cli.command.is_none() && !viewer.stdin && !session.command.is_empty() => {
+            session.command.clone()
+        }
+        _ => return (cli, session, args),
+    };
+    match Cli::try_parse_from(std::iter::once(program).chain(saved.iter().cloned())) {
+        Ok(mut replayed) => {
+            replayed.viewer_mut().session = cli.viewer.session;
+            (replayed, session, saved)
+        }
+        Err(e) => {
+            eprintln!("warning: not reopening {:?}: {}", saved.join(" "), e);
+            (cli, session, args)
+        }
+    }
 }
 
-fn random_point<R: Rng + ?Sized>(rng: &mut R, min: Point, max: Point) -> Point {
-    Point {
-        x: rng.gen_range(min.x..max.x),
-        y: rng.gen_range(min.y..max.y),
+// Subcommands that don't open a window.
+fn run_command(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::View(_) | Command::Scene(_) | Command::Bench(_) => unreachable!(),
+        Command::ExportPng(args) => {
+            let input = &args.export.input;
+            let layout = load_or_exit(input);
+            export::export_png(
+                &layout,
+                input.bbox,
+                args.width,
+                args.height,
+                &export::PngStyle::default(),
+                &args.export.output,
+            )?;
+        }
+        Command::ExportSvg(args) => {
+            let layout = load_or_exit(&args.input);
+            export::export_svg(&layout, args.input.bbox, &args.output)?;
+        }
+        Command::Stats(args) => {
+            let layout = load_or_exit(&args);
+            print!("{}", export::stats(&layout));
+        }
+        Command::Convert(args) => {
+            let layout = load_or_exit(&args.input);
+            export::convert(&layout, &args.output)?;
+        }
+        Command::Diff(args) => {
+            let (old, new) = (load_or_exit(&args.old), load_or_exit(&args.new_input()));
+            print!("{}", diff::diff_report(&old, &new));
+        }
+        Command::Drc(args) => {
+            let rules = drc::read_rules(&args.rules)?;
+            let layout = load_or_exit(&args.input);
+            print!("{}", drc::drc_report(&rules, &drc::check(&layout, &rules)));
+        }
+        Command::Search(args) => {
+            let mut layout = load_or_exit(&args.input);
+            if let Some(path) = &args.layer_stack {
+                nets::name_nets(&mut layout, &nets::read_layer_stack(path)?);
+            }
+            let matches = search::search_layout(&layout, &args.query);
+            let report = search::search_report(&args.query, matches.iter());
+            match &args.output {
+                Some(path) => std::fs::write(path, report)?,
+                None => print!("{}", report),
+            }
+        }
+        Command::Push(args) => {
+            let layout = load_or_exit(&args.input);
+            push(&layout, &args.socket, args.append, args.fit)?;
+        }
     }
+    Ok(())
 }
 
-impl DRect {
-    pub fn random<R: Rng + ?Sized>(rng: &mut R, min: Point, max: Point) -> Self {
-        DRect {
-            p0: random_point(rng, min, max),
-            p1: random_point(rng, min, max),
-            stroke_width: 0.1,
-            color: rng.gen_range(0..5),
+#[cfg(unix)]
+fn push(layout: &Layout, socket: &Path, append: bool, fit: bool) -> std::io::Result<()> {
+    let mut client = ipc::client::Client::connect(socket)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", socket.display(), e)))?;
+    for layer in layout.layers.iter() {
+        let key = layout.layer_keys[layer.index as usize];
+        match append {
+            true => client.append_rects(key, &layer.rects, None)?,
+            false => client.replace_layer(key, &layer.rects, None)?,
         }
     }
+    if let (true, Some(bounds)) = (fit, layout.bounds()) {
+        client.fit_camera([bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y])?;
+    }
+    Ok(())
+}
 
-    pub fn randomly_placed<R: Rng + ?Sized>(rng: &mut R, min: Point, max: Point) -> Self {
-        let p0 = random_point(rng, min, max);
-        DRect {
-            p0,
-            p1: Point {
-                x: p0.x + 1.0,
-                y: p0.y + 1.0,
-            },
-            stroke_width: 0.1,
-            color: rng.gen_range(0..5),
-        }
+#[cfg(not(unix))]
+fn push(_: &Layout, _: &Path, _: bool, _: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "push needs Unix sockets",
+    ))
+}
+
+// What the viewer shows when no file is given.
+fn demo_layout() -> Layout {
+    let rects = ordered_rects(false);
+    let mut layout = Layout::from_rects("demo", rects.clone());
+    layout.texts = rects
+        .iter()
+        .enumerate()
+        .map(|(i, rect)| LayoutText {
+            text: format!("rect {}", i),
+            position: rect.p0,
+            layer: 0,
+            height: 8.0,
+            anchor: LabelAnchor::BottomLeft,
+            rotation: 0.0,
+        })
+        .collect();
+    layout
+}
+
+fn scene_layout(args: &SceneArgs) -> Layout {
+    Layout::from_rects(
+        args.scene.name(),
+        args.scene.generate(args.count, args.seed),
+    )
+}
+
+fn diff_setup(args: &DiffArgs) -> (Layout, DiffPlugin) {
+    let (old, new) = (load_or_exit(&args.old), load_or_exit(&args.new_input()));
+    let (layout, diff) = diff::diff_layout(&old, &new).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    let plugin = DiffPlugin {
+        diff,
+        mode: args.mode,
+    };
+    (layout, plugin)
+}
+
+fn view_setup(args: &ViewArgs) -> (Layout, Option<NetsPlugin>, Option<ReloadPlugin>) {
+    let mut layout = load_or_exit(&args.input);
+    let stack = args.layer_stack.as_ref().map(|path| {
+        nets::read_layer_stack(path).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        })
+    });
+    if let Some(stack) = &stack {
+        let named = nets::name_nets(&mut layout, stack);
+        info!("named {} nets after their labels", named);
     }
+
+    // reloading names the nets again
+    let reload = (!args.no_watch).then(|| {
+        let (file, options, stack) = (
+            args.input.file.clone(),
+            args.input.load_options(),
+            stack.clone(),
+        );
+        ReloadPlugin {
+            files: vec![file.clone()],
+            load: Arc::new(move || {
+                let mut layout = Layout::load(&file, &options).map_err(|e| e.to_string())?;
+                if let Some(stack) = &stack {
+                    nets::name_nets(&mut layout, stack);
+                }
+                Ok(layout)
+            }),
+        }
+    });
+    (layout, stack.map(|stack| NetsPlugin { stack }), reload)
+}
+
+fn drc_setup(args: &DrcArgs) -> (Layout, DrcPlugin) {
+    let rules = drc::read_rules(&args.rules).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    let layout = load_or_exit(&args.input);
+    let violations = drc::check(&layout, &rules);
+    print!("{}", drc::drc_report(&rules, &violations));
+    (layout, DrcPlugin { violations })
+}
+
+fn bench_setup(args: BenchArgs) -> (Layout, BenchSettings) {
+    let (layout, scene) = match &args.layout {
+        Some(path) => (
+            load_or_exit(&InputArgs {
+                file: path.clone(),
+                cell: None,
+                layers: Vec::new(),
+                bbox: None,
+                stroke: 0.01,
+                derive: Vec::new(),
+            }),
+            path.display().to_string(),
+        ),
+        None => {
+            let scene = match args.scene.scene {
+                Scene::Ordered => "ordered".to_string(),
+                scene => format!(
+                    "{} count={} seed={}",
+                    scene.name(),
+                    args.scene.count,
+                    args.scene.seed
+                ),
+            };
+            (scene_layout(&args.scene), scene)
+        }
+    };
+    let settings = BenchSettings {
+        scene,
+        frames: args.frames.max(1),
+        warmup: args.warmup,
+        output: args.output,
+    };
+    (layout, settings)
+}
+
+// Ultimately, Doug converts ints into f32s
+#[derive(Clone, Copy, Default, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+// Rect in the format Doug uses, laid out like `GpuQuad`
+#[derive(Clone, Copy, Default, Component, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct DRect {
+    pub p0: Point,
+    pub p1: Point,
+    pub stroke_width: f32,
+    pub color: u32,
 }
 
+#[derive(Clone, Debug, Default)]
 pub struct LayerRects {
     pub rects: Vec<DRect>,
     pub index: u8,
+    // per-rect attributes, for layouts that have them
+    pub attributes: Option<QuadAttributes>,
 }
 
 #[derive(Clone, Component, Default, Debug)]
 pub struct BatchedQuads {
     pub data: Vec<DRect>,
+    pub layer: u8,
+    pub validated: bool,
     pub extracted: bool,
     pub prepared: bool,
+    // rects added to a validated batch, waiting to be validated on their own
+    pub appended: Vec<DRect>,
+    // how many rects of `data` the render world has; when only some, the others
+    // are extracted and uploaded after them
+    pub extracted_len: usize,
+    // new each time the whole batch is validated, so that what is built from its
+    // rects can tell a batch that was only appended to, and take just the new rects
+    pub generation: u64,
 }
 
-fn setup(mut commands: Commands) {
-    commands
-        .spawn_bundle(OrthographicCameraBundle::new_2d())
-        .insert(PanCam::default());
-
-    let mut batched_rects = BatchedQuads::default();
-    let rects = ordered_rects(false);
-    batched_rects.data = rects;
-    commands.spawn_bundle((batched_rects,));
-}
-
-#[allow(dead_code)]
-fn ordered_rects(reverse: bool) -> Vec<DRect> {
-    let mut rects = vec![
-        DRect {
-            p0: Point { x: 0.0, y: 0.0 },
-            p1: Point { x: 100.0, y: 100.0 },
-            stroke_width: 1.0,
-            color: 0,
-        },
-        DRect {
-            p0: Point { x: 50.0, y: 50.0 },
-            p1: Point { x: 150.0, y: 150.0 },
-            stroke_width: 1.0,
-            color: 2,
-        },
-    ];
-    if reverse {
-        rects.reverse();
-    }
-    rects
-}
-
-#[allow(dead_code)]
-fn overlapping_rects(n: u32) -> Vec<DRect> {
-    let scale = 10.0;
-    let translate = 10.0;
-    (0..n)
-        .map(|ix| {
-            let i = ix as f32;
-            DRect {
-                p0: Point {
-                    x: i * scale - translate,
-                    y: i * scale - translate,
-                },
-                p1: Point {
-                    x: (1.25 * i + 1.0) * scale - translate,
-                    y: (1.25 * i + 1.0) * scale - translate,
-                },
-                stroke_width: 1.0 * i,
-                color: ix % 5,
-            }
-        })
-        .collect()
-}
-
-#[allow(dead_code)]
-fn many_random_rects(n: usize) -> Vec<DRect> {
-    let mut result = Vec::with_capacity(n);
-
-    let min = Point {
-        x: -300.0,
-        y: -300.0,
-    };
-    let max = Point { x: 300.0, y: 300.0 };
-
-    let mut rng = rand::thread_rng();
-    for _ in 0..n {
-        result.push(DRect::random(&mut rng, min, max))
+impl BatchedQuads {
+    // Adds rects to the batch, so that only they are validated, extracted and
+    // uploaded rather than the whole batch again.
+    pub fn append(&mut self, rects: &[DRect]) {
+        match self.validated {
+            true => self.appended.extend_from_slice(rects),
+            false => self.data.extend_from_slice(rects),
+        }
     }
-
-    result
 }
 
-#[allow(dead_code)]
-fn many_small_random_rects(n: usize) -> Vec<DRect> {
-    let mut result = Vec::with_capacity(n);
+// Marks the labels of the layout's texts, as opposed to those of tools.
+#[derive(Component)]
+pub struct LayoutLabel;
 
-    let min = Point {
-        x: -300.0,
-        y: -300.0,
-    };
-    let max = Point { x: 300.0, y: 300.0 };
+pub fn spawn_layout_label(commands: &mut Commands, text: &LayoutText) {
+    commands.spawn_bundle((
+        Label {
+            text: text.text.clone(),
+            position: text.position,
+            anchor: text.anchor,
+            rotation: text.rotation,
+            layer: text.layer,
+            height: text.height,
+            color: Color::WHITE,
+        },
+        LayoutLabel,
+    ));
+}
 
-    let mut rng = rand::thread_rng();
-    for _ in 0..n {
-        result.push(DRect::randomly_placed(&mut rng, min, max))
+fn setup(mut commands: Commands, layout: Res<Layout>, window: Res<WindowDescriptor>) {
+    // start with the whole layout in view
+    let mut camera = OrthographicCameraBundle::new_2d();
+    if let Some(bounds) = layout.bounds() {
+        fit_camera(
+            &mut camera.transform,
+            &mut camera.orthographic_projection,
+            &bounds,
+            Vec2::new(window.width, window.height),
+            FIT_MARGIN,
+        );
     }
+    commands
+        .spawn_bundle(camera)
+        .insert_bundle((Viewport::default(), LayerVisibility::default()))
+        .insert(CameraController::default());
 
-    result
+    for text in layout.texts.iter() {
+        spawn_layout_label(&mut commands, text);
+    }
+    for layer in layout.layers.iter() {
+        let mut batch = commands.spawn_bundle((BatchedQuads {
+            data: layer.rects.clone(),
+            layer: layer.index,
+            ..Default::default()
+        },));
+        if let Some(attributes) = &layer.attributes {
+            batch.insert(attributes.clone());
+        }
+    }
 }
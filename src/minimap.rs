@@ -0,0 +1,497 @@
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use bevy::utils::HashMap;
+
+use crate::bounds::{update_layout_extent, Bounds, LayoutExtent};
+use crate::camera::{screen_to_world, CursorOverUi, UiInput};
+use crate::overlay::Overlay;
+use crate::split_views::{FocusedView, Viewport};
+use crate::{BatchedQuads, DRect};
+
+pub struct MinimapSettings {
+    pub visible: bool,
+    // Longest side of the minimap, in logical pixels.
+    pub size_px: f32,
+    // Distance from the bottom right corner of the window.
+    pub margin_px: f32,
+    // Number of LOD cells along the longest side of the layout.
+    pub resolution: usize,
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            size_px: 220.0,
+            margin_px: 16.0,
+            resolution: 96,
+        }
+    }
+}
+
+// A coarse coverage raster of the whole layout. Runs of cells with the same
+// (quantized) coverage are merged, so drawing it is cheap. Rects appended to a
+// batch are added to the raster as they come.
+#[derive(Default)]
+struct MinimapLod {
+    bounds: Option<Bounds>,
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+    // area of each cell covered by geometry
+    coverage: Vec<f32>,
+    // (row, first column, column count, coverage level)
+    runs: Vec<(usize, usize, usize, u8)>,
+    // the generation and number of rects taken in of each batch
+    taken: HashMap<Entity, (u64, usize)>,
+    // when the raster was last built from scratch, and whether the extent has
+    // grown since
+    built_at: f64,
+    stale: bool,
+}
+
+const COVERAGE_LEVELS: f32 = 8.0;
+
+// While the extent keeps growing, as it does when shapes are streamed in, the
+// raster is built again at most this often; in between, new rects are added to it
+// where they fall inside the old extent.
+const LOD_REBUILD_SECONDS: f64 = 1.0;
+
+impl MinimapLod {
+    fn empty(bounds: Bounds, resolution: usize) -> Self {
+        let size = bounds.size();
+        let cell_size = (size.x.max(size.y) / resolution as f32).max(f32::MIN_POSITIVE);
+        let columns = ((size.x / cell_size).ceil() as usize).clamp(1, resolution);
+        let rows = ((size.y / cell_size).ceil() as usize).clamp(1, resolution);
+        Self {
+            bounds: Some(bounds),
+            cell_size,
+            columns,
+            rows,
+            coverage: vec![0.0; columns * rows],
+            ..Default::default()
+        }
+    }
+
+    fn build<'a>(
+        bounds: Bounds,
+        resolution: usize,
+        batches: impl Iterator<Item = &'a BatchedQuads>,
+    ) -> Self {
+        let mut lod = Self::empty(bounds, resolution);
+        for batch in batches {
+            lod.add(&batch.data);
+        }
+        lod.update_runs();
+        lod
+    }
+
+    fn add(&mut self, rects: &[DRect]) {
+        let bounds = match self.bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let (columns, rows, cell_size) = (self.columns, self.rows, self.cell_size);
+        for rect in rects {
+            let r = Bounds::from_rect(rect);
+            let lo = ((r.min - bounds.min) / cell_size).floor();
+            let hi = ((r.max - bounds.min) / cell_size).ceil();
+            let (c0, c1) = (lo.x.max(0.0) as usize, (hi.x as usize).min(columns));
+            let (r0, r1) = (lo.y.max(0.0) as usize, (hi.y as usize).min(rows));
+            for row in r0..r1 {
+                for column in c0..c1 {
+                    let cell_min = bounds.min + Vec2::new(column as f32, row as f32) * cell_size;
+                    let cell_max = cell_min + Vec2::splat(cell_size);
+                    let overlap = (r.max.min(cell_max) - r.min.max(cell_min)).max(Vec2::ZERO);
+                    self.coverage[row * columns + column] += overlap.x * overlap.y;
+                }
+            }
+        }
+    }
+
+    fn update_runs(&mut self) {
+        let cell_area = self.cell_size * self.cell_size;
+        let level = |coverage: f32| {
+            let fraction = (coverage / cell_area).min(1.0);
+            (fraction * COVERAGE_LEVELS).ceil() as u8
+        };
+        self.runs.clear();
+        for (row, cells) in self.coverage.chunks(self.columns).enumerate() {
+            let mut column = 0;
+            while column < cells.len() {
+                let start = column;
+                let start_level = level(cells[start]);
+                while column < cells.len() && level(cells[column]) == start_level {
+                    column += 1;
+                }
+                if start_level > 0 {
+                    self.runs.push((row, start, column - start, start_level));
+                }
+            }
+        }
+    }
+}
+
+// Where the minimap sits on screen, and how it maps to the world.
+struct MinimapFrame {
+    // screen position of the bottom left of the layout extent
+    origin: Vec2,
+    size: Vec2,
+    // screen pixels per world unit
+    scale: f32,
+    bounds: Bounds,
+}
+
+impl MinimapFrame {
+    fn new(window_width: f32, settings: &MinimapSettings, bounds: Bounds) -> Self {
+        let extent = bounds.size().max(Vec2::splat(f32::MIN_POSITIVE));
+        let scale = settings.size_px / extent.x.max(extent.y);
+        let size = extent * scale;
+        let origin = Vec2::new(
+            window_width - settings.margin_px - size.x,
+            settings.margin_px,
+        );
+        Self {
+            origin,
+            size,
+            scale,
+            bounds,
+        }
+    }
+
+    fn contains(&self, screen_pos: Vec2) -> bool {
+        let local = screen_pos - self.origin;
+        local.x >= 0.0 && local.y >= 0.0 && local.x <= self.size.x && local.y <= self.size.y
+    }
+
+    fn to_screen(&self, world: Vec2) -> Vec2 {
+        self.origin + (world - self.bounds.min) * self.scale
+    }
+
+    fn to_world(&self, screen_pos: Vec2) -> Vec2 {
+        self.bounds.min + (screen_pos - self.origin) / self.scale
+    }
+
+    // The outline of a view's world box on the minimap, cut to the minimap.
+    fn view_rect(&self, view: &Bounds) -> (Vec2, Vec2) {
+        let max = self.origin + self.size;
+        (
+            self.to_screen(view.min).clamp(self.origin, max),
+            self.to_screen(view.max).clamp(self.origin, max),
+        )
+    }
+}
+
+#[derive(Default)]
+struct MinimapState {
+    // a drag that started on the minimap keeps moving the camera until release
+    dragging: bool,
+}
+
+// M toggles the minimap. Clicking or dragging on it moves the camera there.
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MinimapSettings>()
+            .init_resource::<MinimapLod>()
+            .init_resource::<MinimapState>()
+            .init_resource::<CursorOverUi>()
+            .init_resource::<FocusedView>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                minimap_input.label(UiInput).after(InputSystem),
+            )
+            .add_system(toggle_minimap)
+            .add_system(draw_minimap)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                rebuild_minimap_lod.after(update_layout_extent),
+            );
+    }
+}
+
+fn toggle_minimap(keys: Res<Input<KeyCode>>, mut settings: ResMut<MinimapSettings>) {
+    if keys.just_pressed(KeyCode::M) {
+        settings.visible = !settings.visible;
+    }
+}
+
+// Batches that were only appended to add their new rects to the raster; any other
+// change builds it again.
+#[allow(clippy::too_many_arguments)]
+fn rebuild_minimap_lod(
+    time: Res<Time>,
+    settings: Res<MinimapSettings>,
+    extent: Res<LayoutExtent>,
+    changed: Query<(Entity, &BatchedQuads), Changed<BatchedQuads>>,
+    removed: RemovedComponents<BatchedQuads>,
+    batches: Query<(Entity, &BatchedQuads)>,
+    mut lod: ResMut<MinimapLod>,
+) {
+    let now = time.seconds_since_startup();
+    let replaced = changed.iter().any(|(entity, batch)| {
+        lod.taken.get(&entity).is_some_and(|&(generation, len)| {
+            !batch.validated || generation != batch.generation || len > batch.data.len()
+        })
+    });
+    let grown = extent.is_changed() && extent.bounds != lod.bounds;
+    let rebuild = settings.is_changed()
+        || removed.iter().next().is_some()
+        || replaced
+        || lod.bounds.is_none() != extent.bounds.is_none()
+        || ((grown || lod.stale) && now - lod.built_at >= LOD_REBUILD_SECONDS);
+    if rebuild {
+        let validated = || batches.iter().filter(|(_, batch)| batch.validated);
+        *lod = match extent.bounds {
+            Some(bounds) => MinimapLod::build(
+                bounds,
+                settings.resolution.max(1),
+                validated().map(|(_, batch)| batch),
+            ),
+            None => MinimapLod::default(),
+        };
+        lod.taken = validated()
+            .map(|(entity, batch)| (entity, (batch.generation, batch.data.len())))
+            .collect();
+        lod.built_at = now;
+        return;
+    }
+
+    lod.stale |= grown;
+    let lod = &mut *lod;
+    let mut added = false;
+    for (entity, batch) in changed.iter().filter(|(_, batch)| batch.validated) {
+        let taken = lod.taken.entry(entity).or_insert((batch.generation, 0));
+        if taken.1 < batch.data.len() {
+            let start = taken.1;
+            taken.1 = batch.data.len();
+            lod.add(&batch.data[start..]);
+            added = true;
+        }
+    }
+    if added {
+        lod.update_runs();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn minimap_input(
+    windows: Res<Windows>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    settings: Res<MinimapSettings>,
+    lod: Res<MinimapLod>,
+    mut state: ResMut<MinimapState>,
+    focused: Res<FocusedView>,
+    mut over_ui: ResMut<CursorOverUi>,
+    mut cameras: Query<&mut Transform, With<Camera2d>>,
+) {
+    let window = windows.get_primary();
+    let frame = match (window, lod.bounds) {
+        (Some(window), Some(bounds)) if settings.visible => {
+            Some(MinimapFrame::new(window.width(), &settings, bounds))
+        }
+        _ => None,
+    };
+    let cursor = window.and_then(|window| window.cursor_position());
+    let hovered = matches!((&frame, cursor), (Some(frame), Some(cursor)) if frame.contains(cursor));
+
+    if hovered && mouse_buttons.just_pressed(MouseButton::Left) {
+        state.dragging = true;
+    }
+    if !mouse_buttons.pressed(MouseButton::Left) || frame.is_none() {
+        state.dragging = false;
+    }
+    over_ui.0 = hovered || state.dragging;
+
+    // the focused view jumps there; locked views follow it
+    let camera = focused.0.and_then(|entity| cameras.get_mut(entity).ok());
+    if let (true, Some(frame), Some(cursor), Some(mut transform)) =
+        (state.dragging, &frame, cursor, camera)
+    {
+        let target = frame.to_world(cursor);
+        transform.translation.x = target.x;
+        transform.translation.y = target.y;
+    }
+}
+
+fn draw_minimap(
+    windows: Res<Windows>,
+    settings: Res<MinimapSettings>,
+    lod: Res<MinimapLod>,
+    focused: Res<FocusedView>,
+    cameras: Query<(Entity, &Camera, &GlobalTransform, Option<&Viewport>), With<Camera2d>>,
+    mut overlay: ResMut<Overlay>,
+) {
+    let (window, bounds) = match (windows.get_primary(), lod.bounds) {
+        (Some(window), Some(bounds)) if settings.visible => (window, bounds),
+        _ => return,
+    };
+    let frame = MinimapFrame::new(window.width(), &settings, bounds);
+
+    overlay.screen_fill(
+        frame.origin,
+        frame.origin + frame.size,
+        Color::rgba(0.0, 0.0, 0.0, 0.8),
+    );
+    let cell = lod.cell_size * frame.scale;
+    for &(row, column, count, level) in lod.runs.iter() {
+        let min = frame.origin + Vec2::new(column as f32, row as f32) * cell;
+        let max = (min + Vec2::new(count as f32, 1.0) * cell).min(frame.origin + frame.size);
+        let alpha = 0.15 + 0.7 * level as f32 / COVERAGE_LEVELS;
+        overlay.screen_fill(min, max, Color::rgba(0.8, 0.8, 0.8, alpha));
+    }
+    overlay.screen_rect(
+        frame.origin,
+        frame.origin + frame.size,
+        1.0,
+        Color::rgba(1.0, 1.0, 1.0, 0.5),
+    );
+
+    // the part of the layout each view currently shows
+    for (entity, camera, camera_transform, viewport) in cameras.iter() {
+        let viewport = viewport.copied().unwrap_or_default();
+        let (screen_min, screen_max) = viewport.logical_rect(window);
+        let view_min = screen_to_world(
+            window,
+            camera,
+            camera_transform,
+            viewport.window_position(window, screen_min),
+        );
+        let view_max = screen_to_world(
+            window,
+            camera,
+            camera_transform,
+            viewport.window_position(window, screen_max),
+        );
+        let (min, max) = frame.view_rect(&Bounds {
+            min: view_min,
+            max: view_max,
+        });
+        let color = if focused.0 == Some(entity) {
+            Color::YELLOW
+        } else {
+            Color::rgba(1.0, 1.0, 0.0, 0.4)
+        };
+        overlay.screen_rect(min, max, 1.5, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bounds, rect};
+
+    #[test]
+    fn the_raster_covers_the_extent() {
+        let extent = bounds(-50.0, 10.0, 150.0, 60.0);
+        let batch = BatchedQuads {
+            data: vec![rect(-50.0, 10.0, 150.0, 60.0)],
+            ..Default::default()
+        };
+        let lod = MinimapLod::build(extent, 8, std::iter::once(&batch));
+        assert_eq!(lod.cell_size, 25.0);
+        assert_eq!((lod.columns, lod.rows), (8, 2));
+        // every cell is fully covered, so each row is one run at the top level
+        assert_eq!(lod.runs, vec![(0, 0, 8, 8), (1, 0, 8, 8)]);
+
+        // a rect covering half of a cell
+        let batch = BatchedQuads {
+            data: vec![rect(0.0, 10.0, 12.5, 35.0)],
+            ..Default::default()
+        };
+        let lod = MinimapLod::build(extent, 8, std::iter::once(&batch));
+        assert_eq!(lod.runs, vec![(0, 2, 1, 4)]);
+    }
+
+    #[test]
+    fn the_frame_maps_between_the_minimap_and_the_world() {
+        let settings = MinimapSettings::default();
+        let frame = MinimapFrame::new(800.0, &settings, bounds(0.0, 0.0, 200.0, 100.0));
+        // the longer side takes the whole minimap, in the bottom right corner
+        assert_eq!(frame.scale, 1.1);
+        assert_eq!(frame.origin, Vec2::new(564.0, 16.0));
+        assert_eq!(frame.size, Vec2::new(220.0, 110.0));
+        assert_eq!(frame.to_screen(Vec2::ZERO), frame.origin);
+        assert_eq!(
+            frame.to_screen(Vec2::new(200.0, 100.0)),
+            Vec2::new(784.0, 126.0)
+        );
+
+        // a view partly off the layout is outlined where it overlaps it
+        let (min, max) = frame.view_rect(&bounds(-20.0, 50.0, 100.0, 150.0));
+        assert_eq!(
+            (min, max),
+            (Vec2::new(564.0, 71.0), Vec2::new(674.0, 126.0))
+        );
+
+        // a click goes to the world point under it
+        let click = Vec2::new(674.0, 71.0);
+        assert!(frame.contains(click));
+        assert!(!frame.contains(Vec2::new(500.0, 71.0)));
+        assert_eq!(frame.to_world(click), Vec2::new(100.0, 50.0));
+    }
+
+    #[test]
+    fn streamed_rects_are_added_to_the_raster() {
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        world.insert_resource(MinimapSettings::default());
+        world.insert_resource(MinimapLod::default());
+        world.insert_resource(LayoutExtent {
+            bounds: Some(bounds(0.0, 0.0, 100.0, 100.0)),
+        });
+        let batch = world
+            .spawn()
+            .insert(BatchedQuads {
+                data: vec![rect(0.0, 0.0, 100.0, 1.0), rect(10.0, 10.0, 30.0, 30.0)],
+                validated: true,
+                generation: 1,
+                ..Default::default()
+            })
+            .id();
+        let mut stage = SystemStage::single_threaded().with_system(rebuild_minimap_lod);
+        let built = |world: &World| {
+            let batch = world.get::<BatchedQuads>(batch).unwrap();
+            let bounds = world.resource::<LayoutExtent>().bounds.unwrap();
+            MinimapLod::build(bounds, 96, std::iter::once(batch)).runs
+        };
+        stage.run(&mut world);
+        assert_eq!(world.resource::<MinimapLod>().runs, built(&world));
+
+        // appended inside the extent: added as they come
+        let mut quads = world.get_mut::<BatchedQuads>(batch).unwrap();
+        quads.data.push(rect(50.0, 50.0, 60.0, 70.0));
+        stage.run(&mut world);
+        let lod = world.resource::<MinimapLod>();
+        assert_eq!(lod.taken[&batch], (1, 3));
+        assert_eq!(lod.runs, built(&world));
+
+        // past it: added where they overlap the old extent until the raster is due
+        // to be built again
+        let mut quads = world.get_mut::<BatchedQuads>(batch).unwrap();
+        quads.data.push(rect(90.0, 0.0, 150.0, 10.0));
+        world.resource_mut::<LayoutExtent>().bounds = Some(bounds(0.0, 0.0, 150.0, 100.0));
+        stage.run(&mut world);
+        let lod = world.resource::<MinimapLod>();
+        assert!(lod.stale);
+        assert_eq!(lod.bounds, Some(bounds(0.0, 0.0, 100.0, 100.0)));
+        assert_eq!(lod.taken[&batch], (1, 4));
+        world.resource_mut::<MinimapLod>().built_at = -LOD_REBUILD_SECONDS;
+        stage.run(&mut world);
+        let lod = world.resource::<MinimapLod>();
+        assert!(!lod.stale);
+        assert_eq!(lod.bounds, Some(bounds(0.0, 0.0, 150.0, 100.0)));
+        assert_eq!(lod.runs, built(&world));
+
+        // a batch validated again is taken in again
+        let mut quads = world.get_mut::<BatchedQuads>(batch).unwrap();
+        quads.data.truncate(1);
+        quads.generation = 2;
+        stage.run(&mut world);
+        let lod = world.resource::<MinimapLod>();
+        assert_eq!(lod.taken[&batch], (2, 1));
+        assert_eq!(lod.runs, built(&world));
+    }
+}
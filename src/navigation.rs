@@ -0,0 +1,546 @@
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+
+use crate::bounds::{Bounds, LayoutExtent};
+use crate::camera::{fit_camera, view_size, visible_bounds, Bookmarks};
+use crate::layout::Layout;
+use crate::split_views::{FocusedView, LayerVisibility, Viewport};
+use crate::vpull::QuadHighlights;
+use crate::BatchedQuads;
+
+// Fitted shapes take up this fraction less than the whole view, so that their
+// edges stay in sight.
+pub const FIT_MARGIN: f32 = 1.1;
+// A selection gets more room, to show what it connects to.
+const SELECTION_MARGIN: f32 = 1.5;
+
+const NUMBER_KEYS: [KeyCode; 10] = [
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+// Scroll events in pixels are converted to lines at this rate.
+const PIXELS_PER_LINE: f32 = 100.0;
+
+#[derive(Clone, Debug)]
+pub struct NavigationSettings {
+    // zoom factor of one scroll line or +/- press
+    pub zoom_step: f32,
+    // fraction of the view an arrow key pans by
+    pub pan_step: f32,
+    // zooming out stops when the layout fills this fraction of the view
+    pub min_layout_fraction: f32,
+    // zooming in stops when a database unit is this many pixels wide
+    pub max_pixels_per_db_unit: f32,
+    // views kept for going back and forward
+    pub history_size: usize,
+    // a view goes into the history once the camera has rested on it this long
+    pub history_settle_seconds: f64,
+}
+
+impl Default for NavigationSettings {
+    fn default() -> Self {
+        Self {
+            zoom_step: 1.25,
+            pan_step: 0.25,
+            min_layout_fraction: 0.05,
+            max_pixels_per_db_unit: 50.0,
+            history_size: 100,
+            history_settle_seconds: 0.5,
+        }
+    }
+}
+
+// Mouse and keyboard control of a camera. Only enabled controllers follow the
+// mouse; split views enable the one of the focused view.
+#[derive(Clone, Component, Debug)]
+pub struct CameraController {
+    pub grab_buttons: Vec<MouseButton>,
+    pub enabled: bool,
+    history: ViewHistory,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            grab_buttons: vec![MouseButton::Left, MouseButton::Right, MouseButton::Middle],
+            enabled: true,
+            history: ViewHistory::default(),
+        }
+    }
+}
+
+// Where a camera looks and its projection scale.
+type View = (Vec2, f32);
+
+// Views the camera rested on, for going back and forward.
+#[derive(Clone, Debug, Default)]
+struct ViewHistory {
+    views: Vec<View>,
+    current: usize,
+    // a view the camera moved to, and since when it has been there
+    pending: Option<(View, f64)>,
+}
+
+impl ViewHistory {
+    fn record(&mut self, view: View, now: f64, settings: &NavigationSettings) {
+        if self.views.get(self.current) == Some(&view) {
+            self.pending = None;
+            return;
+        }
+        match self.pending {
+            Some((pending, since)) if pending == view => {
+                if now - since >= settings.history_settle_seconds || self.views.is_empty() {
+                    self.views.truncate(self.current + 1);
+                    self.views.push(view);
+                    let excess = self
+                        .views
+                        .len()
+                        .saturating_sub(settings.history_size.max(1));
+                    self.views.drain(..excess);
+                    self.current = self.views.len() - 1;
+                    self.pending = None;
+                }
+            }
+            _ => self.pending = Some((view, now)),
+        }
+    }
+
+    // The view `step` entries back (negative) or forward, if there is one.
+    fn go(&mut self, step: isize) -> Option<View> {
+        let current = self.current.checked_add_signed(step)?;
+        let view = *self.views.get(current)?;
+        self.current = current;
+        self.pending = None;
+        Some(view)
+    }
+}
+
+// The smallest and largest projection scales: a database unit is at most
+// `max_pixels_per_db_unit` pixels wide, and the layout fills at least
+// `min_layout_fraction` of the view. Without a database unit, zooming in stops
+// well before f32 positions run out of precision.
+pub fn scale_limits(
+    settings: &NavigationSettings,
+    extent: Option<&Bounds>,
+    db_unit: Option<f32>,
+    view_size: Vec2,
+) -> (f32, f32) {
+    let layout_size = extent.map_or(0.0, |bounds| bounds.size().max_element());
+    let min = match db_unit {
+        Some(db_unit) => db_unit / settings.max_pixels_per_db_unit,
+        None => layout_size * 1e-6,
+    }
+    .max(f32::MIN_POSITIVE);
+    let max = match extent {
+        Some(_) => layout_size / (view_size.min_element() * settings.min_layout_fraction),
+        None => f32::MAX,
+    };
+    (min, max.max(min))
+}
+
+// The camera translation that keeps the world point `offset` pixels from the view
+// center in place when the scale changes.
+fn zoom_about(translation: Vec2, scale: f32, offset: Vec2, new_scale: f32) -> Vec2 {
+    let point = translation + offset * scale;
+    point - offset * new_scale
+}
+
+// Pan and zoom with the mouse and keyboard, and fitting and bookmarks:
+//   drag            - pan (left, right or middle button)
+//   wheel           - zoom about the cursor
+//   arrows          - pan by a part of the view
+//   + / -           - zoom about the view center
+//   Alt+Left/Right  - back and forward through the views the camera rested on
+//   F               - fit everything on the layers the view shows
+//   S               - fit the selection (the highlighted shapes)
+//   0-9             - jump to the bookmark on that key, restoring its layers
+//   Ctrl+0-9        - bookmark what the view shows and its layers on that key
+// Zooming stays within `scale_limits`.
+#[derive(Default)]
+pub struct NavigationPlugin {
+    pub settings: NavigationSettings,
+}
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone())
+            .init_resource::<Bookmarks>()
+            .init_resource::<FocusedView>()
+            .init_resource::<QuadHighlights>()
+            .init_resource::<LayoutExtent>()
+            .add_system(pan_with_mouse)
+            .add_system(zoom_with_wheel)
+            .add_system(navigate_with_keys)
+            .add_system(zoom_to_fit)
+            .add_system(zoom_to_selection)
+            .add_system(bookmark_views)
+            .add_system_to_stage(CoreStage::PostUpdate, record_history);
+    }
+}
+
+fn pan_with_mouse(
+    windows: Res<Windows>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut cameras: Query<(&CameraController, &mut Transform, &OrthographicProjection)>,
+    mut last_pos: Local<Option<Vec2>>,
+) {
+    // the cursor position rather than mouse motion, to pan exactly as far as it moved
+    let cursor = match windows.get_primary().and_then(Window::cursor_position) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let delta = cursor - last_pos.unwrap_or(cursor);
+    *last_pos = Some(cursor);
+    for (controller, mut transform, projection) in cameras.iter_mut() {
+        let grabbed = controller
+            .grab_buttons
+            .iter()
+            .any(|button| mouse_buttons.pressed(*button));
+        if controller.enabled && grabbed {
+            transform.translation -= (delta * projection.scale).extend(0.0);
+        }
+    }
+}
+
+fn zoom_with_wheel(
+    windows: Res<Windows>,
+    settings: Res<NavigationSettings>,
+    extent: Res<LayoutExtent>,
+    layout: Res<Layout>,
+    mut scroll_events: EventReader<MouseWheel>,
+    mut cameras: Query<(
+        &CameraController,
+        &mut Transform,
+        &mut OrthographicProjection,
+        Option<&Viewport>,
+    )>,
+) {
+    let lines: f32 = scroll_events
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+            MouseScrollUnit::Line => event.y,
+        })
+        .sum();
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    if lines == 0.0 {
+        return;
+    }
+    for (controller, mut transform, mut projection, viewport) in cameras.iter_mut() {
+        if !controller.enabled {
+            continue;
+        }
+        let (min, max) = scale_limits(
+            &settings,
+            extent.bounds.as_ref(),
+            layout.db_unit,
+            view_size(window, viewport),
+        );
+        let scale = (projection.scale * settings.zoom_step.powf(-lines)).clamp(min, max);
+        // the cursor from the view center, in pixels
+        let offset = window.cursor_position().map_or(Vec2::ZERO, |cursor| {
+            let cursor =
+                viewport.map_or(cursor, |viewport| viewport.window_position(window, cursor));
+            cursor - Vec2::new(window.width(), window.height()) * 0.5
+        });
+        let translation = zoom_about(
+            transform.translation.truncate(),
+            projection.scale,
+            offset,
+            scale,
+        );
+        transform.translation.x = translation.x;
+        transform.translation.y = translation.y;
+        projection.scale = scale;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn navigate_with_keys(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    settings: Res<NavigationSettings>,
+    extent: Res<LayoutExtent>,
+    layout: Res<Layout>,
+    focused: Res<FocusedView>,
+    mut cameras: Query<(
+        &mut CameraController,
+        &mut Transform,
+        &mut OrthographicProjection,
+        Option<&Viewport>,
+    )>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (mut controller, mut transform, mut projection, viewport) =
+        match focused.0.and_then(|entity| cameras.get_mut(entity).ok()) {
+            Some(camera) => camera,
+            None => return,
+        };
+    let size = view_size(window, viewport);
+
+    if keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt]) {
+        let step = match (
+            keys.just_pressed(KeyCode::Left),
+            keys.just_pressed(KeyCode::Right),
+        ) {
+            (true, false) => -1,
+            (false, true) => 1,
+            _ => return,
+        };
+        match controller.history.go(step) {
+            Some((center, scale)) => {
+                transform.translation.x = center.x;
+                transform.translation.y = center.y;
+                projection.scale = scale;
+            }
+            None => info!("no view {}", if step < 0 { "back" } else { "forward" }),
+        }
+        return;
+    }
+
+    let pan = [
+        (KeyCode::Left, -Vec2::X),
+        (KeyCode::Right, Vec2::X),
+        (KeyCode::Down, -Vec2::Y),
+        (KeyCode::Up, Vec2::Y),
+    ]
+    .into_iter()
+    .filter(|(key, _)| keys.just_pressed(*key))
+    .fold(Vec2::ZERO, |pan, (_, direction)| pan + direction);
+    if pan != Vec2::ZERO {
+        transform.translation += (pan * size * settings.pan_step * projection.scale).extend(0.0);
+    }
+
+    let zoom = match (
+        keys.any_just_pressed([KeyCode::Equals, KeyCode::NumpadAdd]),
+        keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]),
+    ) {
+        (true, false) => 1.0,
+        (false, true) => -1.0,
+        _ => return,
+    };
+    let (min, max) = scale_limits(&settings, extent.bounds.as_ref(), layout.db_unit, size);
+    projection.scale = (projection.scale * settings.zoom_step.powf(-zoom)).clamp(min, max);
+}
+
+fn record_history(
+    time: Res<Time>,
+    settings: Res<NavigationSettings>,
+    mut cameras: Query<(&mut CameraController, &Transform, &OrthographicProjection)>,
+) {
+    let now = time.seconds_since_startup();
+    for (mut controller, transform, projection) in cameras.iter_mut() {
+        let view = (transform.translation.truncate(), projection.scale);
+        controller.history.record(view, now, &settings);
+    }
+}
+
+type FocusedCamera<'a> = (
+    &'a mut Transform,
+    &'a mut OrthographicProjection,
+    Option<&'a Viewport>,
+    Option<&'a mut LayerVisibility>,
+);
+
+fn zoom_to_fit(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    focused: Res<FocusedView>,
+    batches: Query<&BatchedQuads>,
+    mut cameras: Query<FocusedCamera>,
+) {
+    if !keys.just_pressed(KeyCode::F) {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (mut transform, mut projection, viewport, layers) =
+        match focused.0.and_then(|entity| cameras.get_mut(entity).ok()) {
+            Some(camera) => camera,
+            None => return,
+        };
+    let bounds = batches
+        .iter()
+        .filter(|batch| batch.validated)
+        .filter(|batch| {
+            layers
+                .as_ref()
+                .is_none_or(|layers| layers.is_visible(batch.layer))
+        })
+        .filter_map(|batch| Bounds::from_rects(&batch.data))
+        .reduce(Bounds::union);
+    match bounds {
+        Some(bounds) => fit_camera(
+            &mut transform,
+            &mut projection,
+            &bounds,
+            view_size(window, viewport),
+            FIT_MARGIN,
+        ),
+        None => info!("nothing to fit on the visible layers"),
+    }
+}
+
+fn zoom_to_selection(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    focused: Res<FocusedView>,
+    highlights: Res<QuadHighlights>,
+    batches: Query<&BatchedQuads>,
+    mut cameras: Query<FocusedCamera>,
+) {
+    if !keys.just_pressed(KeyCode::S) {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (mut transform, mut projection, viewport, _) =
+        match focused.0.and_then(|entity| cameras.get_mut(entity).ok()) {
+            Some(camera) => camera,
+            None => return,
+        };
+    let bounds = highlights
+        .quads
+        .iter()
+        .filter_map(|(entity, indices)| {
+            let batch = batches.get(*entity).ok()?;
+            Bounds::from_rects(indices.iter().filter_map(|i| batch.data.get(*i as usize)))
+        })
+        .reduce(Bounds::union);
+    match bounds {
+        Some(bounds) => fit_camera(
+            &mut transform,
+            &mut projection,
+            &bounds,
+            view_size(window, viewport),
+            SELECTION_MARGIN,
+        ),
+        None => info!("nothing selected"),
+    }
+}
+
+fn bookmark_views(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    focused: Res<FocusedView>,
+    layout: Res<Layout>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut cameras: Query<FocusedCamera>,
+) {
+    let key = match NUMBER_KEYS.iter().position(|key| keys.just_pressed(*key)) {
+        Some(key) => key as u8,
+        None => return,
+    };
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (mut transform, mut projection, viewport, mut layers) =
+        match focused.0.and_then(|entity| cameras.get_mut(entity).ok()) {
+            Some(camera) => camera,
+            None => return,
+        };
+    let size = view_size(window, viewport);
+
+    if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        let bounds = visible_bounds(&transform, &projection, size);
+        let updated = bookmarks.save(key, &bounds, &layout.layer_keys, layers.as_deref());
+        let bookmark = bookmarks.get(key).unwrap();
+        if updated {
+            info!("updated {} on {}", bookmark.name, key);
+        } else {
+            info!("added {} on {}", bookmark.name, key);
+        }
+        return;
+    }
+
+    let bookmark = match bookmarks.get(key) {
+        Some(bookmark) => bookmark,
+        None => {
+            info!("no bookmark on {}; Ctrl+{} adds one", key, key);
+            return;
+        }
+    };
+    fit_camera(
+        &mut transform,
+        &mut projection,
+        &bookmark.bounds(),
+        size,
+        1.0,
+    );
+    if let Some(layers) = layers.as_mut() {
+        bookmark.restore_layers(&layout.layer_keys, layers);
+    }
+    info!("{}", bookmark.name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_cursor() {
+        let (translation, scale) = (Vec2::new(10.0, -4.0), 2.0);
+        let offset = Vec2::new(100.0, 50.0);
+        let point = translation + offset * scale;
+        let zoomed = zoom_about(translation, scale, offset, 0.5);
+        assert_eq!(zoomed + offset * 0.5, point);
+    }
+
+    #[test]
+    fn zoom_limits_follow_the_layout() {
+        let settings = NavigationSettings::default();
+        let extent = Bounds {
+            min: Vec2::ZERO,
+            max: Vec2::new(1000.0, 10.0),
+        };
+        let view = Vec2::new(800.0, 500.0);
+        let (min, max) = scale_limits(&settings, Some(&extent), Some(0.001), view);
+        assert_eq!(min, 0.001 / settings.max_pixels_per_db_unit);
+        // the layout's 1000 units fill 5% of the view's 500 pixels
+        assert_eq!(max, 1000.0 / 25.0);
+        let (min, max) = scale_limits(&settings, None, None, view);
+        assert!(min > 0.0 && max == f32::MAX);
+    }
+
+    #[test]
+    fn history_keeps_views_the_camera_rested_on() {
+        let settings = NavigationSettings::default();
+        let mut history = ViewHistory::default();
+        let (a, b, c) = ((Vec2::ZERO, 1.0), (Vec2::X, 1.0), (Vec2::Y, 2.0));
+        history.record(a, 0.0, &settings);
+        history.record(a, 0.0, &settings);
+        // passing through b doesn't count, resting on c does
+        history.record(b, 1.0, &settings);
+        history.record(c, 1.1, &settings);
+        history.record(c, 2.0, &settings);
+        assert_eq!(history.views, vec![a, c]);
+
+        assert_eq!(history.go(-1), Some(a));
+        assert_eq!(history.go(-1), None);
+        // a new view after going back drops the ones ahead
+        history.record(b, 3.0, &settings);
+        history.record(b, 4.0, &settings);
+        assert_eq!(history.views, vec![a, b]);
+        assert_eq!(history.go(1), None);
+    }
+}
@@ -0,0 +1,419 @@
+use std::io;
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::bounds::Bounds;
+use crate::geometry::{group_by, overlaps, touches};
+use crate::layout::{LayerKey, Layout};
+use crate::selection::{SelectShapes, Selection};
+use crate::vpull::QuadHighlights;
+use crate::{BatchedQuads, DRect};
+
+// Connectivity: which shapes are electrically connected, given which layers
+// conduct and which via layers connect which pairs of layers. Shapes on one
+// conducting layer connect where they overlap or abut; a via connects to the
+// shapes of its two layers that it overlaps.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Via {
+    pub layer: LayerKey,
+    pub bottom: LayerKey,
+    pub top: LayerKey,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayerStack {
+    pub conductors: Vec<LayerKey>,
+    pub vias: Vec<Via>,
+}
+
+// One declaration per line, `#` starts a comment:
+//   conductor 1/0
+//   conductor 3/0
+//   via 2/0 1/0 3/0    (2/0 connects 1/0 and 3/0)
+// The layers a via connects conduct, whether or not they are declared.
+pub fn parse_layer_stack(text: &str) -> Result<LayerStack, String> {
+    let mut stack = LayerStack::default();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let error = |e: String| format!("line {}: {}", number + 1, e);
+        match words.as_slice() {
+            [] => {}
+            ["conductor", layer] => {
+                let layer = layer.parse().map_err(error)?;
+                stack.add_conductor(layer);
+            }
+            ["via", layer, bottom, top] => {
+                let via = Via {
+                    layer: layer.parse().map_err(error)?,
+                    bottom: bottom.parse().map_err(error)?,
+                    top: top.parse().map_err(error)?,
+                };
+                stack.add_conductor(via.bottom);
+                stack.add_conductor(via.top);
+                stack.vias.push(via);
+            }
+            _ => {
+                return Err(error(format!(
+                    "expected conductor <layer> or via <layer> <bottom> <top>, got {:?}",
+                    words.join(" ")
+                )))
+            }
+        }
+    }
+    Ok(stack)
+}
+
+pub fn read_layer_stack(path: &Path) -> io::Result<LayerStack> {
+    parse_layer_stack(&std::fs::read_to_string(path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+impl LayerStack {
+    fn add_conductor(&mut self, layer: LayerKey) {
+        if !self.conductors.contains(&layer) {
+            self.conductors.push(layer);
+        }
+    }
+
+    fn contains(&self, layer: LayerKey) -> bool {
+        self.conductors.contains(&layer) || self.vias.iter().any(|via| via.layer == layer)
+    }
+
+    fn via_connects(&self, via: LayerKey, layer: LayerKey) -> bool {
+        self.vias
+            .iter()
+            .any(|v| v.layer == via && (v.bottom == layer || v.top == layer))
+    }
+}
+
+// Shapes on layers outside of the stack belong to no net.
+pub const NO_NET: u32 = u32::MAX;
+
+// The net of every shape, by layer and index in the layer.
+#[derive(Clone, Debug, Default)]
+pub struct Nets {
+    pub net_of: Vec<Vec<u32>>,
+    pub count: usize,
+}
+
+impl Nets {
+    // (layer, index) of every shape on `net`.
+    pub fn shapes(&self, net: u32) -> Vec<(usize, u32)> {
+        let mut shapes = Vec::new();
+        for (layer, nets) in self.net_of.iter().enumerate() {
+            for (index, n) in nets.iter().enumerate() {
+                if *n == net {
+                    shapes.push((layer, index as u32));
+                }
+            }
+        }
+        shapes
+    }
+}
+
+pub fn extract_nets(layers: &[(LayerKey, &[DRect])], stack: &LayerStack) -> Nets {
+    let mut shapes: Vec<(usize, u32)> = Vec::new();
+    let mut bounds: Vec<Bounds> = Vec::new();
+    for (layer, (key, rects)) in layers.iter().enumerate() {
+        if stack.contains(*key) {
+            for (index, rect) in rects.iter().enumerate() {
+                shapes.push((layer, index as u32));
+                bounds.push(Bounds::from_rect(rect));
+            }
+        }
+    }
+
+    let groups = group_by(&bounds, |i, j| {
+        let (a, b) = (layers[shapes[i].0].0, layers[shapes[j].0].0);
+        if a == b {
+            touches(&bounds[i], &bounds[j])
+        } else {
+            (stack.via_connects(a, b) || stack.via_connects(b, a))
+                && overlaps(&bounds[i], &bounds[j])
+        }
+    });
+
+    let mut nets = Nets {
+        net_of: layers
+            .iter()
+            .map(|(_, rects)| vec![NO_NET; rects.len()])
+            .collect(),
+        count: groups.len(),
+    };
+    for (net, group) in groups.into_iter().enumerate() {
+        for i in group {
+            let (layer, index) = shapes[i];
+            nets.net_of[layer][index as usize] = net as u32;
+        }
+    }
+    nets
+}
+
+// Names the nets that have a text label on one of their shapes, on the label's
+// layer, and stores the names as the shapes' net attributes. Returns the number
+// of named nets.
+pub fn name_nets(layout: &mut Layout, stack: &LayerStack) -> usize {
+    let nets = {
+        let layers: Vec<(LayerKey, &[DRect])> = layout
+            .layers
+            .iter()
+            .map(|layer| {
+                let key = layout.layer_keys.get(layer.index as usize);
+                (key.copied().unwrap_or_default(), layer.rects.as_slice())
+            })
+            .collect();
+        extract_nets(&layers, stack)
+    };
+    let mut names: HashMap<u32, String> = HashMap::default();
+    for text in layout.texts.iter() {
+        let point = Vec2::new(text.position.x, text.position.y);
+        for (layer, rects) in layout.layers.iter().enumerate() {
+            if rects.index != text.layer {
+                continue;
+            }
+            for (index, rect) in rects.rects.iter().enumerate() {
+                let net = nets.net_of[layer][index];
+                let r = Bounds::from_rect(rect);
+                if net != NO_NET && point.cmpge(r.min).all() && point.cmple(r.max).all() {
+                    // the first label of a net names it
+                    names.entry(net).or_insert_with(|| text.text.clone());
+                }
+            }
+        }
+    }
+    for (layer, rects) in layout.layers.iter_mut().enumerate() {
+        let net_of = &nets.net_of[layer];
+        if !net_of.iter().any(|net| names.contains_key(net)) {
+            continue;
+        }
+        let attributes = rects.attributes.get_or_insert_with(Default::default);
+        attributes.set_nets(rects.rects.len(), |index| {
+            names.get(&net_of[index]).cloned()
+        });
+    }
+    names.len()
+}
+
+// The nets of the batches, in the order of `batches`, built on the first selection
+// after the batches change.
+#[derive(Default)]
+struct NetCache {
+    batches: Vec<Entity>,
+    nets: Option<Nets>,
+}
+
+// Selecting a shape of the layer stack highlights its whole net.
+pub struct NetsPlugin {
+    pub stack: LayerStack,
+}
+
+impl Plugin for NetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.stack.clone())
+            .init_resource::<NetCache>()
+            .init_resource::<Selection>()
+            .init_resource::<QuadHighlights>()
+            .add_system(invalidate_nets)
+            .add_system(highlight_net.after(invalidate_nets).after(SelectShapes));
+    }
+}
+
+fn invalidate_nets(
+    changed: Query<(), Changed<BatchedQuads>>,
+    removed: RemovedComponents<BatchedQuads>,
+    mut cache: ResMut<NetCache>,
+) {
+    if !changed.is_empty() || removed.iter().next().is_some() {
+        cache.nets = None;
+    }
+}
+
+fn highlight_net(
+    layout: Res<Layout>,
+    stack: Res<LayerStack>,
+    batches: Query<(Entity, &BatchedQuads)>,
+    mut cache: ResMut<NetCache>,
+    selection: Res<Selection>,
+    mut highlights: ResMut<QuadHighlights>,
+) {
+    if !selection.is_changed() {
+        return;
+    }
+    let selected = match selection.shapes.first() {
+        Some(shape) => shape,
+        None => return,
+    };
+
+    let layer_key = |batch: &BatchedQuads| {
+        layout
+            .layer_keys
+            .get(batch.layer as usize)
+            .copied()
+            .unwrap_or_default()
+    };
+    if cache.nets.is_none() {
+        if batches.iter().any(|(_, batch)| !batch.validated) {
+            return;
+        }
+        let mut sorted: Vec<(Entity, &BatchedQuads)> = batches.iter().collect();
+        sorted.sort_by_key(|(_, batch)| batch.layer);
+        let layers: Vec<(LayerKey, &[DRect])> = sorted
+            .iter()
+            .map(|(_, batch)| (layer_key(batch), batch.data.as_slice()))
+            .collect();
+        let nets = extract_nets(&layers, &stack);
+        info!("extracted {} nets", nets.count);
+        cache.batches = sorted.iter().map(|(entity, _)| *entity).collect();
+        cache.nets = Some(nets);
+    }
+    let nets = cache.nets.as_ref().unwrap();
+
+    let net = cache
+        .batches
+        .iter()
+        .position(|entity| *entity == selected.batch)
+        .and_then(|layer| nets.net_of[layer].get(selected.index as usize))
+        .copied();
+    let net = match net {
+        Some(net) if net != NO_NET => net,
+        _ => return,
+    };
+
+    let mut quads: HashMap<Entity, Vec<u32>> = HashMap::default();
+    let shapes = nets.shapes(net);
+    for (layer, index) in shapes.iter() {
+        quads.entry(cache.batches[*layer]).or_default().push(*index);
+    }
+    let name = match &selected.attributes.net {
+        Some(name) => name.clone(),
+        None => format!("#{}", net),
+    };
+    info!(
+        "net {}: {} shapes on {} layers",
+        name,
+        shapes.len(),
+        quads.len()
+    );
+    highlights.quads = quads;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::LabelAnchor;
+    use crate::layout::LayoutText;
+    use crate::test_support::{layer, layout, rect};
+    use crate::Point;
+
+    #[test]
+    fn layer_stack_parses() {
+        let stack =
+            parse_layer_stack("# metals\nconductor 1/0\n\nvia 2/0 1/0 3/0 # via1\n").unwrap();
+        assert_eq!(stack.conductors, vec![layer(1), layer(3)]);
+        assert_eq!(
+            stack.vias,
+            vec![Via {
+                layer: layer(2),
+                bottom: layer(1),
+                top: layer(3)
+            }]
+        );
+        let error = parse_layer_stack("conductor 1/0\nvia 2/0 1/0\n").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{}", error);
+        assert!(parse_layer_stack("conductor x").is_err());
+    }
+
+    #[test]
+    fn nets_follow_vias_across_layers() {
+        let stack = parse_layer_stack("via 2/0 1/0 3/0").unwrap();
+        // metal 1: two wires that abut, one apart, and one far off
+        let metal1 = vec![
+            rect(0.0, 0.0, 5.0, 1.0),
+            rect(5.0, 0.0, 6.0, 4.0),
+            rect(10.0, 0.0, 11.0, 1.0),
+            rect(21.0, 0.0, 22.0, 1.0),
+        ];
+        // vias from the first and the third wire up to the metal 3 wire over both,
+        // which joins them; the last via only touches the edge of the other metal 3
+        // wire, which does not connect
+        let vias = vec![
+            rect(0.2, 0.2, 0.8, 0.8),
+            rect(10.2, 0.2, 10.8, 0.8),
+            rect(21.0, 0.2, 21.6, 0.8),
+        ];
+        let metal3 = vec![rect(0.0, 0.0, 12.0, 0.5), rect(20.0, 0.0, 21.0, 1.0)];
+        // not in the stack
+        let text = vec![rect(0.0, 0.0, 1.0, 1.0)];
+        let nets = extract_nets(
+            &[
+                (layer(1), &metal1),
+                (layer(2), &vias),
+                (layer(3), &metal3),
+                (layer(63), &text),
+            ],
+            &stack,
+        );
+
+        let net = nets.net_of[0][0];
+        assert_eq!(
+            nets.shapes(net),
+            vec![(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (2, 0)]
+        );
+        assert_ne!(nets.net_of[2][1], net);
+        assert_eq!(nets.net_of[1][2], nets.net_of[0][3]);
+        assert_ne!(nets.net_of[1][2], nets.net_of[2][1]);
+        assert_ne!(nets.net_of[1][2], net);
+        assert_eq!(nets.net_of[3][0], NO_NET);
+        assert_eq!(nets.count, 3);
+    }
+
+    #[test]
+    fn corners_and_unrelated_layers_do_not_connect() {
+        let stack = parse_layer_stack("conductor 1/0\nconductor 3/0\nvia 2/0 1/0 4/0").unwrap();
+        let metal1 = vec![rect(0.0, 0.0, 1.0, 1.0), rect(1.0, 1.0, 2.0, 2.0)];
+        // overlaps both, but 3/0 is not connected to anything by a via
+        let metal3 = vec![rect(0.0, 0.0, 2.0, 2.0)];
+        let nets = extract_nets(&[(layer(1), &metal1), (layer(3), &metal3)], &stack);
+        assert_eq!(nets.count, 3);
+    }
+
+    #[test]
+    fn labels_name_nets() {
+        let stack = parse_layer_stack("via 2/0 1/0 3/0").unwrap();
+        let mut layout = Layout {
+            // on metal 3, which is connected to the first metal 1 wire
+            texts: vec![LayoutText {
+                text: "VDD".into(),
+                position: Point { x: 0.5, y: 4.0 },
+                layer: 2,
+                height: 1.0,
+                anchor: LabelAnchor::BottomLeft,
+                rotation: 0.0,
+            }],
+            ..layout(vec![
+                (
+                    1,
+                    vec![rect(0.0, 0.0, 4.0, 1.0), rect(10.0, 0.0, 14.0, 1.0)],
+                ),
+                (2, vec![rect(0.2, 0.2, 0.8, 0.8)]),
+                (3, vec![rect(0.0, 0.0, 1.0, 5.0)]),
+            ])
+        };
+        assert_eq!(name_nets(&mut layout, &stack), 1);
+        let net = |layer: usize, index: usize| {
+            let attributes = layout.layers[layer].attributes.as_ref()?;
+            attributes.get(index)?.net.clone()
+        };
+        assert_eq!(net(0, 0).as_deref(), Some("VDD"));
+        assert_eq!(net(1, 0).as_deref(), Some("VDD"));
+        assert_eq!(net(0, 1), None);
+    }
+}
@@ -0,0 +1,257 @@
+mod pipeline;
+mod render_command;
+mod render_graph;
+
+use bevy::core_pipeline::draw_2d_graph;
+use bevy::prelude::*;
+use bevy::render::camera::{ActiveCamera, Camera2d};
+use bevy::render::render_graph::RenderGraph;
+use bevy::render::render_phase::{AddRenderCommand, DrawFunctions, RenderPhase};
+use bevy::render::render_resource::{BindGroupDescriptor, BindGroupEntry};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+
+use bevy::app::{App, Plugin};
+use bevy::render::{RenderApp, RenderStage};
+
+use crate::gpu_data::{GpuOverlay, GpuOverlayView, GpuSegment};
+use crate::phase_item::OverlayPhaseItem;
+use crate::split_views::{ExtractedViewport, Viewport};
+use crate::vpull::VPULL_PASS;
+
+use self::pipeline::{OverlayPipeline, OVERLAY_SHADER_HANDLE};
+use self::render_command::DrawOverlay;
+use self::render_graph::{OverlayPassNode, OVERLAY_PASS};
+
+// Immediate-mode overlay: it is cleared at the start of every frame, and tools
+// push whatever they want to show on top of the layout for that frame.
+// Screen-space primitives take logical pixels from the bottom left of the window,
+// like `Window::cursor_position`.
+pub struct Overlay {
+    segments: Vec<GpuSegment>,
+    scale_factor: f32,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self {
+            segments: Vec::new(),
+            scale_factor: 1.0,
+        }
+    }
+}
+
+impl Overlay {
+    fn push(&mut self, a: Vec2, b: Vec2, width: f32, color: Color, flags: u32) {
+        self.segments.push(GpuSegment {
+            a,
+            b,
+            width: width * self.scale_factor,
+            color: color.as_rgba_u32(),
+            flags,
+            _padding: 0,
+        });
+    }
+
+    // A line between two world-space points, `width` pixels wide.
+    pub fn line(&mut self, a: Vec2, b: Vec2, width: f32, color: Color) {
+        self.push(a, b, width, color, 0);
+    }
+
+    pub fn screen_line(&mut self, a: Vec2, b: Vec2, width: f32, color: Color) {
+        let (a, b) = (a * self.scale_factor, b * self.scale_factor);
+        self.push(a, b, width, color, GpuSegment::SCREEN_SPACE);
+    }
+
+    // A filled screen-space box, drawn as one segment as wide as the box is high.
+    pub fn screen_fill(&mut self, min: Vec2, max: Vec2, color: Color) {
+        let y = (min.y + max.y) * 0.5 * self.scale_factor;
+        self.segments.push(GpuSegment {
+            a: Vec2::new(min.x * self.scale_factor, y),
+            b: Vec2::new(max.x * self.scale_factor, y),
+            width: (max.y - min.y) * self.scale_factor,
+            color: color.as_rgba_u32(),
+            flags: GpuSegment::SCREEN_SPACE | GpuSegment::BUTT_CAPS,
+            _padding: 0,
+        });
+    }
+
+    pub fn screen_rect(&mut self, min: Vec2, max: Vec2, width: f32, color: Color) {
+        let (p01, p10) = (Vec2::new(min.x, max.y), Vec2::new(max.x, min.y));
+        self.screen_line(min, p10, width, color);
+        self.screen_line(p10, max, width, color);
+        self.screen_line(max, p01, width, color);
+        self.screen_line(p01, min, width, color);
+    }
+
+    // The outline of the axis-aligned box spanned by two world-space points.
+    pub fn rect(&mut self, p0: Vec2, p1: Vec2, width: f32, color: Color) {
+        let (p01, p10) = (Vec2::new(p0.x, p1.y), Vec2::new(p1.x, p0.y));
+        self.line(p0, p10, width, color);
+        self.line(p10, p1, width, color);
+        self.line(p1, p01, width, color);
+        self.line(p01, p0, width, color);
+    }
+
+    fn clear(&mut self) {
+        self.segments.clear();
+    }
+}
+
+struct ExtractedOverlay {
+    segments: Vec<GpuSegment>,
+}
+
+#[derive(Component)]
+struct OverlayBatch;
+
+#[derive(Component)]
+pub struct ViewOverlayOffset {
+    pub offset: u32,
+}
+
+pub struct OverlayPlugin;
+
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.world.resource_mut::<Assets<Shader>>().set_untracked(
+            OVERLAY_SHADER_HANDLE,
+            Shader::from_wgsl(include_str!("../shaders/overlay.wgsl")),
+        );
+
+        app.init_resource::<Overlay>()
+            .add_system_to_stage(CoreStage::First, clear_overlay);
+
+        let render_app = app.sub_app_mut(RenderApp);
+
+        render_app
+            .init_resource::<DrawFunctions<OverlayPhaseItem>>()
+            .add_render_command::<OverlayPhaseItem, DrawOverlay>()
+            .init_resource::<OverlayPipeline>()
+            .init_resource::<GpuOverlay>()
+            .add_system_to_stage(RenderStage::Extract, extract_overlay_phase)
+            .add_system_to_stage(RenderStage::Extract, extract_overlay)
+            .add_system_to_stage(RenderStage::Prepare, prepare_overlay)
+            .add_system_to_stage(RenderStage::Queue, queue_overlay);
+
+        // the overlay is drawn after the quads and after the main 2d pass
+        let overlay_pass_node = OverlayPassNode::new(&mut render_app.world);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        let draw_2d_graph = graph.get_sub_graph_mut(draw_2d_graph::NAME).unwrap();
+        draw_2d_graph.add_node(OVERLAY_PASS, overlay_pass_node);
+        draw_2d_graph
+            .add_node_edge(VPULL_PASS, OVERLAY_PASS)
+            .unwrap();
+        draw_2d_graph
+            .add_node_edge(draw_2d_graph::node::MAIN_PASS, OVERLAY_PASS)
+            .unwrap();
+        draw_2d_graph
+            .add_slot_edge(
+                draw_2d_graph.input_node().unwrap().id,
+                draw_2d_graph::input::VIEW_ENTITY,
+                OVERLAY_PASS,
+                OverlayPassNode::IN_VIEW,
+            )
+            .unwrap();
+    }
+}
+
+fn clear_overlay(windows: Res<Windows>, mut overlay: ResMut<Overlay>) {
+    overlay.clear();
+    if let Some(window) = windows.get_primary() {
+        overlay.scale_factor = window.scale_factor() as f32;
+    }
+}
+
+// EXTRACT:
+fn extract_overlay_phase(
+    mut commands: Commands,
+    active_2d: Res<ActiveCamera<Camera2d>>,
+    views: Query<Entity, (With<Camera2d>, With<Viewport>)>,
+) {
+    let active = active_2d.get().filter(|entity| !views.contains(*entity));
+    for entity in views.iter().chain(active) {
+        commands
+            .get_or_spawn(entity)
+            .insert(RenderPhase::<OverlayPhaseItem>::default());
+    }
+}
+
+// The overlay is small and rebuilt every frame, so it is copied every frame.
+fn extract_overlay(mut commands: Commands, overlay: Res<Overlay>) {
+    commands.insert_resource(ExtractedOverlay {
+        segments: overlay.segments.clone(),
+    });
+    commands.spawn_bundle((OverlayBatch,));
+}
+
+// PREPARE:
+fn prepare_overlay(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut gpu_overlay: ResMut<GpuOverlay>,
+    extracted_overlay: Res<ExtractedOverlay>,
+    overlay_pipeline: Res<OverlayPipeline>,
+    views: Query<(Entity, Option<&ExtractedViewport>), With<RenderPhase<OverlayPhaseItem>>>,
+) {
+    gpu_overlay.views.clear();
+    for (entity, viewport) in views.iter() {
+        let offset = gpu_overlay.views.push(GpuOverlayView {
+            viewport_origin: viewport.map_or(Vec2::ZERO, |viewport| viewport.screen_origin),
+        });
+        commands.entity(entity).insert(ViewOverlayOffset { offset });
+    }
+    gpu_overlay
+        .views
+        .write_buffer(&render_device, &render_queue);
+
+    gpu_overlay.segments.clear();
+    for segment in extracted_overlay.segments.iter() {
+        gpu_overlay.segments.push(*segment);
+    }
+    if gpu_overlay.segments.is_empty() || gpu_overlay.views.is_empty() {
+        gpu_overlay.bind_group = None;
+        return;
+    }
+    gpu_overlay
+        .segments
+        .write_buffer(&render_device, &render_queue);
+
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("overlay_bind_group"),
+        layout: &overlay_pipeline.data_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: gpu_overlay.segments.buffer().unwrap().as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: gpu_overlay.views.binding().unwrap(),
+            },
+        ],
+    });
+    gpu_overlay.bind_group = Some(bind_group);
+}
+
+// QUEUE:
+fn queue_overlay(
+    draw_functions: Res<DrawFunctions<OverlayPhaseItem>>,
+    gpu_overlay: Res<GpuOverlay>,
+    mut views: Query<&mut RenderPhase<OverlayPhaseItem>>,
+    batches: Query<Entity, With<OverlayBatch>>,
+) {
+    if gpu_overlay.bind_group.is_none() {
+        return;
+    }
+    let draw_overlay = draw_functions.read().get_id::<DrawOverlay>().unwrap();
+
+    for mut phase in views.iter_mut() {
+        for entity in batches.iter() {
+            phase.add(OverlayPhaseItem {
+                entity,
+                draw_function: draw_overlay,
+            });
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::PrimitiveTopology,
+        render_resource::{
+            std140::AsStd140, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingType, BlendState, BufferBindingType, BufferSize, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState,
+            PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor, ShaderStages,
+            TextureFormat, VertexState,
+        },
+        renderer::RenderDevice,
+        texture::BevyDefault,
+        view::ViewUniform,
+    },
+};
+
+use crate::gpu_data::GpuOverlayView;
+
+pub struct OverlayPipeline {
+    pub pipeline_id: CachedRenderPipelineId,
+    pub data_layout: BindGroupLayout,
+}
+
+pub const OVERLAY_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 12240389128450193861);
+
+impl FromWorld for OverlayPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let view_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    entries: &[
+                        // View
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: BufferSize::new(
+                                    ViewUniform::std140_size_static() as u64,
+                                ),
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("overlay_view_layout"),
+                });
+
+        let data_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("overlay_data_layout"),
+                    entries: &[
+                        // Segments
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::VERTEX,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(0),
+                            },
+                            count: None,
+                        },
+                        // View origin
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::VERTEX,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: BufferSize::new(
+                                    GpuOverlayView::std140_size_static() as u64,
+                                ),
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("overlay_pipeline".into()),
+            layout: Some(vec![view_layout, data_layout.clone()]),
+            vertex: VertexState {
+                shader: OVERLAY_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader: OVERLAY_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                // segments are widened towards both sides, whatever their direction
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: Msaa::default().samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        });
+
+        Self {
+            pipeline_id,
+            data_layout,
+        }
+    }
+}
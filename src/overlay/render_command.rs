@@ -0,0 +1,90 @@
+use bevy::{
+    ecs::system::{
+        lifetimeless::{Read, SQuery, SRes},
+        SystemParamItem,
+    },
+    pbr::SetShadowViewBindGroup,
+    prelude::Entity,
+    render::{
+        render_phase::{
+            EntityRenderCommand, PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass,
+        },
+        render_resource::PipelineCache,
+    },
+};
+
+use crate::gpu_data::GpuOverlay;
+
+use super::pipeline::OverlayPipeline;
+use super::ViewOverlayOffset;
+
+pub type DrawOverlay = (
+    SetOverlayPipeline,
+    SetShadowViewBindGroup<0>,
+    SetOverlayBindGroup<1>,
+    DrawOverlaySegments,
+);
+
+pub struct SetOverlayPipeline;
+impl<P: PhaseItem> RenderCommand<P> for SetOverlayPipeline {
+    type Param = (SRes<PipelineCache>, SRes<OverlayPipeline>);
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        _item: &P,
+        params: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let (pipeline_cache, overlay_pipeline) = params;
+        if let Some(pipeline) = pipeline_cache
+            .into_inner()
+            .get_render_pipeline(overlay_pipeline.pipeline_id)
+        {
+            pass.set_render_pipeline(pipeline);
+            RenderCommandResult::Success
+        } else {
+            RenderCommandResult::Failure
+        }
+    }
+}
+
+pub struct SetOverlayBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetOverlayBindGroup<I> {
+    type Param = (SRes<GpuOverlay>, SQuery<Read<ViewOverlayOffset>>);
+
+    #[inline]
+    fn render<'w>(
+        view: Entity,
+        _item: Entity,
+        (gpu_overlay, view_offsets): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        match (
+            gpu_overlay.into_inner().bind_group.as_ref(),
+            view_offsets.get(view),
+        ) {
+            (Some(bind_group), Ok(view_offset)) => {
+                pass.set_bind_group(I, bind_group, &[view_offset.offset]);
+                RenderCommandResult::Success
+            }
+            _ => RenderCommandResult::Failure,
+        }
+    }
+}
+
+pub struct DrawOverlaySegments;
+impl EntityRenderCommand for DrawOverlaySegments {
+    type Param = SRes<GpuOverlay>;
+
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        _item: Entity,
+        gpu_overlay: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let segment_count = gpu_overlay.into_inner().segments.len() as u32;
+        pass.draw(0..segment_count * 6, 0..1);
+        RenderCommandResult::Success
+    }
+}
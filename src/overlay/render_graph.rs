@@ -0,0 +1,85 @@
+use bevy::prelude::*;
+use bevy::render::render_graph::{NodeRunError, RenderGraphContext, SlotInfo, SlotType};
+use bevy::render::render_phase::{DrawFunctions, RenderPhase, TrackedRenderPass};
+use bevy::render::render_resource::{LoadOp, Operations, RenderPassDescriptor};
+use bevy::render::view::{ExtractedView, ViewTarget};
+use bevy::render::{render_graph, renderer::RenderContext};
+
+use crate::phase_item::OverlayPhaseItem;
+use crate::split_views::ExtractedViewport;
+
+pub const OVERLAY_PASS: &str = "OVERLAY_PASS";
+
+pub struct OverlayPassNode {
+    #[allow(clippy::type_complexity)]
+    query: QueryState<
+        (
+            &'static RenderPhase<OverlayPhaseItem>,
+            &'static ViewTarget,
+            Option<&'static ExtractedViewport>,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl OverlayPassNode {
+    pub const IN_VIEW: &'static str = "view";
+
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl render_graph::Node for OverlayPassNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(OverlayPassNode::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let (overlay_phase, target, viewport) = match self.query.get_manual(world, view_entity) {
+            Ok(query) => query,
+            Err(_) => return Ok(()), // No window
+        };
+
+        #[cfg(feature = "trace")]
+        let _main_overlay_pass_span = info_span!("main_overlay_pass").entered();
+        let pass_descriptor = RenderPassDescriptor {
+            label: Some("main_overlay_pass"),
+            // NOTE: The overlay is drawn on top of everything else.
+            color_attachments: &[target.get_color_attachment(Operations {
+                load: LoadOp::Load,
+                store: true,
+            })],
+            depth_stencil_attachment: None,
+        };
+
+        let draw_functions = world.resource::<DrawFunctions<OverlayPhaseItem>>();
+
+        let render_pass = render_context
+            .command_encoder
+            .begin_render_pass(&pass_descriptor);
+        let mut draw_functions = draw_functions.write();
+        let mut tracked_pass = TrackedRenderPass::new(render_pass);
+        if let Some(viewport) = viewport {
+            viewport.set(&mut tracked_pass);
+        }
+        for item in &overlay_phase.items {
+            let draw_function = draw_functions.get_mut(item.draw_function).unwrap();
+            draw_function.draw(world, &mut tracked_pass, view_entity, item);
+        }
+
+        Ok(())
+    }
+}
@@ -3,9 +3,14 @@ use bevy::{
     render::render_phase::{DrawFunctionId, EntityPhaseItem, PhaseItem},
 };
 
+// Everything in the quads phase is drawn in ascending order of these keys.
+pub const QUADS_SORT_KEY: u32 = 0;
+pub const LABELS_SORT_KEY: u32 = 1;
+
 pub struct QuadsPhaseItem {
     pub entity: Entity,
     pub draw_function: DrawFunctionId,
+    pub sort_key: u32,
 }
 
 impl PhaseItem for QuadsPhaseItem {
@@ -14,7 +19,7 @@ impl PhaseItem for QuadsPhaseItem {
 
     #[inline]
     fn sort_key(&self) -> Self::SortKey {
-        0
+        self.sort_key
     }
 
     #[inline]
@@ -29,3 +34,30 @@ impl EntityPhaseItem for QuadsPhaseItem {
         self.entity
     }
 }
+
+// Tool feedback (rulers, markers, ...) drawn above all layout geometry.
+pub struct OverlayPhaseItem {
+    pub entity: Entity,
+    pub draw_function: DrawFunctionId,
+}
+
+impl PhaseItem for OverlayPhaseItem {
+    type SortKey = u32;
+
+    #[inline]
+    fn sort_key(&self) -> Self::SortKey {
+        0
+    }
+
+    #[inline]
+    fn draw_function(&self) -> DrawFunctionId {
+        self.draw_function
+    }
+}
+
+impl EntityPhaseItem for OverlayPhaseItem {
+    #[inline]
+    fn entity(&self) -> Entity {
+        self.entity
+    }
+}
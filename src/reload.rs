@@ -0,0 +1,270 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::utils::HashMap;
+use futures_lite::future;
+
+use crate::attributes::QuadAttributes;
+use crate::layout::Layout;
+use crate::selection::{SelectShapes, Selection, ShapeQuery};
+use crate::vpull::QuadHighlights;
+use crate::{spawn_layout_label, BatchedQuads, LayerRects, LayoutLabel};
+
+// Live reload: the files a layout was loaded from are polled, and once one has
+// changed and stopped changing, the layout is loaded again in the background.
+// Only the layers that differ are handed to the batches again, so only they are
+// validated, extracted and prepared again; the camera is left alone, and selected
+// shapes are found again in the new rects.
+
+// How often the files are checked. A file is loaded once it has not changed for
+// one more check, so that a file still being written isn't read.
+const POLL_SECONDS: f64 = 0.5;
+
+pub type LoadLayout = Arc<dyn Fn() -> Result<Layout, String> + Send + Sync>;
+
+// Puts `new`'s layers at the indices the same layers have in `old`, so that batches,
+// layer visibility and texts keep their meaning. Layers that are gone stay as empty
+// ones. Returns the indices of the layers that differ from `old`.
+pub fn align_layers(old: &Layout, new: &mut Layout) -> Vec<u8> {
+    for (i, key) in old.layer_keys.iter().enumerate() {
+        let before = old.layer_keys[..i].iter().filter(|k| *k == key).count();
+        if new.layer_keys.iter().filter(|k| *k == key).count() <= before {
+            new.layers.push(LayerRects {
+                index: new.layers.len() as u8,
+                ..Default::default()
+            });
+            new.layer_keys.push(*key);
+        }
+    }
+    new.reorder_layers(&old.layer_keys);
+    new.layers
+        .iter()
+        .filter(|layer| {
+            old.layers
+                .get(layer.index as usize)
+                .is_none_or(|old| old.rects != layer.rects || old.attributes != layer.attributes)
+        })
+        .map(|layer| layer.index)
+        .collect()
+}
+
+pub struct ReloadPlugin {
+    pub files: Vec<PathBuf>,
+    pub load: LoadLayout,
+}
+
+struct Reloader {
+    files: Vec<PathBuf>,
+    load: LoadLayout,
+    modified: Vec<Option<SystemTime>>,
+    // a file changed since the last check
+    changed: bool,
+    loading: Option<Task<Result<Layout, String>>>,
+    // batches given new rects, in which selected shapes are to be found again
+    reselect: Vec<Entity>,
+}
+
+impl Plugin for ReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Reloader {
+            files: self.files.clone(),
+            load: self.load.clone(),
+            modified: self.files.iter().map(modified).collect(),
+            changed: false,
+            loading: None,
+            reselect: Vec::new(),
+        })
+        .init_resource::<Selection>()
+        .init_resource::<QuadHighlights>()
+        .add_system(watch_files)
+        .add_system(apply_reload.after(watch_files))
+        .add_system(reselect_shapes.before(SelectShapes));
+    }
+}
+
+fn modified(file: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+fn watch_files(
+    time: Res<Time>,
+    pool: Res<AsyncComputeTaskPool>,
+    mut reloader: ResMut<Reloader>,
+    mut last_check: Local<f64>,
+) {
+    let now = time.seconds_since_startup();
+    if now - *last_check < POLL_SECONDS || reloader.loading.is_some() {
+        return;
+    }
+    *last_check = now;
+
+    let modified: Vec<Option<SystemTime>> = reloader.files.iter().map(modified).collect();
+    if modified != reloader.modified {
+        reloader.modified = modified;
+        reloader.changed = true;
+    } else if reloader.changed {
+        reloader.changed = false;
+        let load = reloader.load.clone();
+        reloader.loading = Some(pool.spawn(async move { load() }));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn apply_reload(
+    mut commands: Commands,
+    mut reloader: ResMut<Reloader>,
+    mut layout: ResMut<Layout>,
+    mut batches: Query<(Entity, &mut BatchedQuads, Option<&mut QuadAttributes>)>,
+    labels: Query<Entity, With<LayoutLabel>>,
+) {
+    let result = match reloader.loading.as_mut() {
+        Some(task) => match future::block_on(future::poll_once(task)) {
+            Some(result) => result,
+            None => return,
+        },
+        None => return,
+    };
+    reloader.loading = None;
+    let mut new = match result {
+        Ok(new) => new,
+        Err(e) => {
+            // the file may be half written; the next change loads it again
+            error!("could not reload the layout: {}", e);
+            return;
+        }
+    };
+
+    let changed = align_layers(&layout, &mut new);
+    let mut spawned: Vec<u8> = Vec::new();
+    for layer in changed.iter().map(|index| &new.layers[*index as usize]) {
+        let batch = batches
+            .iter_mut()
+            .find(|(_, batch, _)| batch.layer == layer.index);
+        match batch {
+            Some((entity, mut batch, attributes)) => {
+                *batch = BatchedQuads {
+                    data: layer.rects.clone(),
+                    layer: layer.index,
+                    ..Default::default()
+                };
+                // validation runs after the commands are applied
+                match (attributes, &layer.attributes) {
+                    (Some(mut attributes), Some(new)) => *attributes = new.clone(),
+                    (None, Some(new)) => {
+                        commands.entity(entity).insert(new.clone());
+                    }
+                    (Some(_), None) => {
+                        commands.entity(entity).remove::<QuadAttributes>();
+                    }
+                    (None, None) => {}
+                }
+                reloader.reselect.push(entity);
+            }
+            None => spawned.push(layer.index),
+        }
+    }
+    for layer in spawned.iter().map(|index| &new.layers[*index as usize]) {
+        let mut batch = commands.spawn_bundle((BatchedQuads {
+            data: layer.rects.clone(),
+            layer: layer.index,
+            ..Default::default()
+        },));
+        if let Some(attributes) = &layer.attributes {
+            batch.insert(attributes.clone());
+        }
+    }
+    if new.texts != layout.texts {
+        for entity in labels.iter() {
+            commands.entity(entity).despawn();
+        }
+        for text in new.texts.iter() {
+            spawn_layout_label(&mut commands, text);
+        }
+    }
+    info!(
+        "reloaded {}: {} of {} layers changed",
+        new.name,
+        changed.len(),
+        new.layers.len()
+    );
+    *layout = new;
+}
+
+// Once the reloaded batches are validated, selected shapes on them are looked up by
+// their rect; those that are gone are dropped from the selection.
+fn reselect_shapes(
+    mut reloader: ResMut<Reloader>,
+    shapes: ShapeQuery,
+    batches: Query<&BatchedQuads>,
+    mut selection: ResMut<Selection>,
+    mut highlights: ResMut<QuadHighlights>,
+) {
+    if reloader.reselect.is_empty() || batches.iter().any(|batch| !batch.validated) {
+        return;
+    }
+    let reloaded = std::mem::take(&mut reloader.reselect);
+    if !selection
+        .shapes
+        .iter()
+        .any(|shape| reloaded.contains(&shape.batch))
+    {
+        return;
+    }
+    let kept: Vec<_> = selection
+        .shapes
+        .iter()
+        .filter_map(|shape| match reloaded.contains(&shape.batch) {
+            true => shapes.find(shape.batch, &shape.rect),
+            false => Some(shape.clone()),
+        })
+        .collect();
+    let mut quads: HashMap<Entity, Vec<u32>> = HashMap::default();
+    for shape in kept.iter() {
+        quads.entry(shape.batch).or_default().push(shape.index);
+    }
+    selection.shapes = kept;
+    highlights.quads = quads;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::LabelAnchor;
+    use crate::layout::LayoutText;
+    use crate::test_support::{layout, rect};
+    use crate::Point;
+
+    #[test]
+    fn reloaded_layers_keep_their_indices() {
+        let old = layout(vec![
+            (2, vec![rect(0.0, 0.0, 1.0, 1.0)]),
+            (1, vec![rect(1.0, 0.0, 2.0, 1.0)]),
+            (3, vec![rect(2.0, 0.0, 3.0, 1.0)]),
+        ]);
+        // 3/0 is gone, 1/0 changed and 4/0 is new
+        let mut new = layout(vec![
+            (1, vec![rect(1.0, 0.0, 2.0, 1.0), rect(5.0, 0.0, 6.0, 1.0)]),
+            (2, vec![rect(0.0, 0.0, 1.0, 1.0)]),
+            (4, vec![rect(3.0, 0.0, 4.0, 1.0)]),
+        ]);
+        new.texts.push(LayoutText {
+            text: "A".into(),
+            position: Point::default(),
+            layer: 0,
+            height: 1.0,
+            anchor: LabelAnchor::BottomLeft,
+            rotation: 0.0,
+        });
+        let changed = align_layers(&old, &mut new);
+        let keys: Vec<i16> = new.layer_keys.iter().map(|key| key.layer).collect();
+        assert_eq!(keys, vec![2, 1, 3, 4]);
+        assert_eq!(new.layers[1].rects.len(), 2);
+        assert!(new.layers[2].rects.is_empty());
+        assert_eq!(changed, vec![1, 2, 3]);
+        // the text was on 1/0
+        assert_eq!(new.texts[0].layer, 1);
+    }
+}
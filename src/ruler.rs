@@ -0,0 +1,479 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::camera::CursorOverUi;
+use crate::labels::{Label, LabelAnchor};
+use crate::layout::Layout;
+use crate::navigation::CameraController;
+use crate::overlay::Overlay;
+use crate::snap::{Snap, SnapKind, Snapper};
+use crate::Point;
+
+const RULER_COLOR: Color = Color::YELLOW;
+const RULER_WIDTH_PX: f32 = 1.5;
+// Height of the distance label relative to the length of the ruler.
+const LABEL_HEIGHT_RATIO: f32 = 0.05;
+
+// Rulers measure in database units when the layout has them, and in user units
+// otherwise. `db_unit` is the size of a database unit in user units.
+fn db_unit(layout: &Layout) -> Option<f32> {
+    layout.db_unit.filter(|db_unit| *db_unit > 0.0)
+}
+
+fn measure(d: f32, db_unit: Option<f32>) -> f32 {
+    db_unit.map_or(d, |db_unit| d / db_unit)
+}
+
+fn unit_name(db_unit: Option<f32>) -> &'static str {
+    if db_unit.is_some() {
+        "dbu"
+    } else {
+        "user"
+    }
+}
+
+// Whole numbers are printed without decimals.
+pub fn format_distance(d: f32) -> String {
+    if (d - d.round()).abs() < 1e-3 {
+        format!("{:.0}", d)
+    } else {
+        format!("{:.3}", d)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ruler {
+    pub start: Vec2,
+    pub end: Vec2,
+    label: Entity,
+}
+
+impl Ruler {
+    // A finished ruler, with its label.
+    pub fn spawn(commands: &mut Commands, start: Vec2, end: Vec2, layout: &Layout) -> Ruler {
+        let ruler = Ruler {
+            start,
+            end,
+            label: commands.spawn().id(),
+        };
+        commands
+            .entity(ruler.label)
+            .insert(ruler.label(db_unit(layout)));
+        ruler
+    }
+
+    pub fn delta(&self) -> Vec2 {
+        self.end - self.start
+    }
+
+    pub fn length(&self) -> f32 {
+        self.delta().length()
+    }
+
+    fn text(&self, db_unit: Option<f32>) -> String {
+        let delta = self.delta();
+        let units = if db_unit.is_some() { " dbu" } else { "" };
+        format!(
+            "{}{} (dx {}, dy {})",
+            format_distance(measure(self.length(), db_unit)),
+            units,
+            format_distance(measure(delta.x.abs(), db_unit)),
+            format_distance(measure(delta.y.abs(), db_unit))
+        )
+    }
+
+    // One line of the ruler list; the ends are in user units.
+    fn summary(&self, index: usize, db_unit: Option<f32>) -> String {
+        format!(
+            "ruler {}: ({}, {}) -> ({}, {}): {}",
+            index,
+            self.start.x,
+            self.start.y,
+            self.end.x,
+            self.end.y,
+            self.text(db_unit)
+        )
+    }
+
+    fn label(&self, db_unit: Option<f32>) -> Label {
+        let delta = self.delta();
+        // keep the text upright
+        let mut rotation = delta.y.atan2(delta.x);
+        if rotation.abs() > std::f32::consts::FRAC_PI_2 {
+            rotation -= std::f32::consts::PI.copysign(rotation);
+        }
+        let mid = (self.start + self.end) * 0.5;
+        Label {
+            text: self.text(db_unit),
+            position: Point { x: mid.x, y: mid.y },
+            anchor: LabelAnchor::Bottom,
+            rotation,
+            layer: u8::MAX,
+            height: self.length() * LABEL_HEIGHT_RATIO,
+            color: RULER_COLOR,
+        }
+    }
+}
+
+// Rulers persist until they are cleared.
+#[derive(Default)]
+pub struct Rulers {
+    pub rulers: Vec<Ruler>,
+}
+
+impl Rulers {
+    // The ends are in user units, the distances in `unit`.
+    pub fn to_csv(&self, db_unit: Option<f32>) -> String {
+        let mut csv = String::from("index,x0,y0,x1,y1,dx,dy,distance,unit\n");
+        for (i, ruler) in self.rulers.iter().enumerate() {
+            let delta = ruler.delta();
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{}",
+                i,
+                ruler.start.x,
+                ruler.start.y,
+                ruler.end.x,
+                ruler.end.y,
+                measure(delta.x.abs(), db_unit),
+                measure(delta.y.abs(), db_unit),
+                measure(ruler.length(), db_unit),
+                unit_name(db_unit)
+            );
+        }
+        csv
+    }
+
+    pub fn export_csv(&self, path: impl AsRef<Path>, db_unit: Option<f32>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_csv(db_unit))
+    }
+}
+
+#[derive(Default)]
+struct RulerTool {
+    active: bool,
+    // the ruler being dragged out
+    drag: Option<Ruler>,
+    snap: Option<Snap>,
+    // where the cursor lands, after snapping
+    cursor: Option<Vec2>,
+}
+
+impl RulerTool {
+    fn aim(&mut self, cursor: Vec2, snap: Option<Snap>) {
+        self.snap = snap;
+        self.cursor = Some(snap.map_or(cursor, |snap| snap.point));
+    }
+}
+
+// Ruler mode is toggled with R. While it is active:
+//   left drag  - draw a ruler, snapping to rect corners and edges or the grid
+//   C          - clear all rulers
+//   L          - list rulers in the log
+//   E          - export rulers to rulers.csv
+// Panning stays available on the right and middle mouse buttons.
+pub struct RulerPlugin;
+
+impl Plugin for RulerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Rulers>()
+            .init_resource::<RulerTool>()
+            .init_resource::<CursorOverUi>()
+            .add_system(toggle_ruler_mode)
+            .add_system(track_ruler_cursor.after(toggle_ruler_mode))
+            .add_system(ruler_drag.after(track_ruler_cursor))
+            .add_system(ruler_commands)
+            .add_system(relabel_rulers)
+            .add_system(draw_rulers.after(ruler_drag));
+    }
+}
+
+fn toggle_ruler_mode(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut tool: ResMut<RulerTool>,
+    mut controllers: Query<&mut CameraController>,
+) {
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+    tool.active = !tool.active;
+    if let Some(ruler) = tool.drag.take() {
+        commands.entity(ruler.label).despawn();
+    }
+    info!("ruler mode {}", if tool.active { "on" } else { "off" });
+    for mut controller in controllers.iter_mut() {
+        controller.grab_buttons = if tool.active {
+            vec![MouseButton::Right, MouseButton::Middle]
+        } else {
+            CameraController::default().grab_buttons
+        };
+    }
+}
+
+fn track_ruler_cursor(snapper: Snapper, mut tool: ResMut<RulerTool>) {
+    tool.snap = None;
+    tool.cursor = None;
+    if !tool.active {
+        return;
+    }
+    if let Some((cursor, snap)) = snapper.cursor() {
+        tool.aim(cursor, snap);
+    }
+}
+
+fn ruler_drag(
+    mut commands: Commands,
+    mouse_buttons: Res<Input<MouseButton>>,
+    over_ui: Res<CursorOverUi>,
+    layout: Res<Layout>,
+    mut tool: ResMut<RulerTool>,
+    mut rulers: ResMut<Rulers>,
+) {
+    if !tool.active {
+        return;
+    }
+    let point = match tool.cursor {
+        Some(point) => point,
+        None => return,
+    };
+
+    if mouse_buttons.just_pressed(MouseButton::Left) && !over_ui.0 {
+        tool.drag = Some(Ruler {
+            start: point,
+            end: point,
+            label: commands.spawn().id(),
+        });
+    }
+    if let Some(ruler) = tool.drag.as_mut() {
+        ruler.end = point;
+        commands
+            .entity(ruler.label)
+            .insert(ruler.label(db_unit(&layout)));
+    }
+    if mouse_buttons.just_released(MouseButton::Left) {
+        if let Some(ruler) = tool.drag.take() {
+            if ruler.length() > 0.0 {
+                info!(
+                    "ruler {}: {}",
+                    rulers.rulers.len(),
+                    ruler.text(db_unit(&layout))
+                );
+                rulers.rulers.push(ruler);
+            } else {
+                commands.entity(ruler.label).despawn();
+            }
+        }
+    }
+}
+
+fn ruler_commands(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    layout: Res<Layout>,
+    tool: Res<RulerTool>,
+    mut rulers: ResMut<Rulers>,
+) {
+    if !tool.active {
+        return;
+    }
+    if keys.just_pressed(KeyCode::C) {
+        for ruler in rulers.rulers.drain(..) {
+            commands.entity(ruler.label).despawn();
+        }
+        info!("cleared rulers");
+    }
+    if keys.just_pressed(KeyCode::L) {
+        for (i, ruler) in rulers.rulers.iter().enumerate() {
+            info!("{}", ruler.summary(i, db_unit(&layout)));
+        }
+    }
+    if keys.just_pressed(KeyCode::E) {
+        match rulers.export_csv("rulers.csv", db_unit(&layout)) {
+            Ok(()) => info!("exported {} rulers to rulers.csv", rulers.rulers.len()),
+            Err(e) => error!("could not export rulers: {}", e),
+        }
+    }
+}
+
+// A new layout may have another database unit.
+fn relabel_rulers(mut commands: Commands, layout: Res<Layout>, rulers: Res<Rulers>) {
+    if !layout.is_changed() {
+        return;
+    }
+    for ruler in rulers.rulers.iter() {
+        commands
+            .entity(ruler.label)
+            .insert(ruler.label(db_unit(&layout)));
+    }
+}
+
+fn draw_rulers(
+    snapper: Snapper,
+    tool: Res<RulerTool>,
+    rulers: Res<Rulers>,
+    mut overlay: ResMut<Overlay>,
+) {
+    for ruler in rulers.rulers.iter().chain(tool.drag.iter()) {
+        overlay.line(ruler.start, ruler.end, RULER_WIDTH_PX, RULER_COLOR);
+    }
+
+    // a small box marks the point the cursor snaps to
+    if let (Some(snap), Some(world_per_pixel)) = (tool.snap, snapper.world_units_per_pixel()) {
+        let half = match snap.kind {
+            SnapKind::Corner => 4.0,
+            SnapKind::Edge => 2.5,
+            SnapKind::Grid => 1.5,
+        } * world_per_pixel;
+        overlay.rect(
+            snap.point - Vec2::splat(half),
+            snap.point + Vec2::splat(half),
+            1.0,
+            RULER_COLOR,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snap::snap_to_geometry;
+    use crate::test_support::rect;
+
+    fn ruler(x0: f32, y0: f32, x1: f32, y1: f32) -> Ruler {
+        Ruler {
+            start: Vec2::new(x0, y0),
+            end: Vec2::new(x1, y1),
+            label: Entity::from_raw(0),
+        }
+    }
+
+    #[test]
+    fn rulers_measure_their_length_and_label_it() {
+        let r = ruler(10.0, 20.0, 7.0, 16.0);
+        assert_eq!(r.delta(), Vec2::new(-3.0, -4.0));
+        assert_eq!(r.length(), 5.0);
+        assert_eq!(r.text(None), "5 (dx 3, dy 4)");
+        assert_eq!(
+            ruler(0.0, 0.0, 0.25, 0.0).text(None),
+            "0.250 (dx 0.250, dy 0)"
+        );
+        assert_eq!(format_distance(1999.9999), "2000");
+        assert_eq!(format_distance(0.0125), "0.013");
+
+        // the label sits on the middle of the ruler and reads left to right
+        let label = r.label(None);
+        assert_eq!(label.text, "5 (dx 3, dy 4)");
+        assert_eq!((label.position.x, label.position.y), (8.5, 18.0));
+        assert!(label.rotation.abs() <= std::f32::consts::FRAC_PI_2);
+        assert_eq!(label.height, 5.0 * LABEL_HEIGHT_RATIO);
+    }
+
+    #[test]
+    fn distances_are_in_database_units_when_the_layout_has_them() {
+        let mut layout = Layout::default();
+        assert_eq!(db_unit(&layout), None);
+        layout.db_unit = Some(0.0);
+        assert_eq!(db_unit(&layout), None);
+        layout.db_unit = Some(0.001);
+        assert_eq!(db_unit(&layout), Some(0.001));
+
+        // in microns, 1 nm database units
+        let r = ruler(0.0, 0.0, 0.3, 0.4);
+        assert_eq!(r.text(db_unit(&layout)), "500 dbu (dx 300, dy 400)");
+        assert_eq!(r.label(db_unit(&layout)).text, "500 dbu (dx 300, dy 400)");
+        // without database units, in user units
+        assert_eq!(r.text(None), "0.500 (dx 0.300, dy 0.400)");
+    }
+
+    #[test]
+    fn rulers_are_listed_and_exported() {
+        let rulers = Rulers {
+            rulers: vec![ruler(0.0, 0.0, 3.0, 4.0), ruler(1.5, 2.0, 0.0, 2.0)],
+        };
+        assert_eq!(
+            rulers.rulers[1].summary(1, None),
+            "ruler 1: (1.5, 2) -> (0, 2): 1.500 (dx 1.500, dy 0)"
+        );
+        assert_eq!(
+            rulers.rulers[1].summary(1, Some(0.5)),
+            "ruler 1: (1.5, 2) -> (0, 2): 3 dbu (dx 3, dy 0)"
+        );
+        assert_eq!(
+            rulers.to_csv(None),
+            "index,x0,y0,x1,y1,dx,dy,distance,unit\n\
+             0,0,0,3,4,3,4,5,user\n\
+             1,1.5,2,0,2,1.5,0,1.5,user\n"
+        );
+        // the ends stay in user units
+        assert_eq!(
+            rulers.to_csv(Some(0.5)),
+            "index,x0,y0,x1,y1,dx,dy,distance,unit\n\
+             0,0,0,3,4,6,8,10,dbu\n\
+             1,1.5,2,0,2,3,0,3,dbu\n"
+        );
+        assert_eq!(
+            Rulers::default().to_csv(None),
+            "index,x0,y0,x1,y1,dx,dy,distance,unit\n"
+        );
+    }
+
+    #[test]
+    fn dragged_rulers_snap_to_geometry() {
+        let rects = [rect(0.0, 0.0, 10.0, 4.0)];
+        let mut world = World::new();
+        world.insert_resource(Input::<MouseButton>::default());
+        world.insert_resource(CursorOverUi::default());
+        world.insert_resource(Rulers::default());
+        world.insert_resource(Layout::default());
+        world.insert_resource(RulerTool {
+            active: true,
+            ..default()
+        });
+        let mut stage = SystemStage::single_threaded().with_system(ruler_drag);
+        let mut frame = |world: &mut World, cursor: Vec2, button: Option<bool>| {
+            let snap = snap_to_geometry(rects.iter(), cursor, 0.5);
+            world.resource_mut::<RulerTool>().aim(cursor, snap);
+            let mut mouse_buttons = world.resource_mut::<Input<MouseButton>>();
+            mouse_buttons.clear();
+            match button {
+                Some(true) => mouse_buttons.press(MouseButton::Left),
+                Some(false) => mouse_buttons.release(MouseButton::Left),
+                None => {}
+            }
+            stage.run(world);
+        };
+
+        // pressed next to one corner, moved past an edge and released next to
+        // the opposite corner
+        frame(&mut world, Vec2::new(0.2, -0.3), Some(true));
+        frame(&mut world, Vec2::new(5.0, 4.2), None);
+        let drag = world.resource::<RulerTool>().drag.clone().unwrap();
+        assert_eq!((drag.start, drag.end), (Vec2::ZERO, Vec2::new(5.0, 4.0)));
+        frame(&mut world, Vec2::new(9.8, 4.3), Some(false));
+
+        let rulers = &world.resource::<Rulers>().rulers;
+        assert_eq!(rulers.len(), 1);
+        assert_eq!(
+            (rulers[0].start, rulers[0].end),
+            (Vec2::ZERO, Vec2::new(10.0, 4.0))
+        );
+        assert!(world.resource::<RulerTool>().drag.is_none());
+        let label = world.get::<Label>(rulers[0].label).unwrap();
+        assert_eq!(label.text, rulers[0].text(None));
+
+        // away from any rect the cursor is used as is, and a click without a
+        // drag leaves no ruler behind
+        frame(&mut world, Vec2::new(20.0, 20.0), Some(true));
+        let label = world.resource::<RulerTool>().drag.as_ref().unwrap().label;
+        assert_eq!(
+            world.resource::<RulerTool>().drag.as_ref().unwrap().start,
+            Vec2::new(20.0, 20.0)
+        );
+        frame(&mut world, Vec2::new(20.0, 20.0), Some(false));
+        assert_eq!(world.resource::<Rulers>().rulers.len(), 1);
+        assert!(world.get_entity(label).is_none());
+    }
+}
@@ -0,0 +1,168 @@
+use clap::ArgEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{DRect, Point};
+
+// Generated scenes for trying out and benchmarking the renderer. Random scenes take
+// a seed, so the same arguments always give the same rects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ArgEnum)]
+pub enum Scene {
+    // the two overlapping rects of the demo
+    Ordered,
+    // a diagonal run of growing rects with growing strokes
+    Overlapping,
+    // rects of any size, corners anywhere in a 600 x 600 square
+    Random,
+    // 1 x 1 rects placed anywhere in a 600 x 600 square
+    SmallRandom,
+}
+
+impl Scene {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scene::Ordered => "ordered",
+            Scene::Overlapping => "overlapping",
+            Scene::Random => "random",
+            Scene::SmallRandom => "small-random",
+        }
+    }
+
+    pub fn generate(&self, count: usize, seed: u64) -> Vec<DRect> {
+        match self {
+            Scene::Ordered => ordered_rects(false),
+            Scene::Overlapping => overlapping_rects(count as u32),
+            Scene::Random => many_random_rects(count, seed),
+            Scene::SmallRandom => many_small_random_rects(count, seed),
+        }
+    }
+}
+
+fn random_point<R: Rng + ?Sized>(rng: &mut R, min: Point, max: Point) -> Point {
+    Point {
+        x: rng.gen_range(min.x..max.x),
+        y: rng.gen_range(min.y..max.y),
+    }
+}
+
+impl DRect {
+    pub fn random<R: Rng + ?Sized>(rng: &mut R, min: Point, max: Point) -> Self {
+        DRect {
+            p0: random_point(rng, min, max),
+            p1: random_point(rng, min, max),
+            stroke_width: 0.1,
+            color: rng.gen_range(0..5),
+        }
+    }
+
+    pub fn randomly_placed<R: Rng + ?Sized>(rng: &mut R, min: Point, max: Point) -> Self {
+        let p0 = random_point(rng, min, max);
+        DRect {
+            p0,
+            p1: Point {
+                x: p0.x + 1.0,
+                y: p0.y + 1.0,
+            },
+            stroke_width: 0.1,
+            color: rng.gen_range(0..5),
+        }
+    }
+}
+
+pub fn ordered_rects(reverse: bool) -> Vec<DRect> {
+    let mut rects = vec![
+        DRect {
+            p0: Point { x: 0.0, y: 0.0 },
+            p1: Point { x: 100.0, y: 100.0 },
+            stroke_width: 1.0,
+            color: 0,
+        },
+        DRect {
+            p0: Point { x: 50.0, y: 50.0 },
+            p1: Point { x: 150.0, y: 150.0 },
+            stroke_width: 1.0,
+            color: 2,
+        },
+    ];
+    if reverse {
+        rects.reverse();
+    }
+    rects
+}
+
+fn overlapping_rects(n: u32) -> Vec<DRect> {
+    let scale = 10.0;
+    let translate = 10.0;
+    (0..n)
+        .map(|ix| {
+            let i = ix as f32;
+            DRect {
+                p0: Point {
+                    x: i * scale - translate,
+                    y: i * scale - translate,
+                },
+                p1: Point {
+                    x: (1.25 * i + 1.0) * scale - translate,
+                    y: (1.25 * i + 1.0) * scale - translate,
+                },
+                stroke_width: 1.0 * i,
+                color: ix % 5,
+            }
+        })
+        .collect()
+}
+
+fn many_random_rects(n: usize, seed: u64) -> Vec<DRect> {
+    let mut result = Vec::with_capacity(n);
+
+    let min = Point {
+        x: -300.0,
+        y: -300.0,
+    };
+    let max = Point { x: 300.0, y: 300.0 };
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..n {
+        result.push(DRect::random(&mut rng, min, max))
+    }
+
+    result
+}
+
+fn many_small_random_rects(n: usize, seed: u64) -> Vec<DRect> {
+    let mut result = Vec::with_capacity(n);
+
+    let min = Point {
+        x: -300.0,
+        y: -300.0,
+    };
+    let max = Point { x: 300.0, y: 300.0 };
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..n {
+        result.push(DRect::randomly_placed(&mut rng, min, max))
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_scenes_follow_their_seed() {
+        for scene in [Scene::Random, Scene::SmallRandom] {
+            let rects = scene.generate(100, 7);
+            assert_eq!(rects.len(), 100);
+            assert_eq!(rects, scene.generate(100, 7));
+            assert_ne!(rects, scene.generate(100, 8));
+            // a smaller scene starts like the bigger one
+            assert_eq!(scene.generate(10, 7), rects[..10]);
+        }
+        assert_eq!(
+            Scene::Overlapping.generate(5, 1),
+            Scene::Overlapping.generate(5, 2)
+        );
+    }
+}
@@ -0,0 +1,479 @@
+use std::fmt::{self, Write as _};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy::window::ReceivedCharacter;
+use regex::Regex;
+
+use crate::attributes::{QuadAttributes, ShapeAttributes};
+use crate::bounds::Bounds;
+use crate::camera::{fit_camera, view_size};
+use crate::layout::{LayerKey, Layout};
+use crate::overlay::Overlay;
+use crate::split_views::{FocusedView, Viewport};
+use crate::vpull::QuadHighlights;
+use crate::{BatchedQuads, DRect};
+
+// Finding shapes by their attributes: the net they are on, the cells they were
+// placed through or their GDS properties.
+
+// Where X writes the matches unless told otherwise.
+pub const SEARCH_OUTPUT: &str = "search_results.txt";
+
+const CURRENT_COLOR: Color = Color::YELLOW;
+const MARKER_WIDTH_PX: f32 = 2.0;
+
+#[derive(Clone, Debug)]
+enum Field {
+    Net,
+    // any cell on the instance path
+    Cell,
+    // the values of one property attribute, or of all of them
+    Property(Option<i16>),
+}
+
+// `net=<regex>`, `cell=<regex>`, `property=<regex>` or `property:<attribute>=<regex>`;
+// anything else is a cell name regex. Regexes match whole names.
+#[derive(Clone, Debug)]
+pub struct SearchQuery {
+    text: String,
+    field: Field,
+    pattern: Regex,
+}
+
+impl FromStr for SearchQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let text = s.trim();
+        let (field, pattern) = match text.split_once('=') {
+            Some(("net", pattern)) => (Field::Net, pattern),
+            Some(("cell", pattern)) => (Field::Cell, pattern),
+            Some(("property", pattern)) => (Field::Property(None), pattern),
+            Some((key, pattern)) if key.starts_with("property:") => {
+                let attribute = key["property:".len()..]
+                    .parse()
+                    .map_err(|e| format!("bad property attribute in {:?}: {}", text, e))?;
+                (Field::Property(Some(attribute)), pattern)
+            }
+            _ => (Field::Cell, text),
+        };
+        let pattern = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| format!("bad search {:?}: {}", text, e))?;
+        Ok(SearchQuery {
+            text: text.to_string(),
+            field,
+            pattern,
+        })
+    }
+}
+
+impl fmt::Display for SearchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl SearchQuery {
+    pub fn matches(&self, attributes: &ShapeAttributes) -> bool {
+        match self.field {
+            Field::Net => attributes
+                .net
+                .as_deref()
+                .is_some_and(|net| self.pattern.is_match(net)),
+            Field::Cell => attributes.instance.as_deref().is_some_and(|instance| {
+                instance.split('/').any(|placement| {
+                    // TOP/INV[3] places INV
+                    let cell = placement.split('[').next().unwrap_or_default();
+                    self.pattern.is_match(cell)
+                })
+            }),
+            Field::Property(attribute) => attributes.properties.iter().any(|(a, value)| {
+                attribute.is_none_or(|attribute| attribute == *a) && self.pattern.is_match(value)
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchMatch {
+    pub layer: LayerKey,
+    // index in the searched rects
+    pub index: u32,
+    pub bounds: Bounds,
+    pub attributes: ShapeAttributes,
+}
+
+impl fmt::Display for SearchMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Bounds { min, max } = self.bounds;
+        write!(
+            f,
+            "layer {} shape {} ({}, {}) - ({}, {}): {}",
+            self.layer, self.index, min.x, min.y, max.x, max.y, self.attributes
+        )
+    }
+}
+
+pub fn search_rects(
+    layer: LayerKey,
+    rects: &[DRect],
+    attributes: Option<&QuadAttributes>,
+    query: &SearchQuery,
+) -> Vec<SearchMatch> {
+    let attributes = match attributes {
+        Some(attributes) => attributes,
+        None => return Vec::new(),
+    };
+    let mut matches = Vec::new();
+    // rects of one shape share their attributes, which are only matched once
+    let mut last: Option<(*const ShapeAttributes, bool)> = None;
+    for (index, rect) in rects.iter().enumerate() {
+        let shape = match attributes.get(index) {
+            Some(shape) => shape,
+            None => continue,
+        };
+        let matched = match last {
+            Some((previous, matched)) if std::ptr::eq(previous, shape) => matched,
+            _ => query.matches(shape),
+        };
+        last = Some((shape, matched));
+        if matched {
+            matches.push(SearchMatch {
+                layer,
+                index: index as u32,
+                bounds: Bounds::from_rect(rect),
+                attributes: shape.clone(),
+            });
+        }
+    }
+    matches
+}
+
+pub fn search_layout(layout: &Layout, query: &SearchQuery) -> Vec<SearchMatch> {
+    layout
+        .layers
+        .iter()
+        .flat_map(|layer| {
+            let key = layout.layer_keys.get(layer.index as usize);
+            search_rects(
+                key.copied().unwrap_or_default(),
+                &layer.rects,
+                layer.attributes.as_ref(),
+                query,
+            )
+        })
+        .collect()
+}
+
+// The number of matches, then every match.
+pub fn search_report<'a>(
+    query: &SearchQuery,
+    matches: impl ExactSizeIterator<Item = &'a SearchMatch>,
+) -> String {
+    let mut out = String::new();
+    writeln!(out, "{} shapes match {}", matches.len(), query).unwrap();
+    for (i, found) in matches.enumerate() {
+        writeln!(out, "{:>6}: {}", i + 1, found).unwrap();
+    }
+    out
+}
+
+pub struct SearchResults {
+    pub query: Option<SearchQuery>,
+    pub matches: Vec<(Entity, SearchMatch)>,
+    // the one last stepped to
+    pub current: Option<usize>,
+    // where X writes the matches
+    pub output: PathBuf,
+    // the query has to be run again
+    pending: bool,
+}
+
+// The text typed after /, shown in the window title.
+#[derive(Default)]
+struct SearchPrompt {
+    text: Option<String>,
+    title: String,
+}
+
+// / opens a search prompt in the window title: Enter searches, Escape cancels.
+// Matches are highlighted; Page Down and Page Up step to the next and previous
+// match, centering the focused view on it, and X writes them to a file.
+pub struct SearchPlugin {
+    pub query: Option<SearchQuery>,
+    pub output: PathBuf,
+}
+
+impl Default for SearchPlugin {
+    fn default() -> Self {
+        SearchPlugin {
+            query: None,
+            output: PathBuf::from(SEARCH_OUTPUT),
+        }
+    }
+}
+
+impl Plugin for SearchPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SearchResults {
+            query: self.query.clone(),
+            matches: Vec::new(),
+            current: None,
+            output: self.output.clone(),
+            pending: self.query.is_some(),
+        })
+        .init_resource::<SearchPrompt>()
+        .init_resource::<QuadHighlights>()
+        .init_resource::<FocusedView>()
+        // keys typed into the prompt are taken before the other tools see them
+        .add_system_to_stage(CoreStage::PreUpdate, search_prompt.after(InputSystem))
+        .add_system(run_search)
+        .add_system(step_matches.after(run_search))
+        .add_system(export_matches.after(run_search))
+        .add_system(draw_current_match.after(step_matches));
+    }
+}
+
+fn search_prompt(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut windows: ResMut<Windows>,
+    mut prompt: ResMut<SearchPrompt>,
+    mut results: ResMut<SearchResults>,
+) {
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    if prompt.text.is_none() {
+        if keys.just_pressed(KeyCode::Slash) {
+            // the slash itself is not part of the query
+            characters.iter().for_each(drop);
+            prompt.title = window.title().to_string();
+            prompt.text = Some(String::new());
+        } else {
+            return;
+        }
+    }
+
+    let SearchPrompt { text, title } = &mut *prompt;
+    let text = text.as_mut().unwrap();
+    text.extend(
+        characters
+            .iter()
+            .map(|c| c.char)
+            .filter(|c| !c.is_control()),
+    );
+    if keys.just_pressed(KeyCode::Back) {
+        text.pop();
+    }
+    let done = if keys.just_pressed(KeyCode::Return) {
+        match text.parse::<SearchQuery>() {
+            Ok(query) => {
+                results.query = Some(query);
+                results.pending = true;
+                true
+            }
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
+        }
+    } else {
+        keys.just_pressed(KeyCode::Escape)
+    };
+    let pressed: Vec<KeyCode> = keys.get_pressed().copied().collect();
+    for key in pressed {
+        keys.reset(key);
+    }
+
+    if done {
+        window.set_title(title.clone());
+        prompt.text = None;
+    } else {
+        let title = format!("{} - search: {}_", title, text);
+        if window.title() != title {
+            window.set_title(title);
+        }
+    }
+}
+
+fn run_search(
+    layout: Res<Layout>,
+    changed: Query<(), Changed<BatchedQuads>>,
+    batches: Query<(Entity, &BatchedQuads, Option<&QuadAttributes>)>,
+    mut results: ResMut<SearchResults>,
+    mut highlights: ResMut<QuadHighlights>,
+) {
+    if !changed.is_empty() && results.query.is_some() {
+        results.pending = true;
+    }
+    if !results.pending || batches.iter().any(|(_, batch, _)| !batch.validated) {
+        return;
+    }
+    results.pending = false;
+    let query = match &results.query {
+        Some(query) => query.clone(),
+        None => return,
+    };
+
+    let mut sorted: Vec<_> = batches.iter().collect();
+    sorted.sort_by_key(|(_, batch, _)| batch.layer);
+    let mut matches = Vec::new();
+    let mut quads: HashMap<Entity, Vec<u32>> = HashMap::default();
+    for (entity, batch, attributes) in sorted {
+        let key = layout.layer_keys.get(batch.layer as usize);
+        let key = key.copied().unwrap_or_default();
+        for found in search_rects(key, &batch.data, attributes, &query) {
+            quads.entry(entity).or_default().push(found.index);
+            matches.push((entity, found));
+        }
+    }
+    info!("{} shapes match {}", matches.len(), query);
+    results.matches = matches;
+    results.current = None;
+    highlights.quads = quads;
+}
+
+fn step_matches(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    focused: Res<FocusedView>,
+    mut results: ResMut<SearchResults>,
+    mut cameras: Query<(
+        &mut Transform,
+        &mut OrthographicProjection,
+        Option<&Viewport>,
+    )>,
+) {
+    let count = results.matches.len();
+    let step = match (
+        keys.just_pressed(KeyCode::PageDown),
+        keys.just_pressed(KeyCode::PageUp),
+    ) {
+        (true, false) => 1,
+        (false, true) => count.saturating_sub(1),
+        _ => return,
+    };
+    if count == 0 {
+        info!("no search matches");
+        return;
+    }
+    let current = match results.current {
+        Some(current) => (current + step) % count,
+        None if step == 1 => 0,
+        None => count - 1,
+    };
+    results.current = Some(current);
+    let (_, found) = &results.matches[current];
+    info!("match {}/{}: {}", current + 1, count, found);
+
+    // the match takes up about a quarter of the view
+    let camera = focused.0.and_then(|entity| cameras.get_mut(entity).ok());
+    if let (Some((mut transform, mut projection, viewport)), Some(window)) =
+        (camera, windows.get_primary())
+    {
+        fit_camera(
+            &mut transform,
+            &mut projection,
+            &found.bounds,
+            view_size(window, viewport),
+            4.0,
+        );
+    }
+}
+
+fn export_matches(keys: Res<Input<KeyCode>>, results: Res<SearchResults>) {
+    if !keys.just_pressed(KeyCode::X) {
+        return;
+    }
+    let query = match &results.query {
+        Some(query) => query,
+        None => {
+            info!("nothing searched yet");
+            return;
+        }
+    };
+    let report = search_report(query, results.matches.iter().map(|(_, found)| found));
+    match std::fs::write(&results.output, report) {
+        Ok(()) => info!(
+            "wrote {} matches to {}",
+            results.matches.len(),
+            results.output.display()
+        ),
+        Err(e) => error!("could not write {}: {}", results.output.display(), e),
+    }
+}
+
+fn draw_current_match(results: Res<SearchResults>, mut overlay: ResMut<Overlay>) {
+    if let Some((_, found)) = results.current.and_then(|i| results.matches.get(i)) {
+        let margin = Vec2::splat(found.bounds.size().max_element() * 0.25);
+        overlay.rect(
+            found.bounds.min - margin,
+            found.bounds.max + margin,
+            MARKER_WIDTH_PX,
+            CURRENT_COLOR,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(net: Option<&str>, instance: &str, properties: &[(i16, &str)]) -> ShapeAttributes {
+        ShapeAttributes {
+            net: net.map(String::from),
+            instance: Some(instance.into()),
+            properties: properties
+                .iter()
+                .map(|(attribute, value)| (*attribute, value.to_string()))
+                .collect(),
+        }
+    }
+
+    fn query(text: &str) -> SearchQuery {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn queries_match_attributes() {
+        let inverter = shape(Some("VDD"), "TOP/INV[3]/NMOS[0:1,2]", &[(1, "pin A")]);
+        assert!(query("net=VDD").matches(&inverter));
+        assert!(!query("net=VD").matches(&inverter));
+        assert!(query("net=V.*").matches(&inverter));
+        assert!(query("cell=INV").matches(&inverter));
+        assert!(query("NMOS").matches(&inverter));
+        assert!(!query("INV\\[3\\]").matches(&inverter));
+        assert!(query("property=pin .").matches(&inverter));
+        assert!(query("property:1=pin A").matches(&inverter));
+        assert!(!query("property:2=pin A").matches(&inverter));
+        assert!(!query("net=VDD").matches(&shape(None, "TOP", &[])));
+
+        assert!("property:x=a".parse::<SearchQuery>().is_err());
+        assert!("net=(".parse::<SearchQuery>().is_err());
+    }
+
+    #[test]
+    fn rects_of_matching_shapes_are_found() {
+        let mut attributes = QuadAttributes::default();
+        attributes.push(Some(shape(Some("VDD"), "TOP", &[])), 2);
+        attributes.push(None, 1);
+        attributes.push(Some(shape(Some("VSS"), "TOP", &[])), 1);
+        let rects = vec![DRect::default(); 4];
+        let layer = LayerKey {
+            layer: 1,
+            datatype: 0,
+        };
+        let found = search_rects(layer, &rects, Some(&attributes), &query("net=V(DD|SS)"));
+        let indices: Vec<u32> = found.iter().map(|found| found.index).collect();
+        assert_eq!(indices, vec![0, 1, 3]);
+        assert!(search_rects(layer, &rects, None, &query("TOP")).is_empty());
+        let report = search_report(&query("net=VSS"), found[2..].iter());
+        assert!(report.starts_with("1 shapes match net=VSS\n"), "{}", report);
+    }
+}
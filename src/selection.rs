@@ -0,0 +1,193 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use bevy::utils::HashMap;
+
+use crate::attributes::{QuadAttributes, ShapeAttributes};
+use crate::bounds::Bounds;
+use crate::camera::{cursor_world_position, CursorOverUi};
+use crate::layout::{LayerKey, Layout};
+use crate::split_views::{FocusedView, LayerVisibility, Viewport};
+use crate::vpull::QuadHighlights;
+use crate::{BatchedQuads, DRect};
+
+// A click moves the cursor less than this many pixels between press and release.
+const CLICK_SLOP_PX: f32 = 4.0;
+
+// A shape as the tools see it: where it is drawn and what is known about it.
+#[derive(Clone, Debug)]
+pub struct SelectedShape {
+    pub batch: Entity,
+    pub layer: LayerKey,
+    // index in the batch's validated rects
+    pub index: u32,
+    pub rect: DRect,
+    pub attributes: ShapeAttributes,
+}
+
+// The shapes picked by the last click, empty after a click on nothing.
+#[derive(Default)]
+pub struct Selection {
+    pub shapes: Vec<SelectedShape>,
+}
+
+// Systems that react to a new selection run after this label.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, SystemLabel)]
+pub struct SelectShapes;
+
+// Looking up shapes and their attributes, for tools that pick or search them.
+#[derive(SystemParam)]
+pub struct ShapeQuery<'w, 's> {
+    layout: Res<'w, Layout>,
+    batches: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static BatchedQuads,
+            Option<&'static QuadAttributes>,
+        ),
+    >,
+}
+
+impl<'w, 's> ShapeQuery<'w, 's> {
+    pub fn shape(&self, batch: Entity, index: u32) -> Option<SelectedShape> {
+        let (_, quads, attributes) = self.batches.get(batch).ok()?;
+        Some(SelectedShape {
+            batch,
+            layer: self.layer_key(quads.layer),
+            index,
+            rect: *quads.data.get(index as usize)?,
+            attributes: attributes
+                .and_then(|attributes| attributes.get(index as usize))
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+
+    // The shape of a batch with exactly this rect.
+    pub fn find(&self, batch: Entity, rect: &DRect) -> Option<SelectedShape> {
+        let (_, quads, _) = self.batches.get(batch).ok()?;
+        let index = quads.data.iter().position(|r| r == rect)?;
+        self.shape(batch, index as u32)
+    }
+
+    // Shapes under `point` on the layers `visible` accepts, topmost first.
+    pub fn at(&self, point: Vec2, visible: impl Fn(u8) -> bool) -> Vec<SelectedShape> {
+        let mut batches: Vec<(Entity, &BatchedQuads)> = self
+            .batches
+            .iter()
+            .filter(|(_, quads, _)| quads.validated && visible(quads.layer))
+            .map(|(entity, quads, _)| (entity, quads))
+            .collect();
+        batches.sort_by_key(|(_, quads)| std::cmp::Reverse(quads.layer));
+        batches
+            .into_iter()
+            .flat_map(|(entity, quads)| {
+                quads
+                    .data
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .filter(|(_, rect)| {
+                        let r = Bounds::from_rect(rect);
+                        point.cmpge(r.min).all() && point.cmple(r.max).all()
+                    })
+                    .map(move |(index, _)| (entity, index as u32))
+            })
+            .filter_map(|(entity, index)| self.shape(entity, index))
+            .collect()
+    }
+
+    pub fn layer_key(&self, layer: u8) -> LayerKey {
+        self.layout
+            .layer_keys
+            .get(layer as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+// A click selects the topmost visible shape under the cursor in the focused view,
+// highlights it and logs its attributes; a click on nothing clears the selection.
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .init_resource::<QuadHighlights>()
+            .init_resource::<FocusedView>()
+            .init_resource::<CursorOverUi>()
+            .add_system(select_on_click.label(SelectShapes));
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn select_on_click(
+    windows: Res<Windows>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    over_ui: Res<CursorOverUi>,
+    focused: Res<FocusedView>,
+    cameras: Query<
+        (
+            &Camera,
+            &GlobalTransform,
+            Option<&Viewport>,
+            Option<&LayerVisibility>,
+        ),
+        With<Camera2d>,
+    >,
+    shapes: ShapeQuery,
+    mut pressed_at: Local<Option<Vec2>>,
+    mut selection: ResMut<Selection>,
+    mut highlights: ResMut<QuadHighlights>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    if mouse_buttons.just_pressed(MouseButton::Left) && !over_ui.0 {
+        *pressed_at = window.cursor_position();
+    }
+    if !mouse_buttons.just_released(MouseButton::Left) {
+        return;
+    }
+    let click = match (pressed_at.take(), window.cursor_position()) {
+        (Some(pressed), Some(released)) if pressed.distance(released) < CLICK_SLOP_PX => released,
+        _ => return,
+    };
+    let (camera, camera_transform, viewport, layers) =
+        match focused.0.and_then(|entity| cameras.get(entity).ok()) {
+            Some(view) => view,
+            None => return,
+        };
+    if viewport.is_some_and(|viewport| !viewport.contains(window, click)) {
+        return;
+    }
+    let point = match cursor_world_position(window, camera, camera_transform, viewport) {
+        Some(point) => point,
+        None => return,
+    };
+
+    let picked: Vec<SelectedShape> = shapes
+        .at(point, |layer| {
+            layers.is_none_or(|layers| layers.is_visible(layer))
+        })
+        .into_iter()
+        .take(1)
+        .collect();
+    if picked.is_empty() && selection.shapes.is_empty() {
+        return;
+    }
+    let mut quads: HashMap<Entity, Vec<u32>> = HashMap::default();
+    for shape in picked.iter() {
+        let r = Bounds::from_rect(&shape.rect);
+        info!(
+            "layer {} shape {} ({}, {}) - ({}, {}): {}",
+            shape.layer, shape.index, r.min.x, r.min.y, r.max.x, r.max.y, shape.attributes
+        );
+        quads.entry(shape.batch).or_default().push(shape.index);
+    }
+    selection.shapes = picked;
+    highlights.quads = quads;
+}
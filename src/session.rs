@@ -0,0 +1,326 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{Bookmark, Bookmarks};
+use crate::grid::GridSettings;
+use crate::layout::{LayerKey, Layout};
+use crate::ruler::{Ruler, Rulers};
+use crate::split_views::{LayerVisibility, SplitCamera};
+use crate::vpull::Palette;
+
+// What the viewer remembers between launches, kept as JSON. The view state (camera,
+// layer order and visibility, rulers, bookmarks, grid) belongs to the files that were
+// open and is only restored when the same files are opened again; the palette is
+// always restored. Started without a command, the viewer reopens the last files.
+
+// How often a changed session is written while the viewer runs; it is also
+// written at exit.
+const AUTOSAVE_SECONDS: f64 = 2.0;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    // arguments of the command that opened the files
+    pub command: Vec<String>,
+    pub files: Vec<PathBuf>,
+    pub camera: Option<SessionCamera>,
+    // "layer/datatype" keys, in draw order
+    pub layer_order: Vec<String>,
+    pub hidden_layers: Vec<String>,
+    // "RRGGBB" or "RRGGBBAA"
+    pub palette: Vec<String>,
+    // x0, y0, x1, y1
+    pub rulers: Vec<[f32; 4]>,
+    pub bookmarks: Vec<Bookmark>,
+    // world units; the command line's take precedence
+    pub grid_pitch: Option<f32>,
+    pub manufacturing_grid: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionCamera {
+    pub center: [f32; 2],
+    pub scale: f32,
+}
+
+impl Session {
+    // None if there is no session file yet.
+    pub fn read(path: &Path) -> io::Result<Option<Session>> {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    // Whether the view state was saved for these files.
+    pub fn matches(&self, files: &[PathBuf]) -> bool {
+        self.files == canonical(files)
+    }
+
+    pub fn layer_order(&self) -> Vec<LayerKey> {
+        parse_keys(&self.layer_order)
+    }
+}
+
+// Files as absolute paths where they exist, so that the same file opened from
+// elsewhere still matches.
+fn canonical(files: &[PathBuf]) -> Vec<PathBuf> {
+    files
+        .iter()
+        .map(|file| std::fs::canonicalize(file).unwrap_or_else(|_| file.clone()))
+        .collect()
+}
+
+fn parse_keys(keys: &[String]) -> Vec<LayerKey> {
+    keys.iter().filter_map(|key| key.parse().ok()).collect()
+}
+
+fn color_hex(color: &Color) -> String {
+    let [r, g, b, a] = color.as_rgba_f32().map(|c| (c * 255.0).round() as u8);
+    if a == u8::MAX {
+        format!("{:02X}{:02X}{:02X}", r, g, b)
+    } else {
+        format!("{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+    }
+}
+
+pub struct SessionPlugin {
+    pub path: PathBuf,
+    pub command: Vec<String>,
+    pub files: Vec<PathBuf>,
+    // the session read at startup
+    pub restore: Option<Session>,
+}
+
+struct SessionState {
+    path: PathBuf,
+    command: Vec<String>,
+    files: Vec<PathBuf>,
+    restore: Option<Session>,
+    // what was last written, so that an unchanged session isn't written again
+    saved: Option<Session>,
+}
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SessionState {
+            path: self.path.clone(),
+            command: self.command.clone(),
+            files: canonical(&self.files),
+            restore: self.restore.clone(),
+            saved: None,
+        })
+        .init_resource::<Palette>()
+        .init_resource::<Rulers>()
+        .init_resource::<Bookmarks>()
+        .init_resource::<GridSettings>()
+        // the camera is spawned by a startup system
+        .add_startup_system_to_stage(StartupStage::PostStartup, restore_session)
+        .add_system_to_stage(CoreStage::Last, save_session);
+    }
+}
+
+type MainCamera = (With<Camera2d>, Without<SplitCamera>);
+
+#[allow(clippy::too_many_arguments)]
+fn restore_session(
+    mut commands: Commands,
+    mut state: ResMut<SessionState>,
+    layout: Res<Layout>,
+    mut palette: ResMut<Palette>,
+    mut rulers: ResMut<Rulers>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut grid: ResMut<GridSettings>,
+    mut cameras: Query<
+        (
+            &mut Transform,
+            &mut OrthographicProjection,
+            &mut LayerVisibility,
+        ),
+        MainCamera,
+    >,
+) {
+    let session = match state.restore.take() {
+        Some(session) => session,
+        None => return,
+    };
+    let colors: Vec<Color> = session
+        .palette
+        .iter()
+        .filter_map(|hex| Color::hex(hex).ok())
+        .collect();
+    if !colors.is_empty() {
+        palette.colors = colors;
+    }
+    if session.files != state.files {
+        return;
+    }
+
+    if let Some((mut transform, mut projection, mut layers)) = cameras.iter_mut().next() {
+        if let Some(camera) = session.camera {
+            transform.translation.x = camera.center[0];
+            transform.translation.y = camera.center[1];
+            projection.scale = camera.scale;
+        }
+        let hidden = parse_keys(&session.hidden_layers);
+        for (index, key) in layout.layer_keys.iter().enumerate() {
+            layers.set_visible(index as u8, !hidden.contains(key));
+        }
+    }
+    for [x0, y0, x1, y1] in session.rulers.iter() {
+        let (start, end) = (Vec2::new(*x0, *y0), Vec2::new(*x1, *y1));
+        let ruler = Ruler::spawn(&mut commands, start, end, &layout);
+        rulers.rulers.push(ruler);
+    }
+    bookmarks.bookmarks = session.bookmarks.clone();
+    grid.fixed_pitch = grid.fixed_pitch.or(session.grid_pitch);
+    grid.manufacturing_grid = grid.manufacturing_grid.or(session.manufacturing_grid);
+    info!("restored the session from {}", state.path.display());
+}
+
+#[allow(clippy::too_many_arguments)]
+fn save_session(
+    time: Res<Time>,
+    mut exits: EventReader<AppExit>,
+    mut state: ResMut<SessionState>,
+    layout: Res<Layout>,
+    palette: Res<Palette>,
+    rulers: Res<Rulers>,
+    bookmarks: Res<Bookmarks>,
+    grid: Res<GridSettings>,
+    cameras: Query<(&Transform, &OrthographicProjection, &LayerVisibility), MainCamera>,
+    mut last_check: Local<f64>,
+) {
+    let now = time.seconds_since_startup();
+    let exiting = exits.iter().next().is_some();
+    if !exiting && now - *last_check < AUTOSAVE_SECONDS {
+        return;
+    }
+    *last_check = now;
+
+    let view = cameras.iter().next();
+    let session = Session {
+        command: state.command.clone(),
+        files: state.files.clone(),
+        camera: view.map(|(transform, projection, _)| SessionCamera {
+            center: [transform.translation.x, transform.translation.y],
+            scale: projection.scale,
+        }),
+        layer_order: layout.layer_keys.iter().map(LayerKey::to_string).collect(),
+        hidden_layers: match view {
+            Some((.., layers)) => layout
+                .layer_keys
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !layers.is_visible(*index as u8))
+                .map(|(_, key)| key.to_string())
+                .collect(),
+            None => Vec::new(),
+        },
+        palette: palette.colors.iter().map(color_hex).collect(),
+        rulers: rulers
+            .rulers
+            .iter()
+            .map(|ruler| [ruler.start.x, ruler.start.y, ruler.end.x, ruler.end.y])
+            .collect(),
+        bookmarks: bookmarks.bookmarks.clone(),
+        grid_pitch: grid.fixed_pitch,
+        manufacturing_grid: grid.manufacturing_grid,
+    };
+    if state.saved.as_ref() == Some(&session) {
+        return;
+    }
+    // remembered even when writing fails, so that the error isn't repeated
+    if let Err(e) = session.write(&state.path) {
+        error!(
+            "could not save the session to {}: {}",
+            state.path.display(),
+            e
+        );
+    }
+    state.saved = Some(session);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::LabelAnchor;
+    use crate::layout::LayoutText;
+    use crate::test_support::layer;
+    use crate::{LayerRects, Point};
+
+    #[test]
+    fn layer_order_is_restored() {
+        let mut layout = Layout {
+            layers: (0..3)
+                .map(|index| LayerRects {
+                    rects: Vec::new(),
+                    index,
+                    attributes: None,
+                })
+                .collect(),
+            layer_keys: vec![layer(1), layer(2), layer(3)],
+            texts: vec![LayoutText {
+                text: "VDD".into(),
+                position: Point::default(),
+                layer: 0,
+                height: 1.0,
+                anchor: LabelAnchor::BottomLeft,
+                rotation: 0.0,
+            }],
+            ..Default::default()
+        };
+        let session = Session {
+            layer_order: vec!["3/0".into(), "1/0".into(), "9/0".into()],
+            ..Default::default()
+        };
+        layout.reorder_layers(&session.layer_order());
+        assert_eq!(layout.layer_keys, vec![layer(3), layer(1), layer(2)]);
+        let indices: Vec<u8> = layout.layers.iter().map(|layer| layer.index).collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+        assert_eq!(layout.texts[0].layer, 1);
+    }
+
+    #[test]
+    fn sessions_round_trip() {
+        let session = Session {
+            command: vec!["view".into(), "a.gds".into()],
+            camera: Some(SessionCamera {
+                center: [1.5, -2.0],
+                scale: 0.25,
+            }),
+            palette: Palette::default().colors.iter().map(color_hex).collect(),
+            rulers: vec![[0.0, 0.0, 3.0, 4.0]],
+            bookmarks: vec![Bookmark {
+                name: "bookmark 1".into(),
+                key: 1,
+                bbox: [0.0, 0.0, 10.0, 5.0],
+                hidden_layers: vec!["2/0".into()],
+            }],
+            grid_pitch: Some(0.5),
+            manufacturing_grid: Some(0.005),
+            ..Default::default()
+        };
+        assert_eq!(session.palette[0], crate::vpull::PALETTE[0]);
+        let json = serde_json::to_string(&session).unwrap();
+        assert_eq!(serde_json::from_str::<Session>(&json).unwrap(), session);
+        // fields missing from older files take their defaults
+        let old: Session = serde_json::from_str(r#"{"files": ["a.gds"]}"#).unwrap();
+        assert_eq!(old.files, vec![PathBuf::from("a.gds")]);
+        assert!(old.camera.is_none());
+        assert_eq!(old.manufacturing_grid, None);
+    }
+}
@@ -0,0 +1,64 @@
+struct View {
+    view_proj: mat4x4<f32>;
+    view: mat4x4<f32>;
+    inverse_view: mat4x4<f32>;
+    projection: mat4x4<f32>;
+    world_pos: vec3<f32>;
+    near: f32;
+    far: f32;
+    width: f32;
+    height: f32;
+};
+
+struct Grid {
+    color: vec4<f32>;
+    major_color: vec4<f32>;
+    pitch: f32;
+    major_every: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> view: View;
+
+[[group(0), binding(1)]]
+var<uniform> grid: Grid;
+
+struct VertexOutput {
+    [[builtin(position)]] screen_pos: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+// a single triangle covering the whole screen
+[[stage(vertex)]]
+fn vertex([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = vec2<f32>(f32(vertex_index & 1u) * 4.0 - 1.0, f32(vertex_index >> 1u) * 4.0 - 1.0);
+    out.screen_pos = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// Coverage of the grid lines with spacing `pitch`, for a one pixel wide line.
+fn line_coverage(world: vec2<f32>, world_per_pixel: vec2<f32>, pitch: f32) -> f32 {
+    let to_line = abs(fract(world / pitch + 0.5) - 0.5) * pitch / world_per_pixel;
+    let coverage = max(1.0 - to_line.x, 1.0 - to_line.y);
+    // lines closer together than a few pixels fade out instead of turning into noise
+    let spacing = min(pitch / world_per_pixel.x, pitch / world_per_pixel.y);
+    return clamp(coverage, 0.0, 1.0) * clamp((spacing - 3.0) / 5.0, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // orthographic projection: undo the scale and translation of the projection,
+    // then move from view to world space
+    let view_xy = (in.ndc - view.projection[3].xy) / vec2<f32>(view.projection[0].x, view.projection[1].y);
+    let world = (view.view * vec4<f32>(view_xy, 0.0, 1.0)).xy;
+    let world_per_pixel = max(fwidth(world), vec2<f32>(1e-12, 1e-12));
+
+    let minor = line_coverage(world, world_per_pixel, grid.pitch);
+    let major = line_coverage(world, world_per_pixel, grid.pitch * grid.major_every);
+    if (major > 0.0) {
+        return vec4<f32>(grid.major_color.rgb, grid.major_color.a * major);
+    }
+    return vec4<f32>(grid.color.rgb, grid.color.a * minor);
+}
@@ -0,0 +1,89 @@
+struct View {
+    view_proj: mat4x4<f32>;
+    view: mat4x4<f32>;
+    inverse_view: mat4x4<f32>;
+    projection: mat4x4<f32>;
+    world_pos: vec3<f32>;
+    near: f32;
+    far: f32;
+    width: f32;
+    height: f32;
+};
+
+struct Glyph {
+    origin: vec2<f32>;
+    x_axis: vec2<f32>;
+    y_axis: vec2<f32>;
+    uv_min: vec2<f32>;
+    uv_max: vec2<f32>;
+    color: u32;
+    // minimum on-screen height of the glyph cell, in pixels
+    hide_below: f32;
+};
+
+struct Glyphs {
+    data: array<Glyph>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> view: View;
+
+[[group(1), binding(0)]]
+var<storage> glyphs: Glyphs;
+
+[[group(1), binding(1)]]
+var atlas: texture_2d<f32>;
+
+[[group(1), binding(2)]]
+var atlas_sampler: sampler;
+
+struct VertexOutput {
+    [[builtin(position)]] screen_pos: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vertex([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    // two triangles per glyph, no index buffer
+    let glyph = glyphs.data[vertex_index / 6u];
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index % 6u];
+
+    let world_pos = vec4<f32>(glyph.origin + corner.x * glyph.x_axis + corner.y * glyph.y_axis, 0.0, 1.0);
+    out.screen_pos = view.view_proj * world_pos;
+
+    // labels that would be too small to read collapse to a degenerate triangle
+    let clip_height = view.view_proj * vec4<f32>(glyph.y_axis, 0.0, 0.0);
+    let pixel_height = length(clip_height.xy * vec2<f32>(view.width, view.height)) * 0.5;
+    if (pixel_height < glyph.hide_below) {
+        out.screen_pos = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    // v grows downwards in the atlas
+    out.uv = mix(glyph.uv_min, glyph.uv_max, vec2<f32>(corner.x, 1.0 - corner.y));
+    out.color = unpack4x8unorm(glyph.color);
+    return out;
+}
+
+struct FragmentInput {
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+[[stage(fragment)]]
+fn fragment(in: FragmentInput) -> [[location(0)]] vec4<f32> {
+    let distance = textureSample(atlas, atlas_sampler, in.uv).r;
+    let width = max(fwidth(distance) * 0.7, 0.001);
+    let alpha = smoothStep(0.5 - width, 0.5 + width, distance);
+    return vec4<f32>(in.color.rgb, in.color.a * alpha);
+}
@@ -0,0 +1,97 @@
+struct View {
+    view_proj: mat4x4<f32>;
+    view: mat4x4<f32>;
+    inverse_view: mat4x4<f32>;
+    projection: mat4x4<f32>;
+    world_pos: vec3<f32>;
+    near: f32;
+    far: f32;
+    width: f32;
+    height: f32;
+};
+
+struct Segment {
+    a: vec2<f32>;
+    b: vec2<f32>;
+    width: f32;
+    color: u32;
+    flags: u32;
+    padding: u32;
+};
+
+let SCREEN_SPACE: u32 = 1u;
+let BUTT_CAPS: u32 = 2u;
+
+struct Segments {
+    data: array<Segment>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> view: View;
+
+struct OverlayView {
+    viewport_origin: vec2<f32>;
+};
+
+[[group(1), binding(0)]]
+var<storage> segments: Segments;
+
+[[group(1), binding(1)]]
+var<uniform> overlay_view: OverlayView;
+
+struct VertexOutput {
+    [[builtin(position)]] screen_pos: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vertex([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let segment = segments.data[vertex_index / 6u];
+    // x: position along the segment, y: side of the segment
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index % 6u];
+
+    // the segment is widened in pixel space, so its width does not depend on zoom;
+    // pixel positions are relative to the center of the screen
+    let half_size = vec2<f32>(view.width, view.height) * 0.5;
+    var a = (view.view_proj * vec4<f32>(segment.a, 0.0, 1.0)).xy * half_size;
+    var b = (view.view_proj * vec4<f32>(segment.b, 0.0, 1.0)).xy * half_size;
+    if ((segment.flags & SCREEN_SPACE) != 0u) {
+        // relative to this view's viewport; split views clip whatever falls outside
+        a = segment.a - overlay_view.viewport_origin - half_size;
+        b = segment.b - overlay_view.viewport_origin - half_size;
+    }
+    var dir = vec2<f32>(1.0, 0.0);
+    if (length(b - a) > 0.0001) {
+        dir = normalize(b - a);
+    }
+    let normal = vec2<f32>(-dir.y, dir.x);
+    let half_width = segment.width * 0.5;
+
+    // square caps, so that corners of joined segments are filled
+    var cap = half_width;
+    if ((segment.flags & BUTT_CAPS) != 0u) {
+        cap = 0.0;
+    }
+    let pixel_pos = mix(a, b, corner.x)
+        + dir * cap * (corner.x * 2.0 - 1.0)
+        + normal * half_width * corner.y;
+
+    out.screen_pos = vec4<f32>(pixel_pos / half_size, 0.0, 1.0);
+    out.color = unpack4x8unorm(segment.color);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fragment([[location(0)]] color: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    return color;
+}
@@ -0,0 +1,86 @@
+// Culls the quads of one chunk for one view: quads outside the view, or smaller
+// than `min_pixel_size` on screen, are dropped. The indices of the others are packed
+// into `visible`, and `args.index_count` grows by six for each, so that the chunk
+// can be drawn with draw_indexed_indirect.
+struct View {
+    view_proj: mat4x4<f32>;
+    view: mat4x4<f32>;
+    inverse_view: mat4x4<f32>;
+    projection: mat4x4<f32>;
+    world_pos: vec3<f32>;
+    near: f32;
+    far: f32;
+    width: f32;
+    height: f32;
+};
+
+struct CullSettings {
+    min_pixel_size: f32;
+};
+
+struct Quad {
+    p0: vec2<f32>;
+    p1: vec2<f32>;
+    stroke_width: f32;
+    color: u32;
+};
+
+struct Quads {
+    data: array<Quad>;
+};
+
+struct Visible {
+    indices: array<u32>;
+};
+
+// The first five fields are DrawIndexedIndirect's arguments.
+struct DrawArgs {
+    index_count: atomic<u32>;
+    instance_count: u32;
+    first_index: u32;
+    base_vertex: i32;
+    first_instance: u32;
+    // quads in the chunk; its buffer can be bigger
+    quad_count: u32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> view: View;
+
+[[group(0), binding(1)]]
+var<uniform> settings: CullSettings;
+
+[[group(1), binding(0)]]
+var<storage> quads: Quads;
+
+[[group(1), binding(1)]]
+var<storage, read_write> visible: Visible;
+
+[[group(1), binding(2)]]
+var<storage, read_write> args: DrawArgs;
+
+[[stage(compute), workgroup_size(64)]]
+fn cull([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    // dispatched in rows of 1024 workgroups of 64
+    let index = id.y * 65536u + id.x;
+    if (index >= args.quad_count) {
+        return;
+    }
+    let quad = quads.data[index];
+
+    // the camera is orthographic, so the corners give the box in clip space
+    let a = view.view_proj * vec4<f32>(quad.p0, 0.0, 1.0);
+    let b = view.view_proj * vec4<f32>(quad.p1, 0.0, 1.0);
+    let clip_min = min(a.xy, b.xy);
+    let clip_max = max(a.xy, b.xy);
+    if (any(clip_max < vec2<f32>(-1.0)) || any(clip_min > vec2<f32>(1.0))) {
+        return;
+    }
+    let pixels = (clip_max - clip_min) * 0.5 * vec2<f32>(view.width, view.height);
+    if (max(pixels.x, pixels.y) < settings.min_pixel_size) {
+        return;
+    }
+
+    let slot = atomicAdd(&args.index_count, 6u) / 6u;
+    visible.indices[slot] = index;
+}
@@ -35,23 +35,56 @@ var<storage> quads: Quads;
 [[group(1), binding(1)]]
 var<storage> palette: Palette;
 
+// one bit per quad, set for highlighted quads
+struct Flags {
+    bits: array<u32>;
+};
+
+[[group(1), binding(2)]]
+var<storage> flags: Flags;
+
+#ifdef GPU_CULLED
+// the quads that survived culling, written by quad_cull.wgsl
+struct Visible {
+    indices: array<u32>;
+};
+
+[[group(1), binding(3)]]
+var<storage> visible: Visible;
+#endif
+
 struct VertexOutput {
     [[builtin(position)]] screen_pos: vec4<f32>;
     [[location(0)]] d_bot_left: vec2<f32>;
     [[location(1)]] d_top_right: vec2<f32>;
     [[location(2)]] color: vec4<f32>;
     [[location(3), interpolate(flat)]] stroke_width: f32;
+    [[location(4), interpolate(flat)]] highlighted: u32;
 };
 
 [[stage(vertex)]]
 fn vertex([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
     var out: VertexOutput;
 
-    // x >> 2, divides x by 2
+#ifdef NON_INDEXED
+    // six vertices per quad, no index buffer: the corners come in the same order
+    // the index buffer would give them, 2 0 1 1 3 2, packed two bits each
+    let instance_index = vertex_index / 6u;
+    let corner = (0xb52u >> ((vertex_index % 6u) * 2u)) & 0x3u;
+#else
+    // x >> 2, divides x by 4
     let instance_index = vertex_index >> 2u;
-    let quad = quads.data[instance_index];
+    let corner = vertex_index & 0x3u;
+#endif
+#ifdef GPU_CULLED
+    let quad_index = visible.indices[instance_index];
+#else
+    let quad_index = instance_index;
+#endif
+    let quad = quads.data[quad_index];
+    let highlighted = (flags.bits[quad_index >> 5u] >> (quad_index & 31u)) & 1u;
 
-    let xyz = vec3<i32>(i32(vertex_index & 0x1u), i32((vertex_index & 0x2u) >> 1u), 0);
+    let xyz = vec3<i32>(i32(corner & 0x1u), i32((corner & 0x2u) >> 1u), 0);
     let uv = vec2<f32>(xyz.xy);
     let wh = quad.p1.xy - quad.p0.xy;
     let relative_pos = vec2<f32>(uv * wh);
@@ -62,7 +95,11 @@ fn vertex([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
     out.d_top_right = vec2<f32>(quad.p1 - world_pos.xy);
     out.screen_pos = view.view_proj * world_pos;
     out.color = palette.colors[quad.color];
+    if (highlighted != 0u) {
+        out.color = vec4<f32>(mix(out.color.rgb, vec3<f32>(1.0), 0.5), out.color.a);
+    }
     out.stroke_width = quad.stroke_width;
+    out.highlighted = highlighted;
     return out;
 }
 
@@ -73,6 +110,7 @@ struct FragmentInput {
     [[location(1)]] d_top_right: vec2<f32>;
     [[location(2)]] color: vec4<f32>;
     [[location(3), interpolate(flat)]] stroke_width: f32;
+    [[location(4), interpolate(flat)]] highlighted: u32;
 };
 
 [[stage(fragment)]]
@@ -82,7 +120,9 @@ fn fragment(in: FragmentInput) -> [[location(0)]] vec4<f32> {
     if (in.d_bot_left.x < t || in.d_bot_left.y < t || in.d_top_right.x < t || in.d_top_right.y < t) {
         return in.color;
     } else {
-        let c = vec4<f32>(local_color.xyz, 0.2);
+        // highlighted quads are filled more solidly
+        let alpha = select(0.2, 0.6, in.highlighted != 0u);
+        let c = vec4<f32>(local_color.xyz, alpha);
         return c;
     }
 }
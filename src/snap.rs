@@ -0,0 +1,480 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+
+use crate::camera::{cursor_world_position, world_units_per_pixel};
+use crate::grid::{GridSettings, ViewGrid};
+use crate::split_views::{FocusedView, LayerVisibility, Viewport};
+use crate::validation::validate_batched_quads;
+use crate::{BatchedQuads, DRect};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapKind {
+    Corner,
+    Edge,
+    Grid,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Snap {
+    pub point: Vec2,
+    pub kind: SnapKind,
+}
+
+pub struct SnapSettings {
+    pub geometry: bool,
+    // Snap to the visible grid pitch. The manufacturing grid is always honoured.
+    pub grid: bool,
+    // Pixel distance within which the cursor snaps to a rect corner or edge.
+    pub radius_px: f32,
+}
+
+impl Default for SnapSettings {
+    fn default() -> Self {
+        Self {
+            geometry: true,
+            grid: false,
+            radius_px: 8.0,
+        }
+    }
+}
+
+// Finds the rect corner, or failing that the point on a rect edge, closest to `p`
+// within `radius`. Corners win over edges so that widths are measured exactly.
+pub fn snap_to_geometry<'a>(
+    rects: impl Iterator<Item = &'a DRect>,
+    p: Vec2,
+    radius: f32,
+) -> Option<Snap> {
+    let mut best_corner: Option<(f32, Vec2)> = None;
+    let mut best_edge: Option<(f32, Vec2)> = None;
+    for rect in rects {
+        let (min, max) = (
+            Vec2::new(rect.p0.x, rect.p0.y),
+            Vec2::new(rect.p1.x, rect.p1.y),
+        );
+        if p.x < min.x - radius
+            || p.x > max.x + radius
+            || p.y < min.y - radius
+            || p.y > max.y + radius
+        {
+            continue;
+        }
+
+        for corner in [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)] {
+            let d = corner.distance(p);
+            if d <= radius && best_corner.is_none_or(|(best, _)| d < best) {
+                best_corner = Some((d, corner));
+            }
+        }
+
+        let clamped = p.clamp(min, max);
+        for edge_point in [
+            Vec2::new(min.x, clamped.y),
+            Vec2::new(max.x, clamped.y),
+            Vec2::new(clamped.x, min.y),
+            Vec2::new(clamped.x, max.y),
+        ] {
+            let d = edge_point.distance(p);
+            if d <= radius && best_edge.is_none_or(|(best, _)| d < best) {
+                best_edge = Some((d, edge_point));
+            }
+        }
+    }
+
+    best_corner
+        .map(|(_, point)| Snap {
+            point,
+            kind: SnapKind::Corner,
+        })
+        .or_else(|| {
+            best_edge.map(|(_, point)| Snap {
+                point,
+                kind: SnapKind::Edge,
+            })
+        })
+}
+
+pub fn snap_to_grid(p: Vec2, pitch: f32) -> Vec2 {
+    (p / pitch).round() * pitch
+}
+
+// Rects per block of a `SnapIndex`.
+const SNAP_BLOCK: usize = 64;
+
+// Rects sorted on min.x, with the largest max.x of every block of them.
+#[derive(Debug, Default)]
+struct SortedRun {
+    order: Vec<u32>,
+    block_max: Vec<f32>,
+}
+
+impl SortedRun {
+    fn new(rects: &[DRect], mut order: Vec<u32>) -> Self {
+        order.sort_by(|a, b| rects[*a as usize].p0.x.total_cmp(&rects[*b as usize].p0.x));
+        Self::sorted(rects, order)
+    }
+
+    fn sorted(rects: &[DRect], order: Vec<u32>) -> Self {
+        let block_max = order
+            .chunks(SNAP_BLOCK)
+            .map(|block| {
+                block
+                    .iter()
+                    .map(|&i| rects[i as usize].p1.x)
+                    .fold(f32::MIN, f32::max)
+            })
+            .collect();
+        Self { order, block_max }
+    }
+
+    fn merge(rects: &[DRect], a: SortedRun, b: SortedRun) -> Self {
+        let mut order = Vec::with_capacity(a.order.len() + b.order.len());
+        let (mut a, mut b) = (
+            a.order.into_iter().peekable(),
+            b.order.into_iter().peekable(),
+        );
+        while let (Some(&i), Some(&j)) = (a.peek(), b.peek()) {
+            if rects[j as usize]
+                .p0
+                .x
+                .total_cmp(&rects[i as usize].p0.x)
+                .is_lt()
+            {
+                order.push(j);
+                b.next();
+            } else {
+                order.push(i);
+                a.next();
+            }
+        }
+        order.extend(a);
+        order.extend(b);
+        Self::sorted(rects, order)
+    }
+
+    fn near<'a>(
+        &'a self,
+        rects: &'a [DRect],
+        p: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = &'a DRect> + 'a {
+        let end = self
+            .order
+            .partition_point(|&i| rects[i as usize].p0.x <= p.x + radius);
+        self.order[..end]
+            .chunks(SNAP_BLOCK)
+            .zip(self.block_max.iter())
+            .filter(move |(_, max)| **max >= p.x - radius)
+            .flat_map(|(block, _)| block.iter())
+            .map(move |&i| &rects[i as usize])
+    }
+}
+
+// The rects of a batch sorted on min.x, so that snapping only looks at the rects
+// near the cursor, with the largest max.x of every block of them so that blocks
+// left of the cursor are skipped. Rects appended to the batch are sorted on their
+// own and merged with the runs before them once those are no more than twice as
+// long, so that a long stream of small appends isn't sorted again and again.
+#[derive(Component, Debug, Default)]
+pub struct SnapIndex {
+    // the batch's, see `BatchedQuads::generation`
+    generation: u64,
+    len: usize,
+    // longest first
+    runs: Vec<SortedRun>,
+}
+
+impl SnapIndex {
+    pub fn new(rects: &[DRect]) -> Self {
+        let mut index = Self::default();
+        index.extend(rects);
+        index
+    }
+
+    // Takes in the rects after the ones the index has.
+    pub fn extend(&mut self, rects: &[DRect]) {
+        if rects.len() <= self.len {
+            return;
+        }
+        let added = (self.len as u32..rects.len() as u32).collect();
+        self.runs.push(SortedRun::new(rects, added));
+        self.len = rects.len();
+        while let [.., a, b] = self.runs.as_slice() {
+            if a.order.len() > 2 * b.order.len() {
+                break;
+            }
+            let b = self.runs.pop().unwrap();
+            let a = self.runs.pop().unwrap();
+            self.runs.push(SortedRun::merge(rects, a, b));
+        }
+    }
+
+    // The rects that may be within `radius` of `p`, out of the validated rects the
+    // index was built from.
+    pub fn near<'a>(
+        &'a self,
+        rects: &'a [DRect],
+        p: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = &'a DRect> + 'a {
+        self.runs
+            .iter()
+            .flat_map(move |run| run.near(rects, p, radius))
+    }
+}
+
+// Batches that were only appended to extend their index with the new rects.
+fn index_snap_targets(
+    mut commands: Commands,
+    mut batches: Query<(Entity, &BatchedQuads, Option<&mut SnapIndex>), Changed<BatchedQuads>>,
+) {
+    for (entity, batch, index) in batches.iter_mut() {
+        if !batch.validated {
+            continue;
+        }
+        match index {
+            Some(mut index) if index.generation == batch.generation => {
+                if index.len < batch.data.len() {
+                    index.extend(&batch.data);
+                }
+            }
+            _ => {
+                let mut index = SnapIndex::new(&batch.data);
+                index.generation = batch.generation;
+                commands.entity(entity).insert(index);
+            }
+        }
+    }
+}
+
+// Shared snapping for the interactive tools (ruler, selection, editing): a system
+// takes a `Snapper` and asks it where the cursor should land.
+#[derive(SystemParam)]
+pub struct Snapper<'w, 's> {
+    windows: Res<'w, Windows>,
+    #[allow(clippy::type_complexity)]
+    cameras: Query<
+        'w,
+        's,
+        (
+            &'static Camera,
+            &'static GlobalTransform,
+            Option<&'static ViewGrid>,
+            Option<&'static Viewport>,
+            Option<&'static LayerVisibility>,
+        ),
+        With<Camera2d>,
+    >,
+    focused: Res<'w, FocusedView>,
+    batched_quads: Query<'w, 's, (&'static BatchedQuads, &'static SnapIndex)>,
+    settings: Res<'w, SnapSettings>,
+    grid: Res<'w, GridSettings>,
+}
+
+impl<'w, 's> Snapper<'w, 's> {
+    // Tools work in the focused view.
+    #[allow(clippy::type_complexity)]
+    fn view(
+        &self,
+    ) -> Option<(
+        &Camera,
+        &GlobalTransform,
+        Option<&ViewGrid>,
+        Option<&Viewport>,
+        Option<&LayerVisibility>,
+    )> {
+        let entity = self.focused.0?;
+        self.cameras.get(entity).ok()
+    }
+
+    pub fn world_units_per_pixel(&self) -> Option<f32> {
+        let window = self.windows.get_primary()?;
+        let (camera, camera_transform, ..) = self.view()?;
+        Some(world_units_per_pixel(window, camera, camera_transform))
+    }
+
+    pub fn cursor_world_position(&self) -> Option<Vec2> {
+        let window = self.windows.get_primary()?;
+        let (camera, camera_transform, _, viewport, _) = self.view()?;
+        cursor_world_position(window, camera, camera_transform, viewport)
+    }
+
+    // Snaps a world-space point: to nearby geometry on the view's visible layers
+    // first, then to the grid.
+    pub fn snap(&self, p: Vec2) -> Option<Snap> {
+        let (_, _, view_grid, _, layers) = self.view()?;
+        if self.settings.geometry {
+            let radius = self.settings.radius_px * self.world_units_per_pixel()?;
+            let rects = self
+                .batched_quads
+                .iter()
+                .filter(|(batch, _)| layers.is_none_or(|layers| layers.is_visible(batch.layer)))
+                .flat_map(|(batch, index)| index.near(&batch.data, p, radius));
+            if let Some(snap) = snap_to_geometry(rects, p, radius) {
+                return Some(snap);
+            }
+        }
+        let pitch = match (self.settings.grid, view_grid, self.grid.manufacturing_grid) {
+            (true, Some(view_grid), _) => view_grid.pitch,
+            (_, _, Some(manufacturing_grid)) if manufacturing_grid > 0.0 => manufacturing_grid,
+            _ => return None,
+        };
+        Some(Snap {
+            point: snap_to_grid(p, pitch),
+            kind: SnapKind::Grid,
+        })
+    }
+
+    // The cursor position, and where it snaps to.
+    pub fn cursor(&self) -> Option<(Vec2, Option<Snap>)> {
+        let cursor = self.cursor_world_position()?;
+        Some((cursor, self.snap(cursor)))
+    }
+}
+
+// N toggles snapping to the grid.
+pub struct SnapPlugin;
+
+impl Plugin for SnapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SnapSettings>()
+            .init_resource::<FocusedView>()
+            .add_system(toggle_grid_snap)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                index_snap_targets.after(validate_batched_quads),
+            );
+    }
+}
+
+fn toggle_grid_snap(keys: Res<Input<KeyCode>>, mut settings: ResMut<SnapSettings>) {
+    if keys.just_pressed(KeyCode::N) {
+        settings.grid = !settings.grid;
+        info!("grid snapping {}", if settings.grid { "on" } else { "off" });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::rect;
+
+    fn snap(rects: &[DRect], x: f32, y: f32, radius: f32) -> Option<(Vec2, SnapKind)> {
+        snap_to_geometry(rects.iter(), Vec2::new(x, y), radius).map(|snap| (snap.point, snap.kind))
+    }
+
+    #[test]
+    fn corners_win_over_closer_edges() {
+        let rects = [rect(0.0, 0.0, 10.0, 4.0)];
+        assert_eq!(
+            snap(&rects, 9.5, 1.0, 1.5),
+            Some((Vec2::new(10.0, 0.0), SnapKind::Corner))
+        );
+        assert_eq!(
+            snap(&rects, 5.0, 4.3, 1.0),
+            Some((Vec2::new(5.0, 4.0), SnapKind::Edge))
+        );
+        // inside, the nearest edge
+        assert_eq!(
+            snap(&rects, 5.0, 0.5, 1.0),
+            Some((Vec2::new(5.0, 0.0), SnapKind::Edge))
+        );
+        assert_eq!(snap(&rects, 5.0, 2.0, 1.0), None);
+        assert_eq!(snap(&rects, 12.0, 2.0, 1.0), None);
+    }
+
+    #[test]
+    fn the_closest_corner_or_edge_of_any_rect_wins() {
+        let rects = [rect(0.0, 0.0, 1.0, 1.0), rect(1.5, 0.0, 3.0, 1.0)];
+        assert_eq!(
+            snap(&rects, 1.3, 1.1, 0.5),
+            Some((Vec2::new(1.5, 1.0), SnapKind::Corner))
+        );
+        assert_eq!(
+            snap(&rects, 1.2, 0.5, 0.5),
+            Some((Vec2::new(1.0, 0.5), SnapKind::Edge))
+        );
+    }
+
+    #[test]
+    fn points_snap_to_the_nearest_grid_point() {
+        assert_eq!(
+            snap_to_grid(Vec2::new(0.26, -0.74), 0.5),
+            Vec2::new(0.5, -0.5)
+        );
+        assert_eq!(
+            snap_to_grid(Vec2::new(12.0, 3.0), 5.0),
+            Vec2::new(10.0, 5.0)
+        );
+    }
+
+    #[test]
+    fn the_index_finds_what_a_full_scan_finds() {
+        // a row of cells under one wide rail
+        let mut rects: Vec<DRect> = (0..1000)
+            .map(|i| {
+                let x = i as f32 * 2.0;
+                rect(x, 2.0, x + 1.0, 3.0)
+            })
+            .collect();
+        rects.push(rect(-10.0, 0.0, 2010.0, 1.0));
+        let index = SnapIndex::new(&rects);
+        for (x, y) in [
+            (0.0, 0.0),
+            (501.2, 3.3),
+            (1000.0, 1.2),
+            (1999.5, 2.5),
+            (3000.0, 0.0),
+        ] {
+            let p = Vec2::new(x, y);
+            let near: Vec<&DRect> = index.near(&rects, p, 0.5).collect();
+            assert!(near.len() <= 3 * SNAP_BLOCK);
+            assert_eq!(
+                snap_to_geometry(near.into_iter(), p, 0.5).map(|snap| snap.point),
+                snap_to_geometry(rects.iter(), p, 0.5).map(|snap| snap.point),
+            );
+        }
+        assert_eq!(index.near(&rects, Vec2::new(3000.0, 0.0), 1.0).count(), 0);
+        assert_eq!(index.near(&rects, Vec2::new(-20.0, 0.0), 1.0).count(), 0);
+    }
+
+    #[test]
+    fn appended_rects_are_merged_into_the_index() {
+        // streamed in small pieces, in no particular order along x
+        let rects: Vec<DRect> = (0..1000)
+            .map(|i| {
+                let x = ((i * 379) % 1000) as f32 * 2.0;
+                rect(x, 0.0, x + 1.0, 1.0)
+            })
+            .collect();
+        let mut index = SnapIndex::new(&rects[..100]);
+        for end in (107..rects.len()).step_by(7).chain([rects.len()]) {
+            index.extend(&rects[..end]);
+            assert!(index.runs.len() <= 2 * (end as f32).log2().ceil() as usize);
+        }
+        index.extend(&rects);
+
+        // every rect once, and each run sorted
+        let mut all: Vec<u32> = index
+            .runs
+            .iter()
+            .flat_map(|run| run.order.clone())
+            .collect();
+        all.sort_unstable();
+        assert_eq!(all, (0..1000).collect::<Vec<u32>>());
+        for run in index.runs.iter() {
+            assert!(run
+                .order
+                .windows(2)
+                .all(|w| rects[w[0] as usize].p0.x <= rects[w[1] as usize].p0.x));
+        }
+        for x in [0.0, 3.2, 777.0, 1998.6, 1999.4] {
+            let p = Vec2::new(x, 0.9);
+            assert_eq!(
+                snap_to_geometry(index.near(&rects, p, 0.5), p, 0.5).map(|snap| snap.point),
+                snap_to_geometry(rects.iter(), p, 0.5).map(|snap| snap.point),
+            );
+        }
+    }
+}
@@ -0,0 +1,557 @@
+mod render_graph;
+
+use bevy::core_pipeline::node::MAIN_PASS_DRIVER;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::render::camera::{
+    camera_system, extract_cameras, ActiveCamera, Camera2d, CameraProjection, ExtractedCamera,
+};
+use bevy::render::render_graph::RenderGraph;
+use bevy::render::render_phase::TrackedRenderPass;
+use bevy::render::view::ExtractedView;
+use bevy::render::{RenderApp, RenderStage};
+use bevy::transform::TransformSystem;
+use bevy::utils::HashMap;
+
+use crate::camera::{CursorOverUi, UiInput};
+use crate::navigation::CameraController;
+
+use self::render_graph::{SplitViewsDriverNode, SPLIT_VIEWS_DRIVER};
+
+// The part of the window a camera draws into, as fractions of the window size from
+// the bottom left.
+#[derive(Clone, Copy, Component, Debug, PartialEq)]
+pub struct Viewport {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            min: Vec2::ZERO,
+            max: Vec2::ONE,
+        }
+    }
+}
+
+impl Viewport {
+    // The viewport in logical pixels from the bottom left, as (min, max).
+    pub fn logical_rect(&self, window: &Window) -> (Vec2, Vec2) {
+        let size = Vec2::new(window.width(), window.height());
+        (self.min * size, self.max * size)
+    }
+
+    pub fn contains(&self, window: &Window, screen_pos: Vec2) -> bool {
+        let (min, max) = self.logical_rect(window);
+        screen_pos.cmpge(min).all() && screen_pos.cmple(max).all()
+    }
+
+    // Cameras keep a projection for the whole window, with one pixel per world unit
+    // at scale 1, so a position in the viewport is moved to where it would be if the
+    // viewport was centered on the window.
+    pub fn window_position(&self, window: &Window, screen_pos: Vec2) -> Vec2 {
+        let (min, max) = self.logical_rect(window);
+        let window_center = Vec2::new(window.width(), window.height()) * 0.5;
+        screen_pos - (min + max) * 0.5 + window_center
+    }
+
+    fn extract(&self, window: &Window) -> ExtractedViewport {
+        let size = Vec2::new(
+            window.physical_width() as f32,
+            window.physical_height() as f32,
+        );
+        let (min, max) = ((self.min * size).round(), (self.max * size).round());
+        let viewport_size = (max - min).max(Vec2::ONE);
+        ExtractedViewport {
+            position: Vec2::new(min.x, size.y - max.y),
+            size: viewport_size,
+            screen_origin: min,
+        }
+    }
+}
+
+// Per-view layer visibility; batches on hidden layers are not queued for the view.
+#[derive(Clone, Component, Debug, Default)]
+pub struct LayerVisibility {
+    hidden: [u64; 4],
+}
+
+impl LayerVisibility {
+    pub fn is_visible(&self, layer: u8) -> bool {
+        self.hidden[layer as usize / 64] & (1 << (layer % 64)) == 0
+    }
+
+    pub fn set_visible(&mut self, layer: u8, visible: bool) {
+        let bit = 1 << (layer % 64);
+        if visible {
+            self.hidden[layer as usize / 64] &= !bit;
+        } else {
+            self.hidden[layer as usize / 64] |= bit;
+        }
+    }
+
+    pub fn toggle(&mut self, layer: u8) {
+        self.set_visible(layer, !self.is_visible(layer));
+    }
+
+    // For a layer that is deleted: the layers above it move down.
+    pub fn remove_layer(&mut self, layer: u8) {
+        for above in layer..u8::MAX {
+            self.set_visible(above, self.is_visible(above + 1));
+        }
+        self.set_visible(u8::MAX, true);
+    }
+}
+
+pub struct SplitViews {
+    // Locked views pan and zoom together, keeping their offsets to each other.
+    pub lock: bool,
+}
+
+impl Default for SplitViews {
+    fn default() -> Self {
+        Self { lock: true }
+    }
+}
+
+// The camera under the cursor, which receives pan, zoom and tool input. It stays
+// focused while the cursor is outside of every view.
+#[derive(Default)]
+pub struct FocusedView(pub Option<Entity>);
+
+// Cameras added by the split, removed again when the split is undone.
+#[derive(Component)]
+pub struct SplitCamera;
+
+// Render world: where a view draws into its target, in physical pixels.
+#[derive(Clone, Copy, Component, Debug)]
+pub struct ExtractedViewport {
+    // from the top left, as the render pass expects
+    pub position: Vec2,
+    pub size: Vec2,
+    // from the bottom left, as the overlay's screen-space segments are
+    pub screen_origin: Vec2,
+}
+
+impl ExtractedViewport {
+    pub fn set(&self, pass: &mut TrackedRenderPass) {
+        pass.set_viewport(
+            self.position.x,
+            self.position.y,
+            self.size.x,
+            self.size.y,
+            0.0,
+            1.0,
+        );
+    }
+}
+
+// Render world: views other than the active camera, each drawn by its own run of
+// the 2d graph.
+#[derive(Component)]
+pub struct SplitView;
+
+// V splits the window in two side-by-side views and back, K toggles the pan/zoom
+// lock, F1-F8 toggle layers 0-7 in the focused view.
+pub struct SplitViewsPlugin;
+
+impl Plugin for SplitViewsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SplitViews>()
+            .init_resource::<FocusedView>()
+            .init_resource::<CursorOverUi>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                focus_views.after(InputSystem).after(UiInput),
+            )
+            .add_system(toggle_split)
+            .add_system(toggle_lock)
+            .add_system(toggle_layers)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                sync_locked_views
+                    .before(camera_system::<OrthographicProjection>)
+                    .before(TransformSystem::TransformPropagate),
+            );
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.add_system_to_stage(
+            RenderStage::Extract,
+            extract_split_views.after(extract_cameras::<Camera2d>),
+        );
+
+        // every view besides the active camera is drawn after the main pass
+        let split_views_driver = SplitViewsDriverNode::new(&mut render_app.world);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node(SPLIT_VIEWS_DRIVER, split_views_driver);
+        graph
+            .add_node_edge(MAIN_PASS_DRIVER, SPLIT_VIEWS_DRIVER)
+            .unwrap();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn focus_views(
+    windows: Res<Windows>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    active_2d: Res<ActiveCamera<Camera2d>>,
+    over_ui: Res<CursorOverUi>,
+    mut focused: ResMut<FocusedView>,
+    mut cameras: Query<(Entity, Option<&Viewport>, Option<&mut CameraController>), With<Camera2d>>,
+) {
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| Some((window, window.cursor_position()?)));
+    // a drag keeps going in the view it started in
+    let dragging = mouse_buttons.get_pressed().next().is_some();
+    if let (Some((window, cursor)), false) = (cursor, dragging) {
+        let hovered = cameras.iter().find(|(_, viewport, _)| {
+            viewport.is_none_or(|viewport| viewport.contains(window, cursor))
+        });
+        if let Some((entity, _, _)) = hovered {
+            focused.0 = Some(entity);
+        }
+    }
+    if focused.0.is_none_or(|entity| !cameras.contains(entity)) {
+        focused.0 = active_2d.get();
+    }
+
+    // only the focused view follows the mouse; locked views follow it in turn
+    for (entity, _, controller) in cameras.iter_mut() {
+        if let Some(mut controller) = controller {
+            controller.enabled = !over_ui.0 && focused.0 == Some(entity);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn toggle_split(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut cameras: Query<
+        (
+            Entity,
+            &Transform,
+            &OrthographicProjection,
+            &mut Viewport,
+            Option<&LayerVisibility>,
+            Option<&CameraController>,
+            Option<&SplitCamera>,
+        ),
+        With<Camera2d>,
+    >,
+) {
+    if !keys.just_pressed(KeyCode::V) {
+        return;
+    }
+    let split = cameras
+        .iter()
+        .any(|(.., split_camera)| split_camera.is_some());
+    if split {
+        for (entity, .., split_camera) in cameras.iter_mut() {
+            if split_camera.is_some() {
+                commands.entity(entity).despawn();
+            }
+        }
+        for (.., mut viewport, _, _, _) in cameras.iter_mut() {
+            *viewport = Viewport::default();
+        }
+        info!("single view");
+        return;
+    }
+
+    let (_, transform, projection, mut viewport, layers, controller, _) =
+        match cameras.iter_mut().next() {
+            Some(camera) => camera,
+            None => return,
+        };
+    split_camera(
+        &mut commands,
+        transform,
+        projection,
+        &mut viewport,
+        layers.cloned().unwrap_or_default(),
+        controller,
+    );
+    info!("split view");
+}
+
+// Narrows a camera to the left half of the window, and spawns a camera for the
+// right half that looks at the same place and shows `layers`.
+pub fn split_camera(
+    commands: &mut Commands,
+    transform: &Transform,
+    projection: &OrthographicProjection,
+    viewport: &mut Viewport,
+    layers: LayerVisibility,
+    controller: Option<&CameraController>,
+) -> Entity {
+    *viewport = Viewport {
+        min: Vec2::ZERO,
+        max: Vec2::new(0.5, 1.0),
+    };
+    let mut bundle = OrthographicCameraBundle::new_2d();
+    bundle.transform = *transform;
+    bundle.orthographic_projection.scale = projection.scale;
+    let mut camera = commands.spawn_bundle(bundle);
+    camera.insert_bundle((
+        Viewport {
+            min: Vec2::new(0.5, 0.0),
+            max: Vec2::ONE,
+        },
+        layers,
+        SplitCamera,
+    ));
+    if let Some(controller) = controller {
+        camera.insert(controller.clone());
+    }
+    camera.id()
+}
+
+fn toggle_lock(keys: Res<Input<KeyCode>>, mut split_views: ResMut<SplitViews>) {
+    if keys.just_pressed(KeyCode::K) {
+        split_views.lock = !split_views.lock;
+        info!(
+            "split views {}",
+            if split_views.lock {
+                "locked"
+            } else {
+                "unlocked"
+            }
+        );
+    }
+}
+
+fn toggle_layers(
+    keys: Res<Input<KeyCode>>,
+    focused: Res<FocusedView>,
+    mut layers: Query<&mut LayerVisibility>,
+) {
+    let keys_and_layers = [
+        KeyCode::F1,
+        KeyCode::F2,
+        KeyCode::F3,
+        KeyCode::F4,
+        KeyCode::F5,
+        KeyCode::F6,
+        KeyCode::F7,
+        KeyCode::F8,
+    ]
+    .into_iter()
+    .zip(0u8..);
+    let mut layers = match focused.0.and_then(|entity| layers.get_mut(entity).ok()) {
+        Some(layers) => layers,
+        None => return,
+    };
+    for (key, layer) in keys_and_layers {
+        if keys.just_pressed(key) {
+            layers.toggle(layer);
+            info!(
+                "layer {} {} in the focused view",
+                layer,
+                if layers.is_visible(layer) {
+                    "shown"
+                } else {
+                    "hidden"
+                }
+            );
+        }
+    }
+}
+
+// Where a locked view goes when the focused view moves from `before` to `after`,
+// each as (translation, scale): it pans by the same world distance and zooms by the
+// same factor, keeping its offset to the focused view.
+fn follow_locked_view(before: (Vec3, f32), after: (Vec3, f32), view: (Vec3, f32)) -> (Vec3, f32) {
+    (view.0 + after.0 - before.0, view.1 * after.1 / before.1)
+}
+
+// Applies the pan and zoom of the focused view to the other views, whatever moved
+// it (mouse, minimap, ...).
+fn sync_locked_views(
+    split_views: Res<SplitViews>,
+    focused: Res<FocusedView>,
+    mut cameras: Query<(Entity, &mut Transform, &mut OrthographicProjection), With<Viewport>>,
+    mut last: Local<HashMap<Entity, (Vec3, f32)>>,
+) {
+    let moved = focused.0.and_then(|entity| {
+        let (_, transform, projection) = cameras.get(entity).ok()?;
+        let before = *last.get(&entity)?;
+        let after = (transform.translation, projection.scale);
+        (before != after).then_some((entity, before, after))
+    });
+    if let (true, Some((focused, before, after))) = (split_views.lock, moved) {
+        for (entity, mut transform, mut projection) in cameras.iter_mut() {
+            if entity != focused {
+                let view = (transform.translation, projection.scale);
+                (transform.translation, projection.scale) = follow_locked_view(before, after, view);
+            }
+        }
+    }
+
+    last.clear();
+    for (entity, transform, projection) in cameras.iter() {
+        last.insert(entity, (transform.translation, projection.scale));
+    }
+}
+
+// EXTRACT:
+// Runs after the active camera is extracted, and narrows its view to its viewport.
+// The other cameras become views of their own, drawn into the same window.
+#[allow(clippy::type_complexity)]
+fn extract_split_views(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    active_2d: Res<ActiveCamera<Camera2d>>,
+    cameras: Query<
+        (
+            Entity,
+            &Camera,
+            &OrthographicProjection,
+            &GlobalTransform,
+            &Viewport,
+            Option<&LayerVisibility>,
+        ),
+        With<Camera2d>,
+    >,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    for (entity, camera, projection, transform, viewport, layers) in cameras.iter() {
+        let extracted_viewport = viewport.extract(window);
+        let (min, max) = viewport.logical_rect(window);
+        let mut projection = projection.clone();
+        projection.update(max.x - min.x, max.y - min.y);
+
+        let mut view = commands.get_or_spawn(entity);
+        view.insert_bundle((
+            ExtractedView {
+                projection: projection.get_projection_matrix(),
+                transform: *transform,
+                width: extracted_viewport.size.x as u32,
+                height: extracted_viewport.size.y as u32,
+                near: camera.near,
+                far: camera.far,
+            },
+            extracted_viewport,
+            layers.cloned().unwrap_or_default(),
+        ));
+        if active_2d.get() != Some(entity) {
+            view.insert_bundle((
+                ExtractedCamera {
+                    target: camera.target.clone(),
+                    physical_size: Some(UVec2::new(
+                        window.physical_width(),
+                        window.physical_height(),
+                    )),
+                },
+                SplitView,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locked_views_keep_their_offset_to_the_focused_view() {
+        let before = (Vec3::new(10.0, 20.0, 0.0), 2.0);
+        let after = (Vec3::new(15.0, 10.0, 0.0), 1.0);
+        let view = (Vec3::new(-100.0, 0.0, 0.0), 4.0);
+        assert_eq!(
+            follow_locked_view(before, after, view),
+            (Vec3::new(-95.0, -10.0, 0.0), 2.0)
+        );
+        assert_eq!(follow_locked_view(before, before, view), view);
+    }
+
+    fn spawn_view(world: &mut World, x: f32, scale: f32) -> Entity {
+        world
+            .spawn()
+            .insert_bundle((
+                Transform::from_xyz(x, 0.0, 0.0),
+                OrthographicProjection {
+                    scale,
+                    ..Default::default()
+                },
+                Viewport::default(),
+            ))
+            .id()
+    }
+
+    fn view(world: &World, entity: Entity) -> (Vec3, f32) {
+        let transform = world.get::<Transform>(entity).unwrap();
+        let projection = world.get::<OrthographicProjection>(entity).unwrap();
+        (transform.translation, projection.scale)
+    }
+
+    #[test]
+    fn locked_views_pan_and_zoom_with_the_focused_view() {
+        let mut world = World::new();
+        let left = spawn_view(&mut world, 0.0, 1.0);
+        let right = spawn_view(&mut world, 50.0, 2.0);
+        world.insert_resource(SplitViews::default());
+        world.insert_resource(FocusedView(Some(left)));
+        let mut stage = SystemStage::single_threaded().with_system(sync_locked_views);
+        stage.run(&mut world);
+
+        let mut transform = world.get_mut::<Transform>(left).unwrap();
+        transform.translation.x += 10.0;
+        world.get_mut::<OrthographicProjection>(left).unwrap().scale = 0.5;
+        stage.run(&mut world);
+        assert_eq!(view(&world, right), (Vec3::new(60.0, 0.0, 0.0), 1.0));
+        // the views that followed do not move the focused one in turn
+        stage.run(&mut world);
+        assert_eq!(view(&world, left), (Vec3::new(10.0, 0.0, 0.0), 0.5));
+        assert_eq!(view(&world, right), (Vec3::new(60.0, 0.0, 0.0), 1.0));
+
+        // unlocked, each view moves on its own
+        world.resource_mut::<SplitViews>().lock = false;
+        world.get_mut::<Transform>(left).unwrap().translation.y = 30.0;
+        stage.run(&mut world);
+        assert_eq!(view(&world, right), (Vec3::new(60.0, 0.0, 0.0), 1.0));
+    }
+
+    #[test]
+    fn layer_keys_toggle_layers_in_the_focused_view_only() {
+        let mut world = World::new();
+        let left = world.spawn().insert(LayerVisibility::default()).id();
+        let right = world.spawn().insert(LayerVisibility::default()).id();
+        world.insert_resource(FocusedView(Some(right)));
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::F2);
+        world.insert_resource(keys);
+        let mut stage = SystemStage::single_threaded().with_system(toggle_layers);
+        stage.run(&mut world);
+
+        let layers = world.get::<LayerVisibility>(right).unwrap();
+        assert!(!layers.is_visible(1));
+        assert!(layers.is_visible(0));
+        assert!(world.get::<LayerVisibility>(left).unwrap().is_visible(1));
+
+        // pressed again in the other view
+        world.insert_resource(FocusedView(Some(left)));
+        let mut keys = world.resource_mut::<Input<KeyCode>>();
+        keys.clear();
+        keys.release(KeyCode::F2);
+        keys.press(KeyCode::F2);
+        stage.run(&mut world);
+        assert!(!world.get::<LayerVisibility>(left).unwrap().is_visible(1));
+        assert!(!world.get::<LayerVisibility>(right).unwrap().is_visible(1));
+    }
+
+    #[test]
+    fn removing_a_layer_moves_the_hidden_layers_above_it_down() {
+        let mut layers = LayerVisibility::default();
+        layers.set_visible(3, false);
+        layers.set_visible(70, false);
+        layers.remove_layer(2);
+        assert!(!layers.is_visible(2) && !layers.is_visible(69));
+        assert!(layers.is_visible(3) && layers.is_visible(70));
+    }
+}
@@ -0,0 +1,40 @@
+use bevy::core_pipeline::draw_2d_graph;
+use bevy::prelude::*;
+use bevy::render::render_graph::{Node, NodeRunError, RenderGraphContext, SlotValue};
+use bevy::render::renderer::RenderContext;
+
+use super::SplitView;
+
+pub const SPLIT_VIEWS_DRIVER: &str = "SPLIT_VIEWS_DRIVER";
+
+// Runs the 2d graph once for every split view, the way the main pass driver does
+// for the active camera.
+pub struct SplitViewsDriverNode {
+    query: QueryState<Entity, With<SplitView>>,
+}
+
+impl SplitViewsDriverNode {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for SplitViewsDriverNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        _render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        for view_entity in self.query.iter_manual(world) {
+            graph.run_sub_graph(draw_2d_graph::NAME, vec![SlotValue::Entity(view_entity)])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+// Shapes and layouts shared by the unit tests.
+
+use bevy::math::Vec2;
+
+use crate::bounds::Bounds;
+use crate::layout::{LayerKey, Layout};
+use crate::{DRect, LayerRects, Point};
+
+pub fn rect(x0: f32, y0: f32, x1: f32, y1: f32) -> DRect {
+    DRect {
+        p0: Point { x: x0, y: y0 },
+        p1: Point { x: x1, y: y1 },
+        ..Default::default()
+    }
+}
+
+pub fn bounds(x0: f32, y0: f32, x1: f32, y1: f32) -> Bounds {
+    Bounds {
+        min: Vec2::new(x0, y0),
+        max: Vec2::new(x1, y1),
+    }
+}
+
+// Datatype 0 of `layer`.
+pub fn layer(layer: i16) -> LayerKey {
+    LayerKey { layer, datatype: 0 }
+}
+
+// A layout with the rects of each layer, in order.
+pub fn layout(layers: Vec<(i16, Vec<DRect>)>) -> Layout {
+    let mut layout = Layout::default();
+    for (key, rects) in layers {
+        layout.layers.push(LayerRects {
+            rects,
+            index: layout.layers.len() as u8,
+            attributes: None,
+        });
+        layout.layer_keys.push(layer(key));
+    }
+    layout
+}
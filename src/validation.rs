@@ -0,0 +1,369 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::prelude::*;
+
+use crate::attributes::QuadAttributes;
+use crate::{BatchedQuads, DRect, Point};
+
+pub const REJECTED_SHAPES: DiagnosticId =
+    DiagnosticId::from_u128(138124893517632931597102716203658195781);
+pub const REPAIRED_SHAPES: DiagnosticId =
+    DiagnosticId::from_u128(221760441283713452305917470160372498123);
+
+// Generations of validated batches, see `BatchedQuads::generation`.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+// What was wrong with a rect that came in from Doug.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RectIssue {
+    // A coordinate is NaN or infinite; the rect is dropped.
+    NonFinite,
+    // Zero width or zero height; nothing would be drawn, so the rect is dropped.
+    ZeroArea,
+    // p0 is not the bottom-left corner. The pipeline culls back faces, so the
+    // corners are swapped back into place.
+    Inverted,
+    // Negative or non-finite stroke width; clamped to zero.
+    BadStroke,
+}
+
+// Running totals of everything the validation pass has seen, so that bad Doug
+// output is visible instead of silently culled.
+#[derive(Clone, Debug, Default)]
+pub struct ShapeDiagnostics {
+    pub accepted: usize,
+    pub rejected_non_finite: usize,
+    pub rejected_zero_area: usize,
+    pub repaired_inverted: usize,
+    pub repaired_stroke: usize,
+}
+
+impl ShapeDiagnostics {
+    pub fn rejected(&self) -> usize {
+        self.rejected_non_finite + self.rejected_zero_area
+    }
+
+    // A rect with both an inverted corner and a bad stroke counts once per issue.
+    pub fn repaired(&self) -> usize {
+        self.repaired_inverted + self.repaired_stroke
+    }
+
+    fn record(&mut self, issue: RectIssue) {
+        match issue {
+            RectIssue::NonFinite => self.rejected_non_finite += 1,
+            RectIssue::ZeroArea => self.rejected_zero_area += 1,
+            RectIssue::Inverted => self.repaired_inverted += 1,
+            RectIssue::BadStroke => self.repaired_stroke += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &ShapeDiagnostics) {
+        self.accepted += other.accepted;
+        self.rejected_non_finite += other.rejected_non_finite;
+        self.rejected_zero_area += other.rejected_zero_area;
+        self.repaired_inverted += other.repaired_inverted;
+        self.repaired_stroke += other.repaired_stroke;
+    }
+}
+
+fn is_finite(p: Point) -> bool {
+    p.x.is_finite() && p.y.is_finite()
+}
+
+impl DRect {
+    // Returns the same rect with p0 at the minimum and p1 at the maximum corner.
+    pub fn normalized(&self) -> DRect {
+        DRect {
+            p0: Point {
+                x: self.p0.x.min(self.p1.x),
+                y: self.p0.y.min(self.p1.y),
+            },
+            p1: Point {
+                x: self.p0.x.max(self.p1.x),
+                y: self.p0.y.max(self.p1.y),
+            },
+            ..*self
+        }
+    }
+
+    // Checks the rect and repairs what can be repaired. Returns the rect that
+    // should be drawn (if any) and the issues that were found.
+    pub fn validated(&self) -> (Option<DRect>, Vec<RectIssue>) {
+        let mut issues = Vec::new();
+        if !is_finite(self.p0) || !is_finite(self.p1) {
+            issues.push(RectIssue::NonFinite);
+            return (None, issues);
+        }
+        if self.p0.x == self.p1.x || self.p0.y == self.p1.y {
+            issues.push(RectIssue::ZeroArea);
+            return (None, issues);
+        }
+
+        let mut rect = *self;
+        if rect.p0.x > rect.p1.x || rect.p0.y > rect.p1.y {
+            issues.push(RectIssue::Inverted);
+            rect = rect.normalized();
+        }
+        if !(rect.stroke_width >= 0.0 && rect.stroke_width.is_finite()) {
+            issues.push(RectIssue::BadStroke);
+            rect.stroke_width = 0.0;
+        }
+        (Some(rect), issues)
+    }
+}
+
+// Validates a batch of rects, dropping the ones that cannot be drawn.
+pub fn sanitize_rects(rects: &[DRect]) -> (Vec<DRect>, ShapeDiagnostics) {
+    let mut report = ShapeDiagnostics::default();
+    let mut result = Vec::with_capacity(rects.len());
+    for rect in rects {
+        let (rect, issues) = rect.validated();
+        for issue in issues {
+            report.record(issue);
+        }
+        if let Some(rect) = rect {
+            report.accepted += 1;
+            result.push(rect);
+        }
+    }
+    (result, report)
+}
+
+fn warn_about(count: usize, report: &ShapeDiagnostics) {
+    if report.rejected() > 0 || report.repaired() > 0 {
+        warn!(
+            "validated {} rects: {} rejected ({} non-finite, {} zero-area), {} repaired ({} inverted, {} bad stroke)",
+            count,
+            report.rejected(),
+            report.rejected_non_finite,
+            report.rejected_zero_area,
+            report.repaired(),
+            report.repaired_inverted,
+            report.repaired_stroke,
+        );
+    }
+}
+
+// Rects appended to a validated batch are validated and added to its data; the
+// batch's attributes already cover them.
+fn validate_appended(
+    batched_quads: &mut BatchedQuads,
+    attributes: Option<Mut<QuadAttributes>>,
+    shape_diagnostics: &mut ShapeDiagnostics,
+) {
+    let appended = std::mem::take(&mut batched_quads.appended);
+    let (rects, report) = sanitize_rects(&appended);
+    if let (Some(mut attributes), true) = (attributes, report.rejected() > 0) {
+        let start = batched_quads.data.len();
+        attributes.retain(|i| i < start || appended[i - start].validated().0.is_some());
+    }
+    warn_about(appended.len(), &report);
+    batched_quads.data.extend(rects);
+    batched_quads.extracted = false;
+    shape_diagnostics.merge(&report);
+}
+
+pub fn setup_shape_diagnostics(diagnostics: Option<ResMut<Diagnostics>>) {
+    if let Some(mut diagnostics) = diagnostics {
+        diagnostics.add(Diagnostic::new(REJECTED_SHAPES, "rejected_shapes", 20));
+        diagnostics.add(Diagnostic::new(REPAIRED_SHAPES, "repaired_shapes", 20));
+    }
+}
+
+// Ingestion point for rects: every batch is validated exactly once, before it is
+// extracted to the render world. Attributes drop the rects the batch drops.
+pub fn validate_batched_quads(
+    mut batched_quads_query: Query<(&mut BatchedQuads, Option<&mut QuadAttributes>)>,
+    mut shape_diagnostics: ResMut<ShapeDiagnostics>,
+    diagnostics: Option<ResMut<Diagnostics>>,
+) {
+    for (mut batched_quads, attributes) in batched_quads_query.iter_mut() {
+        if batched_quads.validated {
+            if !batched_quads.appended.is_empty() {
+                validate_appended(&mut batched_quads, attributes, &mut shape_diagnostics);
+            }
+            continue;
+        }
+        let (rects, report) = sanitize_rects(&batched_quads.data);
+        if let (Some(mut attributes), true) = (attributes, report.rejected() > 0) {
+            let data = &batched_quads.data;
+            attributes.retain(|i| data[i].validated().0.is_some());
+        }
+        warn_about(batched_quads.data.len(), &report);
+        batched_quads.data = rects;
+        batched_quads.validated = true;
+        batched_quads.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        shape_diagnostics.merge(&report);
+    }
+
+    if let Some(mut diagnostics) = diagnostics {
+        diagnostics.add_measurement(REJECTED_SHAPES, shape_diagnostics.rejected() as f64);
+        diagnostics.add_measurement(REPAIRED_SHAPES, shape_diagnostics.repaired() as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::ShapeAttributes;
+    use crate::test_support::rect;
+
+    #[test]
+    fn normalized_rects_have_p0_at_the_minimum() {
+        let rect = DRect {
+            stroke_width: 0.5,
+            color: 3,
+            ..rect(2.0, -1.0, -3.0, 4.0)
+        };
+        let normalized = rect.normalized();
+        assert_eq!(normalized.p0, Point { x: -3.0, y: -1.0 });
+        assert_eq!(normalized.p1, Point { x: 2.0, y: 4.0 });
+        assert_eq!((normalized.stroke_width, normalized.color), (0.5, 3));
+        assert_eq!(normalized.normalized(), normalized);
+    }
+
+    #[test]
+    fn rects_are_checked_and_repaired() {
+        let good = rect(0.0, 0.0, 1.0, 2.0);
+        assert_eq!(good.validated(), (Some(good), vec![]));
+
+        for bad in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(
+                rect(bad, 0.0, 1.0, 1.0).validated(),
+                (None, vec![RectIssue::NonFinite])
+            );
+            assert_eq!(
+                rect(0.0, 0.0, 1.0, bad).validated(),
+                (None, vec![RectIssue::NonFinite])
+            );
+        }
+        assert_eq!(
+            rect(1.0, 0.0, 1.0, 5.0).validated(),
+            (None, vec![RectIssue::ZeroArea])
+        );
+        assert_eq!(
+            rect(0.0, 2.0, 5.0, 2.0).validated(),
+            (None, vec![RectIssue::ZeroArea])
+        );
+
+        // inverted in either axis
+        assert_eq!(
+            rect(1.0, 0.0, 0.0, 2.0).validated(),
+            (Some(good), vec![RectIssue::Inverted])
+        );
+        assert_eq!(
+            rect(0.0, 2.0, 1.0, 0.0).validated(),
+            (Some(good), vec![RectIssue::Inverted])
+        );
+
+        for stroke_width in [-0.1, f32::NAN, f32::INFINITY] {
+            let (repaired, issues) = DRect {
+                stroke_width,
+                ..rect(1.0, 2.0, 0.0, 0.0)
+            }
+            .validated();
+            assert_eq!(issues, vec![RectIssue::Inverted, RectIssue::BadStroke]);
+            assert_eq!(
+                repaired,
+                Some(DRect {
+                    stroke_width: 0.0,
+                    ..good
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn sanitized_batches_count_what_was_dropped_and_repaired() {
+        let rects = [
+            rect(0.0, 0.0, 1.0, 1.0),
+            rect(f32::NAN, 0.0, 1.0, 1.0),
+            rect(0.0, f32::NEG_INFINITY, 1.0, 1.0),
+            rect(3.0, 3.0, 3.0, 4.0),
+            rect(5.0, 5.0, 4.0, 4.0),
+            DRect {
+                stroke_width: -1.0,
+                ..rect(4.0, 4.0, 5.0, 5.0)
+            },
+        ];
+        let (kept, report) = sanitize_rects(&rects);
+        assert_eq!(
+            kept,
+            vec![
+                rect(0.0, 0.0, 1.0, 1.0),
+                rect(4.0, 4.0, 5.0, 5.0),
+                DRect {
+                    stroke_width: 0.0,
+                    ..rect(4.0, 4.0, 5.0, 5.0)
+                },
+            ]
+        );
+        assert_eq!(report.accepted, 3);
+        assert_eq!(
+            (report.rejected_non_finite, report.rejected_zero_area),
+            (2, 1)
+        );
+        assert_eq!((report.repaired_inverted, report.repaired_stroke), (1, 1));
+        assert_eq!((report.rejected(), report.repaired()), (3, 2));
+    }
+
+    #[test]
+    fn appended_rects_are_validated_on_their_own() {
+        let mut world = World::default();
+        world.insert_resource(ShapeDiagnostics::default());
+        let mut attributes = QuadAttributes::default();
+        let cell = |name: &str| {
+            Some(ShapeAttributes {
+                net: Some(name.into()),
+                ..Default::default()
+            })
+        };
+        attributes.push(cell("A"), 2);
+        let batch = world
+            .spawn()
+            .insert(BatchedQuads {
+                data: vec![rect(0.0, 0.0, 1.0, 1.0), rect(2.0, 0.0, 3.0, 1.0)],
+                ..Default::default()
+            })
+            .insert(attributes)
+            .id();
+        let mut stage = SystemStage::single_threaded().with_system(validate_batched_quads);
+        stage.run(&mut world);
+        let generation = world.get::<BatchedQuads>(batch).unwrap().generation;
+        assert_ne!(generation, 0);
+
+        // drawn batches keep what they have and take the new rects after it
+        let mut quads = world.get_mut::<BatchedQuads>(batch).unwrap();
+        quads.extracted = true;
+        quads.extracted_len = 2;
+        quads.append(&[
+            rect(f32::NAN, 0.0, 1.0, 1.0),
+            rect(5.0, 5.0, 4.0, 4.0),
+            rect(6.0, 0.0, 7.0, 1.0),
+        ]);
+        assert_eq!(quads.data.len(), 2);
+        world
+            .get_mut::<QuadAttributes>(batch)
+            .unwrap()
+            .push(cell("B"), 3);
+        stage.run(&mut world);
+
+        let quads = world.get::<BatchedQuads>(batch).unwrap();
+        assert!(quads.appended.is_empty());
+        assert_eq!(quads.data.len(), 4);
+        assert_eq!(quads.data[2], rect(4.0, 4.0, 5.0, 5.0));
+        assert!(!quads.extracted);
+        assert_eq!(quads.extracted_len, 2);
+        // only appended to, the batch keeps its generation
+        assert_eq!(quads.generation, generation);
+        let attributes = world.get::<QuadAttributes>(batch).unwrap();
+        let cells: Vec<_> = (0..4)
+            .map(|i| attributes.get(i).unwrap().net.clone().unwrap())
+            .collect();
+        assert_eq!(cells, vec!["A", "A", "B", "B"]);
+        let diagnostics = world.get_resource::<ShapeDiagnostics>().unwrap();
+        assert_eq!((diagnostics.accepted, diagnostics.rejected()), (4, 1));
+        assert_eq!(diagnostics.repaired_inverted, 1);
+    }
+}
@@ -0,0 +1,180 @@
+use std::mem::size_of;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::gpu_data::GpuQuad;
+
+use super::QuadDrawMode;
+
+// How many quads one chunk can hold. A chunk is bound as a single storage buffer,
+// so it must fit the device's max_storage_buffer_binding_size. The u32 indices of
+// a chunk can't overflow: even a 4 GiB binding holds fewer than u32::MAX / 6 quads.
+pub fn quads_per_chunk(max_storage_buffer_binding_size: u32) -> usize {
+    (max_storage_buffer_binding_size as usize / size_of::<GpuQuad>()).max(1)
+}
+
+// Splits `count` quads into consecutive ranges of at most `per_chunk` quads.
+pub fn chunk_ranges(count: usize, per_chunk: usize) -> impl Iterator<Item = Range<usize>> {
+    (0..count)
+        .step_by(per_chunk)
+        .map(move |start| start..(start + per_chunk).min(count))
+}
+
+// For `count` quads appended after the first `start` quads of a batch: the chunks
+// they go into, with the quads that go into each.
+pub fn appended_ranges(
+    start: usize,
+    count: usize,
+    per_chunk: usize,
+) -> impl Iterator<Item = (usize, Range<usize>)> {
+    let end = start + count;
+    chunk_ranges(end, per_chunk)
+        .enumerate()
+        .filter(move |(_, range)| range.end > start)
+        .map(move |(i, range)| (i, range.start.max(start)..range.end))
+}
+
+// Room for at least `needed` quads in a chunk that had room for `capacity`: twice
+// as much, so that appending one quad at a time copies each quad a few times at
+// most, but never more than a chunk can hold.
+pub fn grown_capacity(needed: usize, capacity: usize, per_chunk: usize) -> usize {
+    (capacity * 2)
+        .max(needed.next_power_of_two())
+        .min(per_chunk)
+        .max(needed)
+}
+
+// GPU memory taken by quads: their storage buffers, plus the index buffer shared by
+// all chunks when they are drawn indexed, plus the visible-index buffers that GPU
+// culling needs for each view.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct QuadMemory {
+    pub quad_bytes: u64,
+    pub index_bytes: u64,
+    pub cull_bytes_per_view: u64,
+}
+
+impl QuadMemory {
+    pub fn new(quads: usize, per_chunk: usize, mode: QuadDrawMode) -> Self {
+        let index_bytes = match mode {
+            QuadDrawMode::Indexed | QuadDrawMode::GpuCulled => {
+                quads.min(per_chunk) * 6 * size_of::<u32>()
+            }
+            QuadDrawMode::NonIndexed => 0,
+        };
+        // an index per quad, and six u32 of draw arguments per chunk
+        let cull_bytes_per_view = match mode {
+            QuadDrawMode::GpuCulled => {
+                quads * size_of::<u32>() + chunk_ranges(quads, per_chunk).count() * 24
+            }
+            _ => 0,
+        };
+        QuadMemory {
+            quad_bytes: (quads * size_of::<GpuQuad>()) as u64,
+            index_bytes: index_bytes as u64,
+            cull_bytes_per_view: cull_bytes_per_view as u64,
+        }
+    }
+
+    // with a single view
+    pub fn total(&self) -> u64 {
+        self.quad_bytes + self.index_bytes + self.cull_bytes_per_view
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(count: usize, max_binding_size: u32) {
+        let per_chunk = quads_per_chunk(max_binding_size);
+        let ranges: Vec<Range<usize>> = chunk_ranges(count, per_chunk).collect();
+
+        // every quad is in exactly one chunk, in order
+        let mut next = 0;
+        for range in ranges.iter() {
+            assert_eq!(range.start, next);
+            assert!(!range.is_empty());
+            next = range.end;
+        }
+        assert_eq!(next, count);
+
+        for range in ranges.iter() {
+            let bytes = range.len() * size_of::<GpuQuad>();
+            assert!(bytes <= max_binding_size as usize || range.len() == 1);
+            assert!(range.len() * 6 <= u32::MAX as usize);
+        }
+        // only the last chunk may be partly filled
+        for range in ranges.iter().rev().skip(1) {
+            assert_eq!(range.len(), per_chunk);
+        }
+    }
+
+    #[test]
+    fn chunks_fit_binding_limits() {
+        let quad = size_of::<GpuQuad>() as u32;
+        for max_binding_size in [
+            quad,
+            quad + 1,
+            quad * 2 - 1,
+            quad * 7,
+            1000,
+            65536,
+            128 << 20,
+            u32::MAX,
+        ] {
+            for count in [0, 1, 2, 3, 7, 100, 1001, 65536] {
+                check(count, max_binding_size);
+            }
+        }
+    }
+
+    #[test]
+    fn appended_quads_fill_the_last_chunk_first() {
+        let ranges: Vec<_> = appended_ranges(5, 7, 4).collect();
+        assert_eq!(ranges, vec![(1, 5..8), (2, 8..12)]);
+        let ranges: Vec<_> = appended_ranges(5, 8, 4).collect();
+        assert_eq!(ranges, vec![(1, 5..8), (2, 8..12), (3, 12..13)]);
+        // a batch whose last chunk is full starts a new one
+        let ranges: Vec<_> = appended_ranges(8, 2, 4).collect();
+        assert_eq!(ranges, vec![(2, 8..10)]);
+        assert_eq!(appended_ranges(3, 0, 4).count(), 0);
+    }
+
+    #[test]
+    fn chunks_grow_by_doubling_up_to_the_limit() {
+        assert_eq!(grown_capacity(3, 0, 100), 4);
+        assert_eq!(grown_capacity(5, 4, 100), 8);
+        assert_eq!(grown_capacity(40, 8, 100), 64);
+        assert_eq!(grown_capacity(90, 64, 100), 100);
+        // a chunk never holds less than is needed
+        assert_eq!(grown_capacity(7, 0, 5), 7);
+    }
+
+    #[test]
+    fn default_limit_holds_millions_of_quads() {
+        // 128 MiB, the default max_storage_buffer_binding_size
+        let per_chunk = quads_per_chunk(128 << 20);
+        assert_eq!(per_chunk, (128 << 20) / size_of::<GpuQuad>());
+        assert_eq!(chunk_ranges(12_000_000, per_chunk).count(), 3);
+    }
+
+    #[test]
+    fn non_indexed_halves_memory() {
+        let indexed = QuadMemory::new(1000, 400, QuadDrawMode::Indexed);
+        let non_indexed = QuadMemory::new(1000, 400, QuadDrawMode::NonIndexed);
+        assert_eq!(indexed.quad_bytes, non_indexed.quad_bytes);
+        // the index buffer only covers the largest chunk
+        assert_eq!(indexed.index_bytes, 400 * 24);
+        assert_eq!(non_indexed.index_bytes, 0);
+        let one_chunk = QuadMemory::new(1000, 1000, QuadDrawMode::Indexed);
+        assert_eq!(one_chunk.total(), 2 * non_indexed.total());
+    }
+
+    #[test]
+    fn tiny_limit_still_makes_progress() {
+        assert_eq!(quads_per_chunk(0), 1);
+        assert_eq!(chunk_ranges(5, quads_per_chunk(0)).count(), 5);
+    }
+}
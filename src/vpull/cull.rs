@@ -0,0 +1,390 @@
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::render_graph::{self, NodeRunError, RenderGraphContext, SlotInfo, SlotType};
+use bevy::render::render_phase::RenderPhase;
+use bevy::render::render_resource::{
+    std140::AsStd140, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType,
+    BufferDescriptor, BufferId, BufferSize, BufferUsages, CachedComputePipelineId,
+    ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, ShaderStages, UniformVec,
+};
+use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use bevy::render::view::{ViewUniform, ViewUniformOffset, ViewUniforms};
+use bevy::utils::{HashMap, HashSet};
+use bytemuck::cast_slice;
+
+use crate::gpu_data::{GpuPalette, GpuQuads};
+use crate::phase_item::QuadsPhaseItem;
+
+use super::pipeline::VpullPipeline;
+use super::QuadDrawMode;
+
+// GPU culling for `QuadDrawMode::GpuCulled`. Before a view's quads are drawn, a
+// compute pass tests every quad of every chunk against the view and a minimum size
+// on screen, and packs the indices of the survivors into a buffer per view and
+// chunk. The chunk is then drawn with draw_indexed_indirect, with the index count
+// the compute pass wrote.
+
+pub const QUAD_CULL_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 3907144820965131129);
+
+pub const QUAD_CULL_PASS: &str = "QUAD_CULL_PASS";
+
+const WORKGROUP_SIZE: u32 = 64;
+// Dispatches are at most 65535 workgroups wide, so large chunks are dispatched in
+// rows of this many workgroups; the shader finds its quad as id.y * 65536 + id.x.
+const WORKGROUPS_PER_ROW: u32 = 1024;
+
+// Quads smaller than this on screen, in both directions, are not drawn when culling
+// on the GPU.
+#[derive(Clone, Debug)]
+pub struct QuadCullSettings {
+    pub min_pixel_size: f32,
+}
+
+impl Default for QuadCullSettings {
+    fn default() -> Self {
+        Self {
+            min_pixel_size: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, AsStd140)]
+struct GpuCullSettings {
+    min_pixel_size: f32,
+}
+
+pub struct QuadCullPipeline {
+    pipeline_id: CachedComputePipelineId,
+    view_layout: BindGroupLayout,
+    data_layout: BindGroupLayout,
+}
+
+impl FromWorld for QuadCullPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let uniform_entry = |binding: u32, dynamic: bool, size: usize| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: dynamic,
+                min_binding_size: BufferSize::new(size as u64),
+            },
+            count: None,
+        };
+        let storage_entry = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(0),
+            },
+            count: None,
+        };
+
+        let render_device = world.resource::<RenderDevice>();
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("quad_cull_view_layout"),
+            entries: &[
+                uniform_entry(0, true, ViewUniform::std140_size_static()),
+                uniform_entry(1, false, GpuCullSettings::std140_size_static()),
+            ],
+        });
+        // quads, visible quad indices, draw arguments
+        let data_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("quad_cull_data_layout"),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                storage_entry(2, false),
+            ],
+        });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("quad_cull_pipeline".into()),
+            layout: Some(vec![view_layout.clone(), data_layout.clone()]),
+            shader: QUAD_CULL_SHADER_HANDLE.typed(),
+            shader_defs: vec![],
+            entry_point: "cull".into(),
+        });
+
+        Self {
+            pipeline_id,
+            view_layout,
+            data_layout,
+        }
+    }
+}
+
+// The culling output of one chunk in one view. Its bind groups are kept for as
+// long as the chunk keeps its buffer.
+pub struct CullTarget {
+    quads: BufferId,
+    quad_count: u32,
+    // DrawIndexedIndirect arguments, followed by the chunk's quad count
+    pub args: Buffer,
+    cull_bind_group: BindGroup,
+    pub draw_bind_group: BindGroup,
+}
+
+// Keyed by view, batch and chunk index.
+#[derive(Default)]
+pub struct GpuCulling {
+    settings: UniformVec<GpuCullSettings>,
+    pub targets: HashMap<(Entity, Entity, usize), CullTarget>,
+}
+
+#[derive(Component)]
+pub struct CullViewBindGroup {
+    bind_group: BindGroup,
+}
+
+pub fn extract_cull_settings(mut commands: Commands, settings: Res<QuadCullSettings>) {
+    if settings.is_changed() {
+        commands.insert_resource(settings.clone());
+    }
+}
+
+// Makes sure every view has a visible-index buffer for every chunk, and resets the
+// draw arguments that the compute pass counts into.
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_culling(
+    views: Query<Entity, With<RenderPhase<QuadsPhaseItem>>>,
+    draw_mode: Res<QuadDrawMode>,
+    settings: Res<QuadCullSettings>,
+    gpu_quads: Res<GpuQuads>,
+    gpu_palette: Res<GpuPalette>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    cull_pipeline: Res<QuadCullPipeline>,
+    quads_pipeline: Res<VpullPipeline>,
+    mut culling: ResMut<GpuCulling>,
+) {
+    if *draw_mode != QuadDrawMode::GpuCulled {
+        culling.targets.clear();
+        return;
+    }
+    let culling = culling.into_inner();
+    culling.settings.clear();
+    culling.settings.push(GpuCullSettings {
+        min_pixel_size: settings.min_pixel_size,
+    });
+    culling.settings.write_buffer(&render_device, &render_queue);
+
+    let palette = match gpu_palette.data.buffer() {
+        Some(palette) => palette,
+        None => return,
+    };
+    let mut live = HashSet::default();
+    for view in views.iter() {
+        for (batch_entity, batch) in gpu_quads.batches.iter() {
+            for (i, chunk) in batch.chunks.iter().enumerate() {
+                let key = (view, *batch_entity, i);
+                live.insert(key);
+                let stale = culling
+                    .targets
+                    .get(&key)
+                    .is_none_or(|target| target.quads != chunk.buffer.id());
+                if stale {
+                    let visible = render_device.create_buffer(&BufferDescriptor {
+                        label: Some("quad_cull_visible_buffer"),
+                        size: (chunk.capacity.max(1) * std::mem::size_of::<u32>()) as u64,
+                        usage: BufferUsages::STORAGE,
+                        mapped_at_creation: false,
+                    });
+                    let args = render_device.create_buffer(&BufferDescriptor {
+                        label: Some("quad_cull_args_buffer"),
+                        size: 6 * std::mem::size_of::<u32>() as u64,
+                        usage: BufferUsages::STORAGE
+                            | BufferUsages::INDIRECT
+                            | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    });
+                    let cull_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+                        label: Some("quad_cull_data_bind_group"),
+                        layout: &cull_pipeline.data_layout,
+                        entries: &[
+                            BindGroupEntry {
+                                binding: 0,
+                                resource: chunk.buffer.as_entire_binding(),
+                            },
+                            BindGroupEntry {
+                                binding: 1,
+                                resource: visible.as_entire_binding(),
+                            },
+                            BindGroupEntry {
+                                binding: 2,
+                                resource: args.as_entire_binding(),
+                            },
+                        ],
+                    });
+                    let draw_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+                        label: Some("culled_quads_data_bind_group"),
+                        layout: &quads_pipeline.culled_data_layout,
+                        entries: &[
+                            BindGroupEntry {
+                                binding: 0,
+                                resource: chunk.buffer.as_entire_binding(),
+                            },
+                            BindGroupEntry {
+                                binding: 1,
+                                resource: palette.as_entire_binding(),
+                            },
+                            BindGroupEntry {
+                                binding: 2,
+                                resource: chunk.flags.as_entire_binding(),
+                            },
+                            BindGroupEntry {
+                                binding: 3,
+                                resource: visible.as_entire_binding(),
+                            },
+                        ],
+                    });
+                    culling.targets.insert(
+                        key,
+                        CullTarget {
+                            quads: chunk.buffer.id(),
+                            quad_count: 0,
+                            args,
+                            cull_bind_group,
+                            draw_bind_group,
+                        },
+                    );
+                }
+
+                let target = culling.targets.get_mut(&key).unwrap();
+                target.quad_count = chunk.quad_count;
+                // no indices yet, one instance, and the quad count for the shader
+                let args: [u32; 6] = [0, 1, 0, 0, 0, chunk.quad_count];
+                render_queue.write_buffer(&target.args, 0, cast_slice(&args));
+            }
+        }
+    }
+    culling.targets.retain(|key, _| live.contains(key));
+}
+
+pub fn queue_cull_view_bind_groups(
+    mut commands: Commands,
+    draw_mode: Res<QuadDrawMode>,
+    render_device: Res<RenderDevice>,
+    view_uniforms: Res<ViewUniforms>,
+    culling: Res<GpuCulling>,
+    cull_pipeline: Res<QuadCullPipeline>,
+    views: Query<Entity, (With<RenderPhase<QuadsPhaseItem>>, With<ViewUniformOffset>)>,
+) {
+    if *draw_mode != QuadDrawMode::GpuCulled {
+        return;
+    }
+    let (view_binding, settings_binding) =
+        match (view_uniforms.uniforms.binding(), culling.settings.binding()) {
+            (Some(view), Some(settings)) => (view, settings),
+            _ => return,
+        };
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("quad_cull_view_bind_group"),
+        layout: &cull_pipeline.view_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: view_binding,
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: settings_binding,
+            },
+        ],
+    });
+    for view in views.iter() {
+        commands.entity(view).insert(CullViewBindGroup {
+            bind_group: bind_group.clone(),
+        });
+    }
+}
+
+pub struct QuadCullNode {
+    #[allow(clippy::type_complexity)]
+    query: QueryState<(
+        &'static RenderPhase<QuadsPhaseItem>,
+        &'static ViewUniformOffset,
+        &'static CullViewBindGroup,
+    )>,
+}
+
+impl QuadCullNode {
+    pub const IN_VIEW: &'static str = "view";
+
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl render_graph::Node for QuadCullNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(QuadCullNode::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let (quads_phase, view_offset, view_bind_group) =
+            match self.query.get_manual(world, view_entity) {
+                Ok(query) => query,
+                Err(_) => return Ok(()), // not culling, or no window
+            };
+        let cull_pipeline = world.resource::<QuadCullPipeline>();
+        let pipeline = match world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(cull_pipeline.pipeline_id)
+        {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+        let culling = world.resource::<GpuCulling>();
+        let gpu_quads = world.resource::<GpuQuads>();
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some("quad_cull_pass"),
+            });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &view_bind_group.bind_group, &[view_offset.offset]);
+        // only the batches this view draws
+        for item in quads_phase.items.iter() {
+            let chunks = gpu_quads
+                .batches
+                .get(&item.entity)
+                .map_or(0, |batch| batch.chunks.len());
+            for i in 0..chunks {
+                let target = match culling.targets.get(&(view_entity, item.entity, i)) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                let groups = target.quad_count.div_ceil(WORKGROUP_SIZE);
+                if groups == 0 {
+                    continue;
+                }
+                pass.set_bind_group(1, &target.cull_bind_group, &[]);
+                pass.dispatch(
+                    groups.min(WORKGROUPS_PER_ROW),
+                    groups.div_ceil(WORKGROUPS_PER_ROW),
+                    1,
+                );
+            }
+        }
+        Ok(())
+    }
+}
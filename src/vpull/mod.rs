@@ -1,3 +1,5 @@
+mod chunks;
+mod cull;
 mod pipeline;
 mod render_command;
 mod render_graph;
@@ -6,23 +8,37 @@ use bevy::core_pipeline::draw_2d_graph;
 use bevy::prelude::*;
 use bevy::render::camera::{ActiveCamera, Camera2d};
 use bevy::render::render_graph::RenderGraph;
-use bevy::render::render_phase::{AddRenderCommand, DrawFunctions, RenderPhase};
+use bevy::render::render_phase::{sort_phase_system, AddRenderCommand, DrawFunctions, RenderPhase};
 use bevy::render::render_resource::{
-    BindGroupDescriptor, BindGroupEntry, BufferInitDescriptor, BufferUsages,
+    BindGroupDescriptor, BindGroupEntry, BufferDescriptor, BufferInitDescriptor, BufferUsages,
+    CommandEncoderDescriptor,
 };
 use bevy::render::renderer::{RenderDevice, RenderQueue};
 
 use bevy::app::{App, Plugin};
 use bevy::render::{RenderApp, RenderStage};
+use bevy::utils::HashMap;
 use bytemuck::cast_slice;
+use clap::ArgEnum;
 
-use crate::gpu_data::{GpuDataBindGroup, GpuPalette, GpuQuad, GpuQuads};
-use crate::phase_item::QuadsPhaseItem;
+use crate::bounds::{update_layout_extent, LayoutExtent};
+use crate::gpu_data::{GpuPalette, GpuQuadBatch, GpuQuads, GpuQuadsChunk};
+use crate::phase_item::{QuadsPhaseItem, QUADS_SORT_KEY};
+use crate::split_views::{LayerVisibility, Viewport};
+use crate::validation::{setup_shape_diagnostics, validate_batched_quads, ShapeDiagnostics};
 use crate::{BatchedQuads, DRect};
 
+use self::chunks::{appended_ranges, chunk_ranges, grown_capacity};
+pub use self::chunks::{quads_per_chunk, QuadMemory};
+pub use self::cull::QuadCullSettings;
+use self::cull::{
+    extract_cull_settings, prepare_culling, queue_cull_view_bind_groups, GpuCulling, QuadCullNode,
+    QuadCullPipeline, QUAD_CULL_PASS, QUAD_CULL_SHADER_HANDLE,
+};
 use self::pipeline::{VpullPipeline, QUADS_SHADER_HANDLE};
-use self::render_command::DrawQuadsVertexPulling;
-use self::render_graph::{VpullPassNode, VPULL_PASS};
+use self::render_command::{DrawQuadsGpuCulled, DrawQuadsVertexPulling};
+use self::render_graph::VpullPassNode;
+pub use self::render_graph::VPULL_PASS;
 
 pub struct VpullPlugin;
 
@@ -33,24 +49,56 @@ impl Plugin for VpullPlugin {
             QUADS_SHADER_HANDLE,
             Shader::from_wgsl(include_str!("../shaders/vpull.wgsl")),
         );
+        app.world.resource_mut::<Assets<Shader>>().set_untracked(
+            QUAD_CULL_SHADER_HANDLE,
+            Shader::from_wgsl(include_str!("../shaders/quad_cull.wgsl")),
+        );
+
+        // rects are normalized and validated in the main world, before extraction
+        app.init_resource::<ShapeDiagnostics>()
+            .init_resource::<LayoutExtent>()
+            .init_resource::<QuadDrawMode>()
+            .init_resource::<QuadCullSettings>()
+            .init_resource::<QuadHighlights>()
+            .init_resource::<Palette>()
+            .add_startup_system(setup_shape_diagnostics)
+            .add_system_to_stage(CoreStage::PostUpdate, validate_batched_quads)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                update_layout_extent.after(validate_batched_quads),
+            );
 
         let render_app = app.sub_app_mut(RenderApp);
 
         render_app
             .init_resource::<DrawFunctions<QuadsPhaseItem>>()
             .add_render_command::<QuadsPhaseItem, DrawQuadsVertexPulling>()
+            .add_render_command::<QuadsPhaseItem, DrawQuadsGpuCulled>()
             .init_resource::<VpullPipeline>()
             .init_resource::<GpuQuads>()
             .init_resource::<Palette>()
             .init_resource::<GpuPalette>()
+            .init_resource::<QuadDrawMode>()
+            .init_resource::<QuadCullSettings>()
+            .init_resource::<QuadHighlights>()
+            .init_resource::<QuadCullPipeline>()
+            .init_resource::<GpuCulling>()
+            .add_system_to_stage(RenderStage::Extract, extract_quad_draw_mode)
+            .add_system_to_stage(RenderStage::Extract, extract_cull_settings)
+            .add_system_to_stage(RenderStage::Extract, extract_highlights)
+            .add_system_to_stage(RenderStage::Extract, extract_palette)
             .add_system_to_stage(RenderStage::Extract, extract_quads_phase)
             .add_system_to_stage(RenderStage::Extract, extract_quads)
             .add_system_to_stage(RenderStage::Prepare, prepare_quads)
-            .add_system_to_stage(RenderStage::Queue, queue_quads);
+            .add_system_to_stage(RenderStage::Prepare, prepare_culling.after(prepare_quads))
+            .add_system_to_stage(RenderStage::Queue, queue_quads)
+            .add_system_to_stage(RenderStage::Queue, queue_cull_view_bind_groups)
+            .add_system_to_stage(RenderStage::PhaseSort, sort_phase_system::<QuadsPhaseItem>);
 
         // connect into the main render graph
         // connect vpull as a node before the main render graph node
         let vpull_pass_node = VpullPassNode::new(&mut render_app.world);
+        let quad_cull_node = QuadCullNode::new(&mut render_app.world);
         let mut graph = render_app.world.resource_mut::<RenderGraph>();
         let draw_2d_graph = graph.get_sub_graph_mut(draw_2d_graph::NAME).unwrap();
         draw_2d_graph.add_node(VPULL_PASS, vpull_pass_node);
@@ -65,28 +113,81 @@ impl Plugin for VpullPlugin {
                 VpullPassNode::IN_VIEW,
             )
             .unwrap();
+
+        // culling on the GPU fills the buffers the quads pass draws from
+        draw_2d_graph.add_node(QUAD_CULL_PASS, quad_cull_node);
+        draw_2d_graph
+            .add_node_edge(QUAD_CULL_PASS, VPULL_PASS)
+            .unwrap();
+        draw_2d_graph
+            .add_slot_edge(
+                draw_2d_graph.input_node().unwrap().id,
+                draw_2d_graph::input::VIEW_ENTITY,
+                QUAD_CULL_PASS,
+                QuadCullNode::IN_VIEW,
+            )
+            .unwrap();
     }
 }
 
+// How quads become triangles. Indexed draws four vertices per quad through a
+// shared index buffer of six u32 indices per quad; non-indexed draws six vertices
+// per quad and needs no index buffer, halving the memory per quad. GPU-culled is
+// indexed, but a compute pass first drops the quads each view can't show.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ArgEnum)]
+pub enum QuadDrawMode {
+    #[default]
+    Indexed,
+    NonIndexed,
+    GpuCulled,
+}
+
+impl QuadDrawMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuadDrawMode::Indexed => "indexed",
+            QuadDrawMode::NonIndexed => "non-indexed",
+            QuadDrawMode::GpuCulled => "gpu-culled",
+        }
+    }
+}
+
+// Quads drawn highlighted, by batch entity and index in the batch's validated
+// `data`. Tools replace the whole set.
+#[derive(Clone, Debug, Default)]
+pub struct QuadHighlights {
+    pub quads: HashMap<Entity, Vec<u32>>,
+}
+
 #[derive(Clone, Component, Debug, Default)]
 struct ExtractedQuads {
     data: Vec<DRect>,
+    // index of data's first rect in the batch; above 0, the rects before it are on
+    // the GPU already and `data` is appended to them
+    start: usize,
+    layer: u8,
     prepared: bool,
 }
 
-struct Palette {
-    colors: Vec<Color>,
-    prepared: bool,
+// Rect colors are indices into the palette, which starts out as these colors.
+pub const PALETTE: [&str; 5] = ["648FFF", "785EF0", "DC267F", "FE6100", "FFB000"];
+
+// The palette buffer is allocated once at this size, so that a changed palette is
+// written in place and bind groups stay valid.
+pub const MAX_PALETTE_COLORS: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub colors: Vec<Color>,
 }
 
 impl Default for Palette {
     fn default() -> Self {
         Palette {
-            colors: vec!["648FFF", "785EF0", "DC267F", "FE6100", "FFB000"]
+            colors: PALETTE
                 .into_iter()
                 .map(|c| Color::hex(c).unwrap())
                 .collect::<Vec<Color>>(),
-            prepared: false,
         }
     }
 }
@@ -101,34 +202,180 @@ impl Default for Palette {
 // only copying the relevant components.
 //
 // Entities and components of the render app are cleared every tick, so we must reset them every tick.
-fn extract_quads_phase(mut commands: Commands, active_2d: Res<ActiveCamera<Camera2d>>) {
-    if let Some(entity) = active_2d.get() {
+//
+// Every view (the active camera and any split views) gets its own phase.
+fn extract_quads_phase(
+    mut commands: Commands,
+    active_2d: Res<ActiveCamera<Camera2d>>,
+    views: Query<Entity, (With<Camera2d>, With<Viewport>)>,
+) {
+    let active = active_2d.get().filter(|entity| !views.contains(*entity));
+    for entity in views.iter().chain(active) {
         commands
             .get_or_spawn(entity)
             .insert(RenderPhase::<QuadsPhaseItem>::default());
     }
 }
 
+const QUAD_SIZE: usize = std::mem::size_of::<DRect>();
+
+// Makes the buffers and bind group of a chunk of quads.
+struct ChunkFactory<'a> {
+    render_device: &'a RenderDevice,
+    pipeline: &'a VpullPipeline,
+    palette: &'a GpuPalette,
+}
+
+impl ChunkFactory<'_> {
+    // A chunk room for `capacity` quads, holding `contents` if given. Chunks can be
+    // copied from, so that one that grows keeps its quads.
+    fn new_chunk(&self, capacity: usize, contents: Option<&[u8]>) -> GpuQuadsChunk {
+        let usage = BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC;
+        let buffer = match contents {
+            Some(contents) if contents.len() == capacity * QUAD_SIZE => self
+                .render_device
+                .create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("gpu_quads_chunk_buffer"),
+                    contents,
+                    usage,
+                }),
+            _ => self.render_device.create_buffer(&BufferDescriptor {
+                label: Some("gpu_quads_chunk_buffer"),
+                size: (capacity.max(1) * QUAD_SIZE) as u64,
+                usage,
+                mapped_at_creation: false,
+            }),
+        };
+        let flags = self.render_device.create_buffer(&BufferDescriptor {
+            label: Some("gpu_quads_flags_buffer"),
+            size: (flag_words(capacity) * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("gpu_data_bind_group"),
+            layout: &self.pipeline.data_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.palette.data.buffer().unwrap().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: flags.as_entire_binding(),
+                },
+            ],
+        });
+        GpuQuadsChunk {
+            buffer,
+            flags,
+            capacity,
+            quad_count: contents.map_or(0, |contents| (contents.len() / QUAD_SIZE) as u32),
+            bind_group,
+        }
+    }
+}
+
+// Writes quads appended to a batch after its first `start` quads. Chunks that are
+// full move to a buffer twice as big, with their quads copied over on the GPU, so
+// that a batch that keeps growing is uploaded once, not again with every append.
+fn append_quads(
+    chunks: &ChunkFactory,
+    render_queue: &RenderQueue,
+    batch: &mut GpuQuadBatch,
+    start: usize,
+    contents: &[u8],
+    per_chunk: usize,
+) {
+    let count = contents.len() / QUAD_SIZE;
+    let mut encoder = None;
+    for (i, range) in appended_ranges(start, count, per_chunk) {
+        let chunk_start = i * per_chunk;
+        let needed = range.end - chunk_start;
+        let bytes = &contents[(range.start - start) * QUAD_SIZE..(range.end - start) * QUAD_SIZE];
+        if i == batch.chunks.len() {
+            batch
+                .chunks
+                .push(chunks.new_chunk(grown_capacity(needed, 0, per_chunk), None));
+        }
+        let chunk = &mut batch.chunks[i];
+        if chunk.capacity < needed {
+            let grown = chunks.new_chunk(grown_capacity(needed, chunk.capacity, per_chunk), None);
+            let encoder = encoder.get_or_insert_with(|| {
+                chunks
+                    .render_device
+                    .create_command_encoder(&CommandEncoderDescriptor {
+                        label: Some("gpu_quads_grow_encoder"),
+                    })
+            });
+            let kept = (range.start - chunk_start) * QUAD_SIZE;
+            encoder.copy_buffer_to_buffer(&chunk.buffer, 0, &grown.buffer, 0, kept as u64);
+            *chunk = GpuQuadsChunk {
+                quad_count: chunk.quad_count,
+                ..grown
+            };
+        }
+        // the copies don't overlap the new quads, so their order doesn't matter
+        let offset = (range.start - chunk_start) * QUAD_SIZE;
+        render_queue.write_buffer(&chunk.buffer, offset as u64, bytes);
+        chunk.quad_count = needed as u32;
+    }
+    if let Some(encoder) = encoder {
+        render_queue.submit([encoder.finish()]);
+    }
+}
+
+fn extract_quad_draw_mode(mut commands: Commands, draw_mode: Res<QuadDrawMode>) {
+    if draw_mode.is_changed() {
+        commands.insert_resource(*draw_mode);
+    }
+}
+
+fn extract_highlights(mut commands: Commands, highlights: Res<QuadHighlights>) {
+    if highlights.is_changed() {
+        commands.insert_resource(highlights.clone());
+    }
+}
+
+fn extract_palette(mut commands: Commands, palette: Res<Palette>) {
+    if palette.is_changed() {
+        commands.insert_resource(palette.clone());
+    }
+}
+
 // The commands in this function are from the Render sub app, but the queries access
 // entities from the main app.
+//
+// Every live batch gets an ExtractedQuads each frame, with data only when it is new;
+// batches that don't get one were despawned and their GPU buffers are freed.
 fn extract_quads(
     mut commands: Commands,
     mut batched_quads_query: Query<(Entity, &mut BatchedQuads)>,
 ) {
     for (entity, mut batched_quads) in batched_quads_query.iter_mut() {
-        if !batched_quads.extracted {
+        if batched_quads.validated && !batched_quads.extracted {
+            let start = batched_quads.extracted_len.min(batched_quads.data.len());
             let extracted_quads = ExtractedQuads {
-                data: batched_quads.data.clone(),
+                data: batched_quads.data[start..].to_vec(),
+                start,
+                layer: batched_quads.layer,
                 prepared: false,
             };
-            commands
-                .get_or_spawn(entity)
-                .insert(extracted_quads.clone());
+            commands.get_or_spawn(entity).insert(extracted_quads);
             batched_quads.extracted = true;
-            info!("finished extracting quads.");
+            batched_quads.extracted_len = batched_quads.data.len();
+            if start == 0 {
+                info!("finished extracting quads.");
+            }
         } else {
             commands.get_or_spawn(entity).insert(ExtractedQuads {
                 data: Vec::new(),
+                start: 0,
+                layer: batched_quads.layer,
                 prepared: true,
             });
         }
@@ -140,27 +387,140 @@ fn extract_quads(
 // writing to GPU Buffers and Textures and creating Bind Groups.
 //
 // This time, the resources will come from the render app world.
+//
+// Each batch's quads are split into chunks that fit the device's storage binding
+// limit. An updated batch writes into its existing buffers when they are big enough,
+// so its bind groups are only created when a buffer is. Every chunk also has a
+// bitset of highlighted quads, rewritten whenever highlights or batches change.
+#[allow(clippy::too_many_arguments)]
 fn prepare_quads(
-    mut commands: Commands,
     mut quads: Query<(Entity, &mut ExtractedQuads)>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
-    mut gpu_quads: ResMut<GpuQuads>,
-    mut palette: ResMut<Palette>,
+    gpu_quads: ResMut<GpuQuads>,
+    palette: Res<Palette>,
     mut gpu_palette: ResMut<GpuPalette>,
     quads_pipeline: Res<VpullPipeline>,
+    draw_mode: Res<QuadDrawMode>,
+    highlights: Res<QuadHighlights>,
 ) {
+    if palette.is_changed() {
+        gpu_palette.data.clear();
+        gpu_palette.data.reserve(MAX_PALETTE_COLORS, &render_device);
+        for color in palette.colors.iter().take(MAX_PALETTE_COLORS) {
+            gpu_palette.data.push(color.as_rgba_f32());
+        }
+        gpu_palette.data.write_buffer(&render_device, &render_queue);
+    }
+
+    let gpu_quads = gpu_quads.into_inner();
+    let batch_count = gpu_quads.batches.len();
+    gpu_quads
+        .batches
+        .retain(|entity, _| quads.contains(*entity));
+    let mut changed = gpu_quads.batches.len() != batch_count;
+
+    let per_chunk = quads_per_chunk(render_device.limits().max_storage_buffer_binding_size);
     for (entity, mut quads) in quads.iter_mut() {
-        if !quads.prepared {
-            quads.prepared = true;
-            for quad in quads.data.iter() {
-                gpu_quads.instances.push(GpuQuad::from(quad));
+        if quads.prepared {
+            continue;
+        }
+        quads.prepared = true;
+        changed = true;
+        // rects are laid out like `GpuQuad`, so they are uploaded as they are
+        let contents: &[u8] = cast_slice(&quads.data);
+        let batch = gpu_quads.batches.entry(entity).or_default();
+        let chunks = ChunkFactory {
+            render_device: &render_device,
+            pipeline: &quads_pipeline,
+            palette: &gpu_palette,
+        };
+        if quads.start > 0 {
+            append_quads(
+                &chunks,
+                &render_queue,
+                batch,
+                quads.start,
+                contents,
+                per_chunk,
+            );
+            continue;
+        }
+        let ranges: Vec<_> = chunk_ranges(quads.data.len(), per_chunk).collect();
+        batch.chunks.truncate(ranges.len());
+        for (i, range) in ranges.into_iter().enumerate() {
+            let contents = &contents[range.start * QUAD_SIZE..range.end * QUAD_SIZE];
+            match batch.chunks.get_mut(i) {
+                Some(chunk) if chunk.capacity >= range.len() => {
+                    render_queue.write_buffer(&chunk.buffer, 0, contents);
+                    chunk.quad_count = range.len() as u32;
+                }
+                _ => {
+                    let chunk = chunks.new_chunk(range.len(), Some(contents));
+                    if i < batch.chunks.len() {
+                        batch.chunks[i] = chunk;
+                    } else {
+                        batch.chunks.push(chunk);
+                    }
+                }
+            }
+        }
+    }
+
+    if changed || highlights.is_changed() {
+        for (entity, batch) in gpu_quads.batches.iter() {
+            let highlighted = highlights.quads.get(entity);
+            for (i, chunk) in batch.chunks.iter().enumerate() {
+                let start = i * per_chunk;
+                let mut words = vec![0u32; flag_words(chunk.quad_count as usize)];
+                for index in highlighted.into_iter().flatten() {
+                    let index = (*index as usize).wrapping_sub(start);
+                    if index < chunk.quad_count as usize {
+                        words[index / 32] |= 1 << (index % 32);
+                    }
+                }
+                render_queue.write_buffer(&chunk.flags, 0, cast_slice(&words));
             }
-            info!("count of rects: {}", gpu_quads.instances.len());
-            gpu_quads.index_count = gpu_quads.instances.len() as u32 * 6;
-            info!("index count: {}", gpu_quads.index_count);
-            let mut indices = Vec::with_capacity(gpu_quads.index_count as usize);
-            for i in 0..gpu_quads.instances.len() {
+        }
+    }
+
+    let largest = gpu_quads
+        .batches
+        .values()
+        .flat_map(|batch| batch.chunks.iter())
+        .map(|chunk| chunk.quad_count as usize)
+        .max()
+        .unwrap_or_default();
+    if changed {
+        let quads: usize = gpu_quads
+            .batches
+            .values()
+            .flat_map(|batch| batch.chunks.iter())
+            .map(|chunk| chunk.quad_count as usize)
+            .sum();
+        let memory = QuadMemory::new(quads, largest.max(1), *draw_mode);
+        info!(
+            "quad batches: {}, {} rects, {} draw, {} quad bytes, {} index bytes",
+            gpu_quads.batches.len(),
+            quads,
+            draw_mode.name(),
+            memory.quad_bytes,
+            memory.index_bytes
+        );
+    }
+
+    // the index buffer grows with the largest chunk, and shrinks once the batches
+    // that needed it are gone
+    let index_buffer_fits =
+        largest <= gpu_quads.index_buffer_quads && largest * 2 >= gpu_quads.index_buffer_quads;
+    match *draw_mode {
+        QuadDrawMode::Indexed | QuadDrawMode::GpuCulled if largest == 0 => {
+            gpu_quads.index_buffer = None;
+            gpu_quads.index_buffer_quads = 0;
+        }
+        QuadDrawMode::Indexed | QuadDrawMode::GpuCulled if !index_buffer_fits => {
+            let mut indices = Vec::with_capacity(largest * 6);
+            for i in 0..largest {
                 let base = (i * 4) as u32;
                 indices.push(base + 2);
                 indices.push(base);
@@ -169,6 +529,7 @@ fn prepare_quads(
                 indices.push(base + 3);
                 indices.push(base + 2);
             }
+            info!("index count: {}", indices.len());
             gpu_quads.index_buffer = Some(render_device.create_buffer_with_data(
                 &BufferInitDescriptor {
                     label: Some("gpu_quads_index_buffer"),
@@ -176,59 +537,46 @@ fn prepare_quads(
                     usage: BufferUsages::INDEX,
                 },
             ));
-            gpu_quads
-                .instances
-                .write_buffer(&*render_device, &*render_queue);
+            gpu_quads.index_buffer_quads = largest;
         }
-
-        if !palette.prepared {
-            for color in palette.colors.iter() {
-                gpu_palette.data.push(color.as_rgba_f32());
-            }
-            gpu_palette
-                .data
-                .write_buffer(&*render_device, &*render_queue);
-            palette.prepared = true;
+        QuadDrawMode::NonIndexed if gpu_quads.index_buffer.is_some() => {
+            gpu_quads.index_buffer = None;
+            gpu_quads.index_buffer_quads = 0;
         }
-
-        commands
-            .get_or_spawn(entity)
-            .insert_bundle((GpuDataBindGroup {
-                bind_group: render_device.create_bind_group(&BindGroupDescriptor {
-                    label: Some("gpu_data_bind_group"),
-                    layout: &quads_pipeline.data_layout,
-                    entries: &[
-                        BindGroupEntry {
-                            binding: 0,
-                            resource: gpu_quads.instances.buffer().unwrap().as_entire_binding(),
-                        },
-                        BindGroupEntry {
-                            binding: 1,
-                            resource: gpu_palette.data.buffer().unwrap().as_entire_binding(),
-                        },
-                    ],
-                }),
-            },));
+        _ => {}
     }
 }
 
+// u32 words of a bitset with a bit per quad; bindings can't be empty
+fn flag_words(quads: usize) -> usize {
+    quads.div_ceil(32).max(1)
+}
+
 // QUEUE:
 // This "queues" render jobs that feed off of "prepared" data.
+// Batches on layers hidden in a view are left out of its phase.
 fn queue_quads(
     opaque_2d_draw_functions: Res<DrawFunctions<QuadsPhaseItem>>,
-    mut views: Query<&mut RenderPhase<QuadsPhaseItem>>,
-    quads_query: Query<Entity, With<ExtractedQuads>>,
+    draw_mode: Res<QuadDrawMode>,
+    mut views: Query<(&mut RenderPhase<QuadsPhaseItem>, Option<&LayerVisibility>)>,
+    quads_query: Query<(Entity, &ExtractedQuads)>,
 ) {
-    let draw_quads = opaque_2d_draw_functions
-        .read()
-        .get_id::<DrawQuadsVertexPulling>()
-        .unwrap();
+    let draw_functions = opaque_2d_draw_functions.read();
+    let draw_quads = match *draw_mode {
+        QuadDrawMode::GpuCulled => draw_functions.get_id::<DrawQuadsGpuCulled>(),
+        _ => draw_functions.get_id::<DrawQuadsVertexPulling>(),
+    }
+    .unwrap();
 
-    for mut opaque_phase in views.iter_mut() {
-        for entity in quads_query.iter() {
+    for (mut opaque_phase, layers) in views.iter_mut() {
+        for (entity, quads) in quads_query.iter() {
+            if layers.is_some_and(|layers| !layers.is_visible(quads.layer)) {
+                continue;
+            }
             opaque_phase.add(QuadsPhaseItem {
                 entity,
                 draw_function: draw_quads,
+                sort_key: QUADS_SORT_KEY,
             });
         }
     }
@@ -18,7 +18,12 @@ use bevy::{
 
 pub struct VpullPipeline {
     pub pipeline_id: CachedRenderPipelineId,
+    // the same pipeline for `QuadDrawMode::NonIndexed`
+    pub non_indexed_pipeline_id: CachedRenderPipelineId,
+    // and for `QuadDrawMode::GpuCulled`, which reads the visible quads' indices
+    pub culled_pipeline_id: CachedRenderPipelineId,
     pub data_layout: BindGroupLayout,
+    pub culled_data_layout: BindGroupLayout,
 }
 
 pub const QUADS_SHADER_HANDLE: HandleUntyped =
@@ -48,75 +53,95 @@ impl FromWorld for VpullPipeline {
                     label: Some("shadow_view_layout"),
                 });
 
-        let data_layout =
-            world
-                .resource::<RenderDevice>()
-                .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                    label: None,
-                    entries: &[
-                        BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: ShaderStages::VERTEX,
-                            ty: BindingType::Buffer {
-                                ty: BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: BufferSize::new(0),
-                            },
-                            count: None,
-                        },
-                        BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: ShaderStages::VERTEX,
-                            ty: BindingType::Buffer {
-                                ty: BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: BufferSize::new(0),
-                            },
-                            count: None,
-                        },
-                    ],
-                });
-
-        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
-        let pipeline_id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
-            label: Some("vpull_pipeline".into()),
-            layout: Some(vec![view_layout, data_layout.clone()]),
-            vertex: VertexState {
-                shader: QUADS_SHADER_HANDLE.typed(),
-                shader_defs: vec![],
-                entry_point: "vertex".into(),
-                buffers: vec![],
-            },
-            fragment: Some(FragmentState {
-                shader: QUADS_SHADER_HANDLE.typed(),
-                shader_defs: vec![],
-                entry_point: "fragment".into(),
-                targets: vec![ColorTargetState {
-                    format: TextureFormat::bevy_default(),
-                    blend: Some(BlendState::ALPHA_BLENDING),
-                    write_mask: ColorWrites::ALL,
-                }],
-            }),
-            primitive: PrimitiveState {
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
-                unclipped_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false,
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: Msaa::default().samples,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        // quads, palette, highlight flags, and for culled draws the indices of the
+        // visible quads
+        let storage_entry = |binding: u32| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(0),
             },
+            count: None,
+        };
+        let render_device = world.resource::<RenderDevice>();
+        let data_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[storage_entry(0), storage_entry(1), storage_entry(2)],
         });
+        let culled_data_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("culled_quads_data_layout"),
+                entries: &[
+                    storage_entry(0),
+                    storage_entry(1),
+                    storage_entry(2),
+                    storage_entry(3),
+                ],
+            });
+
+        let descriptor =
+            |label: &'static str, data_layout: &BindGroupLayout, shader_defs: Vec<String>| {
+                RenderPipelineDescriptor {
+                    label: Some(label.into()),
+                    layout: Some(vec![view_layout.clone(), data_layout.clone()]),
+                    vertex: VertexState {
+                        shader: QUADS_SHADER_HANDLE.typed(),
+                        shader_defs,
+                        entry_point: "vertex".into(),
+                        buffers: vec![],
+                    },
+                    fragment: Some(FragmentState {
+                        shader: QUADS_SHADER_HANDLE.typed(),
+                        shader_defs: vec![],
+                        entry_point: "fragment".into(),
+                        targets: vec![ColorTargetState {
+                            format: TextureFormat::bevy_default(),
+                            blend: Some(BlendState::ALPHA_BLENDING),
+                            write_mask: ColorWrites::ALL,
+                        }],
+                    }),
+                    primitive: PrimitiveState {
+                        front_face: FrontFace::Ccw,
+                        cull_mode: Some(Face::Back),
+                        unclipped_depth: false,
+                        polygon_mode: PolygonMode::Fill,
+                        conservative: false,
+                        topology: PrimitiveTopology::TriangleList,
+                        strip_index_format: None,
+                    },
+                    depth_stencil: None,
+                    multisample: MultisampleState {
+                        count: Msaa::default().samples,
+                        mask: !0,
+                        alpha_to_coverage_enabled: false,
+                    },
+                }
+            };
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_render_pipeline(descriptor(
+            "vpull_pipeline",
+            &data_layout,
+            vec![],
+        ));
+        let non_indexed_pipeline_id = pipeline_cache.queue_render_pipeline(descriptor(
+            "vpull_non_indexed_pipeline",
+            &data_layout,
+            vec!["NON_INDEXED".to_string()],
+        ));
+        let culled_pipeline_id = pipeline_cache.queue_render_pipeline(descriptor(
+            "vpull_culled_pipeline",
+            &culled_data_layout,
+            vec!["GPU_CULLED".to_string()],
+        ));
 
         Self {
             pipeline_id,
+            non_indexed_pipeline_id,
+            culled_pipeline_id,
             data_layout,
+            culled_data_layout,
         }
     }
 }
@@ -1,8 +1,5 @@
 use bevy::{
-    ecs::system::{
-        lifetimeless::{Read, SQuery, SRes},
-        SystemParamItem,
-    },
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
     pbr::SetShadowViewBindGroup,
     prelude::Entity,
     render::{
@@ -13,20 +10,28 @@ use bevy::{
     },
 };
 
-use crate::gpu_data::{GpuDataBindGroup, GpuQuads};
+use crate::gpu_data::GpuQuads;
+
+use super::cull::GpuCulling;
 
 use super::pipeline::VpullPipeline;
+use super::QuadDrawMode;
 
 pub type DrawQuadsVertexPulling = (
     SetQuadsPipeline,
     SetShadowViewBindGroup<0>,
-    SetGpuQuadsBindGroup<1>,
-    DrawVertexPulledQuads,
+    DrawVertexPulledQuads<1>,
+);
+
+pub type DrawQuadsGpuCulled = (
+    SetQuadsPipeline,
+    SetShadowViewBindGroup<0>,
+    DrawCulledQuads<1>,
 );
 
 pub struct SetQuadsPipeline;
 impl<P: PhaseItem> RenderCommand<P> for SetQuadsPipeline {
-    type Param = (SRes<PipelineCache>, SRes<VpullPipeline>);
+    type Param = (SRes<PipelineCache>, SRes<VpullPipeline>, SRes<QuadDrawMode>);
     #[inline]
     fn render<'w>(
         _view: Entity,
@@ -34,11 +39,13 @@ impl<P: PhaseItem> RenderCommand<P> for SetQuadsPipeline {
         params: SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let (pipeline_cache, vpull_pipeline) = params;
-        if let Some(pipeline) = pipeline_cache
-            .into_inner()
-            .get_render_pipeline(vpull_pipeline.pipeline_id)
-        {
+        let (pipeline_cache, vpull_pipeline, draw_mode) = params;
+        let pipeline_id = match *draw_mode {
+            QuadDrawMode::Indexed => vpull_pipeline.pipeline_id,
+            QuadDrawMode::NonIndexed => vpull_pipeline.non_indexed_pipeline_id,
+            QuadDrawMode::GpuCulled => vpull_pipeline.culled_pipeline_id,
+        };
+        if let Some(pipeline) = pipeline_cache.into_inner().get_render_pipeline(pipeline_id) {
             pass.set_render_pipeline(pipeline);
             RenderCommandResult::Success
         } else {
@@ -47,42 +54,77 @@ impl<P: PhaseItem> RenderCommand<P> for SetQuadsPipeline {
     }
 }
 
-pub struct SetGpuQuadsBindGroup<const I: usize>;
-impl<const I: usize> EntityRenderCommand for SetGpuQuadsBindGroup<I> {
-    type Param = SQuery<Read<GpuDataBindGroup>>;
+// Draws every chunk of the item's batch, binding each chunk's buffer at group I.
+pub struct DrawVertexPulledQuads<const I: usize>;
+impl<const I: usize> EntityRenderCommand for DrawVertexPulledQuads<I> {
+    type Param = (SRes<GpuQuads>, SRes<QuadDrawMode>);
 
     #[inline]
     fn render<'w>(
         _view: Entity,
         item: Entity,
-        gpu_quads_bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        (gpu_quads, draw_mode): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let gpu_quads_bind_group = gpu_quads_bind_groups.get_inner(item).unwrap();
-        pass.set_bind_group(I, &gpu_quads_bind_group.bind_group, &[]);
-
+        let gpu_quads = gpu_quads.into_inner();
+        // not uploaded yet
+        let batch = match gpu_quads.batches.get(&item) {
+            Some(batch) => batch,
+            None => return RenderCommandResult::Success,
+        };
+        match *draw_mode {
+            QuadDrawMode::Indexed | QuadDrawMode::GpuCulled => {
+                let index_buffer = match gpu_quads.index_buffer.as_ref() {
+                    Some(index_buffer) => index_buffer,
+                    None => return RenderCommandResult::Failure,
+                };
+                pass.set_index_buffer(index_buffer.slice(..), 0, IndexFormat::Uint32);
+                for chunk in batch.chunks.iter() {
+                    pass.set_bind_group(I, &chunk.bind_group, &[]);
+                    pass.draw_indexed(0..chunk.quad_count * 6, 0, 0..1);
+                }
+            }
+            // the vertex shader finds the quad and corner from the vertex index
+            QuadDrawMode::NonIndexed => {
+                for chunk in batch.chunks.iter() {
+                    pass.set_bind_group(I, &chunk.bind_group, &[]);
+                    pass.draw(0..chunk.quad_count * 6, 0..1);
+                }
+            }
+        }
         RenderCommandResult::Success
     }
 }
 
-pub struct DrawVertexPulledQuads;
-impl EntityRenderCommand for DrawVertexPulledQuads {
-    type Param = SRes<GpuQuads>;
+// Draws the quads of the item's batch that survived culling in this view, with the
+// index counts the cull pass wrote.
+pub struct DrawCulledQuads<const I: usize>;
+impl<const I: usize> EntityRenderCommand for DrawCulledQuads<I> {
+    type Param = (SRes<GpuQuads>, SRes<GpuCulling>);
 
     #[inline]
     fn render<'w>(
-        _view: Entity,
-        _item: Entity,
-        gpu_quads: SystemParamItem<'w, '_, Self::Param>,
+        view: Entity,
+        item: Entity,
+        (gpu_quads, culling): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let gpu_quads = gpu_quads.into_inner();
-        pass.set_index_buffer(
-            gpu_quads.index_buffer.as_ref().unwrap().slice(..),
-            0,
-            IndexFormat::Uint32,
-        );
-        pass.draw_indexed(0..gpu_quads.index_count, 0, 0..1);
+        let (gpu_quads, culling) = (gpu_quads.into_inner(), culling.into_inner());
+        let (batch, index_buffer) = match (
+            gpu_quads.batches.get(&item),
+            gpu_quads.index_buffer.as_ref(),
+        ) {
+            (Some(batch), Some(index_buffer)) => (batch, index_buffer),
+            // not uploaded yet
+            _ => return RenderCommandResult::Success,
+        };
+        pass.set_index_buffer(index_buffer.slice(..), 0, IndexFormat::Uint32);
+        for i in 0..batch.chunks.len() {
+            if let Some(target) = culling.targets.get(&(view, item, i)) {
+                pass.set_bind_group(I, &target.draw_bind_group, &[]);
+                pass.draw_indexed_indirect(&target.args, 0);
+            }
+        }
         RenderCommandResult::Success
     }
 }
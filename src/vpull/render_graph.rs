@@ -3,15 +3,15 @@ use bevy::render::render_graph::{
     NodeRunError, RenderGraphContext, RunSubGraphError, SlotInfo, SlotType,
 };
 use bevy::render::render_phase::{DrawFunctions, RenderPhase, TrackedRenderPass};
-use bevy::render::render_resource::{
-    LoadOp, Operations, RenderPassDescriptor,
-};
+use bevy::render::render_resource::{LoadOp, Operations, RenderPassDescriptor};
 use bevy::render::view::{ExtractedView, ViewTarget};
 use bevy::render::{render_graph, renderer::RenderContext};
 
 use crate::phase_item::QuadsPhaseItem;
+use crate::split_views::ExtractedViewport;
 use crate::state::RenderState;
 
+#[allow(dead_code)]
 pub struct MainNode {
     pub state: RenderState,
 }
@@ -42,8 +42,13 @@ impl render_graph::Node for MainNode {
 pub const VPULL_PASS: &str = "VPULL_PASS";
 
 pub struct VpullPassNode {
+    #[allow(clippy::type_complexity)]
     query: QueryState<
-        (&'static RenderPhase<QuadsPhaseItem>, &'static ViewTarget),
+        (
+            &'static RenderPhase<QuadsPhaseItem>,
+            &'static ViewTarget,
+            Option<&'static ExtractedViewport>,
+        ),
         With<ExtractedView>,
     >,
 }
@@ -74,7 +79,7 @@ impl render_graph::Node for VpullPassNode {
         world: &World,
     ) -> Result<(), NodeRunError> {
         let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
-        let (quads_phase, target) = match self.query.get_manual(world, view_entity) {
+        let (quads_phase, target, viewport) = match self.query.get_manual(world, view_entity) {
             Ok(query) => query,
             Err(_) => return Ok(()), // No window
         };
@@ -99,6 +104,9 @@ impl render_graph::Node for VpullPassNode {
             .begin_render_pass(&pass_descriptor);
         let mut draw_functions = draw_functions.write();
         let mut tracked_pass = TrackedRenderPass::new(render_pass);
+        if let Some(viewport) = viewport {
+            viewport.set(&mut tracked_pass);
+        }
         for item in &quads_phase.items {
             let draw_function = draw_functions.get_mut(item.draw_function).unwrap();
             draw_function.draw(world, &mut tracked_pass, view_entity, item);